@@ -1,37 +1,74 @@
-use crate::{espnowcommunication::EspNowCommunicationManager, sensors};
+use crate::{espnowcommunication::{ EspNowCommunicationManager, NodeKeys, HIGHEST_COUNTER }, sensors};
 use crate::airqualitysensors::AirQualitySensors;
+use crate::communicationprotocols::{ adc::SharedAdc, i2c::{ I2cHandler, SharedI2c } };
+use crate::mq7heater::{ mq7_heater_task, Mq7Heater, CO_READING, HEATER_CYCLE };
+use crate::pairing::{ Pairing, PairingStore, COUNTER_SAVE_INTERVAL };
+use crate::pushcounter::{ push_counter, resume_push_counter };
+use crate::powermanager::{ CycleEnergy, PowerManager, Retained, MIN_SLEEP };
 
 use esp_hal::{
+    analog::adc::{ AdcConfig, Attenuation },
     clock::CpuClock,
     delay::Delay,
+    efuse::Efuse,
     rng::Rng,
     timer::{ systimer::SystemTimer, timg::TimerGroup },
 };
 
-use esp_wifi::{ EspWifiController, esp_now::EspNowReceiver };
+use esp_wifi::{ EspWifiController, esp_now::{ EspNowReceiver, EspNowSender } };
+use esp_storage::FlashStorage;
 
 use embassy_executor::Spawner;
+use embassy_futures::select::{ select, select4, Either, Either4 };
+use embassy_time::{ with_deadline, Duration, Instant, Timer };
 
-use alloc::format;
+use telemetryframe::{
+    auth::{ local_master_key, Key, PAIRING_COUNTER },
+    command::{ CommandAck, SensorCommand },
+    pairing::PairingMessage,
+    request::DataResponse,
+    Readings,
+    TelemetryFrame,
+};
+
+use esp_println::println;
+
+use embassy_sync::mutex::Mutex;
+
+use static_cell::StaticCell;
 
 use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering;
 
 static mut INIT: MaybeUninit<EspWifiController<'static>> = MaybeUninit::uninit();
-static mut SENSORS: MaybeUninit<AirQualitySensors> = MaybeUninit::uninit();
+static ADC: StaticCell<SharedAdc<'static>> = StaticCell::new();
+static I2C: StaticCell<SharedI2c<'static>> = StaticCell::new();
 
-#[embassy_executor::task]
-async fn listener_task(receiver: EspNowReceiver<'static>) {
-    EspNowCommunicationManager::wait_for_request(receiver).await;
+/// Seconds between the readings this node pushes to the communication module, e.g.
+/// `PUSH_INTERVAL=300 cargo build --release`. Without it the node only measures when asked to.
+const PUSH_INTERVAL: Option<&str> = option_env!("PUSH_INTERVAL");
+
+/// Set to `1` along with `PUSH_INTERVAL` for the node to deep sleep between pushes, waking on the RTC timer
+const DEEP_SLEEP: Option<&str> = option_env!("DEEP_SLEEP");
+
+/// Time a deep sleeping node listens for commands after each push
+const COMMAND_WINDOW: Duration = Duration::from_secs(1);
+
+/// Pushes are spread by up to this fraction of the interval either way, so nodes started
+/// together don't keep transmitting at the same moment
+const PUSH_JITTER_DIVISOR: u64 = 10;
+
+/// Time until the next push, the interval moved by a random jitter
+fn next_push_delay(interval: Duration, rng: &mut Rng) -> Duration {
+    let jitter = interval.as_millis() / PUSH_JITTER_DIVISOR;
+    let offset = rng.random() as u64 % (2 * jitter + 1);
+
+    Duration::from_millis(interval.as_millis() - jitter + offset)
 }
 
 #[embassy_executor::task]
-async fn read_mq7(sensors_ptr: *mut AirQualitySensors){
-    loop {
-        let sensors = unsafe { &mut *sensors_ptr };
-
-        let co = sensors.read_mq7().await;
-        sensors.last_co_reading = Some(co);
-    }
+async fn listener_task(receiver: EspNowReceiver<'static>, node_key: Key, counter_floor: u32) {
+    EspNowCommunicationManager::wait_for_request(receiver, node_key, counter_floor).await;
 }
 
 
@@ -52,62 +89,363 @@ pub async fn airquality_main(spawner: Spawner) {
 
     let timer = TimerGroup::new(peripherals.TIMG0);
 
+    let mut rng = Rng::new(peripherals.RNG);
+
     let init = unsafe{ 
-        INIT.write(esp_wifi::init(timer.timer0, Rng::new(peripherals.RNG), peripherals.RADIO_CLK,).unwrap()); 
+        INIT.write(esp_wifi::init(timer.timer0, rng.clone(), peripherals.RADIO_CLK,).unwrap()); 
         
         INIT.assume_init_mut()
     };
 
+    // The last two bytes of the MAC address identify this node to the communication module
+    let mac_address = Efuse::read_base_mac_address();
+    let node_id = u16::from_be_bytes([mac_address[4], mac_address[5]]);
+
+    // Counters and the frame sequence carry on after deep sleep, any other reset starts afresh
+    let retained = Retained::take();
+    let mut sequence: u16 = retained.map_or(0, |retained| retained.sequence);
+
+    println!("Node id {:04x}", node_id);
+
+    let keys = NodeKeys::from_build()
+        .expect("Build with NODE_KEY and ESPNOW_PMK set to the keys printed by nodekey on the communication module's console");
+
+    // The communication module this node answers, saved once pairing has completed
+    let mut pairing_store = PairingStore::new(FlashStorage::new());
+    let mut pairing = pairing_store.load();
+
+    match pairing {
+        Some(pairing) => println!("Paired with communication module {:02x?}", pairing.gateway),
+        None => println!("Not paired yet, waiting for a communication module to open pairing"),
+    }
+
     // Initialize ESP-NOW communication
     let espnow_communication = EspNowCommunicationManager::new(
         init, 
-        peripherals.WIFI
+        peripherals.WIFI,
+        &keys,
+        pairing.map(|pairing| pairing.gateway)
     );
 
+    let manager = espnow_communication.manager;
     let receiver = espnow_communication.receiver;
     let mut sender = espnow_communication.sender;
-    let peer_address = espnow_communication.peer_address;
 
-    spawner.spawn(listener_task(receiver)).unwrap();
+    // The floor in flash is only saved now and then, deep sleep keeps the exact one
+    let counter_floor = pairing
+        .map_or(0, |pairing| pairing.counter_floor)
+        .max(retained.map_or(0, |retained| retained.counter_floor));
+    spawner.spawn(listener_task(receiver, keys.node_key, counter_floor)).unwrap();
+
+    // The MQ-7 and MQ-131 share ADC1, so both pins are enabled before it is created
+    let mut adc_config = AdcConfig::new();
+    let co_pin = adc_config.enable_pin(peripherals.GPIO3, Attenuation::_11dB);
+    let o3_pin = adc_config.enable_pin(peripherals.GPIO2, Attenuation::_11dB);
+    let adc = ADC.init(SharedAdc::new(peripherals.ADC1, adc_config));
+
+    let i2c = I2cHandler::new(peripherals.I2C0, peripherals.GPIO6, peripherals.GPIO7).unwrap();
+    let i2c = I2C.init(Mutex::new(i2c.get_inner_i2c()));
+
+    // The sensors can only be initialised once their rail is powered
+    let mut power = PowerManager::new(peripherals.GPIO10, peripherals.LPWR);
+    power.power_up().await;
 
     // Initialize sensors
-    let sensors = unsafe { 
-        SENSORS.write(AirQualitySensors::new(
-            peripherals.ADC1,
-            peripherals.GPIO3,
-            peripherals.I2C0,
-            peripherals.GPIO6,
-            peripherals.GPIO7,
-            peripherals.UART0,
-            peripherals.GPIO17,
-            peripherals.GPIO16,
-            peripherals.UART1,
-            peripherals.GPIO20,
-            peripherals.GPIO21,
-            peripherals.GPIO10,
-            peripherals.MCPWM0,
-            peripherals.GPIO11,
-        ));
-
-        SENSORS.assume_init_mut()
+    let mut sensors = AirQualitySensors::new(
+        adc,
+        o3_pin,
+        i2c,
+        peripherals.UART0,
+        peripherals.GPIO17,
+        peripherals.GPIO16,
+        peripherals.UART1,
+        peripherals.GPIO20,
+        peripherals.GPIO21,
+    );
+
+    sensors.init().await;
+
+    // The MQ-7's heater cycle runs in its own task, which publishes a CO reading after every cycle
+    let mq7_heater = Mq7Heater::new(adc, co_pin, peripherals.MCPWM0, peripherals.GPIO11);
+    spawner.spawn(mq7_heater_task(mq7_heater)).unwrap();
+
+    let mut co_reading = CO_READING.anon_receiver();
+
+    // Kept so a retried request is answered without reading the sensors again
+    let mut last_response: Option<DataResponse> = None;
+
+    // In push mode the node measures on its own schedule, and still answers requests in between
+    let push_interval = PUSH_INTERVAL
+        .and_then(|seconds| seconds.parse::<u64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs);
+    let mut push_counter = match retained {
+        Some(retained) => resume_push_counter(FlashStorage::new(), retained.push_counter),
+        None => push_counter(FlashStorage::new()),
     };
+    let deep_sleep = DEEP_SLEEP == Some("1");
 
-    let sensors_ptr = sensors as *mut AirQualitySensors;
-    spawner.spawn(read_mq7(sensors_ptr)).unwrap();
+    match (push_interval, deep_sleep) {
+        (Some(interval), true) => println!("Pushing readings every {} seconds, deep sleeping in between", interval.as_secs()),
+        (Some(interval), false) => println!("Pushing readings every {} seconds", interval.as_secs()),
+        (None, true) => println!("DEEP_SLEEP needs PUSH_INTERVAL, staying awake to answer the communication module"),
+        (None, false) => println!("Measuring when the communication module asks"),
+    }
+
+    // Nonce of the last Join sent, the only one an Accepted may answer
+    let mut join_nonce: Option<u32> = None;
+
+    // The first push waits for the sensors to warm up and the MQ-7 to finish a heater cycle
+    let mut next_push = power.warm_at().unwrap_or(Instant::now()).max(Instant::now() + HEATER_CYCLE);
+
+    // Between readings the rail is off, and switched back on in time for the sensors to warm up
+    let mut power_up_at: Option<Instant> = None;
+    // When the communication module last asked for a reading, which tells when it will ask next
+    let mut last_request: Option<Instant> = None;
 
     loop {
-        EspNowCommunicationManager::wait_for_signal().await;
-        let (environment_variables, pm, co2, co) = sensors.read_all().await;
+        let gateway = pairing.map(|pairing| pairing.gateway);
+
+        let push_due = async {
+            match (push_interval, gateway) {
+                (Some(_), Some(_)) => Timer::at(next_push).await,
+                // Nothing is pushed before the node has been paired
+                _ => core::future::pending::<()>().await,
+            }
+        };
+
+        let power_up_due = async {
+            match power_up_at {
+                Some(at) => Timer::at(at).await,
+                None => core::future::pending::<()>().await,
+            }
+        };
+
+        let event = select4(
+            EspNowCommunicationManager::wait_for_signal(),
+            EspNowCommunicationManager::wait_for_command(),
+            EspNowCommunicationManager::wait_for_pairing(),
+            select(push_due, power_up_due),
+        ).await;
+
+        // A request is answered with its counter, a push is signed with the next push counter
+        let (gateway_address, request) = match event {
+            Either4::First((source, counter, data_request)) if gateway == Some(source) => (source, Some((counter, data_request))),
+            Either4::Second((source, counter, command)) if gateway == Some(source) => {
+                run_command(&mut sensors, &mut sender, &keys.node_key, &source, counter, command).await;
+
+                // Commands change the sensors' settings, so replaying one must not work even after a restart
+                save_counter_floor(&mut pairing_store, &mut pairing, counter, 1);
+                continue;
+            }
+            // A node only pairs with one communication module, it can be moved by erasing the pairing partition
+            Either4::Third((source, message)) if gateway.is_none() || gateway == Some(source) => {
+                match message {
+                    PairingMessage::Discover => {
+                        // Until it has been accepted the node has no encrypted link to the communication module
+                        if gateway.is_none() {
+                            if let Err(e) = EspNowCommunicationManager::set_peer(&manager, &source, None) {
+                                println!("Failed to add communication module as a peer: {:?}", e);
+                                continue;
+                            }
+                        }
+
+                        // A fresh nonce for every Join, so only the answer to this one is taken
+                        let nonce = rng.random();
+                        join_nonce = Some(nonce);
 
-        let (pm1_0, pm2_5, pm10) = pm;
-        let (temperature, pressure, humidity) = environment_variables;
-        let co = co.unwrap_or(999); 
+                        let join = PairingMessage::Join { node_id, nonce }.encode();
+
+                        if let Err(e) = EspNowCommunicationManager::send_signed(&mut sender, &source, &keys.node_key, PAIRING_COUNTER, &join).await {
+                            println!("Failed to ask to join, {:?}", e);
+                        }
+                    }
+                    PairingMessage::Accepted { node_id: id, nonce, gateway: accepted_by }
+                        if id == node_id && gateway.is_none() && join_nonce == Some(nonce) && accepted_by == source =>
+                    {
+                        join_nonce = None;
+
+                        if let Err(e) = EspNowCommunicationManager::set_peer(&manager, &source, Some(local_master_key(&keys.node_key))) {
+                            println!("Failed to encrypt the link to the communication module: {:?}", e);
+                            continue;
+                        }
+
+                        let paired = Pairing { gateway: source, counter_floor: 0 };
+
+                        match pairing_store.save(&paired) {
+                            Ok(()) => println!("Paired with communication module {:02x?}", source),
+                            Err(e) => println!("Failed to save pairing, it only lasts until a reset: {}", e),
+                        }
+
+                        pairing = Some(paired);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Either4::Fourth(Either::Second(())) => {
+                power_up_at = None;
+                power.power_up().await;
+                continue;
+            }
+            Either4::Fourth(Either::First(())) => match gateway {
+                Some(gateway) => (gateway, None),
+                None => continue,
+            },
+            _ => {
+                println!("Ignored a message from a communication module this node isn't paired with");
+                continue;
+            }
+        };
+
+        // The response to the first attempt was lost on its way back, the retry gets the same frame
+        let cached = match (request, last_response) {
+            (Some((_, data_request)), Some(response)) if data_request.attempt > 0 && response.request_id == data_request.request_id => Some(response.frame),
+            _ => None,
+        };
+
+        let frame = match cached {
+            Some(frame) => frame,
+            None => {
+                // A push can wait for the sensors to warm up, a request has to be answered before the communication module gives up
+                if request.is_none() {
+                    power.power_up().await;
+
+                    if let Some(warm_at) = power.warm_at() {
+                        Timer::at(warm_at).await;
+                    }
+                }
+
+                let (environment_variables, pm, co2, o3, no2, so2) = sensors.read_all(&mut power).await;
+                let co = co_reading.try_get().and_then(|reading| reading.fresh_ppm());
+
+                // Channels that failed to read are left unset in the frame's validity bitmap
+                let readings = Readings {
+                    temperature: environment_variables.map(|(temperature, _, _)| temperature),
+                    pressure: environment_variables.map(|(_, pressure, _)| pressure),
+                    humidity: environment_variables.map(|(_, _, humidity)| humidity),
+                    pm1_0: pm.map(|(pm1_0, _, _)| pm1_0),
+                    pm2_5: pm.map(|(_, pm2_5, _)| pm2_5),
+                    pm10: pm.map(|(_, _, pm10)| pm10),
+                    co2,
+                    co,
+                    o3,
+                    no2,
+                    so2,
+                };
+
+                let frame = TelemetryFrame::new(sequence, node_id, readings);
+                sequence = sequence.wrapping_add(1);
+
+                frame
+            }
+        };
+
+        match request {
+            Some((request_counter, data_request)) => {
+                let response = DataResponse { request_id: data_request.request_id, frame };
+
+                EspNowCommunicationManager::send_response(&mut sender, &gateway_address, &keys.node_key, request_counter, &response).await;
+                last_response = Some(response);
+
+                save_counter_floor(&mut pairing_store, &mut pairing, request_counter, COUNTER_SAVE_INTERVAL);
+
+                // The communication module polls at a steady interval, so the rail can be off until shortly before the next request
+                if cached.is_none() {
+                    let now = Instant::now();
+
+                    if let Some(last) = last_request.replace(now) {
+                        power_up_at = power.power_down_until(now + (now - last));
+                    }
+                }
+            }
+            None => {
+                match push_counter.next_value() {
+                    Ok(counter) => EspNowCommunicationManager::send_push(&mut sender, &gateway_address, &keys.node_key, counter, &frame).await,
+                    Err(e) => println!("Frame {} not pushed, {}", frame.sequence, e),
+                }
+
+                if let Some(interval) = push_interval {
+                    let delay = next_push_delay(interval, &mut rng);
+
+                    if deep_sleep {
+                        // The communication module sends queued commands right after a push, the only time it can reach a sleeping node
+                        let listen_until = Instant::now() + COMMAND_WINDOW;
+
+                        while let Ok((source, counter, command)) = with_deadline(listen_until, EspNowCommunicationManager::wait_for_command()).await {
+                            if gateway == Some(source) {
+                                run_command(&mut sensors, &mut sender, &keys.node_key, &source, counter, command).await;
+                                save_counter_floor(&mut pairing_store, &mut pairing, counter, 1);
+                            }
+                        }
+
+                        // A calibration run only finishes over several cycles, which a single wake doesn't last for
+                        if sensors.calibrating() {
+                            println!("Staying awake until the calibration has finished");
+                        } else {
+                            // Every wake repeats the warm-up, so the time spent awake is taken off the interval
+                            let awake = Instant::now() - Instant::from_ticks(0);
+                            let asleep = delay.checked_sub(awake).unwrap_or(MIN_SLEEP).max(MIN_SLEEP);
+
+                            report_energy(&power.finish_cycle(asleep));
+                            power.sleep_deep(asleep, Retained {
+                                push_counter: push_counter.peek(),
+                                sequence,
+                                counter_floor: HIGHEST_COUNTER.load(Ordering::Relaxed),
+                            });
+                        }
+                    }
+
+                    // Scheduled from now rather than from the last push, so a slow reading doesn't bunch pushes up
+                    next_push = Instant::now() + delay;
+                    power_up_at = power.power_down_until(next_push);
+                }
+            }
+        }
+
+        report_energy(&power.finish_cycle(Duration::from_ticks(0)));
+    }
+}
+
+/// Runs a command from the communication module and acknowledges it, signed with the command's counter
+async fn run_command(sensors: &mut AirQualitySensors, sender: &mut EspNowSender<'static>, node_key: &Key, gateway: &[u8; 6], counter: u32, command: SensorCommand) {
+    let result = sensors.run_command(command).await;
+
+    match result {
+        Ok(()) => println!("Ran {:?}", command),
+        Err(e) => println!("Failed to run {:?}: {}", command, e),
+    }
+
+    let ack = CommandAck { succeeded: result.is_ok() }.encode();
+
+    if let Err(e) = EspNowCommunicationManager::send_signed(sender, gateway, node_key, counter, &ack).await {
+        println!("Failed to acknowledge {:?}, {:?}", command, e);
+    }
+}
+
+fn report_energy(energy: &CycleEnergy) {
+    println!(
+        "Cycle used about {} mJ, {} s awake and {} s asleep, {} mWh a day at this rate",
+        energy.millijoules,
+        energy.awake.as_secs(),
+        energy.asleep.as_secs(),
+        energy.milliwatt_hours_per_day()
+    );
+}
+
+/// Saves the counter of an accepted request once it is at least `interval` above the saved floor
+fn save_counter_floor(store: &mut PairingStore<FlashStorage>, pairing: &mut Option<Pairing>, counter: u32, interval: u32) {
+    let Some(pairing) = pairing else {
+        return;
+    };
+
+    if counter < pairing.counter_floor.saturating_add(interval) {
+        return;
+    }
 
-        let payload = format!(
-            r#"{{ "temperature": {:.2}, "pressure": {:.2}, "humidity": {:.2}, "pm1_0": {}, "pm2_5": {}, "pm10": {}, "co2": {}, "co": {} }}"#,
-            temperature, pressure, humidity, pm1_0, pm2_5, pm10, co2, co
-        );
+    pairing.counter_floor = counter;
 
-        EspNowCommunicationManager::send_response(&mut sender, &peer_address, &payload).await;
+    if let Err(e) = store.save(pairing) {
+        println!("Failed to save the replay floor: {}", e);
     }
 }
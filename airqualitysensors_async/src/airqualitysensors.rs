@@ -1,161 +1,246 @@
-use crate::sensors::{ mq7::Mq7, bme280::Bme280, mhz19b::Mhz19b, pms5003::Pms5003 };
-use crate::communicationprotocols::pwm::PwmHandler;
+use crate::sensors::{
+    self,
+    bme280::Bme280,
+    bme680::Bme680,
+    electrochemical::{ GasSensor, NO2_ADDRESS, SO2_ADDRESS },
+    mhz19b::Mhz19b,
+    mq131::Mq131,
+    pms5003::Pms5003,
+};
+use crate::communicationprotocols::{ adc::SharedAdc, i2c::SharedI2c };
+use crate::calibration::{ CalibrationRun, CalibrationStore };
+use crate::mq7heater::{ MQ7_CALIBRATING, MQ7_COMMANDS, MQ7_ENVIRONMENT };
+use crate::powermanager::{ PowerManager, RailSensor };
+
+use sensordrivers::{ electrochemical::Gas, mq::MqCalibration, AirQualitySensor, SensorError };
+use telemetryframe::command::SensorCommand;
 
 use esp_hal::{
-    mcpwm::PeripheralClockConfig,
-    gpio::{ GpioPin, Output },
-    delay::Delay,
-    peripherals::{ADC1, I2C0, UART0, UART1, MCPWM0},
+    analog::adc::AdcPin,
+    gpio::GpioPin,
+    peripherals::{ ADC1, UART0, UART1 },
 };
-use embassy_time::{Timer, Duration};
+use esp_println::println;
+use esp_storage::FlashStorage;
 use embassy_futures::join::join;
 
-use libm::powf;
-use fugit::RateExtU32;
+use core::sync::atomic::Ordering;
 
 pub struct AirQualitySensors {
     pub bme280: Bme280<'static>,
+    /// Optional BME680 or BME688, `None` once it turned out not to be fitted
+    pub bme680: Option<Bme680<'static>>,
     pub mhz19b: Mhz19b<'static>,
     pub pms5003: Pms5003<'static>,
-    pub mq7: Mq7<'static, GpioPin<3>>,
-    pub activate_pin: Output<'static>,
-    pub pwm_pin: PwmHandler<'static, MCPWM0>,
-    pub last_co_reading: Option<u16>,
+    pub mq131: Mq131<'static, GpioPin<2>>,
+    mq131_calibration_store: CalibrationStore<FlashStorage>,
+    mq131_calibration_run: Option<CalibrationRun>,
+    pub no2: GasSensor<'static>,
+    pub so2: GasSensor<'static>,
 }
 
 impl AirQualitySensors {
     pub fn new(
-        adc: ADC1,
-        adc_pin: GpioPin<3>,
-        i2c: I2C0,
-        sda: GpioPin<6>,
-        scl: GpioPin<7>,
+        adc: &'static SharedAdc<'static>,
+        o3_pin: AdcPin<GpioPin<2>, ADC1>,
+        i2c: &'static SharedI2c<'static>,
         uart0: UART0,
         rx0: GpioPin<17>,
         tx0: GpioPin<16>,
         uart1: UART1,
         rx1: GpioPin<20>,
         tx1: GpioPin<21>,
-        gate_pin: GpioPin<10>,
-        mcpwm: MCPWM0,
-        pwm_pin: GpioPin<11>,
     ) -> Self {
-        let peripheral_clock = PeripheralClockConfig::with_frequency(32.MHz()).unwrap();
-        let mut delay = Delay::new();
-
-        let activate_pin = Output::new(gate_pin, esp_hal::gpio::Level::Low);
+        // The BME280, the BME680 and both gas modules share the I2C bus at different addresses
+        let bme280 = sensors::bme280::new(i2c);
+        let bme680 = Some(sensors::bme680::new(i2c));
+        let no2 = sensors::electrochemical::new(i2c, NO2_ADDRESS, Gas::No2);
+        let so2 = sensors::electrochemical::new(i2c, SO2_ADDRESS, Gas::So2);
 
-        let mq7 = Mq7::new(adc, adc_pin);
-        let pwm_pin = PwmHandler::new(mcpwm, peripheral_clock, pwm_pin);
+        let mhz19b = sensors::mhz19b::new(uart0, rx0, tx0, 9600).unwrap();
+        let pms5003 = sensors::pms5003::new(uart1, rx1, tx1, 9600).unwrap();
+        let mut mq131 = sensors::mq131::new(adc, o3_pin);
 
-        let mut bme280 = Bme280::new(i2c, sda, scl).unwrap();
-        bme280.init(&mut delay);
-
-        let mhz19b = Mhz19b::new(uart0, rx0, tx0, 9600).unwrap();
-        let pms5003 = Pms5003::new(uart1, rx1, tx1, 9600).unwrap();
+        // Like the MQ-7, each board's MQ-131 has its own R0
+        let mut mq131_calibration_store = CalibrationStore::mq131(FlashStorage::new());
+        mq131.set_calibration(mq131_calibration_store.load());
 
         AirQualitySensors {
             bme280,
+            bme680,
             mhz19b,
             pms5003,
-            mq7,
-            activate_pin,
-            pwm_pin,
-            last_co_reading: None,
+            mq131,
+            mq131_calibration_store,
+            mq131_calibration_run: None,
+            no2,
+            so2,
         }
     }
 
-    pub async fn read_uart_sensors(&mut self) -> ((u16, u16, u16), u16) {
-        let (pm_data, co2_data) = join(self.pms5003.read_pm(), self.mhz19b.read_co2()).await;
-        (pm_data.unwrap_or((999, 999, 999)), co2_data.unwrap_or(999))   
+    /// Initialises every sensor, a sensor that fails is reported and retried before its next reading
+    pub async fn init(&mut self) {
+        report_init(self.bme280.name(), self.bme280.init().await);
+
+        if let Some(bme680) = self.bme680.as_mut() {
+            if let Err(e) = bme680.init().await {
+                println!("No BME680 found ({}), VOC gas resistance won't be measured", e);
+                self.bme680 = None;
+            }
+        }
+        report_init(self.mhz19b.name(), self.mhz19b.init().await);
+        report_init(self.pms5003.name(), self.pms5003.init().await);
+        report_init(self.mq131.name(), self.mq131.init().await);
+        report_init(self.no2.name(), self.no2.init().await);
+        report_init(self.so2.name(), self.so2.init().await);
     }
 
-    pub async fn read_bme280(&mut self) -> (f32, f32, f32) {
-        let mut delay = Delay::new();
-        let bme_data = self.bme280.measure(&mut delay).unwrap();
-        (bme_data.temperature, bme_data.pressure, bme_data.humidity)
+    /// Runs a maintenance command sent by the communication module
+    pub async fn run_command(&mut self, command: SensorCommand) -> Result<(), SensorError> {
+        match command {
+            SensorCommand::Co2ZeroPoint => self.mhz19b.calibrate_zero_point().await,
+            SensorCommand::Co2SpanPoint(span) => self.mhz19b.calibrate_span_point(span).await,
+            SensorCommand::Co2AutoBaseline(enabled) => self.mhz19b.set_auto_baseline(enabled).await,
+            SensorCommand::Co2DetectionRange(range) => self.mhz19b.set_detection_range(range).await,
+            // The MQ-7 belongs to the heater task, which runs these between heater phases
+            SensorCommand::CoCalibrate | SensorCommand::CoLoadResistance(_) => {
+                if command == SensorCommand::CoCalibrate {
+                    MQ7_CALIBRATING.store(true, Ordering::Relaxed);
+                }
+
+                MQ7_COMMANDS.send(command).await;
+                Ok(())
+            }
+            SensorCommand::O3Calibrate => {
+                self.mq131_calibration_run = Some(CalibrationRun::default());
+                Ok(())
+            }
+            SensorCommand::O3LoadResistance(ohms) => {
+                if ohms == 0 {
+                    return Err(SensorError::OutOfRange);
+                }
+
+                let mut calibration = *self.mq131.calibration();
+                calibration.load_resistance = ohms as f32;
+                self.save_mq131_calibration(calibration);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether a clean air calibration of the MQ-7 or MQ-131 is still collecting readings
+    ///
+    /// A run takes several heater cycles or readings, so the node stays awake until it has finished.
+    pub fn calibrating(&self) -> bool {
+        self.mq131_calibration_run.is_some() || MQ7_CALIBRATING.load(Ordering::Relaxed)
+    }
+
+    fn save_mq131_calibration(&mut self, calibration: MqCalibration) {
+        self.mq131.set_calibration(calibration);
+
+        match self.mq131_calibration_store.save(&calibration) {
+            Ok(()) => println!("Saved MQ-131 calibration: {:?}", calibration),
+            Err(e) => println!("{}", e),
+        }
     }
 
-    pub async fn read_mq7(&mut self) -> u16 {
-        self.pwm_pin.set_duty_value(99).unwrap();
+    /// Reads the ozone concentration, `None` until the MQ-131 has warmed up
+    ///
+    /// While calibrating, the node is assumed to be in clean air and every warm reading refines R0.
+    pub async fn read_mq131(&mut self, power: &PowerManager) -> Option<u16> {
+        let warm = power.is_warm(RailSensor::Mq131);
 
-        let sample_count = 120; // 60s / 0.5s sampling interval
-        let mut adc_sum: u32 = 0;
+        if let Some(run) = self.mq131_calibration_run.as_mut().filter(|_| warm) {
+            if let Some(r0) = self.mq131.read_clean_air_r0().ok().and_then(|r0| run.add(r0)) {
+                self.mq131_calibration_run = None;
 
-        for _ in 0..sample_count {
-            let reading = self.mq7.read().unwrap_or(999);
-            if reading != 999 {
-                adc_sum += reading as u32;
+                let calibration = MqCalibration { r0, ..*self.mq131.calibration() };
+                self.save_mq131_calibration(calibration);
             }
-            Timer::after(Duration::from_millis(500)).await;
         }
 
-        let avg_reading = if adc_sum == 0 {
-            999
-        } else {
-            (adc_sum / sample_count) as u16
-        };
+        self.mq131.measure().await.ok().filter(|_| warm)
+    }
 
-        self.pwm_pin.set_duty_value(28).unwrap();
-        Timer::after(Duration::from_secs(90)).await;
+    /// Reads the PMS5003 and MH-Z19B, a failed read is reported as `None` rather than a placeholder value
+    pub async fn read_uart_sensors(&mut self) -> (Option<(u16, u16, u16)>, Option<u16>) {
+        let (pm_data, co2_data) = join(self.pms5003.measure(), self.mhz19b.measure()).await;
+        (pm_data.ok().map(|pm| (pm.pm1_0, pm.pm2_5, pm.pm10)), co2_data.ok().map(|co2| co2.co2))
+    }
 
-        let co = self.calculate_ppm(avg_reading);
+    pub async fn read_bme280(&mut self) -> Option<(f32, f32, f32)> {
+        if !self.bme280.health().is_healthy() {
+            self.bme280.init().await.ok()?;
+        }
 
-        co
+        let bme_data = self.bme280.measure().await.ok()?;
 
+        Some((bme_data.temperature, bme_data.pressure, bme_data.humidity))
     }
 
-    pub async fn read_all(&mut self) -> ((f32, f32, f32), (u16, u16, u16), u16, Option<u16>) {
-        self.activate_pin.set_high();
+    /// Reads the BME680 if one is fitted, reporting its gas resistance
+    pub async fn read_bme680(&mut self) -> Option<(f32, f32, f32)> {
+        let bme680 = self.bme680.as_mut()?;
+        let gas_data = bme680.measure().await.ok()?;
 
-        let ((pm1_0, pm2_5, pm10), co2) = self.read_uart_sensors().await;
+        if let Some(gas_resistance) = gas_data.gas_resistance {
+            println!("{} gas resistance: {:.0} Ω", bme680.name(), gas_resistance);
+        }
 
-        let (temperature, pressure, humidity) = self.read_bme280().await;
+        Some((gas_data.temperature, gas_data.pressure, gas_data.humidity))
+    }
+
+    /// Reads the temperature, pressure and humidity, from the BME680 when the BME280 can't be read
+    pub async fn read_environment(&mut self) -> Option<(f32, f32, f32)> {
+        let bme280_data = self.read_bme280().await;
+        let bme680_data = self.read_bme680().await;
 
-        (
-            (temperature, pressure, humidity),
-            (pm1_0, pm2_5, pm10),
-            co2,
-            self.last_co_reading,
-        )
-        
+        let (temperature, pressure, humidity) = bme280_data.or(bme680_data)?;
+        MQ7_ENVIRONMENT.signal((temperature, humidity));
+        self.mq131.set_environment((temperature, humidity));
+
+        Some((temperature, pressure, humidity))
     }
 
-    fn calculate_ppm(&self, reading: u16) -> u16 {
-        const ADC_MAX: f32 = 4095.0;
-        const V_REF: f32 = 3.3;
-        const VOLTAGE_DIVIDER_RATIO: f32 = 3.3 / (2.0 + 3.3); // ~0.6226
-        const INV_VOLTAGE_DIVIDER: f32 = 1.0 / VOLTAGE_DIVIDER_RATIO; // ~1.606
+    /// Reads the NO2 and SO2 modules, re-initialising any that stopped answering
+    pub async fn read_gas_sensors(&mut self) -> (Option<u16>, Option<u16>) {
+        join(read_gas(&mut self.no2), read_gas(&mut self.so2)).await
+    }
 
-        const VC: f32 = 5.0;       // sensor supply voltage
-        const RL: f32 = 10_000.0;  // load resistor ohms (check your board)
-        const R0: f32 = 556.0;     // calibrated baseline resistance in clean air
-        const A: f32 = 99.042;     // calibration constant A
-        const B: f32 = 1.518;      // calibration constant B
+    /// Reads every sensor, dropping the readings of sensors on the power rail that haven't warmed up yet
+    pub async fn read_all(&mut self, power: &mut PowerManager) -> (Option<(f32, f32, f32)>, Option<(u16, u16, u16)>, Option<u16>, Option<u16>, Option<u16>, Option<u16>) {
+        power.power_up().await;
 
-        if reading == 999 {
-            return 999;
-        }
+        let (pm, co2) = self.read_uart_sensors().await;
+        power.record_pms5003_reading();
 
-        // Convert ADC reading to voltage at ADC pin
-        let v_adc = (reading as f32 / ADC_MAX) * V_REF;
+        let environment_variables = self.read_environment().await;
 
-        // Correct for voltage divider to get sensor output voltage
-        let v_aout = v_adc * INV_VOLTAGE_DIVIDER;
+        let o3 = self.read_mq131(power).await;
 
-        if v_aout <= 0.0 || v_aout >= VC {
-            return 0;
-        }
+        let (no2, so2) = self.read_gas_sensors().await;
+
+        let co2 = co2.filter(|_| power.is_warm(RailSensor::Mhz19b));
+        let (no2, so2) = match power.is_warm(RailSensor::GasModules) {
+            true => (no2, so2),
+            false => (None, None),
+        };
 
-        // Calculate sensor resistance Rs
-        let rs = RL * (VC - v_aout) / v_aout;
+        (environment_variables, pm, co2, o3, no2, so2)
+    }
+}
 
-        // Calculate Rs/R0 ratio
-        let ratio = rs / R0;
+async fn read_gas(sensor: &mut GasSensor<'static>) -> Option<u16> {
+    // A module that lost power comes back in active mode, which init switches off again
+    if !sensor.health().is_healthy() {
+        sensor.init().await.ok()?;
+    }
 
-        // Apply standard MQ-7 calibration power-law formula
-        let ppm = A * powf(ratio, -B);
+    sensor.measure().await.ok()
+}
 
-        ppm as u16
+fn report_init(name: &str, result: Result<(), SensorError>) {
+    if let Err(e) = result {
+        println!("Failed to initialise {}: {}", name, e);
     }
 }
@@ -0,0 +1,70 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use sensordrivers::{ mq::{ MqCalibration, CALIBRATION_LEN }, mq131, mq7 };
+use telemetryframe::store::RecordStore;
+
+/// Start of the `calibration` partition in `partitions.csv`, holding the MQ-7's calibration
+pub const MQ7_CALIBRATION_FLASH_OFFSET: u32 = 0x3F0000;
+/// Start of the `o3calib` partition in `partitions.csv`, holding the MQ-131's calibration
+pub const MQ131_CALIBRATION_FLASH_OFFSET: u32 = 0x3F3000;
+/// Size of each calibration partition in `partitions.csv`, a single flash sector
+pub const CALIBRATION_FLASH_SIZE: u32 = 0x1000;
+
+/// Cycles averaged when deriving R0 in clean air
+pub const CALIBRATION_CYCLES: u32 = 5;
+
+const MQ7_MAGIC: [u8; 4] = *b"AQM7";
+const MQ131_MAGIC: [u8; 4] = *b"AQO3";
+
+/// Persists the calibration of one of this board's MQ sensors in its own flash partition
+pub struct CalibrationStore<F: NorFlash> {
+    store: RecordStore<F>,
+    default: MqCalibration,
+}
+
+impl<F: NorFlash> CalibrationStore<F> {
+    pub fn mq7(flash: F) -> Self {
+        CalibrationStore {
+            store: RecordStore::new(flash, MQ7_CALIBRATION_FLASH_OFFSET, CALIBRATION_FLASH_SIZE, MQ7_MAGIC),
+            default: mq7::DEFAULT_CALIBRATION,
+        }
+    }
+
+    pub fn mq131(flash: F) -> Self {
+        CalibrationStore {
+            store: RecordStore::new(flash, MQ131_CALIBRATION_FLASH_OFFSET, CALIBRATION_FLASH_SIZE, MQ131_MAGIC),
+            default: mq131::DEFAULT_CALIBRATION,
+        }
+    }
+
+    /// Reads the saved calibration, falling back to the sensor's defaults when none has been saved yet
+    pub fn load(&mut self) -> MqCalibration {
+        let mut bytes = [0u8; CALIBRATION_LEN];
+
+        match self.store.load(&mut bytes) {
+            Some(payload) if payload.len() == CALIBRATION_LEN => MqCalibration::decode(&bytes).unwrap_or(self.default),
+            _ => self.default,
+        }
+    }
+
+    pub fn save(&mut self, calibration: &MqCalibration) -> Result<(), &'static str> {
+        self.store.save(&calibration.encode())
+    }
+}
+
+/// Clean air calibration in progress, collecting an R0 estimate from every cycle
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CalibrationRun {
+    r0_sum: f32,
+    cycles: u32,
+}
+
+impl CalibrationRun {
+    /// Adds the estimate of one cycle, returning the averaged R0 once enough cycles have run
+    pub fn add(&mut self, r0: f32) -> Option<f32> {
+        self.r0_sum += r0;
+        self.cycles += 1;
+
+        (self.cycles >= CALIBRATION_CYCLES).then(|| self.r0_sum / self.cycles as f32)
+    }
+}
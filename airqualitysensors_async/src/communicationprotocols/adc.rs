@@ -1,13 +1,36 @@
 use esp_hal::{
     gpio::AnalogPin,
-    analog::adc::{Adc, AdcPin, AdcConfig, Attenuation, AdcChannel }, 
+    analog::adc::{Adc, AdcPin, AdcConfig, AdcChannel }, 
     peripherals::ADC1 
 };
+use embassy_sync::blocking_mutex::{ raw::CriticalSectionRawMutex, Mutex };
 
-use core::result::Result;
+use sensordrivers::AnalogInput;
+
+use core::{ cell::RefCell, result::Result };
+
+/// ADC1 shared by every analog sensor, each converting on its own pin
+///
+/// All pins have to be enabled in the `AdcConfig` before the ADC is created.
+pub struct SharedAdc<'d> {
+    adc: Mutex<CriticalSectionRawMutex, RefCell<Adc<'d, ADC1>>>,
+}
+
+impl<'d> SharedAdc<'d> {
+    pub fn new(adc: ADC1, config: AdcConfig<ADC1>) -> Self {
+        Self { adc: Mutex::new(RefCell::new(Adc::new(adc, config))) }
+    }
+
+    pub fn read<PIN>(&self, adc_pin: &mut AdcPin<PIN, ADC1>) -> Result<u16, ()>
+    where
+        PIN: AdcChannel + AnalogPin
+    {
+        self.adc.lock(|adc| nb::block!(adc.borrow_mut().read_oneshot(adc_pin)))
+    }
+}
 
 pub struct AdcHandler<'d, PIN> {
-    adc: Adc<'d, ADC1>,
+    adc: &'d SharedAdc<'d>,
     adc_pin: AdcPin<PIN, ADC1>
 }
 
@@ -15,17 +38,22 @@ impl<'d, PIN> AdcHandler<'d, PIN>
 where 
     PIN: AdcChannel + AnalogPin
 {
-    pub fn new(adc: ADC1, pin: PIN) -> Self {
-        let mut config = AdcConfig::new();
-
-        let mut _adc_pin = config.enable_pin(pin, Attenuation::_11dB);
-
-        let adc = Adc::new(adc, config);
-
-        Self { adc, adc_pin: _adc_pin }
+    pub fn new(adc: &'d SharedAdc<'d>, adc_pin: AdcPin<PIN, ADC1>) -> Self {
+        Self { adc, adc_pin }
     }
 
     pub fn read(&mut self)-> Result<u16, ()> {
-        nb::block!(self.adc.read_oneshot(&mut self.adc_pin))
+        self.adc.read(&mut self.adc_pin)
+    }
+}
+
+impl<PIN> AnalogInput for AdcHandler<'_, PIN>
+where
+    PIN: AdcChannel + AnalogPin
+{
+    type Error = ();
+
+    fn read_raw(&mut self) -> Result<u16, ()> {
+        self.read()
     }
 }
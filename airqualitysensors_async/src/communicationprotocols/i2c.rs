@@ -5,11 +5,19 @@ use esp_hal::{
     Async,
 };
 
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_sync::{ blocking_mutex::raw::NoopRawMutex, mutex::Mutex };
+
 use core::{ 
     result::Result, 
     default::Default, 
 };
 
+/// I2C bus shared by every I2C sensor, each one talking to it through its own `I2cBusDevice`
+pub type SharedI2c<'d> = Mutex<NoopRawMutex, I2c<'d, Async>>;
+
+pub type I2cBusDevice<'d> = I2cDevice<'d, NoopRawMutex, I2c<'d, Async>>;
+
 pub struct I2cHandler<'d> {
     i2c:I2c<'d, Async>,
 }
@@ -39,3 +39,23 @@ impl<'d> UartHandler<'d> {
     }
 }
 
+// Transport traits used by the generic drivers in the sensordrivers crate
+impl embedded_io_async::ErrorType for UartHandler<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for UartHandler<'_> {
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.uart.read_async(buffer).await
+    }
+}
+
+impl embedded_io_async::Write for UartHandler<'_> {
+    async fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.uart.write_async(data).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.uart.flush_async().await
+    }
+}
@@ -1,61 +1,173 @@
-use esp_wifi::{EspWifiController, esp_now::{EspNow, EspNowReceiver, EspNowSender, PeerInfo}};
-use esp_hal::peripherals::WIFI; 
+use esp_wifi::{EspWifiController, esp_now::{EspNow, EspNowError, EspNowManager, EspNowReceiver, EspNowSender, PeerInfo}};
+use esp_hal::peripherals::WIFI;
 
 use esp_println::println;
 
+use telemetryframe::{
+    auth::{ local_master_key, parse_key, sign, Key, ReplayGuard, Signed, AUTH_LEN },
+    command::SensorCommand,
+    pairing::PairingMessage,
+    request::{ DataRequest, DataResponse },
+    TelemetryFrame,
+};
+
 use embassy_sync::{channel::Channel, blocking_mutex::raw::CriticalSectionRawMutex};
 //use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 
-static REQUEST_CHANNEL: Channel<CriticalSectionRawMutex, (), 4> = Channel::new();
+use alloc::vec::Vec;
+use core::sync::atomic::{ AtomicU32, Ordering };
+
+/// Key of this node, printed by `nodekey` on the communication module's console,
+/// e.g. `NODE_KEY=... ESPNOW_PMK=... cargo build --release`
+const NODE_KEY: Option<&str> = option_env!("NODE_KEY");
+/// ESP-NOW primary master key of the network, printed along with the node key
+const ESPNOW_PMK: Option<&str> = option_env!("ESPNOW_PMK");
+
+// Each message carries the address it came from, so the node can ignore modules it isn't paired with,
+// and requests and commands carry their counter, which the response is signed with
+static REQUEST_CHANNEL: Channel<CriticalSectionRawMutex, ([u8; 6], u32, DataRequest), 4> = Channel::new();
+static COMMAND_CHANNEL: Channel<CriticalSectionRawMutex, ([u8; 6], u32, SensorCommand), 4> = Channel::new();
+static PAIRING_CHANNEL: Channel<CriticalSectionRawMutex, ([u8; 6], PairingMessage), 4> = Channel::new();
+
+/// Highest counter accepted from the communication module, carried through deep sleep
+pub static HIGHEST_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Keys this node was built with
+#[derive(Clone, Copy)]
+pub struct NodeKeys {
+    pub node_key: Key,
+    pub pmk: Key,
+}
+
+impl NodeKeys {
+    pub fn from_build() -> Option<Self> {
+        Some(NodeKeys {
+            node_key: parse_key(NODE_KEY?)?,
+            pmk: parse_key(ESPNOW_PMK?)?,
+        })
+    }
+}
 
 pub struct EspNowCommunicationManager<'d> {
+    pub manager: EspNowManager<'d>,
     pub sender: EspNowSender<'d>,
     pub receiver: EspNowReceiver<'d>,
-    pub peer_address: [u8; 6],
 }
 
 impl<'d> EspNowCommunicationManager<'d> {
-    pub fn new(init: &'d EspWifiController, wifi: WIFI) -> Self {
+    /// Sets up ESP-NOW, adding the communication module as an encrypted peer if the node has already been paired
+    pub fn new(init: &'d EspWifiController, wifi: WIFI, keys: &NodeKeys, gateway: Option<[u8; 6]>) -> Self {
 
         let esp_now = EspNow::new(init, wifi).unwrap();
         let (manager, sender, receiver) = esp_now.split();
 
-        let peer_address = [0xf0, 0xf5, 0xbd, 0x0b, 0xfe, 0x88];
-        manager.add_peer(PeerInfo {
-            peer_address,
-            lmk: None,
-            channel: None,
-            encrypt: false,
-        }).unwrap();
+        manager.set_pmk(&keys.pmk).unwrap();
+
+        if let Some(gateway) = gateway {
+            Self::set_peer(&manager, &gateway, Some(local_master_key(&keys.node_key))).unwrap();
+        }
 
-        EspNowCommunicationManager { sender, receiver, peer_address }
+        EspNowCommunicationManager { manager, sender, receiver }
     }
 
-    pub fn take_receiver(self) -> EspNowReceiver<'d> {
-        self.receiver
-    }    
+    /// Adds a peer, or changes the encryption of one that has been added already
+    pub fn set_peer(manager: &EspNowManager<'d>, peer_address: &[u8; 6], lmk: Option<Key>) -> Result<(), EspNowError> {
+        let peer = PeerInfo {
+            peer_address: *peer_address,
+            lmk,
+            channel: None,
+            encrypt: lmk.is_some(),
+        };
 
-    pub fn take_sender(self) -> EspNowSender<'d> {
-        self.sender
+        if manager.peer_exists(peer_address) {
+            manager.modify_peer(peer)
+        } else {
+            manager.add_peer(peer)
+        }
     }
 
-    pub async fn wait_for_request(mut receiver: EspNowReceiver<'d>) {
+    /// Forwards the messages signed with this node's key, dropping replayed requests and commands
+    ///
+    /// `counter_floor` is the highest counter saved before the last restart, or kept through deep sleep.
+    pub async fn wait_for_request(mut receiver: EspNowReceiver<'d>, node_key: Key, counter_floor: u32) {
+        let mut replay_guard = ReplayGuard::new(counter_floor);
+        HIGHEST_COUNTER.store(counter_floor, Ordering::Relaxed);
+
         loop {
             let data = receiver.receive_async().await;
-            if core::str::from_utf8(data.data()).unwrap_or("") == "REQUEST DATA" {
-                let _ = REQUEST_CHANNEL.send(()).await;
+            let source = data.info.src_address;
+
+            // Discover is broadcast to nodes the communication module has no key for yet, so it is the only unsigned message
+            if let Ok(PairingMessage::Discover) = PairingMessage::decode(data.data()) {
+                PAIRING_CHANNEL.send((source, PairingMessage::Discover)).await;
+                continue;
+            }
+
+            let signed = match Signed::split(data.data()).and_then(|signed| signed.verify(&node_key).map(|_| signed)) {
+                Ok(signed) => signed,
+                Err(e) => {
+                    println!("Discarded ESP-NOW message: {}", e);
+                    continue;
+                }
+            };
+
+            // Pairing messages all use the pairing counter, an `Accepted` is checked against the nonce of the node's `Join` instead
+            if let Ok(message) = PairingMessage::decode(signed.message) {
+                PAIRING_CHANNEL.send((source, message)).await;
+                continue;
+            }
+
+            if let Err(e) = replay_guard.accept(signed.counter) {
+                println!("Discarded ESP-NOW message: {}", e);
+                continue;
+            }
+
+            HIGHEST_COUNTER.store(replay_guard.highest(), Ordering::Relaxed);
+
+            if let Ok(request) = DataRequest::decode(signed.message) {
+                REQUEST_CHANNEL.send((source, signed.counter, request)).await;
+            } else if let Ok(command) = SensorCommand::decode(signed.message) {
+                COMMAND_CHANNEL.send((source, signed.counter, command)).await;
             }
         }
     }
 
-    pub async fn wait_for_signal() {
-        REQUEST_CHANNEL.receive().await;
+    pub async fn wait_for_signal() -> ([u8; 6], u32, DataRequest) {
+        REQUEST_CHANNEL.receive().await
+    }
+
+    pub async fn wait_for_command() -> ([u8; 6], u32, SensorCommand) {
+        COMMAND_CHANNEL.receive().await
     }
 
-    pub async fn send_response(sender: &mut EspNowSender<'d>, peer_address: &[u8; 6], payload: &str) {
-        match sender.send_async(peer_address, payload.as_bytes()).await {
-            Ok(_) => println!("ESP-NOW data sent: {}", payload),
+    pub async fn wait_for_pairing() -> ([u8; 6], PairingMessage) {
+        PAIRING_CHANNEL.receive().await
+    }
+
+    /// Sends a message followed by its authentication trailer
+    pub async fn send_signed(sender: &mut EspNowSender<'d>, peer_address: &[u8; 6], key: &Key, counter: u32, message: &[u8]) -> Result<(), EspNowError> {
+        let mut bytes = Vec::with_capacity(message.len() + AUTH_LEN);
+        bytes.extend_from_slice(message);
+        bytes.extend_from_slice(&sign(key, counter, message));
+
+        sender.send_async(peer_address, &bytes).await
+    }
+
+    /// Sends a response signed with the counter of the request it answers
+    pub async fn send_response(sender: &mut EspNowSender<'d>, peer_address: &[u8; 6], key: &Key, request: u32, response: &DataResponse) {
+        let frame = &response.frame;
+
+        match Self::send_signed(sender, peer_address, key, request, &response.encode()).await {
+            Ok(_) => println!("ESP-NOW frame {} sent for request {}: {:?}", frame.sequence, response.request_id, frame.readings),
             Err(e) => println!("ESP-NOW send failed: {:?}", e),
         }
     }
+
+    /// Sends a frame nobody asked for, signed with a counter of this node's own
+    pub async fn send_push(sender: &mut EspNowSender<'d>, peer_address: &[u8; 6], key: &Key, counter: u32, frame: &TelemetryFrame) {
+        match Self::send_signed(sender, peer_address, key, counter, &frame.encode()).await {
+            Ok(_) => println!("ESP-NOW frame {} pushed: {:?}", frame.sequence, frame.readings),
+            Err(e) => println!("ESP-NOW push failed: {:?}", e),
+        }
+    }
 }
@@ -6,4 +6,9 @@ pub mod communicationprotocols;
 pub mod sensors;
 pub mod airquality;
 pub mod airqualitysensors;
-pub mod espnowcommunication;
\ No newline at end of file
+pub mod calibration;
+pub mod pairing;
+pub mod pushcounter;
+pub mod powermanager;
+pub mod espnowcommunication;
+pub mod mq7heater;
\ No newline at end of file
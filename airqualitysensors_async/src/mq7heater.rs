@@ -0,0 +1,234 @@
+use crate::sensors::{ self, mq7::Mq7 };
+use crate::communicationprotocols::{ adc::SharedAdc, pwm::PwmHandler };
+use crate::calibration::{ CalibrationRun, CalibrationStore };
+
+use sensordrivers::{ mq::MqCalibration, mq7::CLEAN_AIR_RATIO, AirQualitySensor, SensorError };
+use telemetryframe::command::SensorCommand;
+
+use esp_hal::{
+    analog::adc::AdcPin,
+    mcpwm::PeripheralClockConfig,
+    gpio::GpioPin,
+    peripherals::{ ADC1, MCPWM0 },
+};
+use esp_println::println;
+use esp_storage::FlashStorage;
+use embassy_time::{ Duration, Instant, Timer };
+use embassy_sync::{ blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal, watch::Watch };
+
+use fugit::RateExtU32;
+
+use core::sync::atomic::{ AtomicBool, Ordering };
+
+/// Heater held at 5 V to burn off adsorbed gases
+const PURGE_DUTY: u16 = 99;
+const PURGE_TIME: Duration = Duration::from_secs(60);
+/// Heater held at 1.4 V, during which the sensor responds to CO
+const MEASUREMENT_DUTY: u16 = 28;
+const MEASUREMENT_TIME: Duration = Duration::from_secs(90);
+/// End of the measurement phase over which the output is averaged, once it has settled
+const SAMPLE_WINDOW: Duration = Duration::from_secs(20);
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// One purge and one measurement phase, the time from power-up to the first CO reading
+pub const HEATER_CYCLE: Duration = Duration::from_secs(PURGE_TIME.as_secs() + MEASUREMENT_TIME.as_secs());
+/// Heater duty averaged over a whole cycle in percent, used to estimate the heater's energy
+pub const AVERAGE_HEATER_DUTY: u64 =
+    (PURGE_DUTY as u64 * PURGE_TIME.as_secs() + MEASUREMENT_DUTY as u64 * MEASUREMENT_TIME.as_secs()) / HEATER_CYCLE.as_secs();
+
+/// Age after which a CO reading is no longer reported, two full heater cycles
+pub const MAX_READING_AGE: Duration = Duration::from_secs(300);
+
+/// Latest CO reading published at the end of every heater cycle
+pub static CO_READING: Watch<CriticalSectionRawMutex, CoReading, 1> = Watch::new();
+
+/// Temperature and humidity of the air, used to compensate the MQ-7's output
+pub static MQ7_ENVIRONMENT: Signal<CriticalSectionRawMutex, (f32, f32)> = Signal::new();
+
+/// MQ-7 maintenance commands forwarded by the main loop
+pub static MQ7_COMMANDS: Channel<CriticalSectionRawMutex, SensorCommand, 2> = Channel::new();
+
+/// Set when a clean air calibration is sent to the heater task, cleared once it has finished
+pub static MQ7_CALIBRATING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy)]
+pub struct CoReading {
+    /// CO in ppm, `None` when the cycle produced no valid sample
+    pub ppm: Option<u16>,
+    pub measured_at: Instant,
+}
+
+impl CoReading {
+    /// The concentration, as long as the reading is valid and recent enough to report
+    pub fn fresh_ppm(&self) -> Option<u16> {
+        self.ppm.filter(|_| self.measured_at.elapsed() <= MAX_READING_AGE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HeaterPhase {
+    Purge,
+    Measurement,
+    Sampling,
+}
+
+/// Runs the MQ-7's heater cycle and owns everything it needs, so no other task touches the sensor
+pub struct Mq7Heater {
+    mq7: Mq7<'static, GpioPin<3>>,
+    pwm_pin: PwmHandler<'static, MCPWM0>,
+    phase: HeaterPhase,
+    calibration: MqCalibration,
+    calibration_store: CalibrationStore<FlashStorage>,
+    calibration_run: Option<CalibrationRun>,
+    environment: Option<(f32, f32)>,
+}
+
+impl Mq7Heater {
+    pub fn new(
+        adc: &'static SharedAdc<'static>,
+        adc_pin: AdcPin<GpioPin<3>, ADC1>,
+        mcpwm: MCPWM0,
+        pwm_pin: GpioPin<11>,
+    ) -> Self {
+        let peripheral_clock = PeripheralClockConfig::with_frequency(32.MHz()).unwrap();
+
+        let mq7 = sensors::mq7::new(adc, adc_pin);
+        let pwm_pin = PwmHandler::new(mcpwm, peripheral_clock, pwm_pin);
+
+        // Each board's MQ-7 has its own R0, the defaults are only used until it is calibrated
+        let mut calibration_store = CalibrationStore::mq7(FlashStorage::new());
+        let calibration = calibration_store.load();
+
+        Mq7Heater {
+            mq7,
+            pwm_pin,
+            phase: HeaterPhase::Purge,
+            calibration,
+            calibration_store,
+            calibration_run: None,
+            environment: None,
+        }
+    }
+
+    /// Runs the current phase to completion and moves on to the next one
+    async fn step(&mut self) {
+        self.apply_updates();
+
+        self.phase = match self.phase {
+            HeaterPhase::Purge => {
+                self.set_duty(PURGE_DUTY);
+                Timer::after(PURGE_TIME).await;
+                HeaterPhase::Measurement
+            }
+            HeaterPhase::Measurement => {
+                self.set_duty(MEASUREMENT_DUTY);
+                Timer::after(MEASUREMENT_TIME - SAMPLE_WINDOW).await;
+                HeaterPhase::Sampling
+            }
+            HeaterPhase::Sampling => {
+                let ppm = match self.sample().await {
+                    Some(reading) => self.convert(reading),
+                    None => None,
+                };
+
+                CO_READING.sender().send(CoReading { ppm, measured_at: Instant::now() });
+                HeaterPhase::Purge
+            }
+        };
+    }
+
+    /// Averages the samples taken over the sample window, `None` if none of them could be read
+    async fn sample(&mut self) -> Option<u16> {
+        let sample_count = SAMPLE_WINDOW.as_millis() / SAMPLE_INTERVAL.as_millis();
+        let mut valid_samples: u32 = 0;
+        let mut adc_sum: u32 = 0;
+
+        for _ in 0..sample_count {
+            if let Ok(reading) = self.mq7.measure().await {
+                adc_sum += reading as u32;
+                valid_samples += 1;
+            }
+            Timer::after(SAMPLE_INTERVAL).await;
+        }
+
+        if valid_samples == 0 {
+            None
+        } else {
+            Some((adc_sum / valid_samples) as u16)
+        }
+    }
+
+    fn convert(&mut self, reading: u16) -> Option<u16> {
+        // While calibrating, the node is assumed to be in clean air and every cycle refines R0
+        if let Some(run) = self.calibration_run.as_mut() {
+            if let Some(r0) = self.calibration.clean_air_r0(reading, self.environment, CLEAN_AIR_RATIO) {
+                if let Some(r0) = run.add(r0) {
+                    self.calibration_run = None;
+                    MQ7_CALIBRATING.store(false, Ordering::Relaxed);
+                    self.calibration.r0 = r0;
+                    self.save_calibration();
+                }
+            }
+        }
+
+        self.calibration.concentration(reading, self.environment)
+    }
+
+    /// Takes the latest environment reading and runs the commands received since the last phase
+    fn apply_updates(&mut self) {
+        if let Some(environment) = MQ7_ENVIRONMENT.try_take() {
+            self.environment = Some(environment);
+        }
+
+        while let Ok(command) = MQ7_COMMANDS.try_receive() {
+            match self.run_command(command) {
+                Ok(()) => println!("Ran {:?}", command),
+                Err(e) => println!("Failed to run {:?}: {}", command, e),
+            }
+        }
+    }
+
+    fn run_command(&mut self, command: SensorCommand) -> Result<(), SensorError> {
+        match command {
+            SensorCommand::CoCalibrate => {
+                self.calibration_run = Some(CalibrationRun::default());
+                Ok(())
+            }
+            SensorCommand::CoLoadResistance(ohms) => {
+                if ohms == 0 {
+                    return Err(SensorError::OutOfRange);
+                }
+
+                self.calibration.load_resistance = ohms as f32;
+                self.save_calibration();
+                Ok(())
+            }
+            // The other commands are for the sensors run by the main loop, which never forwards them
+            _ => Ok(()),
+        }
+    }
+
+    fn save_calibration(&mut self) {
+        match self.calibration_store.save(&self.calibration) {
+            Ok(()) => println!("Saved MQ-7 calibration: {:?}", self.calibration),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        if let Err(e) = self.pwm_pin.set_duty_value(duty) {
+            println!("{}", e);
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn mq7_heater_task(mut heater: Mq7Heater) {
+    if let Err(e) = heater.mq7.init().await {
+        println!("Failed to initialise {}: {}", heater.mq7.name(), e);
+    }
+
+    loop {
+        heater.step().await;
+    }
+}
@@ -0,0 +1,59 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use telemetryframe::store::RecordStore;
+
+/// Start of the `pairing` partition in `partitions.csv`
+pub const PAIRING_FLASH_OFFSET: u32 = 0x3F1000;
+/// Size of the `pairing` partition in `partitions.csv`, a single flash sector
+pub const PAIRING_FLASH_SIZE: u32 = 0x1000;
+
+/// Requests accepted between two saves of the replay floor, bounding both flash wear and how many
+/// recorded requests can be replayed once after the node restarts
+pub const COUNTER_SAVE_INTERVAL: u32 = 1024;
+
+const MAGIC: [u8; 4] = *b"AQGW";
+// Record layout: communication module MAC address, replay floor
+const RECORD_LEN: usize = 6 + 4;
+
+/// Communication module this node is paired with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pairing {
+    pub gateway: [u8; 6],
+    /// Highest request counter accepted when the pairing was last saved, lower counters are replays
+    pub counter_floor: u32,
+}
+
+/// Persists the pairing of this node in its own flash partition
+pub struct PairingStore<F: NorFlash> {
+    store: RecordStore<F>,
+}
+
+impl<F: NorFlash> PairingStore<F> {
+    pub fn new(flash: F) -> Self {
+        PairingStore { store: RecordStore::new(flash, PAIRING_FLASH_OFFSET, PAIRING_FLASH_SIZE, MAGIC) }
+    }
+
+    /// Reads the saved pairing, `None` until the node has been paired
+    pub fn load(&mut self) -> Option<Pairing> {
+        let mut record = [0u8; RECORD_LEN];
+
+        if self.store.load(&mut record)?.len() != RECORD_LEN {
+            return None;
+        }
+
+        let mut gateway = [0u8; 6];
+        gateway.copy_from_slice(&record[0..6]);
+
+        let counter_floor = u32::from_le_bytes([record[6], record[7], record[8], record[9]]);
+
+        Some(Pairing { gateway, counter_floor })
+    }
+
+    pub fn save(&mut self, pairing: &Pairing) -> Result<(), &'static str> {
+        let mut record = [0u8; RECORD_LEN];
+        record[0..6].copy_from_slice(&pairing.gateway);
+        record[6..10].copy_from_slice(&pairing.counter_floor.to_le_bytes());
+
+        self.store.save(&record)
+    }
+}
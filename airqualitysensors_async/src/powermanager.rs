@@ -0,0 +1,237 @@
+use crate::mq7heater::AVERAGE_HEATER_DUTY;
+
+use sensordrivers::pms5003::DEFAULT_WARM_UP_MS;
+
+use esp_hal::{
+    gpio::{ GpioPin, Level, Output },
+    peripherals::LPWR,
+    ram,
+    rtc_cntl::{ sleep::TimerWakeupSource, Rtc },
+};
+use embassy_time::{ Duration, Instant, Timer };
+
+/// Time the sensors on the rail need after power-up before they answer over UART and I2C
+const BOOT_TIME: Duration = Duration::from_secs(1);
+
+/// Extra time the rail is switched on ahead of a reading, as the communication module's polls drift with the time its uploads take
+const WARM_UP_MARGIN: Duration = Duration::from_secs(30);
+
+/// Shortest deep sleep, for when a cycle took longer than the push interval
+pub const MIN_SLEEP: Duration = Duration::from_secs(10);
+
+// Typical power draw from the datasheets, good enough to size a battery and solar panel but not a measurement
+/// ESP32-C6 running with the radio receiving, 3.3 V at about 80 mA
+const MCU_AWAKE_MW: u64 = 264;
+/// ESP32-C6 in deep sleep with the RTC timer running, 3.3 V at about 7 µA
+const MCU_DEEP_SLEEP_UW: u64 = 23;
+/// MH-Z19B, 5 V at its rated average of 60 mA
+const MHZ19B_MW: u64 = 300;
+/// MQ-131 heater, 5 V across its 31 Ω
+const MQ131_HEATER_MW: u64 = 800;
+/// Each electrochemical module, 3.3 V at 5 mA
+const GAS_MODULE_MW: u64 = 17;
+/// PMS5003 while its fan runs, 5 V at 100 mA
+const PMS5003_FAN_MW: u64 = 500;
+/// MQ-7 heater at full duty, 5 V across its 33 Ω
+const MQ7_HEATER_MW: u64 = 750;
+
+/// Everything on the gated rail apart from the PMS5003, whose fan only runs while it measures
+const RAIL_MW: u64 = MHZ19B_MW + MQ131_HEATER_MW + 2 * GAS_MODULE_MW;
+
+const RETAINED_MAGIC: u32 = 0x4151_5253;
+
+/// Kept in RTC memory, which survives deep sleep but not a power loss
+#[ram(rtc_fast, persistent)]
+static mut RETAINED: [u32; 5] = [0; 5];
+
+/// Sensors powered from the rail switched by `activate_pin`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RailSensor {
+    Mhz19b,
+    Mq131,
+    GasModules,
+}
+
+impl RailSensor {
+    pub const ALL: [RailSensor; 3] = [RailSensor::Mhz19b, RailSensor::Mq131, RailSensor::GasModules];
+
+    /// Time after power-up before every sensor on the rail has settled
+    pub fn longest_warm_up() -> Duration {
+        RailSensor::ALL.iter().map(|sensor| sensor.warm_up()).max().unwrap_or_default()
+    }
+
+    /// Time after power-up before the sensor's readings settle
+    ///
+    /// The PMS5003 isn't listed, its driver starts the fan and waits out the warm-up for every reading.
+    pub fn warm_up(&self) -> Duration {
+        match self {
+            // Preheat time from the datasheet
+            RailSensor::Mhz19b => Duration::from_secs(180),
+            // The 24 hour preheat is only needed once, after that the heater settles within minutes
+            RailSensor::Mq131 => Duration::from_secs(120),
+            RailSensor::GasModules => Duration::from_secs(60),
+        }
+    }
+}
+
+/// State carried through deep sleep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retained {
+    /// Next push counter, so waking up doesn't reserve another block of counters in flash
+    pub push_counter: u32,
+    pub sequence: u16,
+    /// Highest counter accepted from the communication module, which is only saved to flash now and then
+    pub counter_floor: u32,
+}
+
+impl Retained {
+    /// Takes the state saved before the last deep sleep, `None` after any other reset
+    pub fn take() -> Option<Self> {
+        let words = unsafe { core::ptr::addr_of!(RETAINED).read() };
+
+        // Cleared straight away, a reset later in this cycle must not resume from it
+        unsafe { core::ptr::addr_of_mut!(RETAINED).write([0; 5]) };
+
+        if words[0] != RETAINED_MAGIC || words[4] != words[0] ^ words[1] ^ words[2] ^ words[3] {
+            return None;
+        }
+
+        Some(Retained { push_counter: words[1], sequence: words[2] as u16, counter_floor: words[3] })
+    }
+
+    fn save(&self) {
+        let words = [RETAINED_MAGIC, self.push_counter, self.sequence as u32, self.counter_floor];
+        let check = words[0] ^ words[1] ^ words[2] ^ words[3];
+
+        unsafe { core::ptr::addr_of_mut!(RETAINED).write([words[0], words[1], words[2], words[3], check]) };
+    }
+}
+
+/// Energy estimated for one measurement cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CycleEnergy {
+    pub awake: Duration,
+    pub asleep: Duration,
+    pub millijoules: u64,
+}
+
+impl CycleEnergy {
+    /// Energy used in a day if every cycle were like this one
+    pub fn milliwatt_hours_per_day(&self) -> u64 {
+        let cycle_ms = (self.awake + self.asleep).as_millis().max(1);
+
+        // 1 mWh is 3600 mJ
+        self.millijoules * Duration::from_secs(24 * 60 * 60).as_millis() / cycle_ms / 3600
+    }
+}
+
+/// Switches the sensors' power rail, tracks their warm-up and puts the node into deep sleep
+///
+/// The rail's gate needs a pull-down so the rail stays off while the node is in deep sleep and
+/// `activate_pin` isn't driven. The same goes for the MQ-7 heater's PWM pin.
+pub struct PowerManager {
+    activate_pin: Output<'static>,
+    rtc: Rtc<'static>,
+    powered_since: Option<Instant>,
+    cycle_start: Instant,
+    /// Time the rail was on during the current cycle, up to when it was last switched off
+    rail_on: Duration,
+    pms5003_readings: u32,
+}
+
+impl PowerManager {
+    pub fn new(gate_pin: GpioPin<10>, lpwr: LPWR) -> Self {
+        PowerManager {
+            activate_pin: Output::new(gate_pin, Level::Low),
+            rtc: Rtc::new(lpwr),
+            powered_since: None,
+            // The first cycle starts at boot, which after deep sleep is when the node woke up
+            cycle_start: Instant::from_ticks(0),
+            rail_on: Duration::from_ticks(0),
+            pms5003_readings: 0,
+        }
+    }
+
+    /// Switches the rail on and waits for the sensors on it to boot
+    pub async fn power_up(&mut self) {
+        if self.powered_since.is_some() {
+            return;
+        }
+
+        self.activate_pin.set_high();
+        self.powered_since = Some(Instant::now());
+
+        Timer::after(BOOT_TIME).await;
+    }
+
+    pub fn power_down(&mut self) {
+        if let Some(since) = self.powered_since.take() {
+            self.activate_pin.set_low();
+            self.rail_on += since.elapsed();
+        }
+    }
+
+    /// Whether a sensor has been powered long enough for its readings to be used
+    pub fn is_warm(&self, sensor: RailSensor) -> bool {
+        self.powered_since.is_some_and(|since| since.elapsed() >= sensor.warm_up())
+    }
+
+    /// When every sensor on the rail will have warmed up, `None` while the rail is off
+    pub fn warm_at(&self) -> Option<Instant> {
+        self.powered_since.map(|since| since + RailSensor::longest_warm_up())
+    }
+
+    /// Switches the rail off after a reading, returning when to switch it back on for the
+    /// sensors to have warmed up by `next_reading`
+    ///
+    /// The rail stays on when the next reading is too close for it to cool down and warm up again.
+    pub fn power_down_until(&mut self, next_reading: Instant) -> Option<Instant> {
+        let power_up_at = next_reading.checked_sub(RailSensor::longest_warm_up() + WARM_UP_MARGIN)?;
+
+        if power_up_at <= Instant::now() {
+            return None;
+        }
+
+        self.power_down();
+        Some(power_up_at)
+    }
+
+    /// Counts a PMS5003 reading, each of which runs its fan for the warm-up period
+    pub fn record_pms5003_reading(&mut self) {
+        self.pms5003_readings += 1;
+    }
+
+    /// Estimates the energy used since the last cycle ended, counting `asleep` as the sleep that follows
+    pub fn finish_cycle(&mut self, asleep: Duration) -> CycleEnergy {
+        let now = Instant::now();
+        let awake = now - self.cycle_start;
+
+        let rail_on = match self.powered_since {
+            Some(since) => self.rail_on + (now - since.max(self.cycle_start)),
+            None => self.rail_on,
+        };
+        let pms5003_ms = self.pms5003_readings as u64 * (DEFAULT_WARM_UP_MS as u64 + 1000);
+
+        // mW over ms gives µJ
+        let microjoules = MCU_AWAKE_MW * awake.as_millis()
+            + MQ7_HEATER_MW * AVERAGE_HEATER_DUTY / 100 * awake.as_millis()
+            + RAIL_MW * rail_on.as_millis()
+            + PMS5003_FAN_MW * pms5003_ms
+            + MCU_DEEP_SLEEP_UW * asleep.as_millis() / 1000;
+
+        self.cycle_start = now;
+        self.rail_on = Duration::from_ticks(0);
+        self.pms5003_readings = 0;
+
+        CycleEnergy { awake, asleep, millijoules: microjoules / 1000 }
+    }
+
+    /// Switches the rail off and sleeps until the RTC timer wakes the node, which then boots afresh
+    pub fn sleep_deep(&mut self, duration: Duration, retained: Retained) -> ! {
+        self.power_down();
+        retained.save();
+
+        let timer = TimerWakeupSource::new(core::time::Duration::from_millis(duration.as_millis()));
+        self.rtc.sleep_deep(&[&timer])
+    }
+}
@@ -0,0 +1,30 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use telemetryframe::{ auth::PAIRING_COUNTER, store::{ RecordStore, ReservedCounter } };
+
+/// Start of the `counter` partition in `partitions.csv`
+pub const COUNTER_FLASH_OFFSET: u32 = 0x3F2000;
+/// Size of the `counter` partition in `partitions.csv`, a single flash sector
+pub const COUNTER_FLASH_SIZE: u32 = 0x1000;
+
+const MAGIC: [u8; 4] = *b"AQPC";
+
+/// Counter authenticating the frames this node pushes without being asked
+///
+/// The communication module rejects counters it has already seen from this node, so the counter
+/// must keep increasing across restarts.
+pub type PushCounter<F> = ReservedCounter<F>;
+
+pub fn push_counter<F: NorFlash>(flash: F) -> PushCounter<F> {
+    let store = RecordStore::new(flash, COUNTER_FLASH_OFFSET, COUNTER_FLASH_SIZE, MAGIC);
+
+    // The pairing messages' counter is never used for anything else
+    ReservedCounter::new(store, PAIRING_COUNTER + 1)
+}
+
+/// Continues from the counter the node had before deep sleep, rather than reserving another block
+pub fn resume_push_counter<F: NorFlash>(flash: F, next: u32) -> PushCounter<F> {
+    let store = RecordStore::new(flash, COUNTER_FLASH_OFFSET, COUNTER_FLASH_SIZE, MAGIC);
+
+    ReservedCounter::resume(store, next.max(PAIRING_COUNTER + 1))
+}
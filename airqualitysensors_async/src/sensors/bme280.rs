@@ -1,39 +1,13 @@
-use crate::communicationprotocols::i2c::I2cHandler;
+use crate::communicationprotocols::i2c::{ I2cBusDevice, SharedI2c };
 
-use bme280::i2c::BME280;
+use sensordrivers::bme280::{ Settings, PRIMARY_ADDRESS };
 
-use esp_hal::{
-    gpio::interconnect::PeripheralOutput,
-    i2c::master::{I2c, Error, Instance}, 
-    peripheral::Peripheral, 
-    delay::Delay,
-    Async, 
-};
+use embassy_time::Delay;
 
-use core::result::Result;
+/// BME280 driver bound to the node's I2C bus
+pub type Bme280<'d> = sensordrivers::bme280::Bme280<I2cBusDevice<'d>, Delay>;
 
-pub struct Bme280<'d> {
-    bme280: BME280<I2c<'d, Async>>
+/// Takes a single forced mode reading per request, the sensor is looked for at the other address if it isn't at 0x76
+pub fn new<'d>(i2c: &'d SharedI2c<'d>) -> Bme280<'d> {
+    Bme280::new(I2cBusDevice::new(i2c), Delay, PRIMARY_ADDRESS, Settings::default())
 }
-
-impl<'d> Bme280<'d> {
-    pub fn new(i2c: impl Peripheral<P = impl Instance> + 'd, 
-    sda: impl Peripheral<P = impl PeripheralOutput> + 'd, 
-    scl: impl Peripheral<P = impl PeripheralOutput> + 'd,) -> Result<Self, Error> {
-
-        let i2c = I2cHandler::new(i2c, sda, scl).unwrap();
-
-        let bme280 = BME280::new_primary(i2c.get_inner_i2c()); 
-
-        Result::Ok(Self { bme280 })
-
-    }
-
-    pub fn init(&mut self, delay: &mut Delay) {
-        self.bme280.init(delay).unwrap();
-    }
-
-    pub fn measure(&mut self, delay: &mut Delay) -> Result<bme280::Measurements<Error>, Error> {
-        Result::Ok(self.bme280.measure(delay).unwrap())
-    }
-}
\ No newline at end of file
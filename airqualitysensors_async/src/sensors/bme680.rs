@@ -0,0 +1,13 @@
+use crate::communicationprotocols::i2c::{ I2cBusDevice, SharedI2c };
+
+use sensordrivers::bme680::{ Settings, SECONDARY_ADDRESS };
+
+use embassy_time::Delay;
+
+/// BME680 or BME688 driver bound to the node's I2C bus
+pub type Bme680<'d> = sensordrivers::bme680::Bme680<I2cBusDevice<'d>, Delay>;
+
+/// The optional gas sensor sits at 0x77, leaving 0x76 to the BME280
+pub fn new<'d>(i2c: &'d SharedI2c<'d>) -> Bme680<'d> {
+    Bme680::new(I2cBusDevice::new(i2c), Delay, SECONDARY_ADDRESS, Settings::default())
+}
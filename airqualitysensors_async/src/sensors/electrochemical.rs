@@ -0,0 +1,17 @@
+use crate::communicationprotocols::i2c::{ I2cBusDevice, SharedI2c };
+
+use sensordrivers::electrochemical::Gas;
+
+use embassy_time::Delay;
+
+/// Address of the NO2 module, with both address switches off
+pub const NO2_ADDRESS: u8 = sensordrivers::electrochemical::DEFAULT_ADDRESS;
+/// Address of the SO2 module, with its first address switch on
+pub const SO2_ADDRESS: u8 = 0x75;
+
+/// Electrochemical gas sensor driver bound to the node's I2C bus
+pub type GasSensor<'d> = sensordrivers::electrochemical::GasSensor<I2cBusDevice<'d>, Delay>;
+
+pub fn new<'d>(i2c: &'d SharedI2c<'d>, address: u8, gas: Gas) -> GasSensor<'d> {
+    GasSensor::new(I2cBusDevice::new(i2c), Delay, address, gas)
+}
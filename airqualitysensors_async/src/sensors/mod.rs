@@ -1,4 +1,7 @@
 pub mod pms5003;
 pub mod mhz19b;
 pub mod bme280;
-pub mod mq7;
\ No newline at end of file
+pub mod bme680;
+pub mod mq7;
+pub mod mq131;
+pub mod electrochemical;
\ No newline at end of file
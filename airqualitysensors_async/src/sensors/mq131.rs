@@ -0,0 +1,17 @@
+use crate::communicationprotocols::adc::{ AdcHandler, SharedAdc };
+
+use esp_hal::{
+    gpio::AnalogPin,
+    analog::adc::{ AdcChannel, AdcPin }, 
+    peripherals::ADC1 
+};
+
+/// MQ-131 driver bound to one of the node's ADC1 channels
+pub type Mq131<'d, PIN> = sensordrivers::mq131::Mq131<AdcHandler<'d, PIN>>;
+
+pub fn new<'d, PIN>(adc: &'d SharedAdc<'d>, pin: AdcPin<PIN, ADC1>) -> Mq131<'d, PIN>
+where
+    PIN: AdcChannel + AnalogPin
+{
+    Mq131::new(AdcHandler::new(adc, pin))
+}
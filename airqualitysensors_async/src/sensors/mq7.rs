@@ -1,29 +1,17 @@
-use crate::communicationprotocols::adc::AdcHandler;
+use crate::communicationprotocols::adc::{ AdcHandler, SharedAdc };
 
 use esp_hal::{
     gpio::AnalogPin,
-    analog::adc::{ AdcChannel }, 
+    analog::adc::{ AdcChannel, AdcPin }, 
     peripherals::ADC1 
 };
 
-pub struct Mq7<'d, PIN>{
-    adc_handler: AdcHandler<'d, PIN>,
-}
+/// MQ-7 driver bound to one of the node's ADC1 channels
+pub type Mq7<'d, PIN> = sensordrivers::mq7::Mq7<AdcHandler<'d, PIN>>;
 
-impl<'d, PIN> Mq7<'d, PIN>
+pub fn new<'d, PIN>(adc: &'d SharedAdc<'d>, pin: AdcPin<PIN, ADC1>) -> Mq7<'d, PIN>
 where
     PIN: AdcChannel + AnalogPin
 {
-    pub fn new(adc: ADC1, pin: PIN ) -> Self {
-        let adc_handler = AdcHandler::new(adc, pin);
-
-        Self { adc_handler }
-    }
-
-    pub fn read(&mut self)-> Result<u16, &'static str> {
-        match self.adc_handler.read() {
-            Ok(reading) => Result::Ok(reading),
-            Err(()) => Result::Err("Failed to read MQ7")
-        }
-    }
-}
\ No newline at end of file
+    Mq7::new(AdcHandler::new(adc, pin))
+}
@@ -6,39 +6,29 @@ use esp_hal::{
     peripheral::Peripheral,
 };
 
-use core::result::Result;
-
-pub struct Pms5003<'d> {
-    uart_handler: UartHandler<'d>,
-}
-
-impl <'d> Pms5003<'d> {
-    pub fn new(
-        uart:impl Peripheral<P = impl Instance> + 'd,
-        rx:impl Peripheral<P = impl PeripheralInput> + 'd,
-        tx: impl Peripheral<P = impl PeripheralOutput> + 'd,
-        baudrate: u32,
-    )  -> Result<Self, Error> {
-        let uart_handler = UartHandler::new(uart, rx, tx, baudrate).unwrap();
+use embassy_time::Delay;
+use sensordrivers::pms5003::{ Mode, Settings, DEFAULT_WARM_UP_MS };
 
-        Result::Ok(Self { uart_handler })
-    }
+use core::result::Result;
 
-    pub async fn read_pm(&mut self) -> Result<(u16, u16, u16), &'static str> {
-        let mut buffer = [0u8; 32];
+/// PMS5003 driver bound to one of the node's UARTs
+pub type Pms5003<'d> = sensordrivers::pms5003::Pms5003<UartHandler<'d>, Delay>;
 
-        self.uart_handler.flush().await;
-        self.uart_handler.read(&mut buffer).await.map_err(|_| "Failed to read data from PMS5003")?;
+/// Readings are requested on demand and the fan is kept off between them, to save power on
+/// solar powered nodes and extend the laser's lifetime
+const SETTINGS: Settings = Settings {
+    mode: Mode::Passive,
+    warm_up_ms: DEFAULT_WARM_UP_MS,
+    sleep_between_measurements: true,
+};
 
-        if buffer[0] == 0x42 && buffer[1] == 0x4D {
-            let pm1_0 = ((buffer[10] as u16) << 8) | (buffer[11] as u16);
-            let pm2_5 = ((buffer[12] as u16) << 8) | (buffer[13] as u16);
-            let pm10 = ((buffer[14] as u16) << 8) | (buffer[15] as u16);
+pub fn new<'d>(
+    uart:impl Peripheral<P = impl Instance> + 'd,
+    rx:impl Peripheral<P = impl PeripheralInput> + 'd,
+    tx: impl Peripheral<P = impl PeripheralOutput> + 'd,
+    baudrate: u32,
+) -> Result<Pms5003<'d>, Error> {
+    let uart_handler = UartHandler::new(uart, rx, tx, baudrate)?;
 
-            Result::Ok((pm1_0, pm2_5, pm10))
-        } else {
-            Result::Err("Invalid header bytes from PMS5003 data")
-        }
-    }
- 
-}
\ No newline at end of file
+    Result::Ok(Pms5003::new(uart_handler, Delay, SETTINGS))
+}
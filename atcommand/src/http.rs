@@ -0,0 +1,81 @@
+//! Parsers for the SIM808's HTTP application commands
+
+use alloc::{ string::String, vec::Vec };
+
+use crate::{ split_fields, Response };
+
+/// Result of `AT+HTTPACTION`, reported in the `+HTTPACTION: <method>,<status>,<length>` URC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HttpAction {
+    /// 0 for GET, 1 for POST, 2 for HEAD
+    pub method: u8,
+    /// HTTP status code, or one of the modem's own 6xx codes when no response was received
+    pub status: u16,
+    /// Length of the response body available through `AT+HTTPREAD`
+    pub length: usize,
+}
+
+impl HttpAction {
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields = split_fields(line.strip_prefix("+HTTPACTION:")?);
+
+        let [method, status, length] = fields[..] else {
+            return None;
+        };
+
+        Some(HttpAction {
+            method: method.parse().ok()?,
+            status: status.parse().ok()?,
+            length: length.parse().ok()?,
+        })
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Codes 600 and above are raised by the modem itself, e.g. 601 network error or 603 DNS error
+    pub fn is_modem_error(&self) -> bool {
+        self.status >= 600
+    }
+}
+
+/// Body returned by `AT+HTTPREAD`, which follows a `+HTTPREAD: <length>` line
+pub fn http_read_body(response: &Response) -> Option<String> {
+    let start = response.lines.iter().position(|line| line.starts_with("+HTTPREAD:"))?;
+    let body: Vec<&str> = response.lines[start + 1..].iter().map(String::as_str).collect();
+
+    Some(body.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_parse_http_action() {
+        let action = HttpAction::parse("+HTTPACTION: 1,200,25").unwrap();
+
+        assert_eq!(action, HttpAction { method: 1, status: 200, length: 25 });
+        assert!(action.is_success());
+        assert!(!action.is_modem_error());
+
+        assert!(HttpAction::parse("+HTTPACTION: 1,601,0").unwrap().is_modem_error());
+        assert!(!HttpAction::parse("+HTTPACTION: 1,429,0").unwrap().is_success());
+
+        assert_eq!(HttpAction::parse("+HTTPACTION: 1,200"), None);
+        assert_eq!(HttpAction::parse("+HTTPACTION: 1,abc,0"), None);
+        assert_eq!(HttpAction::parse("+HTTPREAD: 25"), None);
+    }
+
+    #[test]
+    fn test_http_read_body() {
+        let response = Response {
+            lines: vec!["+HTTPREAD: 25".to_string(), "{\"status\":\"success\"}".to_string()],
+        };
+
+        assert_eq!(http_read_body(&response).as_deref(), Some("{\"status\":\"success\"}"));
+        assert_eq!(http_read_body(&Response::default()), None);
+    }
+}
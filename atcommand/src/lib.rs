@@ -0,0 +1,484 @@
+//! AT command engine for Hayes style modems such as the SIM808
+//!
+//! Commands are written with a trailing `\r\n` and the modem's output is split into lines.
+//! A command completes on its final result code (`OK`, `ERROR`, `+CME ERROR: <n>` or
+//! `+CMS ERROR: <n>`) or when its timeout expires. Unsolicited result codes (URCs) that
+//! arrive in between are queued so they can be waited for separately, e.g. the
+//! `+HTTPACTION: 1,200,25` that follows the `OK` of `AT+HTTPACTION=1`.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod http;
+
+use alloc::{ collections::VecDeque, string::{ String, ToString }, vec::Vec };
+
+use embassy_time::{ with_deadline, Duration, Instant };
+use embedded_io_async::{ Read, Write };
+
+/// Longest line accepted from the modem, longer lines are discarded
+pub const MAX_LINE_LEN: usize = 1024;
+
+/// Maximum number of URCs kept while nobody is waiting for them, the oldest are dropped first
+pub const MAX_QUEUED_URCS: usize = 16;
+
+/// Line prefixes the SIM808 sends unsolicited
+pub const SIM808_URC_PREFIXES: &[&str] = &[
+    "+HTTPACTION:",
+    "+CPIN:",
+    "+CFUN:",
+    "+PDP: DEACT",
+    "+SAPBR 1: DEACT",
+    "RDY",
+    "Call Ready",
+    "SMS Ready",
+    "NORMAL POWER DOWN",
+    "UNDER-VOLTAGE",
+    "OVER-VOLTAGE",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtError {
+    /// No final result code arrived before the command's timeout
+    Timeout,
+    /// The modem answered `ERROR`
+    Error,
+    /// The modem answered `+CME ERROR: <n>`
+    CmeError(u16),
+    /// The modem answered `+CMS ERROR: <n>`
+    CmsError(u16),
+    /// The modem answered with a final result before sending the expected prompt
+    MissingPrompt,
+    /// Reading from or writing to the transport failed
+    Io,
+}
+
+impl core::fmt::Display for AtError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AtError::Timeout => write!(f, "timed out waiting for the modem"),
+            AtError::Error => write!(f, "modem returned ERROR"),
+            AtError::CmeError(code) => write!(f, "modem returned +CME ERROR: {}", code),
+            AtError::CmsError(code) => write!(f, "modem returned +CMS ERROR: {}", code),
+            AtError::MissingPrompt => write!(f, "modem did not send the data prompt"),
+            AtError::Io => write!(f, "modem transport error"),
+        }
+    }
+}
+
+/// Information lines returned by a successful command, without the echo and final `OK`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Response {
+    pub lines: Vec<String>,
+}
+
+impl Response {
+    /// Payload of the first line starting with `prefix`, e.g. `info("+CGNSINF")` returns
+    /// `1,1,20250330123456.000,...` for the line `+CGNSINF: 1,1,20250330123456.000,...`
+    pub fn info(&self, prefix: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| strip_info_prefix(line, prefix))
+    }
+
+    /// Comma separated fields of the first line starting with `prefix`, with quotes removed
+    pub fn fields(&self, prefix: &str) -> Option<Vec<&str>> {
+        self.info(prefix).map(split_fields)
+    }
+}
+
+/// Splits a comma separated parameter list, trimming whitespace and surrounding quotes
+pub fn split_fields(payload: &str) -> Vec<&str> {
+    payload.split(',').map(|field| field.trim().trim_matches('"')).collect()
+}
+
+fn strip_info_prefix<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    line.strip_prefix(prefix)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .map(|rest| rest.trim_start())
+}
+
+/// Information response prefix of a command, e.g. `+CGNSINF` for `AT+CGNSINF=1`
+fn response_prefix(command: &str) -> Option<&str> {
+    let name = command.strip_prefix("AT")?;
+    let end = name.find(['=', '?']).unwrap_or(name.len());
+
+    name[..end].starts_with('+').then(|| &name[..end])
+}
+
+enum FinalResult {
+    Ok,
+    Err(AtError),
+}
+
+fn final_result(line: &str) -> Option<FinalResult> {
+    let error_code = |prefix: &str| line.strip_prefix(prefix).map(|code| code.trim().parse::<u16>());
+
+    match line {
+        "OK" => Some(FinalResult::Ok),
+        "ERROR" => Some(FinalResult::Err(AtError::Error)),
+        _ => match (error_code("+CME ERROR:"), error_code("+CMS ERROR:")) {
+            (Some(Ok(code)), _) => Some(FinalResult::Err(AtError::CmeError(code))),
+            (_, Some(Ok(code))) => Some(FinalResult::Err(AtError::CmsError(code))),
+            // Verbose error reports carry text instead of a code
+            (Some(Err(_)), _) | (_, Some(Err(_))) => Some(FinalResult::Err(AtError::Error)),
+            _ => None,
+        },
+    }
+}
+
+pub struct AtClient<T> {
+    transport: T,
+    /// Bytes received but not yet split into lines
+    received: Vec<u8>,
+    urcs: VecDeque<String>,
+    urc_prefixes: &'static [&'static str],
+}
+
+impl<T: Read + Write> AtClient<T> {
+    pub fn new(transport: T, urc_prefixes: &'static [&'static str]) -> Self {
+        AtClient { transport, received: Vec::new(), urcs: VecDeque::new(), urc_prefixes }
+    }
+
+    /// Sends a command and waits for its final result code
+    pub async fn command(&mut self, command: &str, timeout: Duration) -> Result<Response, AtError> {
+        let deadline = Instant::now() + timeout;
+
+        self.write_command(command).await?;
+        self.read_response(command, deadline, None).await.map(|(response, _)| response)
+    }
+
+    /// Sends a command that is followed by a data phase, e.g. `AT+HTTPDATA=<len>,<ms>`
+    ///
+    /// Once the modem sends the `prompt` line (`DOWNLOAD` for the SIM808's HTTP commands),
+    /// `data` is written and the final result code of the data phase is awaited.
+    pub async fn command_with_data(
+        &mut self,
+        command: &str,
+        prompt: &str,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<Response, AtError> {
+        let deadline = Instant::now() + timeout;
+
+        self.write_command(command).await?;
+
+        let (mut response, prompted) = self.read_response(command, deadline, Some(prompt)).await?;
+
+        if !prompted {
+            return Err(AtError::MissingPrompt);
+        }
+
+        self.transport.write_all(data).await.map_err(|_| AtError::Io)?;
+        self.transport.flush().await.map_err(|_| AtError::Io)?;
+
+        let (data_response, _) = self.read_response(command, deadline, None).await?;
+        response.lines.extend(data_response.lines);
+
+        Ok(response)
+    }
+
+    /// Waits for a URC starting with `prefix`, returning the whole line
+    ///
+    /// URCs already queued are checked first. Other URCs arriving in the meantime stay queued.
+    pub async fn wait_for_urc(&mut self, prefix: &str, timeout: Duration) -> Result<String, AtError> {
+        if let Some(position) = self.urcs.iter().position(|urc| urc.starts_with(prefix)) {
+            return Ok(self.urcs.remove(position).unwrap_or_default());
+        }
+
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let line = self.read_line(deadline).await?;
+
+            if line.starts_with(prefix) {
+                return Ok(line);
+            }
+
+            if self.is_urc(&line) {
+                self.queue_urc(line);
+            }
+        }
+    }
+
+    /// Removes and returns the oldest queued URC
+    pub fn pop_urc(&mut self) -> Option<String> {
+        self.urcs.pop_front()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    async fn write_command(&mut self, command: &str) -> Result<(), AtError> {
+        // Anything left over from an earlier command would be mistaken for this one's response
+        self.drain_unsolicited();
+
+        self.transport.write_all(command.as_bytes()).await.map_err(|_| AtError::Io)?;
+        self.transport.write_all(b"\r\n").await.map_err(|_| AtError::Io)?;
+        self.transport.flush().await.map_err(|_| AtError::Io)
+    }
+
+    /// Reads lines until a final result code, or until `prompt` when one is expected
+    ///
+    /// Returns whether the prompt was seen.
+    async fn read_response(
+        &mut self,
+        command: &str,
+        deadline: Instant,
+        prompt: Option<&str>,
+    ) -> Result<(Response, bool), AtError> {
+        let own_prefix = response_prefix(command);
+        let mut response = Response::default();
+
+        loop {
+            let line = self.read_line(deadline).await?;
+
+            // Echo of the command itself
+            if line == command {
+                continue;
+            }
+
+            if prompt == Some(line.as_str()) {
+                return Ok((response, true));
+            }
+
+            match final_result(&line) {
+                Some(FinalResult::Ok) => return Ok((response, false)),
+                Some(FinalResult::Err(e)) => return Err(e),
+                None => {}
+            }
+
+            let own_line = own_prefix.is_some_and(|prefix| line.starts_with(prefix));
+
+            if !own_line && self.is_urc(&line) {
+                self.queue_urc(line);
+            } else {
+                response.lines.push(line);
+            }
+        }
+    }
+
+    /// Queues URCs from complete lines already received and discards everything else
+    fn drain_unsolicited(&mut self) {
+        while let Some(line) = self.take_line() {
+            if self.is_urc(&line) {
+                self.queue_urc(line);
+            }
+        }
+
+        self.received.clear();
+    }
+
+    async fn read_line(&mut self, deadline: Instant) -> Result<String, AtError> {
+        let mut chunk = [0u8; 64];
+
+        loop {
+            if let Some(line) = self.take_line() {
+                return Ok(line);
+            }
+
+            let read = with_deadline(deadline, self.transport.read(&mut chunk))
+                .await
+                .map_err(|_| AtError::Timeout)?
+                .map_err(|_| AtError::Io)?;
+
+            self.received.extend_from_slice(&chunk[..read]);
+
+            if self.received.len() > MAX_LINE_LEN && !self.received.contains(&b'\n') {
+                self.received.clear();
+            }
+        }
+    }
+
+    /// Splits the next non-empty line off the receive buffer
+    fn take_line(&mut self) -> Option<String> {
+        loop {
+            let end = self.received.iter().position(|&byte| byte == b'\n')?;
+            let line: Vec<u8> = self.received.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+
+            if !line.is_empty() {
+                return Some(line);
+            }
+        }
+    }
+
+    fn is_urc(&self, line: &str) -> bool {
+        self.urc_prefixes.iter().any(|prefix| line.starts_with(prefix))
+    }
+
+    fn queue_urc(&mut self, line: String) {
+        if self.urcs.len() == MAX_QUEUED_URCS {
+            self.urcs.pop_front();
+        }
+
+        self.urcs.push_back(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embassy_futures::block_on;
+    use embedded_io_async::ErrorType;
+
+    /// Fake modem that answers each expected write with a scripted response
+    struct FakeModem {
+        script: VecDeque<(&'static [u8], &'static [u8])>,
+        written: Vec<u8>,
+        pending: VecDeque<u8>,
+    }
+
+    impl FakeModem {
+        fn new(script: &[(&'static str, &'static str)]) -> Self {
+            FakeModem {
+                script: script.iter().map(|(expected, reply)| (expected.as_bytes(), reply.as_bytes())).collect(),
+                written: Vec::new(),
+                pending: VecDeque::new(),
+            }
+        }
+
+        fn with_unsolicited(mut self, bytes: &str) -> Self {
+            self.pending.extend(bytes.as_bytes());
+            self
+        }
+    }
+
+    impl ErrorType for FakeModem {
+        type Error = Infallible;
+    }
+
+    impl Read for FakeModem {
+        async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+            if self.pending.is_empty() {
+                core::future::pending::<()>().await;
+            }
+
+            let count = buffer.len().min(self.pending.len()).min(7);
+
+            for byte in buffer.iter_mut().take(count) {
+                *byte = self.pending.pop_front().unwrap();
+            }
+
+            Ok(count)
+        }
+    }
+
+    impl Write for FakeModem {
+        async fn write(&mut self, buffer: &[u8]) -> Result<usize, Infallible> {
+            self.written.extend_from_slice(buffer);
+
+            if let Some((expected, reply)) = self.script.front() {
+                if self.written == *expected {
+                    self.pending.extend(*reply);
+                    self.written.clear();
+                    self.script.pop_front();
+                }
+            }
+
+            Ok(buffer.len())
+        }
+    }
+
+    fn client(modem: FakeModem) -> AtClient<FakeModem> {
+        AtClient::new(modem, SIM808_URC_PREFIXES)
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn test_command_returns_information_lines() {
+        let mut at = client(FakeModem::new(&[(
+            "AT+CGNSINF\r\n",
+            "AT+CGNSINF\r\r\n+CGNSINF: 1,1,20250330123456.000,-1.292066,36.821945\r\n\r\nOK\r\n",
+        )]));
+
+        let response = block_on(at.command("AT+CGNSINF", TIMEOUT)).unwrap();
+
+        assert_eq!(response.lines, vec!["+CGNSINF: 1,1,20250330123456.000,-1.292066,36.821945"]);
+        assert_eq!(response.info("+CGNSINF"), Some("1,1,20250330123456.000,-1.292066,36.821945"));
+        assert_eq!(response.fields("+CGNSINF").unwrap()[3], "-1.292066");
+        assert!(at.into_inner().script.is_empty());
+    }
+
+    #[test]
+    fn test_error_results_are_parsed() {
+        let mut at = client(FakeModem::new(&[
+            ("AT+HTTPINIT\r\n", "\r\nERROR\r\n"),
+            ("AT+CPIN?\r\n", "\r\n+CME ERROR: 10\r\n"),
+            ("AT+CMGS=\"1\"\r\n", "\r\n+CMS ERROR: 304\r\n"),
+            ("AT+CGATT=1\r\n", "\r\n+CME ERROR: operation not allowed\r\n"),
+        ]));
+
+        assert_eq!(block_on(at.command("AT+HTTPINIT", TIMEOUT)), Err(AtError::Error));
+        assert_eq!(block_on(at.command("AT+CPIN?", TIMEOUT)), Err(AtError::CmeError(10)));
+        assert_eq!(block_on(at.command("AT+CMGS=\"1\"", TIMEOUT)), Err(AtError::CmsError(304)));
+        assert_eq!(block_on(at.command("AT+CGATT=1", TIMEOUT)), Err(AtError::Error));
+    }
+
+    #[test]
+    fn test_command_times_out() {
+        let mut at = client(FakeModem::new(&[("AT+SAPBR=1,1\r\n", "AT+SAPBR=1,1\r\n")]));
+
+        assert_eq!(block_on(at.command("AT+SAPBR=1,1", Duration::from_millis(50))), Err(AtError::Timeout));
+    }
+
+    #[test]
+    fn test_urcs_are_queued_during_commands() {
+        let mut at = client(
+            FakeModem::new(&[
+                ("AT+CPIN?\r\n", "\r\n+CPIN: READY\r\n\r\nOK\r\n"),
+                ("AT+HTTPACTION=1\r\n", "\r\nOK\r\n\r\n+PDP: DEACT\r\n"),
+            ])
+            .with_unsolicited("\r\nRDY\r\n\r\nCall Ready\r\n"),
+        );
+
+        // +CPIN: is a URC in general, but here it is the answer to AT+CPIN?
+        let response = block_on(at.command("AT+CPIN?", TIMEOUT)).unwrap();
+        assert_eq!(response.info("+CPIN"), Some("READY"));
+        assert_eq!(at.pop_urc().as_deref(), Some("RDY"));
+        assert_eq!(at.pop_urc().as_deref(), Some("Call Ready"));
+
+        block_on(at.command("AT+HTTPACTION=1", TIMEOUT)).unwrap();
+        at.transport.pending.extend(b"\r\n+HTTPACTION: 1,200,25\r\n");
+
+        let urc = block_on(at.wait_for_urc("+HTTPACTION:", TIMEOUT)).unwrap();
+        assert_eq!(urc, "+HTTPACTION: 1,200,25");
+        assert_eq!(at.pop_urc().as_deref(), Some("+PDP: DEACT"));
+        assert_eq!(at.pop_urc(), None);
+
+        assert_eq!(block_on(at.wait_for_urc("+HTTPACTION:", Duration::from_millis(50))), Err(AtError::Timeout));
+    }
+
+    #[test]
+    fn test_command_with_data() {
+        let payload = "{\"pm2_5\": 10}";
+
+        let mut at = client(FakeModem::new(&[
+            ("AT+HTTPDATA=13,10000\r\n", "\r\nDOWNLOAD\r\n"),
+            ("{\"pm2_5\": 10}", "\r\nOK\r\n"),
+            ("AT+HTTPDATA=13,10000\r\n", "\r\nERROR\r\n"),
+            ("AT+HTTPDATA=13,10000\r\n", "\r\nOK\r\n"),
+        ]));
+
+        block_on(at.command_with_data("AT+HTTPDATA=13,10000", "DOWNLOAD", payload.as_bytes(), TIMEOUT)).unwrap();
+
+        assert_eq!(
+            block_on(at.command_with_data("AT+HTTPDATA=13,10000", "DOWNLOAD", payload.as_bytes(), TIMEOUT)),
+            Err(AtError::Error)
+        );
+        assert_eq!(
+            block_on(at.command_with_data("AT+HTTPDATA=13,10000", "DOWNLOAD", payload.as_bytes(), TIMEOUT)),
+            Err(AtError::MissingPrompt)
+        );
+    }
+
+    #[test]
+    fn test_response_prefix() {
+        assert_eq!(response_prefix("AT+CGNSINF"), Some("+CGNSINF"));
+        assert_eq!(response_prefix("AT+HTTPPARA=\"URL\",\"x\""), Some("+HTTPPARA"));
+        assert_eq!(response_prefix("AT+CPIN?"), Some("+CPIN"));
+        assert_eq!(response_prefix("ATE0"), None);
+    }
+}
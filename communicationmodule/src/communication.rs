@@ -1,5 +1,12 @@
-use crate::{ espnowcommunication::EspNowCommunicationManager }; 
+use crate::espnowcommunication::{ acknowledge_command, pairing_task, EspNowCommunicationManager, SharedRequestCounter, SharedSender, JOIN_REQUESTS, RESPONSES };
+use crate::peertable::{ PeerStore, PEERS };
+use crate::pushbuffer::{ buffer_push, take_pushed, Pushed };
 use crate::sim808_functions::Sim808Functions;
+use crate::readingqueue::ReadingQueue;
+use crate::config::{ key_hex, ConfigStore, CONFIG_UPDATED };
+use crate::requestcounter::request_counter;
+use crate::console::{ console_task, Console };
+use crate::sensors::serial::Serial;
 
 use esp_hal::{
     clock::CpuClock,
@@ -8,75 +15,146 @@ use esp_hal::{
     timer::{ systimer::SystemTimer, timg::TimerGroup }
 };
 use esp_wifi::{ esp_now::EspNowReceiver, EspWifiController };
+use esp_storage::FlashStorage;
 
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
 
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_sync::mutex::Mutex;
 
-static SENSOR_CHANNEL: Channel<CriticalSectionRawMutex, SensorData, 1> = Channel::new();
+use static_cell::StaticCell;
+
+static SENDER: StaticCell<SharedSender<'static>> = StaticCell::new();
+static REQUEST_COUNTER: StaticCell<SharedRequestCounter> = StaticCell::new();
 
 
 use esp_println::println;
 
 use core::mem::MaybeUninit;
 
-use heapless::Vec;
+use telemetryframe::{ auth::{ node_key, Key, Signed, KEY_LEN }, command::CommandAck, pairing::PairingMessage, request::DataResponse, TelemetryFrame };
+
+use alloc::vec::Vec;
 
+/// Readings received from a sensor node, `None` for channels the node failed to read
 #[derive(Debug, Clone)]
 pub struct SensorData {
-    pub temperature: f32,
-    pub pressure: f32,
-    pub humidity: f32,
-    pub pm1_0: u16,
-    pub pm2_5: u16,
-    pub pm10: u16,
-    pub co2: u16,
-    pub co: u16,
-    pub o3: u16,
+    pub node_id: u16,
+    pub sequence: u16,
+    pub temperature: Option<f32>,
+    pub pressure: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pm1_0: Option<u16>,
+    pub pm2_5: Option<u16>,
+    pub pm10: Option<u16>,
+    pub co2: Option<u16>,
+    pub co: Option<u16>,
+    pub o3: Option<u16>,
+    pub no2: Option<u16>,
+    pub so2: Option<u16>,
+}
+
+impl From<TelemetryFrame> for SensorData {
+    fn from(frame: TelemetryFrame) -> Self {
+        let readings = frame.readings;
+
+        SensorData {
+            node_id: frame.node_id,
+            sequence: frame.sequence,
+            temperature: readings.temperature,
+            pressure: readings.pressure,
+            humidity: readings.humidity,
+            pm1_0: readings.pm1_0,
+            pm2_5: readings.pm2_5,
+            pm10: readings.pm10,
+            co2: readings.co2,
+            co: readings.co,
+            o3: readings.o3,
+            no2: readings.no2,
+            so2: readings.so2,
+        }
+    }
 }
 
 
 static mut INIT: MaybeUninit<EspWifiController<'static>> = MaybeUninit::uninit();
 
 #[embassy_executor::task]
-async fn receiver_task(mut receiver: EspNowReceiver<'static>){
+async fn receiver_task(
+    mut receiver: EspNowReceiver<'static>,
+    sender: &'static SharedSender<'static>,
+    request_counter: &'static SharedRequestCounter,
+    network_key: Key,
+) {
     loop {
         let data = receiver.receive_async().await;
-        match core::str::from_utf8(data.data()) {
-            Ok(text) => {
-                println!("Received data: {}", text);
-
-                let values: Vec<&str, 16> = text.split(',').collect();
-
-                if values.len() == 8 {
-                    if let (Ok(temp), Ok(press), Ok(hum), Ok(pm1), Ok(pm2), Ok(pm10), Ok(co2), Ok(co)) = (
-                        values[0].parse::<f32>(),
-                        values[1].parse::<f32>(),
-                        values[2].parse::<f32>(),
-                        values[3].parse::<u16>(),
-                        values[4].parse::<u16>(),
-                        values[5].parse::<u16>(),
-                        values[6].parse::<u16>(),
-                        values[7].parse::<u16>()
-                    ) {
-                        let sensor_data = SensorData {
-                            temperature: temp,
-                            pressure: press,
-                            humidity: hum,
-                            pm1_0: pm1,
-                            pm2_5: pm2,
-                            pm10: pm10,
-                            co2: co2,
-                            co: co,
-                            o3: 0, 
-                        };
-
-                        SENSOR_CHANNEL.send(sensor_data).await;
-                    } 
+        let source = data.info.src_address;
+
+        // Nodes sign everything they send with their own key, the message tells which node it claims to be from
+        let signed = match Signed::split(data.data()) {
+            Ok(signed) => signed,
+            Err(e) => {
+                println!("Discarded ESP-NOW frame: {}", e);
+                continue;
+            }
+        };
+
+        if let Ok(message) = PairingMessage::decode(signed.message) {
+            if let PairingMessage::Join { node_id, nonce } = message {
+                match signed.verify(&node_key(&network_key, node_id)) {
+                    Ok(()) => JOIN_REQUESTS.send((source, node_id, nonce)).await,
+                    Err(e) => println!("Discarded join from node {:04x}: {}", node_id, e),
                 }
             }
-            Err(_) => println!("Received invalid UTF-8 data"),
+            continue;
+        }
+
+        // A node acknowledges a command with the command's counter, which tells which command it ran
+        if let Ok(ack) = CommandAck::decode(signed.message) {
+            match PEERS.lock(|peers| peers.borrow().by_address(&source)) {
+                Some(peer) => match signed.verify(&node_key(&network_key, peer.node_id)) {
+                    Ok(()) => acknowledge_command(peer.node_id, signed.counter, ack),
+                    Err(e) => println!("Discarded acknowledgement from node {:04x}: {}", peer.node_id, e),
+                },
+                None => println!("Discarded acknowledgement from unpaired node {:02x?}", source),
+            }
+            continue;
+        }
+
+        // Responses carry the request id they answer, frames a node pushes on its own schedule are sent as they are
+        let (frame, request_id) = match DataResponse::decode(signed.message) {
+            Ok(response) => (response.frame, Some(response.request_id)),
+            Err(_) => match TelemetryFrame::decode(signed.message) {
+                Ok(frame) => (frame, None),
+                Err(e) => {
+                    println!("Discarded ESP-NOW frame: {}", e);
+                    continue;
+                }
+            },
+        };
+
+        match PEERS.lock(|peers| peers.borrow().by_address(&source)) {
+            Some(peer) if peer.node_id == frame.node_id => match signed.verify(&node_key(&network_key, peer.node_id)) {
+                Ok(()) => match request_id {
+                    Some(request_id) => {
+                        println!("Received frame {} from node {:04x}", frame.sequence, peer.node_id);
+
+                        RESPONSES.send((DataResponse { request_id, frame }, signed.counter, data.info.rx_control.rssi)).await;
+                    }
+                    None => match buffer_push(peer.node_id, signed.counter, frame) {
+                        Ok(()) => {
+                            println!("Node {:04x} pushed frame {}", peer.node_id, frame.sequence);
+
+                            // The node listens briefly after pushing, it may be asleep at any other time
+                            EspNowCommunicationManager::send_commands(sender, request_counter, &network_key, &peer).await;
+                        }
+                        Err(e) => println!("Discarded frame pushed by node {:04x}: {}", peer.node_id, e),
+                    },
+                },
+                Err(e) => println!("Discarded frame from node {:04x}: {}", peer.node_id, e),
+            },
+            Some(peer) => println!("Discarded frame claiming node {:04x} from node {:04x}", frame.node_id, peer.node_id),
+            None => println!("Discarded frame from unpaired node {:02x?}", source),
         }
     }
 }
@@ -98,46 +176,129 @@ pub async fn communication_main(spawner: Spawner) {
 
     let timer = TimerGroup::new(peripherals.TIMG0);
 
+    let mut rng = Rng::new(peripherals.RNG);
+
     let init = unsafe{ 
-        INIT.write(esp_wifi::init(timer.timer0, Rng::new(peripherals.RNG), peripherals.RADIO_CLK,).unwrap()); 
+        INIT.write(esp_wifi::init(timer.timer0, rng.clone(), peripherals.RADIO_CLK,).unwrap()); 
         
         INIT.assume_init_mut()
     };
 
+    // Settings saved over the console survive a reset, otherwise the defaults are used
+    let mut config_store = ConfigStore::new(FlashStorage::new());
+    let mut config = config_store.load();
+
+    println!("Using APN {} and endpoint {}", config.apn, config.server_url);
+
+    // Every communication module gets its own network key, the sensor node keys are derived from it
+    let network_key = match config.network_key() {
+        Some(network_key) => network_key,
+        None => {
+            let mut network_key: Key = [0u8; KEY_LEN];
+            rng.read(&mut network_key);
+
+            config.network_key = key_hex(&network_key);
+
+            match config_store.save(&config) {
+                Ok(()) => println!("Generated a network key, use nodekey on the console to provision the sensor nodes"),
+                Err(e) => println!("Failed to save the generated network key, it only lasts until a reset: {}", e),
+            }
+
+            network_key
+        }
+    };
+
+    // Sensor nodes paired before the last reset are polled straight away
+    let mut peer_store = PeerStore::new(FlashStorage::new());
+    let peers = peer_store.load();
+
+    println!("{} sensor nodes paired", peers.peers().len());
 
     let espnow_communication = EspNowCommunicationManager::new(
         init, 
-        peripherals.WIFI
+        peripherals.WIFI,
+        &network_key,
+        peers.peers()
     );
 
+    PEERS.lock(|table| *table.borrow_mut() = peers);
+
     let receiver = espnow_communication.receiver;
-    let mut sender = espnow_communication.sender;
-    let peer_address = espnow_communication.peer_address;
+    let sender = SENDER.init(Mutex::new(espnow_communication.sender));
+
+    // Requests carry a counter that keeps increasing across resets, so the nodes can reject replays
+    let request_counter = REQUEST_COUNTER.init(Mutex::new(request_counter(FlashStorage::new())));
+
+    spawner.spawn(receiver_task(receiver, sender, request_counter, network_key)).unwrap();
+    spawner.spawn(pairing_task(espnow_communication.manager, sender, peer_store, network_key)).unwrap();
 
-    spawner.spawn(receiver_task(receiver)).unwrap();
+    // Numbers the exchanges with the nodes, a retried request keeps its id
+    let mut request_id: u16 = 0;
+
+    let serial = Serial::new(peripherals.UART0, peripherals.GPIO17, peripherals.GPIO16, 9600).unwrap();
+
+    spawner.spawn(console_task(Console::new(serial, config_store, config.clone()))).unwrap();
 
     let mut sim808_functions = Sim808Functions::new(
-        peripherals.UART0, 
-        peripherals.GPIO17, 
-        peripherals.GPIO16, 
         peripherals.UART1, 
         peripherals.GPIO20, 
-        peripherals.GPIO21
+        peripherals.GPIO21,
+        config
     );
-    
+
+    // Readings are persisted until the webserver acknowledges them
+    let mut reading_queue = ReadingQueue::new(FlashStorage::new()).unwrap();
+
+    println!("{} readings pending upload after boot", reading_queue.pending());
 
     loop {
-        EspNowCommunicationManager::send_data_request(&mut sender, &peer_address).await;
+        if let Some(config) = CONFIG_UPDATED.try_take() {
+            println!("Applying new configuration, APN {} and endpoint {}", config.apn, config.server_url);
+            sim808_functions.config = config;
+        }
 
-        sim808_functions.config_sim808().await;
+        // Nodes are polled one after another, so their answers never compete for the channel
+        let peers = PEERS.lock(|peers| peers.borrow().peers().to_vec());
+        let mut frames = Vec::with_capacity(peers.len());
+
+        if peers.is_empty() {
+            println!("No sensor nodes paired, use pair on the console to add some");
+        }
+
+        for peer in peers {
+            // Nodes in push mode measure on their own schedule, only the latest frame they pushed is uploaded
+            let frame = match take_pushed(peer.node_id) {
+                Pushed::NotPushing => {
+                    let frame = EspNowCommunicationManager::request_data(
+                        sender,
+                        request_counter,
+                        &network_key,
+                        &peer,
+                        request_id,
+                    ).await;
+
+                    request_id = request_id.wrapping_add(1);
+                    frame
+                }
+                // Queued commands are sent right after the node's next push
+                Pushed::Frame(frame) => Some(frame),
+                Pushed::NothingNew => {
+                    println!("Node {:04x} pushed nothing since the last upload", peer.node_id);
+                    None
+                }
+            };
 
-        let sensor_data = SENSOR_CHANNEL.receive().await;
+            if let Some(frame) = frame {
+                println!("Received sensor data: {:?}", SensorData::from(frame));
+                frames.push(frame);
+            }
+        }
 
-        println!("Received sensor data: {:?}", sensor_data);
+        sim808_functions.config_sim808().await;
 
-        sim808_functions.send_data(sensor_data.temperature, sensor_data.pressure, sensor_data.humidity, sensor_data.pm1_0, sensor_data.pm2_5, sensor_data.pm10, sensor_data.co2, sensor_data.co).await;
+        sim808_functions.send_data(&frames, &mut reading_queue).await;
 
-        Timer::after(Duration::from_secs(1)).await;
+        Timer::after(Duration::from_secs(sim808_functions.config.reporting_interval as u64)).await;
     }
 
 }
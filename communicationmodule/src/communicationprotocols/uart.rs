@@ -37,4 +37,25 @@ impl<'d> UartHandler<'d> {
     pub async fn flush(&mut self) {
         self.uart.flush_async().await.unwrap();
     }
-}
\ No newline at end of file
+}
+
+// Blocking reads for the AT command engine, unlike `UartHandler::read` which only returns buffered bytes
+impl embedded_io_async::ErrorType for UartHandler<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for UartHandler<'_> {
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.uart.read_async(buffer).await
+    }
+}
+
+impl embedded_io_async::Write for UartHandler<'_> {
+    async fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.uart.write_async(data).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.uart.flush_async().await
+    }
+}
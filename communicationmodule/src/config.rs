@@ -0,0 +1,193 @@
+use embedded_storage::nor_flash::NorFlash;
+use embassy_sync::{ blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal };
+
+use telemetryframe::{ auth::{ parse_key, Key }, store::RecordStore };
+
+use alloc::{ format, string::{ String, ToString }, vec::Vec };
+
+/// Start of the `config` partition in `partitions.csv`
+pub const CONFIG_FLASH_OFFSET: u32 = 0x3F0000;
+/// Size of the `config` partition in `partitions.csv`, a single flash sector
+pub const CONFIG_FLASH_SIZE: u32 = 0x1000;
+
+const MAGIC: [u8; 4] = *b"AQCF";
+
+/// Signalled by the console whenever a new configuration has been saved
+pub static CONFIG_UPDATED: Signal<CriticalSectionRawMutex, Config> = Signal::new();
+
+/// API key issued by the backend's device registry (`register_device`), used until one is set
+/// over the console, e.g. `DEVICE_KEY=... cargo build --release`
+const DEFAULT_DEVICE_KEY: &str = match option_env!("DEVICE_KEY") {
+    Some(key) => key,
+    None => "",
+};
+
+/// Key the sensor node keys are derived from, generated on the first boot unless built in,
+/// e.g. `NETWORK_KEY=... cargo build --release`
+const DEFAULT_NETWORK_KEY: &str = match option_env!("NETWORK_KEY") {
+    Some(key) => key,
+    None => "",
+};
+
+/// Deployment specific settings of the communication module
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub apn: String,
+    pub apn_user: String,
+    pub apn_password: String,
+    /// Endpoint the queued readings are posted to
+    pub server_url: String,
+    pub device_key: String,
+    /// Offset of the station's local time from UTC, included in the timestamps sent to the webserver
+    pub utc_offset_minutes: i32,
+    /// Delay in seconds between requests for new readings
+    pub reporting_interval: u32,
+    /// 32 hex digits, applied after a restart, the sensor nodes have to be reprovisioned when it changes
+    pub network_key: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            apn: String::from("safaricom"),
+            apn_user: String::new(),
+            apn_password: String::new(),
+            server_url: String::from("https://airqualitymonitoring.cc/airquality/batch"),
+            device_key: String::from(DEFAULT_DEVICE_KEY),
+            utc_offset_minutes: 180,
+            reporting_interval: 1,
+            network_key: String::from(DEFAULT_NETWORK_KEY),
+        }
+    }
+}
+
+impl Config {
+    pub const KEYS: [&'static str; 8] = [
+        "apn", "apn_user", "apn_password", "server_url", "device_key", "utc_offset_minutes", "reporting_interval",
+        "network_key",
+    ];
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "apn" => self.apn.clone(),
+            "apn_user" => self.apn_user.clone(),
+            "apn_password" => self.apn_password.clone(),
+            "server_url" => self.server_url.clone(),
+            "device_key" => self.device_key.clone(),
+            "utc_offset_minutes" => self.utc_offset_minutes.to_string(),
+            "reporting_interval" => self.reporting_interval.to_string(),
+            "network_key" => self.network_key.clone(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        // Values are stored one per line and end up inside quoted AT command parameters
+        if value.contains(['\r', '\n', '"']) {
+            return Result::Err("Value must not contain quotes or line breaks");
+        }
+
+        match key {
+            "apn" => self.apn = String::from(value),
+            "apn_user" => self.apn_user = String::from(value),
+            "apn_password" => self.apn_password = String::from(value),
+            "server_url" => {
+                if !value.starts_with("http://") && !value.starts_with("https://") {
+                    return Result::Err("URL must start with http:// or https://");
+                }
+                self.server_url = String::from(value);
+            }
+            "device_key" => self.device_key = String::from(value),
+            "utc_offset_minutes" => {
+                let offset: i32 = value.parse().map_err(|_| "UTC offset must be a whole number of minutes")?;
+
+                if !(-12 * 60..=14 * 60).contains(&offset) {
+                    return Result::Err("UTC offset must be between -720 and 840 minutes");
+                }
+                self.utc_offset_minutes = offset;
+            }
+            "reporting_interval" => {
+                let interval: u32 = value.parse().map_err(|_| "Reporting interval must be a whole number of seconds")?;
+
+                if interval == 0 {
+                    return Result::Err("Reporting interval must be at least 1 second");
+                }
+                self.reporting_interval = interval;
+            }
+            "network_key" => {
+                if parse_key(value).is_none() {
+                    return Result::Err("Network key must be 32 hex digits");
+                }
+                self.network_key = String::from(value);
+            }
+            _ => return Result::Err("Unknown configuration key"),
+        }
+
+        Ok(())
+    }
+
+    pub fn network_key(&self) -> Option<Key> {
+        parse_key(&self.network_key)
+    }
+
+    /// Serializes the configuration as `key=value` lines
+    fn encode(&self) -> String {
+        let lines: Vec<String> = Self::KEYS
+            .iter()
+            .filter_map(|key| self.get(key).map(|value| format!("{}={}", key, value)))
+            .collect();
+
+        lines.join("\n")
+    }
+
+    /// Parses `key=value` lines, keeping the default for keys that are missing or invalid
+    fn decode(text: &str) -> Self {
+        let mut config = Config::default();
+
+        for line in text.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                let _ = config.set(key, value);
+            }
+        }
+
+        config
+    }
+}
+
+/// Formats a key as the 32 hex digits `network_key` and the sensor node builds expect
+pub fn key_hex(key: &Key) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Persists the configuration in its own flash partition
+pub struct ConfigStore<F: NorFlash> {
+    store: RecordStore<F>,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    pub fn new(flash: F) -> Self {
+        ConfigStore { store: RecordStore::new(flash, CONFIG_FLASH_OFFSET, CONFIG_FLASH_SIZE, MAGIC) }
+    }
+
+    /// Reads the saved configuration, falling back to the defaults when none has been saved yet
+    pub fn load(&mut self) -> Config {
+        let mut text = alloc::vec![0u8; self.store.capacity()];
+
+        match self.store.load(&mut text) {
+            Some(text) => core::str::from_utf8(text).map(Config::decode).unwrap_or_default(),
+            None => Config::default(),
+        }
+    }
+
+    pub fn save(&mut self, config: &Config) -> Result<(), &'static str> {
+        let text = config.encode();
+
+        if text.len() > self.store.capacity() {
+            return Result::Err("Configuration is too large");
+        }
+
+        self.store.save(text.as_bytes())
+    }
+}
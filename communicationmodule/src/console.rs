@@ -0,0 +1,235 @@
+use crate::config::{ key_hex, Config, ConfigStore, CONFIG_UPDATED };
+use crate::espnowcommunication::{ link_report, queue_command, FORGET_REQUESTS, PAIRING_WINDOW };
+use crate::peertable::PEERS;
+use crate::sensors::serial::Serial;
+
+use esp_storage::FlashStorage;
+
+use embassy_time::{ Duration, Timer };
+
+use telemetryframe::{ auth::{ node_key, primary_master_key }, command::SensorCommand };
+
+use alloc::{ format, string::String, vec::Vec };
+
+const MAX_LINE_LEN: usize = 256;
+
+/// How long `pair` accepts new sensor nodes when no duration is given
+const DEFAULT_PAIRING_SECONDS: u32 = 60;
+
+const HELP: &str = "Commands:\r\n  \
+    show                 print the active and edited configuration\r\n  \
+    set <key> <value>    edit a setting, an empty value clears it\r\n  \
+    save                 persist the edited configuration and apply it\r\n  \
+    defaults             reset the edited configuration to the defaults\r\n  \
+    pair [seconds]       accept new sensor nodes, for 60 seconds by default\r\n  \
+    nodes                list the paired sensor nodes and their link quality\r\n  \
+    forget <node>        unpair a sensor node, given its hex id\r\n  \
+    nodekey <node>       print the keys to build a sensor node's firmware with\r\n  \
+    node <node> <cmd>    queue one of these sensor commands for a node:\r\n    \
+      co2 zero           calibrate the CO2 zero point, in fresh air\r\n    \
+      co2 span <ppm>     calibrate the CO2 span against a reference gas\r\n    \
+      co2 abc on|off     turn CO2 automatic baseline correction on or off\r\n    \
+      co2 range <ppm>    set the CO2 detection range, 2000, 5000 or 10000\r\n    \
+      co calibrate       derive the CO sensor R0, in clean air\r\n    \
+      co rl <ohms>       set the load resistor fitted next to the CO sensor\r\n    \
+      o3 calibrate       derive the ozone sensor R0, in clean air\r\n    \
+      o3 rl <ohms>       set the load resistor fitted next to the ozone sensor\r\n  \
+    help                 print this message\r\n";
+
+/// Line based console on the debug UART for changing the configuration without reflashing
+///
+/// Settings are edited on a copy and only persisted and applied on `save`.
+pub struct Console {
+    serial: Serial<'static>,
+    store: ConfigStore<FlashStorage>,
+    active: Config,
+    edited: Config,
+    line: Vec<u8>,
+}
+
+impl Console {
+    pub fn new(serial: Serial<'static>, store: ConfigStore<FlashStorage>, config: Config) -> Self {
+        Console { serial, store, active: config.clone(), edited: config, line: Vec::new() }
+    }
+
+    async fn write(&mut self, text: &str) {
+        let _ = self.serial.send_response(text.as_bytes()).await;
+    }
+
+    async fn run_command(&mut self, line: &str) {
+        let mut parts = line.splitn(3, ' ');
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("show"), None, None) => {
+                let mut report = String::new();
+
+                for key in Config::KEYS {
+                    let active = self.active.get(key).unwrap_or_default();
+                    let edited = self.edited.get(key).unwrap_or_default();
+
+                    if active == edited {
+                        report.push_str(&format!("{} = {}\r\n", key, active));
+                    } else {
+                        report.push_str(&format!("{} = {} (unsaved: {})\r\n", key, active, edited));
+                    }
+                }
+
+                self.write(&report).await;
+            }
+            (Some("set"), Some(key), value) => match self.edited.set(key, value.unwrap_or("")) {
+                Ok(()) => self.write("OK\r\n").await,
+                Err(e) => self.write(&format!("ERROR: {}\r\n", e)).await,
+            },
+            (Some("save"), None, None) => match self.store.save(&self.edited) {
+                Ok(()) => {
+                    self.active = self.edited.clone();
+                    CONFIG_UPDATED.signal(self.active.clone());
+                    self.write("Configuration saved\r\n").await;
+                }
+                Err(e) => self.write(&format!("ERROR: {}\r\n", e)).await,
+            },
+            (Some("defaults"), None, None) => {
+                self.edited = Config::default();
+                self.write("Defaults restored, use save to apply them\r\n").await;
+            }
+            (Some("pair"), seconds, None) => match seconds.map(str::parse::<u32>).unwrap_or(Ok(DEFAULT_PAIRING_SECONDS)) {
+                Ok(seconds) => {
+                    PAIRING_WINDOW.signal(seconds);
+                    self.write("Pairing open, power up or reset the nodes to add\r\n").await;
+                }
+                Err(_) => self.write("ERROR: Expected a whole number of seconds\r\n").await,
+            },
+            (Some("nodes"), None, None) => {
+                let report = PEERS.lock(|peers| {
+                    peers
+                        .borrow()
+                        .peers()
+                        .iter()
+                        .map(|peer| {
+                            let report = link_report(peer.node_id);
+
+                            let link = match report.last {
+                                Some(link) => format!("{} dBm, {} retries, {} ms", link.rssi, link.retries, link.latency.as_millis()),
+                                None => String::from("no answer yet"),
+                            };
+
+                            match report.unanswered {
+                                0 => format!("{:04x} at {:02x?}, {}\r\n", peer.node_id, peer.address, link),
+                                unanswered => format!("{:04x} at {:02x?}, {}, {} requests unanswered in a row\r\n", peer.node_id, peer.address, link, unanswered),
+                            }
+                        })
+                        .collect::<String>()
+                });
+
+                if report.is_empty() {
+                    self.write("No sensor nodes paired\r\n").await;
+                } else {
+                    self.write(&report).await;
+                }
+            }
+            (Some("forget"), Some(node), None) => match parse_node_id(node) {
+                Ok(node_id) if FORGET_REQUESTS.try_send(node_id).is_ok() => self.write("OK\r\n").await,
+                Ok(_) => self.write("ERROR: Too many nodes waiting to be forgotten\r\n").await,
+                Err(e) => self.write(&format!("ERROR: {}\r\n", e)).await,
+            },
+            (Some("nodekey"), Some(node), None) => match (u16::from_str_radix(node, 16), self.active.network_key()) {
+                (Ok(node_id), Some(network_key)) => {
+                    let keys = format!(
+                        "NODE_KEY={} ESPNOW_PMK={}\r\n",
+                        key_hex(&node_key(&network_key, node_id)),
+                        key_hex(&primary_master_key(&network_key)),
+                    );
+
+                    self.write(&keys).await;
+                }
+                (Err(_), _) => self.write("ERROR: Expected a node id in hex\r\n").await,
+                (_, None) => self.write("ERROR: No network key set\r\n").await,
+            },
+            (Some("node"), Some(node), Some(command)) => {
+                let mut parts = command.splitn(3, ' ');
+
+                let queued = parse_node_id(node).and_then(|node_id| {
+                    let command = match (parts.next(), parts.next(), parts.next()) {
+                        (Some(sensor), Some(action), argument) => parse_sensor_command(sensor, action, argument)?,
+                        _ => return Result::Err("Unknown sensor command, type help for a list of commands"),
+                    };
+
+                    queue_command(node_id, command)
+                });
+
+                match queued {
+                    Ok(()) => self.write("Command queued, it is sent ahead of the node's next data request or right after its next push, until the node acknowledges it\r\n").await,
+                    Err(e) => self.write(&format!("ERROR: {}\r\n", e)).await,
+                }
+            }
+            (Some("help"), None, None) => self.write(HELP).await,
+            (None, _, _) | (Some(""), _, _) => {}
+            _ => self.write("ERROR: Unknown command, type help for a list of commands\r\n").await,
+        }
+    }
+
+    /// Reads the bytes buffered by the UART, running each command once a full line has arrived
+    pub async fn poll(&mut self) {
+        let mut buffer = [0u8; 64];
+
+        let Ok(len) = self.serial.read_command(&mut buffer).await else {
+            return;
+        };
+
+        for &byte in &buffer[..len] {
+            match byte {
+                b'\r' | b'\n' => {
+                    let line = core::mem::take(&mut self.line);
+
+                    if let Ok(line) = core::str::from_utf8(&line) {
+                        self.run_command(line.trim()).await;
+                    } else {
+                        self.write("ERROR: Command is not valid UTF-8\r\n").await;
+                    }
+                }
+                _ if self.line.len() < MAX_LINE_LEN => self.line.push(byte),
+                // Bytes past the maximum line length are dropped
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Parses a node id as shown by `nodes`, checking that the node is paired
+fn parse_node_id(node: &str) -> Result<u16, &'static str> {
+    let node_id = u16::from_str_radix(node, 16).map_err(|_| "Expected a node id in hex")?;
+
+    if PEERS.lock(|peers| peers.borrow().peers().iter().any(|peer| peer.node_id == node_id)) {
+        Ok(node_id)
+    } else {
+        Result::Err("No paired node has that id, type nodes for a list")
+    }
+}
+
+fn parse_sensor_command(sensor: &str, action: &str, argument: Option<&str>) -> Result<SensorCommand, &'static str> {
+    let number = || argument.and_then(|value| value.parse::<u16>().ok()).ok_or("Expected a whole number");
+
+    match (sensor, action, argument) {
+        ("co2", "zero", None) => Ok(SensorCommand::Co2ZeroPoint),
+        ("co2", "span", Some(_)) => Ok(SensorCommand::Co2SpanPoint(number()?)),
+        ("co2", "abc", Some("on")) => Ok(SensorCommand::Co2AutoBaseline(true)),
+        ("co2", "abc", Some("off")) => Ok(SensorCommand::Co2AutoBaseline(false)),
+        ("co2", "range", Some(_)) => Ok(SensorCommand::Co2DetectionRange(number()?)),
+        ("co", "calibrate", None) => Ok(SensorCommand::CoCalibrate),
+        ("co", "rl", Some(_)) => Ok(SensorCommand::CoLoadResistance(number()?)),
+        ("o3", "calibrate", None) => Ok(SensorCommand::O3Calibrate),
+        ("o3", "rl", Some(_)) => Ok(SensorCommand::O3LoadResistance(number()?)),
+        _ => Result::Err("Unknown sensor command, type help for a list of commands"),
+    }
+}
+
+#[embassy_executor::task]
+pub async fn console_task(mut console: Console) {
+    console.write("Configuration console ready, type help for a list of commands\r\n").await;
+
+    loop {
+        console.poll().await;
+
+        Timer::after(Duration::from_millis(50)).await;
+    }
+}
@@ -1,54 +1,453 @@
-use esp_wifi::{esp_now::{EspNow, EspNowReceiver, EspNowSender, PeerInfo }, EspWifiController};
-use esp_hal::peripherals::WIFI; 
+use crate::peertable::{ Peer, PeerStore, PEERS };
+use crate::pushbuffer::forget_pushes;
+use crate::requestcounter::RequestCounter;
+
+use esp_wifi::{esp_now::{EspNow, EspNowError, EspNowManager, EspNowReceiver, EspNowSender, PeerInfo, BROADCAST_ADDRESS }, EspWifiController};
+use esp_hal::{ efuse::Efuse, peripherals::WIFI };
 use esp_println::println;
+use esp_storage::FlashStorage;
+
+use embassy_futures::select::{ select4, Either4 };
+use embassy_time::{ with_timeout, Duration, Instant, Timer };
+use embassy_sync::{ blocking_mutex::{ raw::CriticalSectionRawMutex, Mutex as BlockingMutex }, channel::Channel, mutex::Mutex, signal::Signal };
+
+use telemetryframe::{
+    auth::{ local_master_key, node_key, primary_master_key, sign, Key, AUTH_LEN, PAIRING_COUNTER },
+    command::{ CommandAck, SensorCommand },
+    pairing::PairingMessage,
+    request::{ DataRequest, DataResponse },
+    TelemetryFrame,
+};
+
+use alloc::{ format, string::String, vec::Vec };
+use core::cell::RefCell;
+
+/// Most commands waiting to be acknowledged, across all nodes
+const MAX_QUEUED_COMMANDS: usize = 8;
+/// Transmissions of a command before it is dropped without an acknowledgement
+const MAX_COMMAND_ATTEMPTS: u8 = 5;
+
+/// Interval between `Discover` broadcasts while pairing is open
+const DISCOVER_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Transmissions of a data request before the node is reported as not answering
+const MAX_ATTEMPTS: u8 = 3;
+/// Time a node gets to read its sensors and answer one transmission of a data request
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Commands queued by the console, kept until the node acknowledges them
+static SENSOR_COMMANDS: BlockingMutex<CriticalSectionRawMutex, RefCell<Vec<QueuedCommand>>> = BlockingMutex::new(RefCell::new(Vec::new()));
+
+/// A command waiting for its node's acknowledgement
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct QueuedCommand {
+    node_id: u16,
+    command: SensorCommand,
+    /// Counter of the latest transmission, which the acknowledgement is signed with
+    counter: Option<u32>,
+    attempts: u8,
+}
+
+/// Opens pairing for the given number of seconds
+pub static PAIRING_WINDOW: Signal<CriticalSectionRawMutex, u32> = Signal::new();
+/// Node ids the console asked to remove from the peer table
+pub static FORGET_REQUESTS: Channel<CriticalSectionRawMutex, u16, 4> = Channel::new();
+/// `Join` messages received from sensor nodes, with the address they came from, the node id and the nonce
+pub static JOIN_REQUESTS: Channel<CriticalSectionRawMutex, ([u8; 6], u16, u32), 4> = Channel::new();
+
+/// Verified responses, with the counter of the request they answer and their signal strength in dBm
+pub static RESPONSES: Channel<CriticalSectionRawMutex, (DataResponse, u32, i32), 4> = Channel::new();
+
+/// Link quality reported for each node, shown by `nodes` on the console
+static LINK_REPORTS: BlockingMutex<CriticalSectionRawMutex, RefCell<Vec<(u16, LinkReport)>>> = BlockingMutex::new(RefCell::new(Vec::new()));
 
-use embassy_time::{ Duration, Timer };
+/// The sender is shared between the polling loop and the pairing task
+pub type SharedSender<'d> = Mutex<CriticalSectionRawMutex, EspNowSender<'d>>;
+/// The request counter is shared between the polling loop and the receiver, which sends commands after a push
+pub type SharedRequestCounter = Mutex<CriticalSectionRawMutex, RequestCounter<FlashStorage>>;
+
+/// Queues a command for a node, failing when too many commands are already waiting
+pub fn queue_command(node_id: u16, command: SensorCommand) -> Result<(), &'static str> {
+    SENSOR_COMMANDS.lock(|commands| {
+        let mut commands = commands.borrow_mut();
+
+        if commands.len() >= MAX_QUEUED_COMMANDS {
+            return Result::Err("Too many commands waiting to be acknowledged");
+        }
+
+        // Acknowledgements are matched by node and command, so the same command can only wait once
+        if commands.iter().any(|queued| queued.node_id == node_id && queued.command == command) {
+            return Result::Err("That command is already waiting for the node");
+        }
+
+        commands.push(QueuedCommand { node_id, command, counter: None, attempts: 0 });
+        Ok(())
+    })
+}
+
+/// Takes the commands due to be sent to a node, dropping those sent too often without an acknowledgement
+fn commands_to_send(node_id: u16) -> Vec<SensorCommand> {
+    SENSOR_COMMANDS.lock(|commands| {
+        let mut commands = commands.borrow_mut();
+
+        commands.retain(|queued| {
+            let expired = queued.node_id == node_id && queued.attempts >= MAX_COMMAND_ATTEMPTS;
+
+            if expired {
+                println!("Node {:04x} never acknowledged {:?}, dropped it", node_id, queued.command);
+            }
+
+            !expired
+        });
+
+        commands.iter().filter(|queued| queued.node_id == node_id).map(|queued| queued.command).collect()
+    })
+}
+
+/// Notes the counter a command was sent with, which its acknowledgement has to carry
+fn command_sent(node_id: u16, command: SensorCommand, counter: u32) {
+    SENSOR_COMMANDS.lock(|commands| {
+        let mut commands = commands.borrow_mut();
+
+        if let Some(queued) = commands.iter_mut().find(|queued| queued.node_id == node_id && queued.command == command) {
+            queued.counter = Some(counter);
+            queued.attempts += 1;
+        }
+    });
+}
+
+/// Removes the command a node acknowledged, given the counter the acknowledgement was signed with
+pub fn acknowledge_command(node_id: u16, counter: u32, ack: CommandAck) {
+    let acknowledged = SENSOR_COMMANDS.lock(|commands| {
+        let mut commands = commands.borrow_mut();
+        let index = commands.iter().position(|queued| queued.node_id == node_id && queued.counter == Some(counter))?;
+
+        Some(commands.remove(index).command)
+    });
+
+    match (acknowledged, ack.succeeded) {
+        (Some(command), true) => println!("Node {:04x} ran {:?}", node_id, command),
+        (Some(command), false) => println!("Node {:04x} failed to run {:?}", node_id, command),
+        // An acknowledgement of an earlier transmission, the command was sent again since
+        (None, _) => {}
+    }
+}
+
+/// Link quality of one answered data request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkQuality {
+    /// Signal strength of the response in dBm
+    pub rssi: i32,
+    /// Transmissions of the request that went unanswered
+    pub retries: u8,
+    /// Time from the first transmission of the request to the response
+    pub latency: Duration,
+}
+
+/// Latest answered exchange with a node, and the exchanges left unanswered since
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkReport {
+    pub last: Option<LinkQuality>,
+    pub unanswered: u32,
+}
+
+pub fn link_report(node_id: u16) -> LinkReport {
+    LINK_REPORTS.lock(|reports| {
+        reports.borrow().iter().find(|(id, _)| *id == node_id).map(|(_, report)| *report).unwrap_or_default()
+    })
+}
+
+fn record_exchange(node_id: u16, link: Option<LinkQuality>) {
+    LINK_REPORTS.lock(|reports| {
+        let mut reports = reports.borrow_mut();
+
+        let report = match reports.iter().position(|(id, _)| *id == node_id) {
+            Some(index) => &mut reports[index].1,
+            None => {
+                reports.push((node_id, LinkReport::default()));
+                &mut reports.last_mut().unwrap().1
+            }
+        };
+
+        match link {
+            Some(link) => *report = LinkReport { last: Some(link), unanswered: 0 },
+            None => report.unanswered += 1,
+        }
+    })
+}
 
 pub struct EspNowCommunicationManager<'d> {
+    pub manager: EspNowManager<'d>,
     pub sender: EspNowSender<'d>,
     pub receiver: EspNowReceiver<'d>,
-    pub peer_address: [u8; 6],
 }
 
 impl<'d> EspNowCommunicationManager<'d> {
-    pub fn new(init: &'d EspWifiController, wifi: WIFI) -> Self {
+    /// Sets up ESP-NOW with every paired node as an encrypted peer, plus the broadcast address used for pairing
+    pub fn new(init: &'d EspWifiController, wifi: WIFI, network_key: &Key, peers: &[Peer]) -> Self {
 
         let esp_now = EspNow::new(init, wifi).unwrap();
         let (manager, sender, receiver) = esp_now.split();
 
-        let peer_address = [0xf0, 0xf5, 0xbd, 0x0c, 0x0d, 0xc4]; 
-        manager.add_peer(PeerInfo {
-            peer_address,
-            lmk: None,
+        manager.set_pmk(&primary_master_key(network_key)).unwrap();
+
+        Self::set_peer(&manager, &BROADCAST_ADDRESS, None).unwrap();
+
+        for peer in peers {
+            Self::add_node(&manager, network_key, peer);
+        }
+
+        EspNowCommunicationManager { manager, sender, receiver }
+    }
+
+    /// Adds a peer, or changes the encryption of one that has been added already
+    pub fn set_peer(manager: &EspNowManager<'d>, peer_address: &[u8; 6], lmk: Option<Key>) -> Result<(), EspNowError> {
+        let peer = PeerInfo {
+            peer_address: *peer_address,
+            lmk,
             channel: None,
-            encrypt: false,
-        }).unwrap();
+            encrypt: lmk.is_some(),
+        };
 
-        EspNowCommunicationManager { sender, receiver, peer_address }
+        if manager.peer_exists(peer_address) {
+            manager.modify_peer(peer)
+        } else {
+            manager.add_peer(peer)
+        }
     }
 
-    pub async fn send_data_request(sender: &mut EspNowSender<'d>, peer_address: &[u8; 6]) {
-        let message = "REQUEST DATA";
+    /// Adds a paired node as a peer, encrypting the link with the node's local master key
+    ///
+    /// ESP-NOW only keeps a limited number of encrypted peers, nodes past that limit are reported here.
+    pub fn add_node(manager: &EspNowManager<'d>, network_key: &Key, peer: &Peer) {
+        let lmk = local_master_key(&node_key(network_key, peer.node_id));
 
-        loop {
-            match sender.send_async(peer_address, message.as_bytes()).await {
-                Ok(_) => println!("ESP-NOW data request sent successfully"),
-                Err(e) => println!("ESP-NOW data request send failed, {:?}", e),
+        if let Err(e) = Self::set_peer(manager, &peer.address, Some(lmk)) {
+            println!("Failed to add node {:04x} as an encrypted peer: {:?}", peer.node_id, e);
+        }
+    }
+
+    /// Sends a message followed by its authentication trailer
+    pub async fn send_signed(sender: &mut EspNowSender<'d>, peer_address: &[u8; 6], key: &Key, counter: u32, message: &[u8]) -> Result<(), EspNowError> {
+        let mut bytes = Vec::with_capacity(message.len() + AUTH_LEN);
+        bytes.extend_from_slice(message);
+        bytes.extend_from_slice(&sign(key, counter, message));
+
+        sender.send_async(peer_address, &bytes).await
+    }
+
+    /// Sends the commands queued for a node that it hasn't acknowledged yet
+    ///
+    /// A node that pushes may be asleep at any other time, so its commands are sent right after it pushed.
+    pub async fn send_commands(sender: &SharedSender<'d>, counter: &SharedRequestCounter, network_key: &Key, peer: &Peer) {
+        let key = node_key(network_key, peer.node_id);
+
+        for command in commands_to_send(peer.node_id) {
+            let next_counter = counter.lock().await.next_value();
+
+            let result = match next_counter {
+                Ok(command_counter) => {
+                    command_sent(peer.node_id, command, command_counter);
+                    Self::send_signed(&mut *sender.lock().await, &peer.address, &key, command_counter, &command.encode()).await.map_err(|e| format!("{:?}", e))
+                }
+                Err(e) => Result::Err(String::from(e)),
             };
 
-            Timer::after(Duration::from_secs(1)).await;
+            match result {
+                Ok(_) => println!("ESP-NOW command {:?} sent to node {:04x}", command, peer.node_id),
+                Err(e) => println!("ESP-NOW command {:?} send to node {:04x} failed, {}", command, peer.node_id, e),
+            };
         }
     }
 
-    pub async fn receive_sensor_data(&mut self) {
-        loop {
-            let data = self.receiver.receive_async().await;
+    /// Sends the commands queued for a node, then asks it for a reading, resending the request
+    /// until the node answers or `MAX_ATTEMPTS` transmissions went unanswered
+    ///
+    /// Each transmission is signed with a new counter and the response has to carry one of them,
+    /// along with `request_id`. The link quality of the exchange is logged and kept for the console.
+    pub async fn request_data(
+        sender: &SharedSender<'d>,
+        counter: &SharedRequestCounter,
+        network_key: &Key,
+        peer: &Peer,
+        request_id: u16,
+    ) -> Option<TelemetryFrame> {
+        let key = node_key(network_key, peer.node_id);
+
+        Self::send_commands(sender, counter, network_key, peer).await;
+
+        // A late answer to an earlier exchange would otherwise be taken for this one
+        while RESPONSES.try_receive().is_ok() {}
 
-            match core::str::from_utf8(data.data()) {
-                Ok(received_data) => println!("Received Air Quality Data: {}", received_data),
-                Err(_) => println!("Invalid UTF-8")
+        let started = Instant::now();
+        let mut first_request_counter = None;
+
+        for attempt in 0..MAX_ATTEMPTS {
+            let next_counter = counter.lock().await.next_value();
+
+            let request_counter = match next_counter {
+                Ok(request_counter) => request_counter,
+                Err(e) => {
+                    println!("ESP-NOW data request to node {:04x} not sent, {}", peer.node_id, e);
+                    break;
+                }
             };
+            let first_counter = *first_request_counter.get_or_insert(request_counter);
+
+            let request = DataRequest { request_id, attempt }.encode();
+
+            // A failed send only means the acknowledgement didn't arrive, the node may still have the request
+            match Self::send_signed(&mut *sender.lock().await, &peer.address, &key, request_counter, &request).await {
+                Ok(_) => println!("ESP-NOW data request {} sent to node {:04x}, attempt {}", request_id, peer.node_id, attempt + 1),
+                Err(e) => println!("ESP-NOW data request {} send to node {:04x} failed, {:?}", request_id, peer.node_id, e),
+            };
+
+            let response = with_timeout(RESPONSE_TIMEOUT, async {
+                loop {
+                    let (response, response_counter, rssi) = RESPONSES.receive().await;
+
+                    // A recorded response replayed by someone else carries the counter of an older request
+                    if response.frame.node_id == peer.node_id && response.request_id == request_id && response_counter >= first_counter {
+                        return (response.frame, rssi);
+                    }
+                }
+            }).await;
+
+            if let Ok((frame, rssi)) = response {
+                let link = LinkQuality { rssi, retries: attempt, latency: started.elapsed() };
+
+                println!(
+                    "Node {:04x} answered request {}: {} dBm, {} retries, {} ms",
+                    peer.node_id, request_id, link.rssi, link.retries, link.latency.as_millis()
+                );
+
+                record_exchange(peer.node_id, Some(link));
+                return Some(frame);
+            }
+        }
+
+        println!("Node {:04x} did not answer request {}", peer.node_id, request_id);
+        record_exchange(peer.node_id, None);
+
+        None
+    }
+
+    /// Broadcasts a `Discover`, the only message sent without an authentication trailer
+    pub async fn send_discover(sender: &mut EspNowSender<'d>) {
+        match sender.send_async(&BROADCAST_ADDRESS, &PairingMessage::Discover.encode()).await {
+            Ok(_) => println!("ESP-NOW pairing discovery sent"),
+            Err(e) => println!("ESP-NOW pairing discovery send failed, {:?}", e),
+        }
+    }
+}
+
+/// Writes the peer table to flash, outside the critical section guarding it
+fn save_peers(store: &mut PeerStore<FlashStorage>) -> Result<(), &'static str> {
+    let table = PEERS.lock(|peers| peers.borrow().clone());
+    store.save(&table)
+}
+
+/// Adds a new node to the peer table and saves it
+fn pair_node(manager: &EspNowManager<'static>, store: &mut PeerStore<FlashStorage>, peer: Peer) -> Result<(), &'static str> {
+    // The handshake runs unencrypted, the link is only encrypted once the node has been accepted
+    EspNowCommunicationManager::set_peer(manager, &peer.address, None).map_err(|_| "Failed to add ESP-NOW peer")?;
+
+    let replaced = PEERS.lock(|peers| peers.borrow_mut().add(peer))?;
+
+    // The node id moved to another board, or the board reports a new id
+    if let Some(old) = replaced.filter(|old| old.address != peer.address) {
+        let _ = manager.remove_peer(&old.address);
+    }
+
+    save_peers(store)
+}
+
+/// Runs the pairing handshake and keeps the peer table, in flash and in ESP-NOW, up to date
+#[embassy_executor::task]
+pub async fn pairing_task(
+    manager: EspNowManager<'static>,
+    sender: &'static SharedSender<'static>,
+    mut store: PeerStore<FlashStorage>,
+    network_key: Key,
+) {
+    let mut open_until: Option<Instant> = None;
+
+    // ESP-NOW sends from the station interface, whose MAC address is the base one
+    let own_address = Efuse::read_base_mac_address();
+
+    loop {
+        let event = select4(
+            PAIRING_WINDOW.wait(),
+            JOIN_REQUESTS.receive(),
+            FORGET_REQUESTS.receive(),
+            Timer::after(DISCOVER_INTERVAL),
+        ).await;
+
+        match event {
+            Either4::First(seconds) => {
+                println!("Pairing open for {} seconds", seconds);
+                open_until = Some(Instant::now() + Duration::from_secs(seconds as u64));
+            }
+            Either4::Second((address, node_id, nonce)) => {
+                let peer = Peer { node_id, address };
+                // A paired node answers over the encrypted link it already has
+                let paired = PEERS.lock(|peers| peers.borrow().by_address(&address)) == Some(peer);
+
+                if open_until.is_none() && !paired {
+                    println!("Ignored node {:04x}, pairing is closed", node_id);
+                    continue;
+                }
+
+                if !paired {
+                    match pair_node(&manager, &mut store, peer) {
+                        Ok(()) => println!("Paired node {:04x} at {:02x?}", node_id, address),
+                        Err(e) => {
+                            println!("Failed to pair node {:04x}: {}", node_id, e);
+                            continue;
+                        }
+                    }
+                }
+
+                let key = node_key(&network_key, node_id);
+                // The node only takes an Accepted that echoes its Join's nonce and names the address it came from
+                let accepted = PairingMessage::Accepted { node_id, nonce, gateway: own_address }.encode();
+
+                if let Err(e) = EspNowCommunicationManager::send_signed(&mut *sender.lock().await, &address, &key, PAIRING_COUNTER, &accepted).await {
+                    println!("Failed to confirm pairing of node {:04x}, {:?}", node_id, e);
+                }
+
+                if !paired {
+                    EspNowCommunicationManager::add_node(&manager, &network_key, &peer);
+                }
+                continue;
+            }
+            Either4::Third(node_id) => {
+                let Some(peer) = PEERS.lock(|peers| peers.borrow_mut().remove(node_id)) else {
+                    continue;
+                };
+
+                let _ = manager.remove_peer(&peer.address);
+                LINK_REPORTS.lock(|reports| reports.borrow_mut().retain(|(id, _)| *id != node_id));
+                forget_pushes(node_id);
+
+                match save_peers(&mut store) {
+                    Ok(()) => println!("Forgot node {:04x}", node_id),
+                    Err(e) => println!("Failed to save peer table: {}", e),
+                }
+                continue;
+            }
+            Either4::Fourth(()) => {}
+        }
 
+        match open_until {
+            Some(until) if Instant::now() < until => {
+                EspNowCommunicationManager::send_discover(&mut *sender.lock().await).await;
+            }
+            Some(_) => {
+                println!("Pairing closed");
+                open_until = None;
+            }
+            None => {}
         }
     }
 }
@@ -7,3 +7,9 @@ pub mod sensors;
 pub mod espnowcommunication;
 pub mod communication;
 pub mod sim808_functions;
+pub mod readingqueue;
+pub mod config;
+pub mod peertable;
+pub mod requestcounter;
+pub mod pushbuffer;
+pub mod console;
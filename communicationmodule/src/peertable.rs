@@ -0,0 +1,114 @@
+use embedded_storage::nor_flash::NorFlash;
+use embassy_sync::blocking_mutex::{ raw::CriticalSectionRawMutex, Mutex };
+
+use telemetryframe::store::RecordStore;
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Start of the `peers` partition in `partitions.csv`
+pub const PEERS_FLASH_OFFSET: u32 = 0x3F1000;
+/// Size of the `peers` partition in `partitions.csv`, a single flash sector
+pub const PEERS_FLASH_SIZE: u32 = 0x1000;
+
+/// Most sensor nodes one communication module serves, ESP-NOW itself allows 20 peers
+pub const MAX_PEERS: usize = 16;
+
+const MAGIC: [u8; 4] = *b"AQPT";
+// Entry layout: MAC address, node id
+const ENTRY_LEN: usize = 8;
+
+/// Sensor nodes paired with this communication module, shared by the main loop, the pairing task and the console
+pub static PEERS: Mutex<CriticalSectionRawMutex, RefCell<PeerTable>> = Mutex::new(RefCell::new(PeerTable::new()));
+
+/// A paired sensor node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peer {
+    pub node_id: u16,
+    pub address: [u8; 6],
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerTable {
+    peers: Vec<Peer>,
+}
+
+impl PeerTable {
+    pub const fn new() -> Self {
+        PeerTable { peers: Vec::new() }
+    }
+
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    pub fn by_address(&self, address: &[u8; 6]) -> Option<Peer> {
+        self.peers.iter().find(|peer| peer.address == *address).copied()
+    }
+
+    /// Adds a node, replacing an entry with the same node id or address, which is returned
+    ///
+    /// A node that pairs again after its board was swapped keeps its place in the polling order.
+    pub fn add(&mut self, peer: Peer) -> Result<Option<Peer>, &'static str> {
+        let existing = self.peers.iter().position(|entry| entry.node_id == peer.node_id || entry.address == peer.address);
+
+        match existing {
+            Some(index) => Ok(Some(core::mem::replace(&mut self.peers[index], peer))),
+            None if self.peers.len() >= MAX_PEERS => Result::Err("Peer table is full"),
+            None => {
+                self.peers.push(peer);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, node_id: u16) -> Option<Peer> {
+        let index = self.peers.iter().position(|peer| peer.node_id == node_id)?;
+        Some(self.peers.remove(index))
+    }
+}
+
+/// Persists the peer table in its own flash partition
+pub struct PeerStore<F: NorFlash> {
+    store: RecordStore<F>,
+}
+
+impl<F: NorFlash> PeerStore<F> {
+    pub fn new(flash: F) -> Self {
+        PeerStore { store: RecordStore::new(flash, PEERS_FLASH_OFFSET, PEERS_FLASH_SIZE, MAGIC) }
+    }
+
+    /// Reads the saved peer table, which is empty until the first node has paired
+    pub fn load(&mut self) -> PeerTable {
+        let mut entries = [0u8; MAX_PEERS * ENTRY_LEN];
+
+        let Some(entries) = self.store.load(&mut entries) else {
+            return PeerTable::new();
+        };
+
+        let peers = entries
+            .as_chunks::<ENTRY_LEN>()
+            .0
+            .iter()
+            .map(|entry| {
+                let mut address = [0u8; 6];
+                address.copy_from_slice(&entry[0..6]);
+
+                Peer { node_id: u16::from_le_bytes([entry[6], entry[7]]), address }
+            })
+            .collect();
+
+        PeerTable { peers }
+    }
+
+    pub fn save(&mut self, table: &PeerTable) -> Result<(), &'static str> {
+        let mut entries = Vec::with_capacity(table.peers.len() * ENTRY_LEN);
+
+        for peer in &table.peers {
+            entries.extend_from_slice(&peer.address);
+            entries.extend_from_slice(&peer.node_id.to_le_bytes());
+        }
+
+        self.store.save(&entries)
+    }
+}
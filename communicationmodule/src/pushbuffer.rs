@@ -0,0 +1,118 @@
+use crate::peertable::MAX_PEERS;
+
+use esp_println::println;
+use esp_storage::FlashStorage;
+
+use embassy_sync::blocking_mutex::{ raw::CriticalSectionRawMutex, Mutex };
+use embassy_time::{ Duration, Instant };
+
+use telemetryframe::{ auth::ReplayGuard, store::CounterLog, FrameError, TelemetryFrame };
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// A node that hasn't pushed for this long is polled again, e.g. after it was rebuilt without `PUSH_INTERVAL`
+const PUSH_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Start of the `pushfloors` partition in `partitions.csv`
+pub const PUSH_FLOORS_FLASH_OFFSET: u32 = 0x3F3000;
+
+/// Frames pushed by the nodes, kept until the next upload takes them
+static PUSHED: Mutex<CriticalSectionRawMutex, RefCell<Vec<PushedFrames>>> = Mutex::new(RefCell::new(Vec::new()));
+
+/// Highest push counter accepted from each node, loaded from flash on the first push
+///
+/// Push counters are the node's own, separate from the counters of this module's requests. They
+/// are logged in flash, so a recorded push stays rejected after this module restarts.
+static PUSH_FLOORS: Mutex<CriticalSectionRawMutex, RefCell<Option<CounterLog<FlashStorage, MAX_PEERS>>>> = Mutex::new(RefCell::new(None));
+
+struct PushedFrames {
+    node_id: u16,
+    received: Instant,
+    latest: Option<TelemetryFrame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PushError {
+    /// The push was already accepted, or is older than one that was
+    Replayed(FrameError),
+    /// The push counter could not be logged, so the push could be replayed after a restart
+    NotLogged(&'static str),
+}
+
+impl core::fmt::Display for PushError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PushError::Replayed(e) => write!(f, "{}", e),
+            PushError::NotLogged(e) => write!(f, "push counter not logged, {}", e),
+        }
+    }
+}
+
+/// What an upload gets from a node's pushes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pushed {
+    /// The node hasn't pushed recently, it has to be polled
+    NotPushing,
+    /// The node pushes, but nothing arrived since the last upload
+    NothingNew,
+    Frame(TelemetryFrame),
+}
+
+fn with_push_floors<R>(f: impl FnOnce(&mut CounterLog<FlashStorage, MAX_PEERS>) -> R) -> R {
+    PUSH_FLOORS.lock(|floors| {
+        let mut floors = floors.borrow_mut();
+        f(floors.get_or_insert_with(|| CounterLog::new(FlashStorage::new(), PUSH_FLOORS_FLASH_OFFSET)))
+    })
+}
+
+/// Keeps a verified frame pushed by a node, replacing any it pushed before
+///
+/// A push is only taken once its counter is in flash, otherwise it could be taken again after a restart.
+pub fn buffer_push(node_id: u16, counter: u32, frame: TelemetryFrame) -> Result<(), PushError> {
+    with_push_floors(|floors| {
+        ReplayGuard::new(floors.get(node_id)).accept(counter).map_err(PushError::Replayed)?;
+
+        floors.record(node_id, counter).map_err(PushError::NotLogged)
+    })?;
+
+    PUSHED.lock(|pushed| {
+        let mut pushed = pushed.borrow_mut();
+
+        match pushed.iter_mut().find(|entry| entry.node_id == node_id) {
+            Some(entry) => {
+                entry.received = Instant::now();
+                entry.latest = Some(frame);
+            }
+            None => pushed.push(PushedFrames { node_id, received: Instant::now(), latest: Some(frame) }),
+        }
+    });
+
+    Ok(())
+}
+
+/// Takes the latest frame a node pushed since the last call
+pub fn take_pushed(node_id: u16) -> Pushed {
+    PUSHED.lock(|pushed| {
+        let mut pushed = pushed.borrow_mut();
+
+        match pushed.iter_mut().find(|entry| entry.node_id == node_id) {
+            Some(entry) if entry.received.elapsed() <= PUSH_EXPIRY => match entry.latest.take() {
+                Some(frame) => Pushed::Frame(frame),
+                None => Pushed::NothingNew,
+            },
+            _ => Pushed::NotPushing,
+        }
+    })
+}
+
+/// Drops the pushes of a node that has been unpaired, along with its push counter
+///
+/// A node whose flash was erased starts counting again, so it has to be forgotten and paired again.
+pub fn forget_pushes(node_id: u16) {
+    PUSHED.lock(|pushed| pushed.borrow_mut().retain(|entry| entry.node_id != node_id));
+
+    if let Err(e) = with_push_floors(|floors| floors.forget(node_id)) {
+        println!("Failed to forget push counter of node {:04x}: {}", node_id, e);
+    }
+}
@@ -0,0 +1,185 @@
+use embedded_storage::nor_flash::NorFlash;
+use embassy_time::{ Duration, Instant };
+
+use telemetryframe::{ store::SlotRing, TelemetryFrame, FRAME_LEN };
+
+use alloc::{ string::String, vec::Vec };
+
+/// Start of the `queue` partition in `partitions.csv`
+pub const QUEUE_FLASH_OFFSET: u32 = 0x3D0000;
+/// Size of the `queue` partition in `partitions.csv`
+pub const QUEUE_FLASH_SIZE: u32 = 0x20000;
+
+const SLOT_SIZE: usize = 128;
+
+/// Length of an RFC 3339 timestamp with an offset, e.g. `2025-03-30T15:34:56+03:00`
+const TIMESTAMP_LEN: usize = 25;
+
+// Record layout: timestamp length and text, latitude, longitude, frame
+const LATITUDE_OFFSET: usize = 1 + TIMESTAMP_LEN;
+const LONGITUDE_OFFSET: usize = LATITUDE_OFFSET + 8;
+const FRAME_OFFSET: usize = LONGITUDE_OFFSET + 8;
+const RECORD_LEN: usize = FRAME_OFFSET + FRAME_LEN;
+
+/// A reading waiting to be uploaded, stamped with the time and place it was received
+#[derive(Debug, Clone)]
+pub struct QueuedReading {
+    pub timestamp: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub frame: TelemetryFrame,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueCounters {
+    /// Readings written to the queue since boot
+    pub queued: u32,
+    /// Readings acknowledged by the webserver since boot
+    pub sent: u32,
+    /// Readings overwritten, corrupted or never queued before they could be sent
+    pub dropped: u32,
+}
+
+/// Bounded store-and-forward queue of readings persisted in a flash ring buffer
+///
+/// When the ring wraps, the oldest readings still pending are dropped, so the queue never
+/// blocks new data.
+pub struct ReadingQueue<F: NorFlash> {
+    ring: SlotRing<F, SLOT_SIZE>,
+    queued: u32,
+    sent: u32,
+    /// Readings the webserver rejected or that never made it into the queue, on top of those the ring dropped
+    discarded: u32,
+}
+
+impl<F: NorFlash> ReadingQueue<F> {
+    /// Scans the queue partition and restores the readings left pending before a reset
+    pub fn new(flash: F) -> Result<Self, &'static str> {
+        let ring = SlotRing::new(flash, QUEUE_FLASH_OFFSET, QUEUE_FLASH_SIZE, RECORD_LEN)?;
+
+        Ok(ReadingQueue { ring, queued: 0, sent: 0, discarded: 0 })
+    }
+
+    pub fn pending(&self) -> usize {
+        self.ring.pending()
+    }
+
+    pub fn counters(&self) -> QueueCounters {
+        QueueCounters { queued: self.queued, sent: self.sent, dropped: self.ring.dropped() + self.discarded }
+    }
+
+    pub fn push(&mut self, reading: &QueuedReading) -> Result<(), &'static str> {
+        self.ring.push(&encode_record(reading))?;
+        self.queued += 1;
+
+        Ok(())
+    }
+
+    /// Returns up to `max` of the oldest pending readings without removing them
+    ///
+    /// Readings that can no longer be decoded are dropped from the queue and marked consumed in flash.
+    pub fn peek(&mut self, max: usize) -> Result<Vec<QueuedReading>, &'static str> {
+        let mut readings = Vec::new();
+
+        self.ring.peek(max, |record| match decode_record(record) {
+            Some(reading) => {
+                readings.push(reading);
+                true
+            }
+            None => false,
+        })?;
+
+        Ok(readings)
+    }
+
+    /// Marks the `count` oldest pending readings as sent
+    pub fn mark_sent(&mut self, count: usize) -> Result<(), &'static str> {
+        self.sent += self.ring.consume(count)?;
+        Ok(())
+    }
+
+    /// Counts readings lost before they could be queued as dropped
+    pub fn count_dropped(&mut self, count: u32) {
+        self.discarded += count;
+    }
+
+    /// Removes the `count` oldest pending readings without sending them
+    pub fn discard(&mut self, count: usize) -> Result<(), &'static str> {
+        self.discarded += self.ring.consume(count)?;
+        Ok(())
+    }
+}
+
+/// Exponential backoff between upload attempts while the cellular link is down
+pub struct RetryBackoff {
+    delay: Duration,
+    retry_at: Instant,
+}
+
+impl RetryBackoff {
+    const INITIAL_DELAY: Duration = Duration::from_secs(5);
+    const MAX_DELAY: Duration = Duration::from_secs(300);
+
+    pub fn new() -> Self {
+        RetryBackoff { delay: Self::INITIAL_DELAY, retry_at: Instant::now() }
+    }
+
+    pub fn ready(&self) -> bool {
+        Instant::now() >= self.retry_at
+    }
+
+    pub fn succeeded(&mut self) {
+        self.delay = Self::INITIAL_DELAY;
+        self.retry_at = Instant::now();
+    }
+
+    pub fn failed(&mut self) {
+        self.retry_at = Instant::now() + self.delay;
+        self.delay = (self.delay * 2).min(Self::MAX_DELAY);
+    }
+
+    /// Waits the longest delay after the webserver asked us to slow down
+    pub fn throttled(&mut self) {
+        self.delay = Self::MAX_DELAY;
+        self.retry_at = Instant::now() + Self::MAX_DELAY;
+    }
+}
+
+impl Default for RetryBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn encode_record(reading: &QueuedReading) -> [u8; RECORD_LEN] {
+    let mut record = [0xFFu8; RECORD_LEN];
+    let timestamp = reading.timestamp.as_bytes();
+    let timestamp_len = timestamp.len().min(TIMESTAMP_LEN);
+
+    record[0] = timestamp_len as u8;
+    record[1..1 + timestamp_len].copy_from_slice(&timestamp[..timestamp_len]);
+    record[LATITUDE_OFFSET..LONGITUDE_OFFSET].copy_from_slice(&reading.latitude.to_le_bytes());
+    record[LONGITUDE_OFFSET..FRAME_OFFSET].copy_from_slice(&reading.longitude.to_le_bytes());
+    record[FRAME_OFFSET..RECORD_LEN].copy_from_slice(&reading.frame.encode());
+
+    record
+}
+
+fn decode_record(record: &[u8]) -> Option<QueuedReading> {
+    let timestamp_len = (record[0] as usize).min(TIMESTAMP_LEN);
+    let timestamp = core::str::from_utf8(&record[1..1 + timestamp_len]).ok()?;
+    let frame = TelemetryFrame::decode(&record[FRAME_OFFSET..RECORD_LEN]).ok()?;
+
+    Some(QueuedReading {
+        timestamp: String::from(timestamp),
+        latitude: read_f64(record, LATITUDE_OFFSET),
+        longitude: read_f64(record, LONGITUDE_OFFSET),
+        frame,
+    })
+}
+
+fn read_f64(record: &[u8], offset: usize) -> f64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&record[offset..offset + 8]);
+    f64::from_le_bytes(bytes)
+}
@@ -0,0 +1,22 @@
+use embedded_storage::nor_flash::NorFlash;
+
+use telemetryframe::{ auth::PAIRING_COUNTER, store::{ RecordStore, ReservedCounter } };
+
+/// Start of the `counter` partition in `partitions.csv`
+pub const COUNTER_FLASH_OFFSET: u32 = 0x3F2000;
+/// Size of the `counter` partition in `partitions.csv`, a single flash sector
+pub const COUNTER_FLASH_SIZE: u32 = 0x1000;
+
+const MAGIC: [u8; 4] = *b"AQRC";
+
+/// Counter authenticating the requests and commands sent to the sensor nodes
+///
+/// Nodes reject counters they have already seen, so the counter must keep increasing across restarts.
+pub type RequestCounter<F> = ReservedCounter<F>;
+
+pub fn request_counter<F: NorFlash>(flash: F) -> RequestCounter<F> {
+    let store = RecordStore::new(flash, COUNTER_FLASH_OFFSET, COUNTER_FLASH_SIZE, MAGIC);
+
+    // The pairing messages' counter is never used for anything else
+    ReservedCounter::new(store, PAIRING_COUNTER + 1)
+}
@@ -6,13 +6,28 @@ use esp_hal::{
     peripheral::Peripheral,
 };
 
+use embassy_time::Duration;
+
+use atcommand::{ AtClient, AtError, Response, SIM808_URC_PREFIXES };
+
+use alloc::string::String;
+
 use core::result::Result;
 
 pub struct Sim808<'d> {
-    uart_handler: UartHandler<'d> 
+    at: AtClient<UartHandler<'d>>
 }
 
 impl<'d> Sim808<'d> {
+    /// Timeout for commands answered straight away by the modem
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Opening the GPRS bearer can take up to 85 seconds according to the SIM800 series AT manual
+    pub const BEARER_TIMEOUT: Duration = Duration::from_secs(85);
+    /// `AT+HTTPDATA` allows up to 10 seconds to send the payload after `DOWNLOAD`
+    pub const HTTP_DATA_TIMEOUT: Duration = Duration::from_secs(15);
+    /// Time allowed for the webserver to answer an HTTP request
+    pub const HTTP_ACTION_TIMEOUT: Duration = Duration::from_secs(120);
+
     pub fn new(
     uart:impl Peripheral<P = impl Instance> + 'd,
     rx:impl Peripheral<P = impl PeripheralInput> + 'd,
@@ -20,14 +35,19 @@ impl<'d> Sim808<'d> {
     baudrate: u32,) -> Result<Self, Error> {
         let uart_handler = UartHandler::new(uart, rx, tx, baudrate).unwrap();
 
-        Result::Ok(Self{ uart_handler })
+        Result::Ok(Self{ at: AtClient::new(uart_handler, SIM808_URC_PREFIXES) })
+    }
+
+    pub async fn command(&mut self, command: &str, timeout: Duration) -> Result<Response, AtError> {
+        self.at.command(command, timeout).await
     }
 
-    pub async fn send_command(&mut self, data: &[u8]) -> Result<usize, Error> {
-        self.uart_handler.write(data).await
+    /// Sends a command followed by a data phase, writing `data` once the modem answers `DOWNLOAD`
+    pub async fn command_with_data(&mut self, command: &str, data: &[u8], timeout: Duration) -> Result<Response, AtError> {
+        self.at.command_with_data(command, "DOWNLOAD", data, timeout).await
     }
 
-    pub async fn read_response(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
-        self.uart_handler.read(buffer).await
+    pub async fn wait_for_urc(&mut self, prefix: &str, timeout: Duration) -> Result<String, AtError> {
+        self.at.wait_for_urc(prefix, timeout).await
     }
 }
@@ -1,205 +1,390 @@
-use crate::sensors::{ sim808::Sim808, serial::Serial };
+use crate::sensors::sim808::Sim808;
+use crate::communication::SensorData;
+use crate::config::Config;
+use crate::readingqueue::{ QueueCounters, QueuedReading, ReadingQueue, RetryBackoff };
 use esp_hal::{
     gpio::GpioPin,
-    peripherals::{ UART0, UART1 }
+    peripherals::UART1
 };
 
 use esp_println::println;
+use esp_storage::FlashStorage;
 
-use chrono::{NaiveDateTime, Duration };
+use telemetryframe::TelemetryFrame;
 
-use alloc::{string::String, vec::Vec, format};
+use atcommand::{ AtError, http::{ http_read_body, HttpAction } };
+
+use chrono::{ FixedOffset, NaiveDateTime, Offset, SecondsFormat, TimeDelta, Utc };
+
+use embassy_time::Instant;
+
+use alloc::{string::String, vec, vec::Vec, format};
 use alloc::string::ToString;
 
+/// Formats an optional reading as a JSON value, a missing reading becomes `null`
+fn json_value<T: core::fmt::Display>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_else(|| "null".to_string())
+}
+
+/// Formats an optional floating point reading to two decimal places, a missing reading becomes `null`
+fn json_float(value: Option<f32>) -> String {
+    value.map(|value| format!("{:.2}", value)).unwrap_or_else(|| "null".to_string())
+}
+
+/// Response to an HTTP request made through the SIM808
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    /// HTTP status code, or one of the SIM808's 6xx codes when no response was received
+    pub status: u16,
+    pub body_length: usize,
+    /// Body read with `AT+HTTPREAD`, if any
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum UploadError {
+    /// The modem rejected or did not answer a command
+    Modem { step: &'static str, cause: AtError },
+    /// The GPRS bearer could not be opened
+    NoBearer,
+    /// The webserver, or the modem on its behalf, answered with a status other than 2xx
+    Http(HttpResponse),
+}
+
+impl core::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            UploadError::Modem { step, cause } => write!(f, "failed to {}: {}", step, cause),
+            UploadError::NoBearer => write!(f, "GPRS bearer is not connected"),
+            UploadError::Http(response) => write!(f, "webserver returned status {}", response.status),
+        }
+    }
+}
+
+/// Maximum number of queued readings uploaded in a single POST
+const BATCH_SIZE: usize = 10;
+
+/// Readings held in RAM while the SIM808 has had no fix since boot, the oldest are dropped beyond this
+const MAX_UNSTAMPED: usize = 64;
+
+/// Position and UTC time reported by the SIM808's GPS
+#[derive(Debug, Clone, Copy)]
+pub struct GpsFix {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub utc: NaiveDateTime,
+    /// Uptime when the fix was read, so later readings can be timed from it
+    pub taken: Instant,
+}
+
+impl GpsFix {
+    /// UTC time at `instant`, counted from the fix with the module's own clock
+    fn utc_at(&self, instant: Instant) -> NaiveDateTime {
+        let since_fix = instant.as_millis() as i64 - self.taken.as_millis() as i64;
+
+        self.utc + TimeDelta::milliseconds(since_fix)
+    }
+}
+
+/// Formats a queued reading as a JSON record, including the queue counters at upload time
+fn reading_json(reading: &QueuedReading, counters: &QueueCounters, pending: usize) -> String {
+    let sensor_data = SensorData::from(reading.frame);
+
+    format!(
+        r#"{{
+            "node_id": {},
+            "timestamp": "{}",
+            "latitude": {:.6},
+            "longitude": {:.6},
+            "temperature": {},
+            "pressure": {},
+            "humidity": {},
+            "pm1_0": {},
+            "pm2_5": {},
+            "pm10": {},
+            "co2": {},
+            "co": {},
+            "o3": {},
+            "no2": {},
+            "so2": {},
+            "queue": {{ "queued": {}, "sent": {}, "dropped": {}, "pending": {} }}
+        }}"#,
+        sensor_data.node_id, reading.timestamp, reading.latitude, reading.longitude,
+        json_float(sensor_data.temperature), json_float(sensor_data.pressure), json_float(sensor_data.humidity),
+        json_value(sensor_data.pm1_0), json_value(sensor_data.pm2_5), json_value(sensor_data.pm10),
+        json_value(sensor_data.co2), json_value(sensor_data.co), json_value(sensor_data.o3),
+        json_value(sensor_data.no2), json_value(sensor_data.so2),
+        counters.queued, counters.sent, counters.dropped, pending
+    )
+}
+
 pub struct Sim808Functions {
     pub sim808: Sim808<'static>,
-    pub serial: Serial<'static>,
+    pub backoff: RetryBackoff,
+    pub config: Config,
+    /// Latest fix, readings received while the GPS has none are stamped from it
+    last_fix: Option<GpsFix>,
+    /// Readings received before the first fix since boot, with the uptime they arrived at
+    unstamped: Vec<(TelemetryFrame, Instant)>,
 }
 
 impl Sim808Functions {
     pub fn new(
-        uart0: UART0, 
-        rx0: GpioPin<17>, 
-        tx0: GpioPin<16>, 
         uart1: UART1, 
         rx1: GpioPin<20>, 
         tx1: GpioPin<21>,
+        config: Config,
     ) -> Self {
 
-        let serial = Serial::new(uart0, rx0, tx0, 9600).unwrap();
         let sim808 = Sim808::new(uart1, rx1, tx1, 9600).unwrap();
 
-        Sim808Functions { sim808, serial }
+        Sim808Functions { sim808, backoff: RetryBackoff::new(), config, last_fix: None, unstamped: Vec::new() }
     }
 
     pub async fn config_sim808(&mut self) {
-        let mut buffer = [0u8; 64];
-
-        self.sim808.send_command("AT+SAPBR=1,1".as_bytes()).await.unwrap();
-        
-        if let Ok(bytes_read) = self.sim808.read_response(&mut buffer).await {
-            if bytes_read > 0 {
-                if buffer[..bytes_read] == *"ERROR".as_bytes() {
-                    println!("Error sending AT+SAPBR=1,1 command")
-                } else {
-                    println!("SIM808 responded with: {:?}", buffer);
-                    self.serial.send_response(&buffer).await.unwrap();
-                }
-                buffer.fill(0);
+        let commands = [
+            ("ATE0", Sim808::DEFAULT_TIMEOUT),
+            ("AT+SAPBR=1,1", Sim808::BEARER_TIMEOUT),
+            ("AT+CGNSPWR=1", Sim808::DEFAULT_TIMEOUT),
+        ];
+
+        for (command, timeout) in commands {
+            match self.sim808.command(command, timeout).await {
+                Ok(response) => println!("SIM808 responded to {} with: {:?}", command, response.lines),
+                Err(e) => println!("Error sending {} command: {}", command, e),
             }
         }
+    }
 
-        self.sim808.send_command("AT+CGNSPWR=1".as_bytes()).await.unwrap();
+    pub async fn get_fix(&mut self) -> Option<GpsFix> {
+        let response = self.sim808.command("AT+CGNSINF", Sim808::DEFAULT_TIMEOUT).await.ok()?;
 
-        if let Ok(bytes_read) = self.sim808.read_response(&mut buffer).await {
-            if bytes_read > 0 {
-                if buffer[..bytes_read] == *"ERROR".as_bytes() {
-                    println!("Error sending AT+CGNSPWR=1 command")
-                } else {
-                    println!("SIM808 responded with: {:?}", buffer);
-                    self.serial.send_response(&buffer).await.unwrap();
-                }
-                buffer.fill(0);
-            }
+        // <run status>,<fix status>,<UTC date & time>,<latitude>,<longitude>,...
+        let fields = response.fields("+CGNSINF")?;
+
+        if fields.len() < 5 || fields[1] != "1" {
+            return None;
         }
+
+        let latitude = fields[3].parse::<f64>().ok()?;
+        let longitude = fields[4].parse::<f64>().ok()?;
+
+        let utc = NaiveDateTime::parse_from_str(fields[2].get(..14)?, "%Y%m%d%H%M%S").ok()?;
+
+        Some(GpsFix { latitude, longitude, utc, taken: Instant::now() })
     }
 
-    pub async fn get_location_timestamp(&mut self) -> Option<(f64, f64, String)> {
-        self.sim808.send_command("AT+CGNSINF".as_bytes()).await.ok()?;
-        
-        let mut buffer = [0u8; 128];
+    /// RFC 3339 in the station's local time, the offset lets the webserver store it in UTC
+    fn local_timestamp(&self, utc: NaiveDateTime) -> String {
+        let offset = FixedOffset::east_opt(self.config.utc_offset_minutes * 60).unwrap_or(Utc.fix());
 
-        let bytes_read = self.sim808.read_response(&mut buffer).await.ok()?;
+        utc.and_utc().with_timezone(&offset).to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
 
-        let response = core::str::from_utf8(&buffer[..bytes_read]).ok()?;
+    fn queue_reading(&self, queue: &mut ReadingQueue<FlashStorage>, fix: &GpsFix, frame: TelemetryFrame, received: Instant) {
+        let reading = QueuedReading {
+            timestamp: self.local_timestamp(fix.utc_at(received)),
+            latitude: fix.latitude,
+            longitude: fix.longitude,
+            frame,
+        };
 
-        let line = response.trim();
+        if let Err(e) = queue.push(&reading) {
+            println!("Failed to queue reading: {}", e);
+        }
+    }
 
-        if let Some(start) = line.find("+CGNSINF:") {
-            let fields: Vec<&str> = line[start..].split(',').collect();
+    /// Posts the payload, succeeding only when the webserver responds with a 2xx status
+    pub async fn send_to_webserver(&mut self, url: &str, json_payload: &str) -> Result<HttpResponse, UploadError> {
+        // Ensure GPRS context is open
+        self.sim808.command("AT+SAPBR=3,1,\"Contype\",\"GPRS\"", Sim808::DEFAULT_TIMEOUT).await
+            .map_err(|cause| UploadError::Modem { step: "configure GPRS bearer", cause })?;
 
-            if fields.len() >= 5 && fields[1] == "1" {
-                let timestamp_raw = fields[2];
+        let mut bearer_parameters = vec![format!("AT+SAPBR=3,1,\"APN\",\"{}\"", self.config.apn)];
 
-                let latitude = fields[3].parse::<f64>().ok()?;
-                let longitude = fields[4].parse::<f64>().ok()?;
-                let utc_timestamp = format!(
-                    "{}-{}-{}T{}:{}:{}Z",
-                    &timestamp_raw[0..4],
-                    &timestamp_raw[4..6],
-                    &timestamp_raw[6..8],
-                    &timestamp_raw[8..10],
-                    &timestamp_raw[10..12],
-                    &timestamp_raw[12..14]
-                );
+        if !self.config.apn_user.is_empty() {
+            bearer_parameters.push(format!("AT+SAPBR=3,1,\"USER\",\"{}\"", self.config.apn_user));
+        }
 
-                let utc_naive = NaiveDateTime::parse_from_str(&utc_timestamp, "%Y-%m-%dT%H:%M:%SZ").ok()?;
+        if !self.config.apn_password.is_empty() {
+            bearer_parameters.push(format!("AT+SAPBR=3,1,\"PWD\",\"{}\"", self.config.apn_password));
+        }
 
-                let local_time = utc_naive + Duration::hours(3); 
+        for parameter in bearer_parameters {
+            self.sim808.command(&parameter, Sim808::DEFAULT_TIMEOUT).await
+                .map_err(|cause| UploadError::Modem { step: "configure APN", cause })?;
+        }
 
-                let formatted_time = local_time.format("%Y-%m-%d %H:%M:%S").to_string();
+        // Opening a bearer that is already open returns ERROR, so only the status query below decides
+        let _ = self.sim808.command("AT+SAPBR=1,1", Sim808::BEARER_TIMEOUT).await;
 
-                return Some((latitude, longitude, formatted_time));
-            } else {
-                return None
-            }
-        } else {
-            return None
+        // +SAPBR: <cid>,<status>,<ip address>, where status 1 means connected
+        let bearer = self.sim808.command("AT+SAPBR=2,1", Sim808::DEFAULT_TIMEOUT).await
+            .map_err(|cause| UploadError::Modem { step: "query GPRS bearer", cause })?;
+
+        if bearer.fields("+SAPBR").and_then(|fields| fields.get(1).copied()) != Some("1") {
+            return Result::Err(UploadError::NoBearer);
         }
- 
+
+        // A session left open by an earlier failed upload would make AT+HTTPINIT fail
+        let _ = self.sim808.command("AT+HTTPTERM", Sim808::DEFAULT_TIMEOUT).await;
+
+        self.sim808.command("AT+HTTPINIT", Sim808::DEFAULT_TIMEOUT).await
+            .map_err(|cause| UploadError::Modem { step: "start HTTP service", cause })?;
+
+        let result = self.http_post(url, json_payload).await;
+
+        // End HTTP session
+        let _ = self.sim808.command("AT+HTTPTERM", Sim808::DEFAULT_TIMEOUT).await;
+
+        result
     }
 
-    pub async fn send_to_webserver(&mut self, url: &str, json_payload: &str) {
-        let mut buffer = [0u8; 128];
+    async fn http_post(&mut self, url: &str, json_payload: &str) -> Result<HttpResponse, UploadError> {
+        // Set HTTP parameters
+        let parameters = [
+            String::from("AT+HTTPPARA=\"CID\",1"),
+            format!("AT+HTTPPARA=\"URL\",\"{}\"", url),
+            String::from("AT+HTTPPARA=\"CONTENT\",\"application/json\""),
+            // Identify this node to the backend
+            format!("AT+HTTPPARA=\"USERDATA\",\"X-Device-Key: {}\"", self.config.device_key),
+        ];
+
+        for parameter in parameters {
+            self.sim808.command(&parameter, Sim808::DEFAULT_TIMEOUT).await
+                .map_err(|cause| UploadError::Modem { step: "set HTTP parameter", cause })?;
+        }
 
-        // Ensure GPRS context is open
-        self.sim808.send_command(b"AT+SAPBR=3,1,\"Contype\",\"GPRS\"").await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok();
-        buffer.fill(0);
+        // Provide data length, then send the actual payload once the modem answers DOWNLOAD
+        let data_len_cmd = format!("AT+HTTPDATA={},10000", json_payload.len());
+        self.sim808.command_with_data(&data_len_cmd, json_payload.as_bytes(), Sim808::HTTP_DATA_TIMEOUT).await
+            .map_err(|cause| UploadError::Modem { step: "upload HTTP payload", cause })?;
+
+        // Start POST, the status follows later as "+HTTPACTION: 1,200,xxx"
+        self.sim808.command("AT+HTTPACTION=1", Sim808::DEFAULT_TIMEOUT).await
+            .map_err(|cause| UploadError::Modem { step: "start HTTP POST", cause })?;
+
+        let action_line = self.sim808.wait_for_urc("+HTTPACTION:", Sim808::HTTP_ACTION_TIMEOUT).await
+            .map_err(|cause| UploadError::Modem { step: "wait for HTTP response", cause })?;
+
+        let action = HttpAction::parse(&action_line).ok_or(UploadError::Modem {
+            step: "parse HTTP response",
+            cause: AtError::Error,
+        })?;
+
+        // Read server response, the body is only informative so a failed read is not an error
+        let body = if action.length > 0 {
+            match self.sim808.command("AT+HTTPREAD", Sim808::DEFAULT_TIMEOUT).await {
+                Ok(response) => http_read_body(&response),
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        let response = HttpResponse { status: action.status, body_length: action.length, body };
 
-        self.sim808.send_command(b"AT+SAPBR=3,1,\"APN\",\"safaricom\"").await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok();
-        buffer.fill(0);
+        if action.is_success() {
+            Result::Ok(response)
+        } else {
+            Result::Err(UploadError::Http(response))
+        }
+    }
 
-        self.sim808.send_command(b"AT+SAPBR=1,1").await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok();
-        buffer.fill(0);
+    /// Queues the readings of a polling round, stamped with the current time and location, then uploads whatever is pending
+    ///
+    /// The nodes served by one communication module are close together, so they share a single GPS fix.
+    /// Without a fix the readings are stamped from the last one, timed with the module's own clock. Until
+    /// the first fix since boot they are held in RAM.
+    pub async fn send_data(&mut self, frames: &[TelemetryFrame], queue: &mut ReadingQueue<FlashStorage>) {
+        let received = Instant::now();
+
+        // Readings queued earlier are still uploaded when no node answered this round
+        if !frames.is_empty() || !self.unstamped.is_empty() {
+            match self.get_fix().await {
+                Some(fix) => self.last_fix = Some(fix),
+                None if self.last_fix.is_some() => println!("No GPS fix, stamping readings from the last one"),
+                None => println!("Failed to get location or timestamp"),
+            }
+        }
 
-        self.sim808.send_command(b"AT+SAPBR=2,1").await.unwrap(); // Query bearer status
-        self.sim808.read_response(&mut buffer).await.ok();
-        buffer.fill(0);
+        match self.last_fix {
+            Some(fix) => {
+                for (frame, received) in core::mem::take(&mut self.unstamped) {
+                    self.queue_reading(queue, &fix, frame, received);
+                }
 
-        // Start HTTP service
-        self.sim808.send_command(b"AT+HTTPINIT").await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok();
-        buffer.fill(0);
+                for &frame in frames {
+                    self.queue_reading(queue, &fix, frame, received);
+                }
+            }
+            None => {
+                self.unstamped.extend(frames.iter().map(|&frame| (frame, received)));
 
-        // Set HTTP parameters
-        let url_cmd = format!("AT+HTTPPARA=\"URL\",\"{}\"", url);
-        self.sim808.send_command(url_cmd.as_bytes()).await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok();
-        buffer.fill(0);
+                let excess = self.unstamped.len().saturating_sub(MAX_UNSTAMPED);
 
-        self.sim808.send_command(b"AT+HTTPPARA=\"CONTENT\",\"application/json\"").await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok();
-        buffer.fill(0);
+                if excess > 0 {
+                    self.unstamped.drain(..excess);
+                    queue.count_dropped(excess as u32);
+                }
 
-        // Provide data length
-        let data_len_cmd = format!("AT+HTTPDATA={},10000", json_payload.len());
-        self.sim808.send_command(data_len_cmd.as_bytes()).await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok(); // Wait for "DOWNLOAD"
-        buffer.fill(0);
-
-        // Send the actual payload
-        self.sim808.send_command(json_payload.as_bytes()).await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok(); // Wait for "OK"
-        buffer.fill(0);
-
-        // Start POST
-        self.sim808.send_command(b"AT+HTTPACTION=1").await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok(); // Wait for status like "+HTTPACTION: 1,200,xxx"
-        buffer.fill(0);
-
-        // Read server response (optional)
-        self.sim808.send_command(b"AT+HTTPREAD").await.unwrap();
-        if let Ok(read_bytes) = self.sim808.read_response(&mut buffer).await {
-            if read_bytes > 0 {
-                println!("Server response: {:?}", &buffer[..read_bytes]);
+                if !self.unstamped.is_empty() {
+                    println!("No GPS fix since boot, holding {} readings until there is one", self.unstamped.len());
+                }
             }
-        }   
-        buffer.fill(0);
+        }
 
-        // End HTTP session
-        self.sim808.send_command(b"AT+HTTPTERM").await.unwrap();
-        self.sim808.read_response(&mut buffer).await.ok();
+        self.flush_queue(queue).await;
     }
 
-    pub async fn send_data(&mut self, temperature: f32, pressure: f32, humidity: f32, pm1_0: u16, pm2_5: u16, pm10: u16, co2: u16, co: u16) {
-        if let Some((latitude, longitude, timestamp)) = self.get_location_timestamp().await {
-            let payload = format!(
-                r#"{{
-                    "timestamp": "{}",
-                    "latitude": {:.6},
-                    "longitude": {:.6},
-                    "temperature": {:.2},
-                    "pressure": {:.2},
-                    "humidity": {:.2},
-                    "pm1_0": {},
-                    "pm2_5": {},
-                    "pm10": {},
-                    "co2": {},
-                    "co": {},
-                    "o3": 0
-                }}"#,
-                timestamp, latitude, longitude,
-                temperature, pressure, humidity,
-                pm1_0, pm2_5, pm10, co2, co
-            );
-
-        // Send the payload to the server
-            self.send_to_webserver("https://airqualitymonitoring.cc/airquality", &payload).await;
-        } else {
-            println!("Failed to get location or timestamp");
+    /// Uploads pending readings in batches until the queue is empty or an upload fails
+    pub async fn flush_queue(&mut self, queue: &mut ReadingQueue<FlashStorage>) {
+        while queue.pending() > 0 && self.backoff.ready() {
+            let batch = match queue.peek(BATCH_SIZE) {
+                Ok(batch) => batch,
+                Err(e) => {
+                    println!("Failed to read queued readings: {}", e);
+                    return;
+                }
+            };
+
+            let counters = queue.counters();
+            let records: Vec<String> = batch
+                .iter()
+                .map(|reading| reading_json(reading, &counters, queue.pending()))
+                .collect();
+            let payload = format!("[{}]", records.join(","));
+
+            let url = self.config.server_url.clone();
+
+            match self.send_to_webserver(&url, &payload).await {
+                Ok(response) => {
+                    println!("Uploaded {} readings, status {}: {:?}", batch.len(), response.status, response.body);
+                    self.backoff.succeeded();
+
+                    if let Err(e) = queue.mark_sent(batch.len()) {
+                        println!("Failed to update reading queue: {}", e);
+                        return;
+                    }
+                }
+                Err(UploadError::Http(response)) if response.status == 429 => {
+                    println!("Webserver is rate limiting uploads, backing off");
+                    self.backoff.throttled();
+                }
+                Err(UploadError::Http(response)) if response.status == 400 || response.status == 413 => {
+                    // Resending a batch the webserver cannot parse would block the queue forever
+                    println!("Webserver rejected batch with status {}: {:?}", response.status, response.body);
+
+                    if let Err(e) = queue.discard(batch.len()) {
+                        println!("Failed to update reading queue: {}", e);
+                        return;
+                    }
+                }
+                Err(e) => {
+                    self.backoff.failed();
+                    println!("Upload failed, {} readings pending: {}", queue.pending(), e);
+                }
+            }
         }
     }
 
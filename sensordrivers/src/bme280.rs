@@ -0,0 +1,496 @@
+//! Bosch BME280 temperature, pressure and humidity sensor over I2C
+//!
+//! Oversampling, the IIR filter and the operating mode are set through [`Settings`]. In forced
+//! mode every measurement triggers a single conversion, in normal mode the sensor converts
+//! continuously and a measurement reads the latest result. The readings are compensated with
+//! the floating point formulas from section 4.2.3 of the datasheet.
+
+use embedded_hal_async::{ delay::DelayNs, i2c::I2c };
+
+use crate::{ registers, AirQualitySensor, SensorError, SensorHealth };
+
+/// Address with SDO pulled low
+pub const PRIMARY_ADDRESS: u8 = 0x76;
+/// Address with SDO pulled high
+pub const SECONDARY_ADDRESS: u8 = 0x77;
+
+const CHIP_ID: u8 = 0x60;
+
+const REGISTER_CALIBRATION_TP: u8 = 0x88;
+const REGISTER_CHIP_ID: u8 = 0xD0;
+const REGISTER_RESET: u8 = 0xE0;
+const REGISTER_CALIBRATION_H: u8 = 0xE1;
+const REGISTER_CTRL_HUM: u8 = 0xF2;
+const REGISTER_CTRL_MEAS: u8 = 0xF4;
+const REGISTER_CONFIG: u8 = 0xF5;
+const REGISTER_DATA: u8 = 0xF7;
+
+const SOFT_RESET: u8 = 0xB6;
+
+const MODE_SLEEP: u8 = 0b00;
+const MODE_FORCED: u8 = 0b01;
+const MODE_NORMAL: u8 = 0b11;
+
+/// Number of samples averaged for a channel, higher oversampling lowers noise but lengthens a conversion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Oversampling {
+    X1 = 1,
+    X2 = 2,
+    X4 = 3,
+    X8 = 4,
+    X16 = 5,
+}
+
+impl Oversampling {
+    pub(crate) fn samples(self) -> u32 {
+        1 << (self as u32 - 1)
+    }
+}
+
+/// IIR filter coefficient, smoothing out short disturbances of the pressure and temperature
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Off = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+    X16 = 4,
+}
+
+/// Time the sensor waits between conversions in normal mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Standby {
+    Ms0_5 = 0,
+    Ms10 = 6,
+    Ms20 = 7,
+    Ms62_5 = 1,
+    Ms125 = 2,
+    Ms250 = 3,
+    Ms500 = 4,
+    Ms1000 = 5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// A single conversion for every measurement, the sensor sleeps in between
+    Forced,
+    /// Continuous conversions separated by the standby time
+    Normal(Standby),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub temperature_oversampling: Oversampling,
+    pub pressure_oversampling: Oversampling,
+    pub humidity_oversampling: Oversampling,
+    pub filter: Filter,
+    pub mode: Mode,
+}
+
+impl Default for Settings {
+    /// The datasheet's recommended settings for weather monitoring
+    fn default() -> Self {
+        Settings {
+            temperature_oversampling: Oversampling::X1,
+            pressure_oversampling: Oversampling::X1,
+            humidity_oversampling: Oversampling::X1,
+            filter: Filter::Off,
+            mode: Mode::Forced,
+        }
+    }
+}
+
+impl Settings {
+    fn ctrl_hum(&self) -> u8 {
+        self.humidity_oversampling as u8
+    }
+
+    /// Temperature oversampling in bits 7..5, pressure oversampling in bits 4..2 and the mode
+    fn ctrl_meas(&self, mode: u8) -> u8 {
+        ((self.temperature_oversampling as u8) << 5) | ((self.pressure_oversampling as u8) << 2) | mode
+    }
+
+    /// Standby time in bits 7..5 and filter coefficient in bits 4..2
+    fn config(&self) -> u8 {
+        let standby = match self.mode {
+            Mode::Forced => 0,
+            Mode::Normal(standby) => standby as u8,
+        };
+
+        (standby << 5) | ((self.filter as u8) << 2)
+    }
+
+    /// Longest time a conversion can take, from appendix B of the datasheet
+    fn measurement_time_us(&self) -> u32 {
+        1250 + 2300 * self.temperature_oversampling.samples()
+            + 2300 * self.pressure_oversampling.samples() + 575
+            + 2300 * self.humidity_oversampling.samples() + 575
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvironmentMeasurement {
+    /// °C
+    pub temperature: f32,
+    /// Pa
+    pub pressure: f32,
+    /// % relative humidity
+    pub humidity: f32,
+}
+
+/// Trimming parameters programmed into each sensor at the factory
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+impl Calibration {
+    /// Parses the registers starting at 0x88 and 0xE1
+    fn parse(tp: &[u8; 26], h: &[u8; 7]) -> Self {
+        let u16_at = |index: usize| u16::from_le_bytes([tp[index], tp[index + 1]]);
+        let i16_at = |index: usize| i16::from_le_bytes([tp[index], tp[index + 1]]);
+
+        Calibration {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p1: u16_at(6),
+            p2: i16_at(8),
+            p3: i16_at(10),
+            p4: i16_at(12),
+            p5: i16_at(14),
+            p6: i16_at(16),
+            p7: i16_at(18),
+            p8: i16_at(20),
+            p9: i16_at(22),
+            h1: tp[25],
+            h2: i16::from_le_bytes([h[0], h[1]]),
+            h3: h[2],
+            // H4 and H5 are 12 bit values sharing the nibbles of 0xE5
+            h4: ((h[3] as i8 as i16) << 4) | (h[4] & 0x0F) as i16,
+            h5: ((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16,
+            h6: h[6] as i8,
+        }
+    }
+
+    /// Returns the temperature in °C and the fine temperature used by the other channels
+    fn temperature(&self, adc_t: i32) -> (f64, f64) {
+        let adc_t = adc_t as f64;
+        let t1 = self.t1 as f64;
+
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * self.t2 as f64;
+        let var2 = (adc_t / 131072.0 - t1 / 8192.0) * (adc_t / 131072.0 - t1 / 8192.0) * self.t3 as f64;
+        let t_fine = var1 + var2;
+
+        (t_fine / 5120.0, t_fine)
+    }
+
+    /// Returns the pressure in Pa
+    fn pressure(&self, adc_p: i32, t_fine: f64) -> f64 {
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * self.p6 as f64 / 32768.0;
+        var2 += var1 * self.p5 as f64 * 2.0;
+        var2 = var2 / 4.0 + self.p4 as f64 * 65536.0;
+        var1 = (self.p3 as f64 * var1 * var1 / 524288.0 + self.p2 as f64 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * self.p1 as f64;
+
+        // Avoid a division by zero on uncalibrated parts
+        if var1 == 0.0 {
+            return 0.0;
+        }
+
+        let mut pressure = 1048576.0 - adc_p as f64;
+        pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+        var1 = self.p9 as f64 * pressure * pressure / 2147483648.0;
+        var2 = pressure * self.p8 as f64 / 32768.0;
+
+        pressure + (var1 + var2 + self.p7 as f64) / 16.0
+    }
+
+    /// Returns the relative humidity in %
+    fn humidity(&self, adc_h: i32, t_fine: f64) -> f64 {
+        let mut humidity = t_fine - 76800.0;
+        humidity = (adc_h as f64 - (self.h4 as f64 * 64.0 + self.h5 as f64 / 16384.0 * humidity))
+            * (self.h2 as f64 / 65536.0
+                * (1.0 + self.h6 as f64 / 67108864.0 * humidity * (1.0 + self.h3 as f64 / 67108864.0 * humidity)));
+        humidity *= 1.0 - self.h1 as f64 * humidity / 524288.0;
+
+        humidity.clamp(0.0, 100.0)
+    }
+}
+
+pub struct Bme280<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+    settings: Settings,
+    calibration: Option<Calibration>,
+    health: SensorHealth,
+}
+
+impl<I: I2c, D: DelayNs> Bme280<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8, settings: Settings) -> Self {
+        Bme280 { i2c, delay, address, settings, calibration: None, health: SensorHealth::default() }
+    }
+
+    /// Address the sensor answered at, which may differ from the configured one after `init`
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// Changes the settings, taking effect at the next `init`
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+        self.calibration = None;
+    }
+
+    async fn read_registers(&mut self, register: u8, buffer: &mut [u8]) -> Result<(), SensorError> {
+        registers::read(&mut self.i2c, &mut self.delay, self.address, register, buffer).await
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), SensorError> {
+        registers::write(&mut self.i2c, &mut self.delay, self.address, register, value).await
+    }
+
+    /// Reads the chip ID, trying the other BME280 address if nothing answers at the configured one
+    async fn read_chip_id(&mut self) -> Result<u8, SensorError> {
+        let mut chip_id = [0u8];
+
+        if let Err(e) = self.read_registers(REGISTER_CHIP_ID, &mut chip_id).await {
+            let configured = self.address;
+            self.address = match configured {
+                PRIMARY_ADDRESS => SECONDARY_ADDRESS,
+                SECONDARY_ADDRESS => PRIMARY_ADDRESS,
+                _ => return Err(e),
+            };
+
+            if let Err(e) = self.read_registers(REGISTER_CHIP_ID, &mut chip_id).await {
+                self.address = configured;
+                return Err(e);
+            }
+        }
+
+        Ok(chip_id[0])
+    }
+
+    async fn reset_and_calibrate(&mut self) -> Result<(), SensorError> {
+        let chip_id = self.read_chip_id().await?;
+
+        if chip_id != CHIP_ID {
+            return Err(SensorError::UnknownChipId(chip_id));
+        }
+
+        self.write_register(REGISTER_RESET, SOFT_RESET).await?;
+        // Start-up time after a reset is 2 ms
+        self.delay.delay_ms(2).await;
+
+        let mut tp = [0u8; 26];
+        let mut h = [0u8; 7];
+        self.read_registers(REGISTER_CALIBRATION_TP, &mut tp).await?;
+        self.read_registers(REGISTER_CALIBRATION_H, &mut h).await?;
+
+        // config is only guaranteed to be written in sleep mode, and ctrl_hum only takes effect
+        // after a write to ctrl_meas, which also starts normal mode
+        let mode = match self.settings.mode {
+            Mode::Forced => MODE_SLEEP,
+            Mode::Normal(_) => MODE_NORMAL,
+        };
+        self.write_register(REGISTER_CTRL_HUM, self.settings.ctrl_hum()).await?;
+        self.write_register(REGISTER_CONFIG, self.settings.config()).await?;
+        self.write_register(REGISTER_CTRL_MEAS, self.settings.ctrl_meas(mode)).await?;
+
+        self.calibration = Some(Calibration::parse(&tp, &h));
+
+        Ok(())
+    }
+
+    async fn read_measurement(&mut self) -> Result<EnvironmentMeasurement, SensorError> {
+        let calibration = self.calibration.ok_or(SensorError::NotInitialized)?;
+
+        match self.settings.mode {
+            Mode::Forced => {
+                self.write_register(REGISTER_CTRL_MEAS, self.settings.ctrl_meas(MODE_FORCED)).await?;
+                self.delay.delay_us(self.settings.measurement_time_us()).await;
+            }
+            // The data registers always hold the latest conversion
+            Mode::Normal(_) => {}
+        }
+
+        let mut data = [0u8; 8];
+        self.read_registers(REGISTER_DATA, &mut data).await?;
+
+        let adc_p = ((data[0] as i32) << 12) | ((data[1] as i32) << 4) | ((data[2] as i32) >> 4);
+        let adc_t = ((data[3] as i32) << 12) | ((data[4] as i32) << 4) | ((data[5] as i32) >> 4);
+        let adc_h = ((data[6] as i32) << 8) | data[7] as i32;
+
+        let (temperature, t_fine) = calibration.temperature(adc_t);
+
+        Ok(EnvironmentMeasurement {
+            temperature: temperature as f32,
+            pressure: calibration.pressure(adc_p, t_fine) as f32,
+            humidity: calibration.humidity(adc_h, t_fine) as f32,
+        })
+    }
+}
+
+impl<I: I2c, D: DelayNs> AirQualitySensor for Bme280<I, D> {
+    type Measurement = EnvironmentMeasurement;
+
+    fn name(&self) -> &'static str {
+        "BME280"
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &["°C", "Pa", "%"]
+    }
+
+    /// Resets the sensor and reads its calibration, which every measurement depends on
+    async fn init(&mut self) -> Result<(), SensorError> {
+        let result = self.reset_and_calibrate().await;
+        self.health.record(&result);
+        result
+    }
+
+    async fn measure(&mut self) -> Result<EnvironmentMeasurement, SensorError> {
+        let result = self.read_measurement().await;
+        self.health.record(&result);
+        result
+    }
+
+    fn health(&self) -> SensorHealth {
+        self.health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{ MockDelay, MockI2c, NoDelay };
+    use embassy_futures::block_on;
+
+    /// Sensor loaded with the example trimming values from the BMP280 datasheet and typical
+    /// humidity trimming, reporting the datasheet's example raw temperature and pressure
+    fn recorded_sensor() -> MockI2c {
+        let mut i2c = MockI2c::new(PRIMARY_ADDRESS);
+
+        let tp: [i32; 12] = [27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000];
+        for (index, value) in tp.iter().enumerate() {
+            i2c.set(REGISTER_CALIBRATION_TP + 2 * index as u8, &(*value as u16).to_le_bytes());
+        }
+
+        // H1 = 75, H2 = 362, H3 = 0, H4 = 313, H5 = 50, H6 = 30
+        i2c.set(0xA1, &[75]);
+        i2c.set(REGISTER_CALIBRATION_H, &[0x6A, 0x01, 0x00, 0x13, 0x29, 0x03, 0x1E]);
+
+        i2c.set(REGISTER_CHIP_ID, &[CHIP_ID]);
+
+        // Raw pressure 415148, raw temperature 519888, raw humidity 30000
+        i2c.set(REGISTER_DATA, &[0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x75, 0x30]);
+
+        i2c
+    }
+
+    #[test]
+    fn test_measurement_is_compensated() {
+        let mut sensor = Bme280::new(recorded_sensor(), MockDelay::default(), PRIMARY_ADDRESS, Settings::default());
+
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::NotInitialized));
+
+        block_on(sensor.init()).unwrap();
+
+        let calibration = sensor.calibration.unwrap();
+        assert_eq!((calibration.h4, calibration.h5), (313, 50));
+
+        let elapsed_ns = sensor.delay.elapsed_ns;
+        let measurement = block_on(sensor.measure()).unwrap();
+
+        // Expected values from the datasheet's compensation example
+        assert!((measurement.temperature - 25.08).abs() < 0.01, "{}", measurement.temperature);
+        assert!((measurement.pressure - 100653.27).abs() < 1.0, "{}", measurement.pressure);
+        assert!((0.0..=100.0).contains(&measurement.humidity), "{}", measurement.humidity);
+
+        assert!(sensor.i2c.writes.contains(&(REGISTER_RESET, SOFT_RESET)));
+        assert_eq!(sensor.i2c.writes.last(), Some(&(REGISTER_CTRL_MEAS, 0x25)));
+        // The longest conversion time with 1x oversampling on all channels is 9.3 ms
+        assert_eq!(sensor.delay.elapsed_ns - elapsed_ns, 9_300_000);
+        assert!(sensor.health().is_healthy());
+    }
+
+    #[test]
+    fn test_settings_are_applied() {
+        let settings = Settings {
+            temperature_oversampling: Oversampling::X2,
+            pressure_oversampling: Oversampling::X16,
+            humidity_oversampling: Oversampling::X1,
+            filter: Filter::X16,
+            mode: Mode::Normal(Standby::Ms0_5),
+        };
+
+        let mut sensor = Bme280::new(recorded_sensor(), MockDelay::default(), PRIMARY_ADDRESS, settings);
+        block_on(sensor.init()).unwrap();
+
+        let writes = &sensor.i2c.writes;
+        let configuration = &writes[writes.len() - 3..];
+        assert_eq!(configuration, [(REGISTER_CTRL_HUM, 0x01), (REGISTER_CONFIG, 0x10), (REGISTER_CTRL_MEAS, 0x57)]);
+
+        // In normal mode a measurement only reads the latest conversion
+        let write_count = writes.len();
+        block_on(sensor.measure()).unwrap();
+        assert_eq!(sensor.i2c.writes.len(), write_count);
+    }
+
+    #[test]
+    fn test_transient_bus_errors_are_retried() {
+        let mut i2c = recorded_sensor();
+        i2c.failures = 2;
+
+        let mut sensor = Bme280::new(i2c, NoDelay, PRIMARY_ADDRESS, Settings::default());
+        block_on(sensor.init()).unwrap();
+        assert_eq!(sensor.address(), PRIMARY_ADDRESS);
+
+        sensor.i2c.failures = 3;
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Bus));
+        assert!(block_on(sensor.measure()).is_ok());
+    }
+
+    #[test]
+    fn test_wrong_device_is_rejected() {
+        let mut i2c = recorded_sensor();
+        i2c.set(REGISTER_CHIP_ID, &[0x58]);
+
+        let mut sensor = Bme280::new(i2c, NoDelay, PRIMARY_ADDRESS, Settings::default());
+        assert_eq!(block_on(sensor.init()), Err(SensorError::UnknownChipId(0x58)));
+
+        // A sensor strapped to the other address is found there
+        let mut sensor = Bme280::new(recorded_sensor(), NoDelay, SECONDARY_ADDRESS, Settings::default());
+        block_on(sensor.init()).unwrap();
+        assert_eq!(sensor.address(), PRIMARY_ADDRESS);
+
+        let mut i2c = recorded_sensor();
+        i2c.address = 0x40;
+        let mut sensor = Bme280::new(i2c, NoDelay, PRIMARY_ADDRESS, Settings::default());
+        assert_eq!(block_on(sensor.init()), Err(SensorError::Bus));
+        assert_eq!(sensor.address(), PRIMARY_ADDRESS);
+    }
+}
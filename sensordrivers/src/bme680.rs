@@ -0,0 +1,581 @@
+//! Bosch BME680 and BME688 temperature, pressure, humidity and gas sensors over I2C
+//!
+//! Measurements are taken in forced mode. When a heater profile is set, the hot plate is
+//! heated to the target temperature after the other channels are converted and the
+//! resistance of the metal oxide layer is measured, which drops as the concentration of
+//! volatile organic compounds rises. The readings are compensated with the floating point
+//! formulas of Bosch's BME68x API. The two variants differ only in how the gas resistance
+//! is read and converted.
+
+use embedded_hal_async::{ delay::DelayNs, i2c::I2c };
+
+use crate::{ registers, AirQualitySensor, SensorError, SensorHealth };
+
+pub use crate::bme280::{ Filter, Oversampling, PRIMARY_ADDRESS, SECONDARY_ADDRESS };
+
+const CHIP_ID: u8 = 0x61;
+
+const REGISTER_FIELD_0: u8 = 0x1D;
+const REGISTER_IDAC_HEAT_0: u8 = 0x50;
+const REGISTER_RES_HEAT_0: u8 = 0x5A;
+const REGISTER_GAS_WAIT_0: u8 = 0x64;
+const REGISTER_CTRL_GAS_0: u8 = 0x70;
+const REGISTER_CTRL_GAS_1: u8 = 0x71;
+const REGISTER_CTRL_HUM: u8 = 0x72;
+const REGISTER_CTRL_MEAS: u8 = 0x74;
+const REGISTER_CONFIG: u8 = 0x75;
+const REGISTER_CHIP_ID: u8 = 0xD0;
+const REGISTER_RESET: u8 = 0xE0;
+const REGISTER_VARIANT_ID: u8 = 0xF0;
+const REGISTER_CALIBRATION_1: u8 = 0x8A;
+const REGISTER_CALIBRATION_2: u8 = 0xE1;
+const REGISTER_CALIBRATION_3: u8 = 0x00;
+
+const SOFT_RESET: u8 = 0xB6;
+const VARIANT_BME688: u8 = 0x01;
+
+const MODE_SLEEP: u8 = 0b00;
+const MODE_FORCED: u8 = 0b01;
+
+const HEAT_OFF: u8 = 1 << 3;
+const FIELD_LEN: usize = 17;
+const NEW_DATA: u8 = 1 << 7;
+const GAS_VALID: u8 = 1 << 5;
+const HEAT_STABLE: u8 = 1 << 4;
+
+/// Conversion factors for the BME680's gas ranges, from the BME68x API
+const GAS_RANGE_K1: [f32; 16] = [0.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, -0.8, 0.0, 0.0, -0.2, -0.5, 0.0, -1.0, 0.0, 0.0];
+const GAS_RANGE_K2: [f32; 16] = [0.0, 0.0, 0.0, 0.0, 0.1, 0.7, 0.0, -0.8, -0.1, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+
+/// Hot plate temperature and how long it is held there before the gas resistance is measured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeaterProfile {
+    /// °C, at most 400
+    pub temperature: u16,
+    pub duration_ms: u16,
+}
+
+impl Default for HeaterProfile {
+    /// The profile Bosch's examples use for indoor air quality
+    fn default() -> Self {
+        HeaterProfile { temperature: 320, duration_ms: 150 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub temperature_oversampling: Oversampling,
+    pub pressure_oversampling: Oversampling,
+    pub humidity_oversampling: Oversampling,
+    pub filter: Filter,
+    /// `None` to leave the heater off and skip the gas measurement
+    pub heater: Option<HeaterProfile>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            temperature_oversampling: Oversampling::X2,
+            pressure_oversampling: Oversampling::X4,
+            humidity_oversampling: Oversampling::X2,
+            filter: Filter::X4,
+            heater: Some(HeaterProfile::default()),
+        }
+    }
+}
+
+impl Settings {
+    fn ctrl_meas(&self, mode: u8) -> u8 {
+        ((self.temperature_oversampling as u8) << 5) | ((self.pressure_oversampling as u8) << 2) | mode
+    }
+
+    /// Time the temperature, pressure and humidity conversions take, from the BME68x API
+    fn conversion_time_us(&self) -> u32 {
+        let cycles: u32 = [self.temperature_oversampling, self.pressure_oversampling, self.humidity_oversampling]
+            .iter()
+            .map(|oversampling| oversampling.samples())
+            .sum();
+
+        // Conversions, switching between the channels, the gas measurement and waking up
+        cycles * 1963 + 477 * 4 + 477 * 5 + 1000
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Bme680,
+    Bme688,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasMeasurement {
+    /// °C
+    pub temperature: f32,
+    /// Pa
+    pub pressure: f32,
+    /// % relative humidity
+    pub humidity: f32,
+    /// Ω, `None` when the heater is off or didn't reach its target temperature in time
+    pub gas_resistance: Option<f32>,
+}
+
+/// Trimming parameters programmed into each sensor at the factory
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i8,
+    p1: u16,
+    p2: i16,
+    p3: i8,
+    p4: i16,
+    p5: i16,
+    p6: i8,
+    p7: i8,
+    p8: i16,
+    p9: i16,
+    p10: u8,
+    h1: u16,
+    h2: u16,
+    h3: i8,
+    h4: i8,
+    h5: i8,
+    h6: u8,
+    h7: i8,
+    gh1: i8,
+    gh2: i16,
+    gh3: i8,
+    res_heat_range: u8,
+    res_heat_val: i8,
+    range_switching_error: i8,
+}
+
+impl Calibration {
+    /// Parses the registers starting at 0x8A, 0xE1 and 0x00, concatenated in that order
+    fn parse(c: &[u8; 42]) -> Self {
+        let u16_at = |msb: usize, lsb: usize| u16::from_be_bytes([c[msb], c[lsb]]);
+        let i16_at = |msb: usize, lsb: usize| i16::from_be_bytes([c[msb], c[lsb]]);
+
+        Calibration {
+            t1: u16_at(32, 31),
+            t2: i16_at(1, 0),
+            t3: c[2] as i8,
+            p1: u16_at(5, 4),
+            p2: i16_at(7, 6),
+            p3: c[8] as i8,
+            p4: i16_at(11, 10),
+            p5: i16_at(13, 12),
+            p6: c[15] as i8,
+            p7: c[14] as i8,
+            p8: i16_at(19, 18),
+            p9: i16_at(21, 20),
+            p10: c[22],
+            // H1 and H2 are 12 bit values sharing the nibbles of 0xE2
+            h1: ((c[25] as u16) << 4) | (c[24] & 0x0F) as u16,
+            h2: ((c[23] as u16) << 4) | (c[24] >> 4) as u16,
+            h3: c[26] as i8,
+            h4: c[27] as i8,
+            h5: c[28] as i8,
+            h6: c[29],
+            h7: c[30] as i8,
+            gh1: c[35] as i8,
+            gh2: i16_at(34, 33),
+            gh3: c[36] as i8,
+            res_heat_range: (c[39] & 0x30) >> 4,
+            res_heat_val: c[37] as i8,
+            range_switching_error: (c[41] & 0xF0) as i8 / 16,
+        }
+    }
+
+    /// Returns the temperature in °C and the fine temperature used by the other channels
+    fn temperature(&self, adc_t: u32) -> (f32, f32) {
+        let adc_t = adc_t as f32;
+        let t1 = self.t1 as f32;
+
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * self.t2 as f32;
+        let var2 = (adc_t / 131072.0 - t1 / 8192.0) * (adc_t / 131072.0 - t1 / 8192.0) * (self.t3 as f32 * 16.0);
+        let t_fine = var1 + var2;
+
+        (t_fine / 5120.0, t_fine)
+    }
+
+    /// Returns the pressure in Pa
+    fn pressure(&self, adc_p: u32, t_fine: f32) -> f32 {
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * (self.p6 as f32 / 131072.0);
+        var2 += var1 * self.p5 as f32 * 2.0;
+        var2 = var2 / 4.0 + self.p4 as f32 * 65536.0;
+        var1 = (self.p3 as f32 * var1 * var1 / 16384.0 + self.p2 as f32 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * self.p1 as f32;
+
+        // Avoid a division by zero on uncalibrated parts
+        if var1 == 0.0 {
+            return 0.0;
+        }
+
+        let mut pressure = 1048576.0 - adc_p as f32;
+        pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+        var1 = self.p9 as f32 * pressure * pressure / 2147483648.0;
+        var2 = pressure * (self.p8 as f32 / 32768.0);
+        let var3 = (pressure / 256.0) * (pressure / 256.0) * (pressure / 256.0) * (self.p10 as f32 / 131072.0);
+
+        pressure + (var1 + var2 + var3 + self.p7 as f32 * 128.0) / 16.0
+    }
+
+    /// Returns the relative humidity in %
+    fn humidity(&self, adc_h: u16, t_fine: f32) -> f32 {
+        let temperature = t_fine / 5120.0;
+
+        let var1 = adc_h as f32 - (self.h1 as f32 * 16.0 + self.h3 as f32 / 2.0 * temperature);
+        let var2 = var1
+            * (self.h2 as f32 / 262144.0
+                * (1.0 + self.h4 as f32 / 16384.0 * temperature + self.h5 as f32 / 1048576.0 * temperature * temperature));
+        let var3 = self.h6 as f32 / 16384.0;
+        let var4 = self.h7 as f32 / 2097152.0;
+
+        (var2 + (var3 + var4 * temperature) * var2 * var2).clamp(0.0, 100.0)
+    }
+
+    /// Returns the BME680's gas resistance in Ω
+    fn gas_resistance_low(&self, adc_gas: u16, range: u8) -> f32 {
+        let var1 = 1340.0 + 5.0 * self.range_switching_error as f32;
+        let var2 = var1 * (1.0 + GAS_RANGE_K1[range as usize] / 100.0);
+        let var3 = 1.0 + GAS_RANGE_K2[range as usize] / 100.0;
+
+        1.0 / (var3 * 0.000000125 * (1u32 << range) as f32 * ((adc_gas as f32 - 512.0) / var2 + 1.0))
+    }
+
+    /// Returns the BME688's gas resistance in Ω
+    fn gas_resistance_high(&self, adc_gas: u16, range: u8) -> f32 {
+        let var1 = (262144u32 >> range) as f32;
+        let var2 = 4096.0 + (adc_gas as f32 - 512.0) * 3.0;
+
+        1000000.0 * var1 / var2
+    }
+
+    /// Value of the heater resistance register that heats the hot plate to `target` °C
+    fn heater_resistance(&self, target: u16, ambient: f32) -> u8 {
+        let target = target.min(400) as f32;
+
+        let var1 = self.gh1 as f32 / 16.0 + 49.0;
+        let var2 = (self.gh2 as f32 / 32768.0) * 0.0005 + 0.00235;
+        let var3 = self.gh3 as f32 / 1024.0;
+        let var4 = var1 * (1.0 + var2 * target);
+        let var5 = var4 + var3 * ambient;
+
+        let resistance = 3.4
+            * (var5 * (4.0 / (4.0 + self.res_heat_range as f32)) * (1.0 / (1.0 + self.res_heat_val as f32 * 0.002))
+                - 25.0);
+
+        resistance.clamp(0.0, u8::MAX as f32) as u8
+    }
+}
+
+/// Encodes a heating duration as a 6 bit value and a multiplication factor of 1, 4, 16 or 64
+fn gas_wait(duration_ms: u16) -> u8 {
+    if duration_ms >= 0xFC0 {
+        return 0xFF;
+    }
+
+    let mut duration = duration_ms;
+    let mut factor = 0;
+
+    while duration > 0x3F {
+        duration /= 4;
+        factor += 1;
+    }
+
+    duration as u8 + factor * 64
+}
+
+pub struct Bme680<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+    settings: Settings,
+    variant: Variant,
+    calibration: Option<Calibration>,
+    /// Latest temperature, used to compensate the heater for the ambient temperature
+    ambient_temperature: f32,
+    health: SensorHealth,
+}
+
+impl<I: I2c, D: DelayNs> Bme680<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8, settings: Settings) -> Self {
+        Bme680 {
+            i2c,
+            delay,
+            address,
+            settings,
+            variant: Variant::Bme680,
+            calibration: None,
+            ambient_temperature: 25.0,
+            health: SensorHealth::default(),
+        }
+    }
+
+    /// Which variant the sensor reported, only known after `init`
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    async fn read_registers(&mut self, register: u8, buffer: &mut [u8]) -> Result<(), SensorError> {
+        registers::read(&mut self.i2c, &mut self.delay, self.address, register, buffer).await
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), SensorError> {
+        registers::write(&mut self.i2c, &mut self.delay, self.address, register, value).await
+    }
+
+    async fn reset_and_calibrate(&mut self) -> Result<(), SensorError> {
+        let mut chip_id = [0u8];
+        self.read_registers(REGISTER_CHIP_ID, &mut chip_id).await?;
+
+        if chip_id[0] != CHIP_ID {
+            return Err(SensorError::UnknownChipId(chip_id[0]));
+        }
+
+        self.write_register(REGISTER_RESET, SOFT_RESET).await?;
+        // Start-up time after a reset is 2 ms
+        self.delay.delay_ms(2).await;
+
+        let mut variant = [0u8];
+        self.read_registers(REGISTER_VARIANT_ID, &mut variant).await?;
+        self.variant = if variant[0] == VARIANT_BME688 { Variant::Bme688 } else { Variant::Bme680 };
+
+        let mut calibration = [0u8; 42];
+        self.read_registers(REGISTER_CALIBRATION_1, &mut calibration[..23]).await?;
+        self.read_registers(REGISTER_CALIBRATION_2, &mut calibration[23..37]).await?;
+        self.read_registers(REGISTER_CALIBRATION_3, &mut calibration[37..]).await?;
+
+        self.write_register(REGISTER_CTRL_HUM, self.settings.humidity_oversampling as u8).await?;
+        self.write_register(REGISTER_CONFIG, (self.settings.filter as u8) << 2).await?;
+        self.write_register(REGISTER_CTRL_MEAS, self.settings.ctrl_meas(MODE_SLEEP)).await?;
+
+        self.calibration = Some(Calibration::parse(&calibration));
+
+        Ok(())
+    }
+
+    /// Sets the heater for the next conversion, recomputing its resistance for the current ambient temperature
+    async fn configure_heater(&mut self, calibration: &Calibration) -> Result<(), SensorError> {
+        match self.settings.heater {
+            Some(heater) => {
+                let resistance = calibration.heater_resistance(heater.temperature, self.ambient_temperature);
+                // The gas measurement is enabled by bit 4 on the BME680 and bit 5 on the BME688
+                let run_gas = match self.variant {
+                    Variant::Bme680 => 1 << 4,
+                    Variant::Bme688 => 1 << 5,
+                };
+
+                self.write_register(REGISTER_IDAC_HEAT_0, 0).await?;
+                self.write_register(REGISTER_RES_HEAT_0, resistance).await?;
+                self.write_register(REGISTER_GAS_WAIT_0, gas_wait(heater.duration_ms)).await?;
+                self.write_register(REGISTER_CTRL_GAS_0, 0).await?;
+                self.write_register(REGISTER_CTRL_GAS_1, run_gas).await
+            }
+            None => {
+                self.write_register(REGISTER_CTRL_GAS_0, HEAT_OFF).await?;
+                self.write_register(REGISTER_CTRL_GAS_1, 0).await
+            }
+        }
+    }
+
+    async fn read_measurement(&mut self) -> Result<GasMeasurement, SensorError> {
+        let calibration = self.calibration.ok_or(SensorError::NotInitialized)?;
+
+        self.configure_heater(&calibration).await?;
+        self.write_register(REGISTER_CTRL_MEAS, self.settings.ctrl_meas(MODE_FORCED)).await?;
+
+        self.delay.delay_us(self.settings.conversion_time_us()).await;
+        if let Some(heater) = self.settings.heater {
+            self.delay.delay_ms(heater.duration_ms as u32).await;
+        }
+
+        let mut field = [0u8; FIELD_LEN];
+        self.read_registers(REGISTER_FIELD_0, &mut field).await?;
+
+        if field[0] & NEW_DATA == 0 {
+            return Err(SensorError::Timeout);
+        }
+
+        let adc_p = ((field[2] as u32) << 12) | ((field[3] as u32) << 4) | ((field[4] as u32) >> 4);
+        let adc_t = ((field[5] as u32) << 12) | ((field[6] as u32) << 4) | ((field[7] as u32) >> 4);
+        let adc_h = u16::from_be_bytes([field[8], field[9]]);
+
+        let (temperature, t_fine) = calibration.temperature(adc_t);
+        self.ambient_temperature = temperature;
+
+        // The BME680 reports the gas resistance at 0x2A, the BME688 at 0x2C
+        let gas_offset = match self.variant {
+            Variant::Bme680 => 13,
+            Variant::Bme688 => 15,
+        };
+        let adc_gas = ((field[gas_offset] as u16) << 2) | (field[gas_offset + 1] >> 6) as u16;
+        let gas_range = field[gas_offset + 1] & 0x0F;
+        let gas_status = field[gas_offset + 1] & (GAS_VALID | HEAT_STABLE);
+
+        let gas_resistance = (self.settings.heater.is_some() && gas_status == GAS_VALID | HEAT_STABLE).then(|| {
+            match self.variant {
+                Variant::Bme680 => calibration.gas_resistance_low(adc_gas, gas_range),
+                Variant::Bme688 => calibration.gas_resistance_high(adc_gas, gas_range),
+            }
+        });
+
+        Ok(GasMeasurement {
+            temperature,
+            pressure: calibration.pressure(adc_p, t_fine),
+            humidity: calibration.humidity(adc_h, t_fine),
+            gas_resistance,
+        })
+    }
+}
+
+impl<I: I2c, D: DelayNs> AirQualitySensor for Bme680<I, D> {
+    type Measurement = GasMeasurement;
+
+    fn name(&self) -> &'static str {
+        match self.variant {
+            Variant::Bme680 => "BME680",
+            Variant::Bme688 => "BME688",
+        }
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &["°C", "Pa", "%", "Ω"]
+    }
+
+    /// Resets the sensor, detects its variant and reads its calibration
+    async fn init(&mut self) -> Result<(), SensorError> {
+        let result = self.reset_and_calibrate().await;
+        self.health.record(&result);
+        result
+    }
+
+    async fn measure(&mut self) -> Result<GasMeasurement, SensorError> {
+        let result = self.read_measurement().await;
+        self.health.record(&result);
+        result
+    }
+
+    fn health(&self) -> SensorHealth {
+        self.health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{ MockI2c, NoDelay };
+    use embassy_futures::block_on;
+
+    /// Register holding a byte of the concatenated calibration data
+    fn calibration_register(index: usize) -> u8 {
+        match index {
+            0..=22 => REGISTER_CALIBRATION_1 + index as u8,
+            23..=36 => REGISTER_CALIBRATION_2 + (index - 23) as u8,
+            _ => REGISTER_CALIBRATION_3 + (index - 37) as u8,
+        }
+    }
+
+    /// Sensor with typical trimming values, reporting raw temperature 500000, pressure 330000,
+    /// humidity 25000 and a gas reading of 300 in range 5 for the given variant
+    fn recorded_sensor(variant: Variant) -> MockI2c {
+        let mut i2c = MockI2c::new(PRIMARY_ADDRESS);
+
+        let words: [(usize, i32); 9] =
+            [(0, 26200), (4, 36500), (6, -10300), (10, 7000), (12, -150), (18, -3300), (20, -1900), (31, 26000), (33, -6000)];
+        for (index, value) in words {
+            let bytes = (value as u16).to_le_bytes();
+            i2c.set(calibration_register(index), &bytes[..1]);
+            i2c.set(calibration_register(index + 1), &bytes[1..]);
+        }
+
+        // T3, P3, P7, P6, P10, H2 MSB, H2 LSB and H1 LSB, H1 MSB, H3 to H7, GH1, GH3, heater trims
+        let bytes: [(usize, i32); 18] = [
+            (2, 3), (8, 88), (14, 40), (15, 30), (22, 30), (23, 0x3E), (24, 0x80), (25, 0x32), (26, 0), (27, 45),
+            (28, 20), (29, 120), (30, -100), (35, -30), (36, 18), (37, 40), (39, 0x10), (41, 0xF0),
+        ];
+        for (index, value) in bytes {
+            i2c.set(calibration_register(index), &[value as u8]);
+        }
+
+        i2c.set(REGISTER_CHIP_ID, &[CHIP_ID]);
+        i2c.set(REGISTER_VARIANT_ID, &[if variant == Variant::Bme688 { VARIANT_BME688 } else { 0 }]);
+
+        let (adc_p, adc_t, adc_h, adc_gas): (u32, u32, u16, u16) = (330_000, 500_000, 25_000, 300);
+        let gas = [(adc_gas >> 2) as u8, ((adc_gas & 0x03) << 6) as u8 | GAS_VALID | HEAT_STABLE | 5];
+        let mut field = [0u8; FIELD_LEN];
+        field[0] = NEW_DATA;
+        field[2..5].copy_from_slice(&[(adc_p >> 12) as u8, (adc_p >> 4) as u8, (adc_p << 4) as u8]);
+        field[5..8].copy_from_slice(&[(adc_t >> 12) as u8, (adc_t >> 4) as u8, (adc_t << 4) as u8]);
+        field[8..10].copy_from_slice(&adc_h.to_be_bytes());
+        match variant {
+            Variant::Bme680 => field[13..15].copy_from_slice(&gas),
+            Variant::Bme688 => field[15..17].copy_from_slice(&gas),
+        }
+        i2c.set(REGISTER_FIELD_0, &field);
+
+        i2c
+    }
+
+    #[test]
+    fn test_measurement_is_compensated() {
+        let mut sensor = Bme680::new(recorded_sensor(Variant::Bme680), NoDelay, PRIMARY_ADDRESS, Settings::default());
+
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::NotInitialized));
+
+        block_on(sensor.init()).unwrap();
+        assert_eq!(sensor.variant(), Variant::Bme680);
+
+        let calibration = sensor.calibration.unwrap();
+        assert_eq!((calibration.h1, calibration.h2, calibration.h7), (800, 1000, -100));
+        assert_eq!((calibration.gh2, calibration.res_heat_range, calibration.range_switching_error), (-6000, 1, -1));
+
+        let measurement = block_on(sensor.measure()).unwrap();
+
+        // Expected values computed with the BME68x API's double precision formulas
+        assert!((measurement.temperature - 26.239).abs() < 0.01, "{}", measurement.temperature);
+        assert!((measurement.pressure - 104100.47).abs() < 1.0, "{}", measurement.pressure);
+        assert!((measurement.humidity - 65.995).abs() < 0.01, "{}", measurement.humidity);
+        let gas_resistance = measurement.gas_resistance.unwrap();
+        assert!((gas_resistance - 295692.96).abs() < 30.0, "{}", gas_resistance);
+
+        let writes = &sensor.i2c.writes;
+        assert!(writes.contains(&(REGISTER_RES_HEAT_0, 120)));
+        assert!(writes.contains(&(REGISTER_GAS_WAIT_0, gas_wait(150))));
+        assert!(writes.contains(&(REGISTER_CTRL_GAS_1, 1 << 4)));
+        assert_eq!(writes.last(), Some(&(REGISTER_CTRL_MEAS, Settings::default().ctrl_meas(MODE_FORCED))));
+        assert!(sensor.health().is_healthy());
+    }
+
+    #[test]
+    fn test_bme688_gas_resistance() {
+        let settings = Settings { heater: None, ..Settings::default() };
+        let mut sensor = Bme680::new(recorded_sensor(Variant::Bme688), NoDelay, PRIMARY_ADDRESS, settings);
+
+        block_on(sensor.init()).unwrap();
+        assert_eq!(sensor.name(), "BME688");
+
+        // With the heater off there is no gas measurement
+        assert_eq!(block_on(sensor.measure()).unwrap().gas_resistance, None);
+        assert!(sensor.i2c.writes.contains(&(REGISTER_CTRL_GAS_0, HEAT_OFF)));
+
+        sensor.settings.heater = Some(HeaterProfile::default());
+        let gas_resistance = block_on(sensor.measure()).unwrap().gas_resistance.unwrap();
+        assert!((gas_resistance - 2367630.0).abs() < 1.0, "{}", gas_resistance);
+        assert_eq!(sensor.i2c.writes.iter().rev().find(|(register, _)| *register == REGISTER_CTRL_GAS_1), Some(&(REGISTER_CTRL_GAS_1, 1 << 5)));
+    }
+
+    #[test]
+    fn test_gas_wait() {
+        assert_eq!(gas_wait(63), 63);
+        assert_eq!(gas_wait(100), 0x59);
+        assert_eq!(gas_wait(150), 101);
+        assert_eq!(gas_wait(5000), 0xFF);
+    }
+}
@@ -0,0 +1,216 @@
+//! DFRobot Gravity electrochemical gas sensor modules over I2C
+//!
+//! Each module carries a single electrochemical cell and speaks the 9 byte command and
+//! response protocol of Winsen's ZE series, written to and read from register 0x00. The
+//! modules are switched to passive mode so they only answer when asked for a reading, and
+//! report which gas they measure in every response, which is used to check that the module
+//! at an address is the one the node expects there.
+
+use embedded_hal_async::{ delay::DelayNs, i2c::I2c };
+
+use crate::{ AirQualitySensor, SensorError, SensorHealth };
+
+/// Address with both address switches off, the others are 0x75 to 0x77
+pub const DEFAULT_ADDRESS: u8 = 0x74;
+
+const REGISTER_DATA: u8 = 0x00;
+const FRAME_LEN: usize = 9;
+const START_BYTE: u8 = 0xFF;
+const SENSOR_NUMBER: u8 = 0x01;
+
+const COMMAND_SET_MODE: u8 = 0x78;
+const COMMAND_READ_CONCENTRATION: u8 = 0x86;
+const PASSIVE_MODE: u8 = 0x04;
+const MODE_ACCEPTED: u8 = 0x01;
+
+/// Time the module needs to process a command before its response can be read
+const RESPONSE_TIME_MS: u32 = 100;
+
+/// Gas measured by a module, as reported in its responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gas {
+    No2,
+    So2,
+}
+
+impl Gas {
+    fn code(self) -> u8 {
+        match self {
+            Gas::No2 => 0x2C,
+            Gas::So2 => 0x2B,
+        }
+    }
+}
+
+pub struct GasSensor<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+    gas: Gas,
+    health: SensorHealth,
+}
+
+impl<I: I2c, D: DelayNs> GasSensor<I, D> {
+    pub fn new(i2c: I, delay: D, address: u8, gas: Gas) -> Self {
+        GasSensor { i2c, delay, address, gas, health: SensorHealth::default() }
+    }
+
+    pub fn gas(&self) -> Gas {
+        self.gas
+    }
+
+    /// Sends a command and returns the module's response once its checksum has been verified
+    async fn send_command(&mut self, command: u8, argument: u8) -> Result<[u8; FRAME_LEN], SensorError> {
+        let mut frame = [REGISTER_DATA, START_BYTE, SENSOR_NUMBER, command, argument, 0, 0, 0, 0, 0];
+        frame[FRAME_LEN] = checksum(&frame[1..]);
+
+        self.i2c.write(self.address, &frame).await.map_err(|_| SensorError::Bus)?;
+        self.delay.delay_ms(RESPONSE_TIME_MS).await;
+
+        let mut response = [0u8; FRAME_LEN];
+        self.i2c.write_read(self.address, &[REGISTER_DATA], &mut response).await.map_err(|_| SensorError::Bus)?;
+
+        if response[0] != START_BYTE || response[1] != command {
+            return Err(SensorError::InvalidHeader);
+        }
+
+        if response[FRAME_LEN - 1] != checksum(&response) {
+            return Err(SensorError::Checksum);
+        }
+
+        Ok(response)
+    }
+
+    /// Stops the module from streaming readings on its own
+    async fn set_passive_mode(&mut self) -> Result<(), SensorError> {
+        let response = self.send_command(COMMAND_SET_MODE, PASSIVE_MODE).await?;
+
+        if response[2] != MODE_ACCEPTED {
+            return Err(SensorError::Bus);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the concentration in ppb
+    pub async fn read_concentration(&mut self) -> Result<u16, SensorError> {
+        let response = self.send_command(COMMAND_READ_CONCENTRATION, 0).await?;
+
+        // A module for another gas has been fitted at this address
+        if response[4] != self.gas.code() {
+            return Err(SensorError::UnknownChipId(response[4]));
+        }
+
+        parse_ppb(u16::from_be_bytes([response[2], response[3]]), response[5])
+    }
+}
+
+impl<I: I2c, D: DelayNs> AirQualitySensor for GasSensor<I, D> {
+    type Measurement = u16;
+
+    fn name(&self) -> &'static str {
+        match self.gas {
+            Gas::No2 => "NO2 sensor",
+            Gas::So2 => "SO2 sensor",
+        }
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &["ppb"]
+    }
+
+    /// Puts the module in passive mode and checks that it measures the expected gas
+    async fn init(&mut self) -> Result<(), SensorError> {
+        let result = match self.set_passive_mode().await {
+            Ok(()) => self.read_concentration().await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        self.health.record(&result);
+        result
+    }
+
+    async fn measure(&mut self) -> Result<u16, SensorError> {
+        let result = self.read_concentration().await;
+        self.health.record(&result);
+        result
+    }
+
+    fn health(&self) -> SensorHealth {
+        self.health
+    }
+}
+
+/// Two's complement of the sum of every byte between the start byte and the checksum
+fn checksum(frame: &[u8]) -> u8 {
+    let sum = frame[1..FRAME_LEN - 1].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    (!sum).wrapping_add(1)
+}
+
+/// Converts a concentration in ppm with the given number of decimal places to ppb
+fn parse_ppb(raw: u16, decimals: u8) -> Result<u16, SensorError> {
+    let divisor = match decimals {
+        0 => 1,
+        1 => 10,
+        2 => 100,
+        _ => return Err(SensorError::OutOfRange),
+    };
+
+    Ok((raw as u32 * 1000 / divisor).min(u16::MAX as u32) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{ MockMailbox, NoDelay };
+    use embassy_futures::block_on;
+
+    fn response(bytes: [u8; 8]) -> Vec<u8> {
+        let mut response = bytes.to_vec();
+        response.push(0);
+        response[FRAME_LEN - 1] = checksum(&response);
+        response
+    }
+
+    #[test]
+    fn test_init_and_measure() {
+        let mode = response([0xFF, COMMAND_SET_MODE, MODE_ACCEPTED, 0, 0, 0, 0, 0]);
+        // 0.21 ppm of NO2 reported with two decimal places
+        let reading = response([0xFF, COMMAND_READ_CONCENTRATION, 0x00, 0x15, 0x2C, 0x02, 0x01, 0x90]);
+
+        let i2c = MockMailbox::new(DEFAULT_ADDRESS, &[&mode, &reading, &reading]);
+        let mut sensor = GasSensor::new(i2c, NoDelay, DEFAULT_ADDRESS, Gas::No2);
+
+        block_on(sensor.init()).unwrap();
+        assert_eq!(block_on(sensor.measure()), Ok(210));
+        assert!(sensor.health().is_healthy());
+
+        let commands = &sensor.i2c.commands;
+        assert_eq!(commands[0], [0xFF, 0x01, 0x78, 0x04, 0x00, 0x00, 0x00, 0x00, 0x83]);
+        assert_eq!(commands[1], [0xFF, 0x01, 0x86, 0x00, 0x00, 0x00, 0x00, 0x00, 0x79]);
+    }
+
+    #[test]
+    fn test_invalid_responses_are_rejected() {
+        let so2 = response([0xFF, COMMAND_READ_CONCENTRATION, 0x00, 0x15, 0x2B, 0x02, 0x01, 0x90]);
+        let mut corrupted = so2.clone();
+        corrupted[3] ^= 0x01;
+        let mut wrong_command = so2.clone();
+        wrong_command[1] = COMMAND_SET_MODE;
+
+        let i2c = MockMailbox::new(DEFAULT_ADDRESS, &[&so2, &corrupted, &wrong_command]);
+        let mut sensor = GasSensor::new(i2c, NoDelay, DEFAULT_ADDRESS, Gas::No2);
+
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::UnknownChipId(0x2B)));
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Checksum));
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::InvalidHeader));
+        assert_eq!(sensor.health(), SensorHealth::Failing { consecutive_failures: 3, last_error: SensorError::InvalidHeader });
+    }
+
+    #[test]
+    fn test_parse_ppb() {
+        assert_eq!(parse_ppb(5, 0), Ok(5000));
+        assert_eq!(parse_ppb(125, 1), Ok(12_500));
+        assert_eq!(parse_ppb(2000, 0), Ok(u16::MAX));
+        assert_eq!(parse_ppb(1, 3), Err(SensorError::OutOfRange));
+    }
+}
@@ -0,0 +1,124 @@
+//! Drivers for the air quality node's sensors, generic over the `embedded-io-async` and
+//! `embedded-hal-async` traits so they can run against mock transports on the host
+//!
+//! Every driver implements [`AirQualitySensor`], which gives the node a uniform way to
+//! initialise a sensor, take a measurement and check whether it is still responding.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod bme280;
+pub mod bme680;
+pub mod electrochemical;
+pub mod mhz19b;
+pub mod mq;
+pub mod mq131;
+pub mod mq7;
+pub mod pms5003;
+
+mod registers;
+
+#[cfg(test)]
+mod mock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorError {
+    /// Reading from or writing to the bus failed
+    Bus,
+    /// A response did not start with the bytes the sensor sends
+    InvalidHeader,
+    /// A response's checksum did not match its contents
+    Checksum,
+    /// The device at the sensor's address reported an unexpected chip ID
+    UnknownChipId(u8),
+    /// A measurement was requested before the sensor was initialised
+    NotInitialized,
+    /// The raw reading lies outside the range the sensor can produce
+    OutOfRange,
+    /// A measurement wasn't finished in the time the sensor should need for it
+    Timeout,
+}
+
+impl core::fmt::Display for SensorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SensorError::Bus => write!(f, "bus error"),
+            SensorError::InvalidHeader => write!(f, "invalid response header"),
+            SensorError::Checksum => write!(f, "checksum mismatch"),
+            SensorError::UnknownChipId(id) => write!(f, "unknown chip ID 0x{:02x}", id),
+            SensorError::NotInitialized => write!(f, "sensor is not initialised"),
+            SensorError::OutOfRange => write!(f, "reading out of range"),
+            SensorError::Timeout => write!(f, "measurement timed out"),
+        }
+    }
+}
+
+/// Whether a sensor has been answering, based on the outcome of its latest operations
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SensorHealth {
+    #[default]
+    Uninitialized,
+    Healthy,
+    Failing { consecutive_failures: u32, last_error: SensorError },
+}
+
+impl SensorHealth {
+    /// Updates the health with the outcome of an initialisation or measurement
+    pub fn record<T>(&mut self, result: &Result<T, SensorError>) {
+        *self = match (result, *self) {
+            (Ok(_), _) => SensorHealth::Healthy,
+            (Err(error), SensorHealth::Failing { consecutive_failures, .. }) => SensorHealth::Failing {
+                consecutive_failures: consecutive_failures.saturating_add(1),
+                last_error: *error,
+            },
+            (Err(error), _) => SensorHealth::Failing { consecutive_failures: 1, last_error: *error },
+        };
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        *self == SensorHealth::Healthy
+    }
+}
+
+/// Common interface of the node's sensors
+#[allow(async_fn_in_trait)]
+pub trait AirQualitySensor {
+    type Measurement;
+
+    fn name(&self) -> &'static str;
+
+    /// Units of the values in a measurement, in the order they appear in it
+    fn units(&self) -> &'static [&'static str];
+
+    async fn init(&mut self) -> Result<(), SensorError>;
+
+    async fn measure(&mut self) -> Result<Self::Measurement, SensorError>;
+
+    fn health(&self) -> SensorHealth;
+}
+
+/// One-shot analog input, implemented by the board's ADC channels
+///
+/// `embedded-hal` 1.0 has no ADC trait, so drivers reading analog sensors use this one.
+pub trait AnalogInput {
+    type Error;
+
+    fn read_raw(&mut self) -> Result<u16, Self::Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_tracks_consecutive_failures() {
+        let mut health = SensorHealth::default();
+        assert_eq!(health, SensorHealth::Uninitialized);
+
+        health.record(&Err::<(), _>(SensorError::Bus));
+        health.record(&Err::<(), _>(SensorError::InvalidHeader));
+        assert_eq!(health, SensorHealth::Failing { consecutive_failures: 2, last_error: SensorError::InvalidHeader });
+
+        health.record(&Ok(()));
+        assert!(health.is_healthy());
+    }
+}
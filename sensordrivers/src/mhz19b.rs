@@ -0,0 +1,243 @@
+//! Winsen MH-Z19B NDIR CO2 sensor on its 9600 baud UART protocol
+//!
+//! Every command is a 9 byte packet starting with `0xFF 0x01` and ending with a checksum. Only
+//! the read command is answered, the calibration and configuration commands are not.
+
+use embedded_io_async::{ Read, Write };
+
+use crate::{ AirQualitySensor, SensorError, SensorHealth };
+
+const PACKET_LEN: usize = 9;
+const START: u8 = 0xFF;
+const SENSOR: u8 = 0x01;
+
+const CMD_READ_CO2: u8 = 0x86;
+const CMD_ZERO_POINT: u8 = 0x87;
+const CMD_SPAN_POINT: u8 = 0x88;
+const CMD_AUTO_BASELINE: u8 = 0x79;
+const CMD_DETECTION_RANGE: u8 = 0x99;
+
+/// Bytes read while looking for the start of a response, enough to skip a stale response
+const MAX_SEARCH_LEN: usize = 2 * PACKET_LEN;
+
+/// Detection ranges the sensor can be configured for, in ppm
+pub const DETECTION_RANGES: [u16; 3] = [2000, 5000, 10000];
+
+/// Response to the read command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Co2Measurement {
+    /// CO2 concentration in ppm
+    pub co2: u16,
+    /// Temperature of the sensor in °C, only accurate to a few degrees
+    pub temperature: i16,
+    /// Status byte, undocumented by Winsen and reported as is
+    pub status: u8,
+}
+
+/// Checksum of a packet: the two's complement of the sum of bytes 1 to 7
+fn checksum(packet: &[u8; PACKET_LEN]) -> u8 {
+    let sum = packet[1..PACKET_LEN - 1].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    (!sum).wrapping_add(1)
+}
+
+/// Builds a command packet with the given argument bytes, which start at byte 3
+fn command(cmd: u8, arguments: &[u8]) -> [u8; PACKET_LEN] {
+    let mut packet = [0u8; PACKET_LEN];
+    packet[0] = START;
+    packet[1] = SENSOR;
+    packet[2] = cmd;
+    packet[3..3 + arguments.len()].copy_from_slice(arguments);
+    packet[PACKET_LEN - 1] = checksum(&packet);
+    packet
+}
+
+pub struct Mhz19b<T> {
+    transport: T,
+    health: SensorHealth,
+}
+
+impl<T: Read + Write> Mhz19b<T> {
+    pub fn new(transport: T) -> Self {
+        Mhz19b { transport, health: SensorHealth::default() }
+    }
+
+    /// Reads the CO2 concentration, temperature and status
+    pub async fn read(&mut self) -> Result<Co2Measurement, SensorError> {
+        self.send(&command(CMD_READ_CO2, &[])).await?;
+
+        let response = self.read_response(CMD_READ_CO2).await?;
+
+        Ok(Co2Measurement {
+            co2: u16::from_be_bytes([response[2], response[3]]),
+            temperature: response[4] as i16 - 40,
+            status: response[5],
+        })
+    }
+
+    /// Reads the CO2 concentration in ppm
+    pub async fn read_co2(&mut self) -> Result<u16, SensorError> {
+        self.read().await.map(|measurement| measurement.co2)
+    }
+
+    /// Sets the current concentration as the 400 ppm zero point
+    ///
+    /// The sensor must have been in fresh outdoor air for at least 20 minutes.
+    pub async fn calibrate_zero_point(&mut self) -> Result<(), SensorError> {
+        self.send(&command(CMD_ZERO_POINT, &[])).await
+    }
+
+    /// Sets the current concentration as `span` ppm, after the zero point has been calibrated
+    pub async fn calibrate_span_point(&mut self, span: u16) -> Result<(), SensorError> {
+        if span < 1000 {
+            return Err(SensorError::OutOfRange);
+        }
+
+        self.send(&command(CMD_SPAN_POINT, &span.to_be_bytes())).await
+    }
+
+    /// Enables or disables the automatic baseline correction the sensor runs every 24 hours
+    pub async fn set_auto_baseline(&mut self, enabled: bool) -> Result<(), SensorError> {
+        let argument = if enabled { 0xA0 } else { 0x00 };
+        self.send(&command(CMD_AUTO_BASELINE, &[argument])).await
+    }
+
+    /// Sets the upper limit of the detection range, one of [`DETECTION_RANGES`]
+    pub async fn set_detection_range(&mut self, range: u16) -> Result<(), SensorError> {
+        if !DETECTION_RANGES.contains(&range) {
+            return Err(SensorError::OutOfRange);
+        }
+
+        let [high, low] = range.to_be_bytes();
+        self.send(&command(CMD_DETECTION_RANGE, &[0x00, 0x00, 0x00, high, low])).await
+    }
+
+    async fn send(&mut self, packet: &[u8; PACKET_LEN]) -> Result<(), SensorError> {
+        self.transport.write_all(packet).await.map_err(|_| SensorError::Bus)?;
+        self.transport.flush().await.map_err(|_| SensorError::Bus)
+    }
+
+    /// Skips bytes until the start of the response to `cmd`, then reads and checks the rest of it
+    async fn read_response(&mut self, cmd: u8) -> Result<[u8; PACKET_LEN], SensorError> {
+        let mut response = [0u8; PACKET_LEN];
+        let mut searched = 0;
+
+        loop {
+            if searched == MAX_SEARCH_LEN {
+                return Err(SensorError::InvalidHeader);
+            }
+
+            let mut byte = [0u8; 1];
+            self.transport.read_exact(&mut byte).await.map_err(|_| SensorError::Bus)?;
+            searched += 1;
+
+            response[0] = response[1];
+            response[1] = byte[0];
+
+            if response[0] == START && response[1] == cmd {
+                break;
+            }
+        }
+
+        self.transport.read_exact(&mut response[2..]).await.map_err(|_| SensorError::Bus)?;
+
+        if response[PACKET_LEN - 1] != checksum(&response) {
+            return Err(SensorError::Checksum);
+        }
+
+        Ok(response)
+    }
+}
+
+impl<T: Read + Write> AirQualitySensor for Mhz19b<T> {
+    type Measurement = Co2Measurement;
+
+    fn name(&self) -> &'static str {
+        "MH-Z19B"
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &["ppm", "°C", ""]
+    }
+
+    /// The sensor measures continuously from power up, so there is nothing to configure
+    async fn init(&mut self) -> Result<(), SensorError> {
+        Ok(())
+    }
+
+    async fn measure(&mut self) -> Result<Co2Measurement, SensorError> {
+        let result = self.read().await;
+        self.health.record(&result);
+        result
+    }
+
+    fn health(&self) -> SensorHealth {
+        self.health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockSerial;
+    use embassy_futures::block_on;
+
+    /// 420 ppm at 31 °C
+    const RESPONSE: [u8; PACKET_LEN] = [0xFF, 0x86, 0x01, 0xA4, 0x47, 0x00, 0x00, 0x00, 0x8E];
+
+    #[test]
+    fn test_read_co2() {
+        let mut sensor = Mhz19b::new(MockSerial::new(&RESPONSE));
+
+        assert_eq!(block_on(sensor.measure()), Ok(Co2Measurement { co2: 420, temperature: 31, status: 0 }));
+        assert_eq!(sensor.transport.written, [0xFF, 0x01, 0x86, 0x00, 0x00, 0x00, 0x00, 0x00, 0x79]);
+    }
+
+    #[test]
+    fn test_stale_bytes_before_response_are_skipped() {
+        let mut stream = vec![0x00, 0xFF, 0x79, 0x01];
+        stream.extend_from_slice(&RESPONSE);
+
+        let mut sensor = Mhz19b::new(MockSerial::new(&stream));
+
+        assert_eq!(block_on(sensor.read_co2()), Ok(420));
+    }
+
+    #[test]
+    fn test_invalid_response_is_rejected() {
+        let mut wrong_command = RESPONSE.to_vec();
+        wrong_command[1] = 0x87;
+        wrong_command.extend_from_slice(&[0x00; PACKET_LEN]);
+
+        let mut sensor = Mhz19b::new(MockSerial::new(&wrong_command));
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::InvalidHeader));
+        assert!(!sensor.health().is_healthy());
+
+        let mut corrupted = RESPONSE;
+        corrupted[3] = 0xA5;
+
+        let mut sensor = Mhz19b::new(MockSerial::new(&corrupted));
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Checksum));
+    }
+
+    #[test]
+    fn test_calibration_and_configuration_commands() {
+        let mut sensor = Mhz19b::new(MockSerial::new(&[]));
+
+        block_on(sensor.calibrate_zero_point()).unwrap();
+        block_on(sensor.calibrate_span_point(2000)).unwrap();
+        block_on(sensor.set_auto_baseline(false)).unwrap();
+        block_on(sensor.set_detection_range(5000)).unwrap();
+
+        let expected = [
+            [0xFF, 0x01, 0x87, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78],
+            [0xFF, 0x01, 0x88, 0x07, 0xD0, 0x00, 0x00, 0x00, 0xA0],
+            [0xFF, 0x01, 0x79, 0x00, 0x00, 0x00, 0x00, 0x00, 0x86],
+            [0xFF, 0x01, 0x99, 0x00, 0x00, 0x00, 0x13, 0x88, 0xCB],
+        ]
+        .concat();
+        assert_eq!(sensor.transport.written, expected);
+
+        assert_eq!(block_on(sensor.set_detection_range(3000)), Err(SensorError::OutOfRange));
+        assert_eq!(block_on(sensor.calibrate_span_point(400)), Err(SensorError::OutOfRange));
+    }
+}
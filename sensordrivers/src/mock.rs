@@ -0,0 +1,174 @@
+//! Mock transports that replay recorded sensor traffic in host tests
+
+use std::collections::VecDeque;
+
+use embedded_hal_async::{ delay::DelayNs, i2c::{ self, ErrorKind, NoAcknowledgeSource, Operation } };
+use embedded_io_async::{ ErrorType, Read, Write };
+
+use crate::AnalogInput;
+
+/// Serial port that replays a recorded byte stream and keeps everything written to it
+pub struct MockSerial {
+    incoming: VecDeque<u8>,
+    pub written: Vec<u8>,
+}
+
+impl MockSerial {
+    pub fn new(recorded: &[u8]) -> Self {
+        MockSerial { incoming: recorded.iter().copied().collect(), written: Vec::new() }
+    }
+}
+
+impl ErrorType for MockSerial {
+    type Error = core::convert::Infallible;
+}
+
+impl Read for MockSerial {
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        // Deliver the stream in small chunks like a UART FIFO would
+        let count = buffer.len().min(self.incoming.len()).min(5);
+
+        for byte in buffer.iter_mut().take(count) {
+            *byte = self.incoming.pop_front().unwrap();
+        }
+
+        Ok(count)
+    }
+}
+
+impl Write for MockSerial {
+    async fn write(&mut self, buffer: &[u8]) -> Result<usize, Self::Error> {
+        self.written.extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+}
+
+/// I2C device with an auto-incrementing register map
+pub struct MockI2c {
+    pub address: u8,
+    pub registers: [u8; 256],
+    /// Register writes in the order they were made
+    pub writes: Vec<(u8, u8)>,
+    /// Transactions left to fail before the device answers again
+    pub failures: u32,
+}
+
+impl MockI2c {
+    pub fn new(address: u8) -> Self {
+        MockI2c { address, registers: [0; 256], writes: Vec::new(), failures: 0 }
+    }
+
+    pub fn set(&mut self, register: u8, values: &[u8]) {
+        let start = register as usize;
+        self.registers[start..start + values.len()].copy_from_slice(values);
+    }
+}
+
+impl i2c::ErrorType for MockI2c {
+    type Error = ErrorKind;
+}
+
+impl i2c::I2c for MockI2c {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+        if address != self.address {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+
+        if self.failures > 0 {
+            self.failures -= 1;
+            return Err(ErrorKind::Bus);
+        }
+
+        let mut pointer = 0usize;
+
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    pointer = bytes[0] as usize;
+
+                    for &value in &bytes[1..] {
+                        self.registers[pointer] = value;
+                        self.writes.push((pointer as u8, value));
+                        pointer += 1;
+                    }
+                }
+                Operation::Read(buffer) => {
+                    buffer.copy_from_slice(&self.registers[pointer..pointer + buffer.len()]);
+                    pointer += buffer.len();
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// I2C device that takes command frames and answers each one with the next recorded response
+pub struct MockMailbox {
+    pub address: u8,
+    pub responses: VecDeque<Vec<u8>>,
+    /// Command frames in the order they were written, without the register byte
+    pub commands: Vec<Vec<u8>>,
+}
+
+impl MockMailbox {
+    pub fn new(address: u8, responses: &[&[u8]]) -> Self {
+        MockMailbox { address, responses: responses.iter().map(|response| response.to_vec()).collect(), commands: Vec::new() }
+    }
+}
+
+impl i2c::ErrorType for MockMailbox {
+    type Error = ErrorKind;
+}
+
+impl i2c::I2c for MockMailbox {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+        if address != self.address {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) if bytes.len() > 1 => self.commands.push(bytes[1..].to_vec()),
+                Operation::Write(_) => {}
+                Operation::Read(buffer) => {
+                    let response = self.responses.pop_front().ok_or(ErrorKind::Other)?;
+                    buffer.copy_from_slice(&response);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// Delay that returns immediately and keeps the total time that was requested
+#[derive(Default)]
+pub struct MockDelay {
+    pub elapsed_ns: u64,
+}
+
+impl DelayNs for MockDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.elapsed_ns += ns as u64;
+    }
+}
+
+/// ADC channel returning recorded samples, `None` for a failed conversion
+pub struct MockAdc {
+    pub samples: VecDeque<Option<u16>>,
+}
+
+impl AnalogInput for MockAdc {
+    type Error = ();
+
+    fn read_raw(&mut self) -> Result<u16, ()> {
+        self.samples.pop_front().flatten().ok_or(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Conversion shared by the Hanwei MQ gas sensors, whose resistance follows a power law of the
+//! concentration
+//!
+//! Every board has its own load resistor, divider and sensor, so the constants are kept in an
+//! [`MqCalibration`] the node can adjust and persist. Each sensor's driver provides its defaults.
+
+use libm::powf;
+
+const ADC_MAX: f32 = 4095.0;
+const V_REF: f32 = 3.3;
+const VC: f32 = 5.0; // sensor supply voltage
+
+// Conditions the datasheets' sensitivity curves were measured at
+const REFERENCE_TEMPERATURE: f32 = 20.0;
+const REFERENCE_HUMIDITY: f32 = 65.0;
+
+/// Length of an encoded [`MqCalibration`]
+pub const CALIBRATION_LEN: usize = 28;
+
+/// Board specific constants used to convert a sensor's output to a concentration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MqCalibration {
+    /// Load resistor in ohms
+    pub load_resistance: f32,
+    /// Sensor resistance at the reference point of the datasheet's curve, derived from a clean air measurement
+    pub r0: f32,
+    /// Constants of the power law `concentration = a * (Rs / R0) ^ -b` fitted to the datasheet's curve
+    ///
+    /// `b` is negative for oxidising gases such as ozone, which raise Rs rather than lower it.
+    pub a: f32,
+    pub b: f32,
+    /// Ratio of the divider between the sensor's output and the ADC pin
+    pub divider_ratio: f32,
+    /// Relative change of Rs per °C and per %RH away from 20 °C and 65 %RH, approximated from
+    /// the datasheet's temperature and humidity curves
+    pub temperature_coefficient: f32,
+    pub humidity_coefficient: f32,
+}
+
+impl MqCalibration {
+    /// Sensor resistance in ohms for an averaged ADC reading
+    ///
+    /// Returns `None` when the voltage is outside what the sensor can output, which means the
+    /// sensor or its wiring is faulty.
+    pub fn sensor_resistance(&self, reading: u16) -> Option<f32> {
+        // Voltage at the ADC pin, corrected for the divider to get the sensor's output voltage
+        let v_adc = (reading as f32 / ADC_MAX) * V_REF;
+        let v_aout = v_adc / self.divider_ratio;
+
+        if v_aout <= 0.0 || v_aout >= VC {
+            return None;
+        }
+
+        Some(self.load_resistance * (VC - v_aout) / v_aout)
+    }
+
+    /// Factor by which Rs deviates from the reference conditions at the given temperature in °C
+    /// and relative humidity in %
+    pub fn compensation(&self, temperature: f32, humidity: f32) -> f32 {
+        1.0 + self.temperature_coefficient * (temperature - REFERENCE_TEMPERATURE)
+            + self.humidity_coefficient * (humidity - REFERENCE_HUMIDITY)
+    }
+
+    /// Converts an averaged ADC reading to a concentration in the unit the curve was fitted in,
+    /// compensating for the `(temperature, humidity)` of the air when it is known
+    pub fn concentration(&self, reading: u16, environment: Option<(f32, f32)>) -> Option<u16> {
+        let rs = self.compensated_resistance(reading, environment)?;
+
+        let concentration = self.a * powf(rs / self.r0, -self.b);
+
+        Some(concentration.min(u16::MAX as f32) as u16)
+    }
+
+    /// R0 for an averaged ADC reading taken in clean air, where the sensor's datasheet gives Rs/R0 as `clean_air_ratio`
+    pub fn clean_air_r0(&self, reading: u16, environment: Option<(f32, f32)>, clean_air_ratio: f32) -> Option<f32> {
+        self.compensated_resistance(reading, environment).map(|rs| rs / clean_air_ratio)
+    }
+
+    fn compensated_resistance(&self, reading: u16, environment: Option<(f32, f32)>) -> Option<f32> {
+        let rs = self.sensor_resistance(reading)?;
+
+        let factor = environment.map_or(1.0, |(temperature, humidity)| self.compensation(temperature, humidity));
+
+        // Coefficients far beyond the datasheet's curves could flip the sign
+        (factor > 0.0).then(|| rs / factor)
+    }
+
+    pub fn encode(&self) -> [u8; CALIBRATION_LEN] {
+        let mut bytes = [0u8; CALIBRATION_LEN];
+
+        for (chunk, value) in bytes.as_chunks_mut::<4>().0.iter_mut().zip(self.values()) {
+            *chunk = value.to_le_bytes();
+        }
+
+        bytes
+    }
+
+    /// Decodes a calibration, rejecting values no board could have
+    pub fn decode(bytes: &[u8; CALIBRATION_LEN]) -> Option<Self> {
+        let mut values = [0f32; CALIBRATION_LEN / 4];
+
+        for (value, chunk) in values.iter_mut().zip(bytes.as_chunks::<4>().0) {
+            *value = f32::from_le_bytes(*chunk);
+        }
+
+        let [load_resistance, r0, a, b, divider_ratio, temperature_coefficient, humidity_coefficient] = values;
+
+        let calibration = MqCalibration {
+            load_resistance,
+            r0,
+            a,
+            b,
+            divider_ratio,
+            temperature_coefficient,
+            humidity_coefficient,
+        };
+
+        calibration.is_valid().then_some(calibration)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.values().iter().all(|value| value.is_finite())
+            && self.load_resistance > 0.0
+            && self.r0 > 0.0
+            && self.a > 0.0
+            && self.b != 0.0
+            && self.divider_ratio > 0.0
+            && self.divider_ratio <= 1.0
+    }
+
+    fn values(&self) -> [f32; CALIBRATION_LEN / 4] {
+        [
+            self.load_resistance,
+            self.r0,
+            self.a,
+            self.b,
+            self.divider_ratio,
+            self.temperature_coefficient,
+            self.humidity_coefficient,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mq7;
+
+    #[test]
+    fn test_decode_rejects_invalid_calibrations() {
+        let calibration = mq7::DEFAULT_CALIBRATION;
+        assert_eq!(MqCalibration::decode(&calibration.encode()), Some(calibration));
+
+        let mut invalid = calibration;
+        invalid.r0 = f32::NAN;
+        assert_eq!(MqCalibration::decode(&invalid.encode()), None);
+
+        invalid = calibration;
+        invalid.divider_ratio = 1.5;
+        assert_eq!(MqCalibration::decode(&invalid.encode()), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Hanwei MQ-131 ozone sensor, low concentration variant, read through an ADC channel
+//!
+//! Unlike the MQ-7, the heater runs continuously at 5 V, so a measurement is simply the
+//! average of a few samples. The sensor needs to preheat for 24 hours after it is first
+//! powered before its readings are meaningful.
+
+use crate::{ mq::MqCalibration, AirQualitySensor, AnalogInput, SensorError, SensorHealth };
+
+/// Rs/R0 in clean air, R0 being the sensor's resistance in clean air
+pub const CLEAN_AIR_RATIO: f32 = 1.0;
+
+/// Constants for the datasheet's curve, used until the board is calibrated
+///
+/// Ozone is oxidising, so unlike CO it raises the sensor resistance and `b` is negative. The
+/// datasheet gives no usable temperature and humidity curves, so no compensation is applied
+/// unless the board's calibration adds it.
+pub const DEFAULT_CALIBRATION: MqCalibration = MqCalibration {
+    load_resistance: 10_000.0,
+    r0: 2_000.0,
+    a: 9.4783,
+    b: -2.3348,
+    divider_ratio: 3.3 / (2.0 + 3.3),
+    temperature_coefficient: 0.0,
+    humidity_coefficient: 0.0,
+};
+
+/// Samples averaged for every measurement
+const SAMPLE_COUNT: u32 = 8;
+
+pub struct Mq131<ADC> {
+    adc: ADC,
+    health: SensorHealth,
+    calibration: MqCalibration,
+    environment: Option<(f32, f32)>,
+}
+
+impl<ADC: AnalogInput> Mq131<ADC> {
+    pub fn new(adc: ADC) -> Self {
+        Mq131 { adc, health: SensorHealth::default(), calibration: DEFAULT_CALIBRATION, environment: None }
+    }
+
+    pub fn calibration(&self) -> &MqCalibration {
+        &self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: MqCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// Sets the `(temperature, humidity)` of the air, used to compensate later measurements
+    pub fn set_environment(&mut self, environment: (f32, f32)) {
+        self.environment = Some(environment);
+    }
+
+    /// Averages a few raw ADC samples of the sensor's output
+    pub fn read(&mut self) -> Result<u16, SensorError> {
+        let mut sum: u32 = 0;
+
+        for _ in 0..SAMPLE_COUNT {
+            sum += self.adc.read_raw().map_err(|_| SensorError::Bus)? as u32;
+        }
+
+        Ok((sum / SAMPLE_COUNT) as u16)
+    }
+
+    /// Reads the ozone concentration in ppb
+    pub fn read_o3(&mut self) -> Result<u16, SensorError> {
+        let reading = self.read()?;
+
+        self.calibration.concentration(reading, self.environment).ok_or(SensorError::OutOfRange)
+    }
+
+    /// Derives R0 from a reading taken in clean air
+    pub fn read_clean_air_r0(&mut self) -> Result<f32, SensorError> {
+        let reading = self.read()?;
+
+        self.calibration
+            .clean_air_r0(reading, self.environment, CLEAN_AIR_RATIO)
+            .ok_or(SensorError::OutOfRange)
+    }
+}
+
+impl<ADC: AnalogInput> AirQualitySensor for Mq131<ADC> {
+    type Measurement = u16;
+
+    fn name(&self) -> &'static str {
+        "MQ-131"
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &["ppb"]
+    }
+
+    async fn init(&mut self) -> Result<(), SensorError> {
+        Ok(())
+    }
+
+    async fn measure(&mut self) -> Result<u16, SensorError> {
+        let result = self.read_o3();
+        self.health.record(&result);
+        result
+    }
+
+    fn health(&self) -> SensorHealth {
+        self.health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockAdc;
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_measure_averages_samples() {
+        let samples = [1000, 1010, 990, 1000, 1005, 995, 1000, 1000].map(Some);
+        let mut sensor = Mq131::new(MockAdc { samples: samples.into() });
+
+        assert_eq!(block_on(sensor.measure()), Ok(DEFAULT_CALIBRATION.concentration(1000, None).unwrap()));
+
+        // A failed conversion fails the whole measurement
+        let mut sensor = Mq131::new(MockAdc { samples: [Some(1000), None].into() });
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Bus));
+    }
+
+    #[test]
+    fn test_calculate_ppb() {
+        let calibration = DEFAULT_CALIBRATION;
+
+        assert_eq!(calibration.concentration(0, None), None);
+        assert_eq!(calibration.concentration(4095, None), None);
+
+        // Lower output voltages mean a higher sensor resistance and more ozone
+        let high = calibration.concentration(1000, None).unwrap();
+        let low = calibration.concentration(1500, None).unwrap();
+        assert!(low < high, "{} should be below {}", low, high);
+    }
+
+    #[test]
+    fn test_clean_air_calibration() {
+        let mut sensor = Mq131::new(MockAdc { samples: [Some(1200); 16].into() });
+
+        let r0 = sensor.read_clean_air_r0().unwrap();
+        sensor.set_calibration(MqCalibration { r0, ..DEFAULT_CALIBRATION });
+
+        // Clean air now reads as the curve's value at Rs = R0
+        assert_eq!(block_on(sensor.measure()), Ok(DEFAULT_CALIBRATION.a as u16));
+    }
+}
@@ -0,0 +1,109 @@
+//! Hanwei MQ-7 carbon monoxide sensor read through an ADC channel
+//!
+//! The sensor's heater has to alternate between 5 V and 1.4 V, which is left to the caller.
+//! This driver only samples the analog output, and an [`MqCalibration`] converts it to a
+//! concentration using the board's constants.
+
+use crate::{ mq::MqCalibration, AirQualitySensor, AnalogInput, SensorError, SensorHealth };
+
+/// Rs/R0 in clean air, read off the datasheet's sensitivity curve
+pub const CLEAN_AIR_RATIO: f32 = 27.5;
+
+/// Constants for the datasheet's curve, with R0 at 100 ppm CO, used until the board is calibrated
+pub const DEFAULT_CALIBRATION: MqCalibration = MqCalibration {
+    load_resistance: 10_000.0,
+    r0: 556.0,
+    a: 99.042,
+    b: 1.518,
+    divider_ratio: 3.3 / (2.0 + 3.3),
+    temperature_coefficient: -0.005,
+    humidity_coefficient: -0.002,
+};
+
+pub struct Mq7<ADC> {
+    adc: ADC,
+    health: SensorHealth,
+}
+
+impl<ADC: AnalogInput> Mq7<ADC> {
+    pub fn new(adc: ADC) -> Self {
+        Mq7 { adc, health: SensorHealth::default() }
+    }
+
+    /// Takes a single raw ADC sample of the sensor's output
+    pub fn read(&mut self) -> Result<u16, SensorError> {
+        self.adc.read_raw().map_err(|_| SensorError::Bus)
+    }
+}
+
+impl<ADC: AnalogInput> AirQualitySensor for Mq7<ADC> {
+    type Measurement = u16;
+
+    fn name(&self) -> &'static str {
+        "MQ-7"
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &["ADC counts"]
+    }
+
+    async fn init(&mut self) -> Result<(), SensorError> {
+        Ok(())
+    }
+
+    async fn measure(&mut self) -> Result<u16, SensorError> {
+        let result = self.read();
+        self.health.record(&result);
+        result
+    }
+
+    fn health(&self) -> SensorHealth {
+        self.health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockAdc;
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_samples_are_read_from_the_adc() {
+        let mut sensor = Mq7::new(MockAdc { samples: [Some(1850), None].into() });
+
+        assert_eq!(block_on(sensor.measure()), Ok(1850));
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Bus));
+        assert!(!sensor.health().is_healthy());
+    }
+
+    #[test]
+    fn test_calculate_ppm() {
+        let calibration = DEFAULT_CALIBRATION;
+
+        assert_eq!(calibration.concentration(0, None), None);
+        assert_eq!(calibration.concentration(4095, None), None);
+
+        // Higher output voltages mean a lower sensor resistance and more CO
+        let low = calibration.concentration(1000, None).unwrap();
+        let high = calibration.concentration(2000, None).unwrap();
+        assert!(low < high, "{} should be below {}", low, high);
+
+        // Warm, humid air lowers Rs, which would otherwise read as extra CO
+        let uncompensated = calibration.concentration(3000, None).unwrap();
+        assert!(calibration.concentration(3000, Some((35.0, 90.0))).unwrap() < uncompensated);
+        assert_eq!(calibration.concentration(3000, Some((20.0, 65.0))), Some(uncompensated));
+    }
+
+    #[test]
+    fn test_clean_air_calibration() {
+        let mut calibration = DEFAULT_CALIBRATION;
+        calibration.r0 = calibration.clean_air_r0(700, None, CLEAN_AIR_RATIO).unwrap();
+
+        // The reading used for calibration now sits on the clean air point of the curve
+        let rs = calibration.sensor_resistance(700).unwrap();
+        assert!((rs / calibration.r0 - CLEAN_AIR_RATIO).abs() < 1e-3);
+
+        assert_eq!(MqCalibration::decode(&calibration.encode()), Some(calibration));
+    }
+}
@@ -0,0 +1,432 @@
+//! Plantower PMS5003 particulate matter sensor
+//!
+//! In its default active mode the sensor streams a 32 byte frame roughly every second. In
+//! passive mode it only sends a frame when asked, and it can be put to sleep, which stops the
+//! fan and laser until it is woken again.
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{ Read, Write };
+
+use crate::{ AirQualitySensor, SensorError, SensorHealth };
+
+const FRAME_LEN: usize = 32;
+const HEADER: [u8; 2] = [0x42, 0x4D];
+/// Value of the frame length field, which counts the bytes after it
+const DATA_LEN: u16 = FRAME_LEN as u16 - 4;
+/// Bytes read while looking for a valid frame before giving up, enough to skip a partial and
+/// a corrupted frame
+const MAX_SEARCH_LEN: usize = 3 * FRAME_LEN;
+
+const CMD_READ: u8 = 0xE2;
+const CMD_MODE: u8 = 0xE1;
+const CMD_SLEEP: u8 = 0xE4;
+
+/// Time the fan needs after waking up before readings are stable, per the datasheet
+pub const DEFAULT_WARM_UP_MS: u32 = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// The sensor streams frames continuously
+    Active,
+    /// The sensor only sends a frame when one is requested
+    Passive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub mode: Mode,
+    /// Delay after waking the sensor before a reading is taken
+    pub warm_up_ms: u32,
+    /// Put the sensor to sleep after each measurement, keeping the fan off in between
+    pub sleep_between_measurements: bool,
+}
+
+/// The sensor's power up behaviour: streaming continuously and never sleeping
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { mode: Mode::Active, warm_up_ms: DEFAULT_WARM_UP_MS, sleep_between_measurements: false }
+    }
+}
+
+/// Every data field of a PMS5003 frame
+///
+/// Mass concentrations are in µg/m³, particle counts are the number of particles in 0.1 L of
+/// air with a diameter above the given size.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PmMeasurement {
+    /// Concentrations under the factory calibration conditions (CF=1)
+    pub pm1_0_cf1: u16,
+    pub pm2_5_cf1: u16,
+    pub pm10_cf1: u16,
+    /// Concentrations under atmospheric conditions, the values the node reports
+    pub pm1_0: u16,
+    pub pm2_5: u16,
+    pub pm10: u16,
+    pub particles_0_3: u16,
+    pub particles_0_5: u16,
+    pub particles_1_0: u16,
+    pub particles_2_5: u16,
+    pub particles_5_0: u16,
+    pub particles_10: u16,
+}
+
+impl PmMeasurement {
+    fn from_frame(frame: &[u8; FRAME_LEN]) -> Self {
+        let field = |index: usize| u16::from_be_bytes([frame[4 + 2 * index], frame[5 + 2 * index]]);
+
+        PmMeasurement {
+            pm1_0_cf1: field(0),
+            pm2_5_cf1: field(1),
+            pm10_cf1: field(2),
+            pm1_0: field(3),
+            pm2_5: field(4),
+            pm10: field(5),
+            particles_0_3: field(6),
+            particles_0_5: field(7),
+            particles_1_0: field(8),
+            particles_2_5: field(9),
+            particles_5_0: field(10),
+            particles_10: field(11),
+        }
+    }
+}
+
+/// Finds PMS5003 frames in an arbitrary byte stream
+///
+/// Bytes before a header are skipped, and after a frame with a bad length or checksum the
+/// parser resynchronises on the next header inside it, so a frame split across reads or
+/// preceded by a partial one is still found.
+#[derive(Debug, Clone)]
+pub struct FrameParser {
+    buffer: [u8; FRAME_LEN],
+    len: usize,
+}
+
+impl FrameParser {
+    pub fn new() -> Self {
+        FrameParser { buffer: [0; FRAME_LEN], len: 0 }
+    }
+
+    /// Drops a partially received frame
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Feeds one byte, returning the result once a complete frame has been received
+    pub fn push(&mut self, byte: u8) -> Option<Result<PmMeasurement, SensorError>> {
+        self.buffer[self.len] = byte;
+        self.len += 1;
+
+        loop {
+            if self.len >= 1 && self.buffer[0] != HEADER[0] {
+                self.shift();
+                continue;
+            }
+
+            if self.len >= 2 && self.buffer[1] != HEADER[1] {
+                self.shift();
+                continue;
+            }
+
+            if self.len >= 4 && u16::from_be_bytes([self.buffer[2], self.buffer[3]]) != DATA_LEN {
+                self.shift();
+                continue;
+            }
+
+            if self.len < FRAME_LEN {
+                return None;
+            }
+
+            let expected = u16::from_be_bytes([self.buffer[FRAME_LEN - 2], self.buffer[FRAME_LEN - 1]]);
+
+            if checksum(&self.buffer) != expected {
+                // A header may start inside the corrupted frame, so only its first byte is dropped
+                self.shift();
+                return Some(Err(SensorError::Checksum));
+            }
+
+            self.len = 0;
+            return Some(Ok(PmMeasurement::from_frame(&self.buffer)));
+        }
+    }
+
+    /// Drops the first buffered byte
+    fn shift(&mut self) {
+        self.buffer.copy_within(1..self.len, 0);
+        self.len -= 1;
+    }
+}
+
+impl Default for FrameParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sum of every byte before the checksum field
+fn checksum(frame: &[u8; FRAME_LEN]) -> u16 {
+    frame[..FRAME_LEN - 2].iter().map(|&byte| byte as u16).sum()
+}
+
+/// Builds a command frame: header, command, two data bytes and the checksum of the bytes before it
+fn command(cmd: u8, data: u16) -> [u8; 7] {
+    let [data_high, data_low] = data.to_be_bytes();
+    let mut frame = [HEADER[0], HEADER[1], cmd, data_high, data_low, 0, 0];
+
+    let sum: u16 = frame[..5].iter().map(|&byte| byte as u16).sum();
+    frame[5..].copy_from_slice(&sum.to_be_bytes());
+
+    frame
+}
+
+pub struct Pms5003<T, D> {
+    transport: T,
+    delay: D,
+    settings: Settings,
+    parser: FrameParser,
+    asleep: bool,
+    health: SensorHealth,
+}
+
+impl<T: Read + Write, D: DelayNs> Pms5003<T, D> {
+    pub fn new(transport: T, delay: D, settings: Settings) -> Self {
+        Pms5003 { transport, delay, settings, parser: FrameParser::new(), asleep: false, health: SensorHealth::default() }
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    /// Switches between streaming and on demand frames
+    ///
+    /// The sensor acknowledges the command with a short frame, which the frame parser skips.
+    pub async fn set_mode(&mut self, mode: Mode) -> Result<(), SensorError> {
+        let data = match mode {
+            Mode::Passive => 0x00,
+            Mode::Active => 0x01,
+        };
+
+        self.send(CMD_MODE, data).await?;
+        self.settings.mode = mode;
+        Ok(())
+    }
+
+    /// Stops the fan and laser until the sensor is woken
+    pub async fn sleep(&mut self) -> Result<(), SensorError> {
+        self.send(CMD_SLEEP, 0x00).await?;
+        self.asleep = true;
+        Ok(())
+    }
+
+    /// Starts the fan and waits for the warm-up period so the next reading is valid
+    pub async fn wake(&mut self) -> Result<(), SensorError> {
+        self.send(CMD_SLEEP, 0x01).await?;
+        self.asleep = false;
+
+        self.delay.delay_ms(self.settings.warm_up_ms).await;
+
+        // The sensor may come back in active mode, so the configured mode is applied again
+        self.set_mode(self.settings.mode).await
+    }
+
+    /// Reads a frame, waking the sensor first if it is asleep and requesting the frame in passive mode
+    pub async fn read_pm(&mut self) -> Result<PmMeasurement, SensorError> {
+        if self.asleep {
+            self.wake().await?;
+        }
+
+        self.transport.flush().await.map_err(|_| SensorError::Bus)?;
+
+        if self.settings.mode == Mode::Passive {
+            self.parser.reset();
+            self.send(CMD_READ, 0x00).await?;
+        }
+
+        self.read_frame().await
+    }
+
+    /// Reads the stream until a valid frame arrives
+    async fn read_frame(&mut self) -> Result<PmMeasurement, SensorError> {
+        let mut buffer = [0u8; FRAME_LEN];
+        let mut searched = 0;
+        let mut last_error = SensorError::InvalidHeader;
+
+        while searched < MAX_SEARCH_LEN {
+            let len = self.transport.read(&mut buffer).await.map_err(|_| SensorError::Bus)?;
+
+            if len == 0 {
+                return Err(SensorError::Bus);
+            }
+
+            for (position, &byte) in buffer[..len].iter().enumerate() {
+                match self.parser.push(byte) {
+                    Some(Ok(measurement)) => {
+                        // Bytes after the frame belong to the next one
+                        for &byte in &buffer[position + 1..len] {
+                            let _ = self.parser.push(byte);
+                        }
+                        return Ok(measurement);
+                    }
+                    Some(Err(error)) => last_error = error,
+                    None => {}
+                }
+            }
+
+            searched += len;
+        }
+
+        Err(last_error)
+    }
+
+    async fn send(&mut self, cmd: u8, data: u16) -> Result<(), SensorError> {
+        self.transport.write_all(&command(cmd, data)).await.map_err(|_| SensorError::Bus)?;
+        self.transport.flush().await.map_err(|_| SensorError::Bus)
+    }
+}
+
+impl<T: Read + Write, D: DelayNs> AirQualitySensor for Pms5003<T, D> {
+    type Measurement = PmMeasurement;
+
+    fn name(&self) -> &'static str {
+        "PMS5003"
+    }
+
+    fn units(&self) -> &'static [&'static str] {
+        &[
+            "µg/m³", "µg/m³", "µg/m³", "µg/m³", "µg/m³", "µg/m³",
+            "/0.1L", "/0.1L", "/0.1L", "/0.1L", "/0.1L", "/0.1L",
+        ]
+    }
+
+    /// Applies the configured mode, and puts the sensor to sleep until the first measurement
+    /// when it should sleep between measurements
+    async fn init(&mut self) -> Result<(), SensorError> {
+        let mut result = self.set_mode(self.settings.mode).await;
+
+        if result.is_ok() && self.settings.sleep_between_measurements {
+            result = self.sleep().await;
+        }
+
+        self.health.record(&result);
+        result
+    }
+
+    async fn measure(&mut self) -> Result<PmMeasurement, SensorError> {
+        let mut result = self.read_pm().await;
+
+        if self.settings.sleep_between_measurements {
+            let slept = self.sleep().await;
+            result = result.and_then(|measurement| slept.map(|_| measurement));
+        }
+
+        self.health.record(&result);
+        result
+    }
+
+    fn health(&self) -> SensorHealth {
+        self.health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{ MockDelay, MockSerial, NoDelay };
+    use embassy_futures::block_on;
+
+    /// Active mode frame reporting 5, 8 and 9 µg/m³ of PM1.0, PM2.5 and PM10
+    const RECORDED_FRAME: [u8; FRAME_LEN] = [
+        0x42, 0x4D, 0x00, 0x1C, 0x00, 0x05, 0x00, 0x08, 0x00, 0x09, 0x00, 0x05, 0x00, 0x08, 0x00, 0x09,
+        0x03, 0x6C, 0x01, 0x07, 0x00, 0x3C, 0x00, 0x06, 0x00, 0x02, 0x00, 0x01, 0x97, 0x00, 0x02, 0x2A,
+    ];
+
+    fn expected_measurement() -> PmMeasurement {
+        PmMeasurement {
+            pm1_0_cf1: 5,
+            pm2_5_cf1: 8,
+            pm10_cf1: 9,
+            pm1_0: 5,
+            pm2_5: 8,
+            pm10: 9,
+            particles_0_3: 876,
+            particles_0_5: 263,
+            particles_1_0: 60,
+            particles_2_5: 6,
+            particles_5_0: 2,
+            particles_10: 1,
+        }
+    }
+
+    #[test]
+    fn test_read_recorded_frame() {
+        let mut sensor = Pms5003::new(MockSerial::new(&RECORDED_FRAME), NoDelay, Settings::default());
+
+        let measurement = block_on(sensor.measure()).unwrap();
+
+        assert_eq!(measurement, expected_measurement());
+        assert!(sensor.health().is_healthy());
+    }
+
+    #[test]
+    fn test_parser_resynchronises_after_noise_and_corruption() {
+        let mut corrupted = RECORDED_FRAME;
+        corrupted[12] = 0xFF;
+
+        // Tail of a previous frame, a stray header byte, a corrupted frame, then a valid one
+        let mut stream = vec![0x00, 0x97, 0x42];
+        stream.extend_from_slice(&corrupted);
+        stream.extend_from_slice(&RECORDED_FRAME);
+
+        let mut parser = FrameParser::new();
+        let results: Vec<_> = stream.iter().filter_map(|&byte| parser.push(byte)).collect();
+
+        assert_eq!(results, vec![Err(SensorError::Checksum), Ok(expected_measurement())]);
+    }
+
+    #[test]
+    fn test_invalid_streams_are_rejected() {
+        let mut corrupted = RECORDED_FRAME;
+        corrupted[12] = 0xFF;
+        let stream: Vec<u8> = corrupted.iter().copied().cycle().take(MAX_SEARCH_LEN).collect();
+
+        let mut sensor = Pms5003::new(MockSerial::new(&stream), NoDelay, Settings::default());
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Checksum));
+
+        let mut sensor = Pms5003::new(MockSerial::new(&[0x00; MAX_SEARCH_LEN]), NoDelay, Settings::default());
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::InvalidHeader));
+
+        let mut sensor = Pms5003::new(MockSerial::new(&RECORDED_FRAME[..20]), NoDelay, Settings::default());
+        assert_eq!(block_on(sensor.measure()), Err(SensorError::Bus));
+        assert_eq!(sensor.health(), SensorHealth::Failing { consecutive_failures: 1, last_error: SensorError::Bus });
+    }
+
+    #[test]
+    fn test_passive_mode_sleeps_between_measurements() {
+        let settings = Settings { mode: Mode::Passive, warm_up_ms: DEFAULT_WARM_UP_MS, sleep_between_measurements: true };
+
+        // Acknowledgement of the mode command, then the requested frame
+        let mut stream = vec![0x42, 0x4D, 0x00, 0x04, 0xE1, 0x00, 0x01, 0x74];
+        stream.extend_from_slice(&RECORDED_FRAME);
+
+        let mut sensor = Pms5003::new(MockSerial::new(&stream), MockDelay::default(), settings);
+
+        block_on(sensor.init()).unwrap();
+        assert!(sensor.is_asleep());
+
+        assert_eq!(block_on(sensor.measure()), Ok(expected_measurement()));
+        assert!(sensor.is_asleep());
+        assert_eq!(sensor.delay.elapsed_ns, DEFAULT_WARM_UP_MS as u64 * 1_000_000);
+
+        let passive = [0x42, 0x4D, 0xE1, 0x00, 0x00, 0x01, 0x70];
+        let sleep = [0x42, 0x4D, 0xE4, 0x00, 0x00, 0x01, 0x73];
+        let wake = [0x42, 0x4D, 0xE4, 0x00, 0x01, 0x01, 0x74];
+        let read = [0x42, 0x4D, 0xE2, 0x00, 0x00, 0x01, 0x71];
+
+        let expected: Vec<u8> = [passive, sleep, wake, passive, read, sleep].concat();
+        assert_eq!(sensor.transport.written, expected);
+    }
+}
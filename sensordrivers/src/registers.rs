@@ -0,0 +1,53 @@
+//! Register access for the I2C sensors with an auto-incrementing register map
+//!
+//! Every transfer is retried a few times before it is reported as a bus error, so a single
+//! glitch on a long or loose I2C wire doesn't fail the whole measurement.
+
+use embedded_hal_async::{ delay::DelayNs, i2c::I2c };
+
+use crate::SensorError;
+
+/// Attempts made after a failed transfer before giving up
+const BUS_RETRIES: u32 = 2;
+const RETRY_DELAY_MS: u32 = 5;
+
+/// Reads consecutive registers starting at `register`
+pub(crate) async fn read<I: I2c, D: DelayNs>(
+    i2c: &mut I,
+    delay: &mut D,
+    address: u8,
+    register: u8,
+    buffer: &mut [u8],
+) -> Result<(), SensorError> {
+    for attempt in 0..=BUS_RETRIES {
+        if i2c.write_read(address, &[register], buffer).await.is_ok() {
+            return Ok(());
+        }
+
+        if attempt < BUS_RETRIES {
+            delay.delay_ms(RETRY_DELAY_MS).await;
+        }
+    }
+
+    Err(SensorError::Bus)
+}
+
+pub(crate) async fn write<I: I2c, D: DelayNs>(
+    i2c: &mut I,
+    delay: &mut D,
+    address: u8,
+    register: u8,
+    value: u8,
+) -> Result<(), SensorError> {
+    for attempt in 0..=BUS_RETRIES {
+        if i2c.write(address, &[register, value]).await.is_ok() {
+            return Ok(());
+        }
+
+        if attempt < BUS_RETRIES {
+            delay.delay_ms(RETRY_DELAY_MS).await;
+        }
+    }
+
+    Err(SensorError::Bus)
+}
@@ -0,0 +1,219 @@
+//! Authentication of the messages exchanged between the communication module and the sensor nodes
+//!
+//! Every message except the `Discover` broadcast is followed by a 12 byte trailer:
+//!
+//! | Offset | Size | Field                                                     |
+//! |--------|------|-----------------------------------------------------------|
+//! | 0      | 4    | Counter, little-endian                                    |
+//! | 4      | 8    | HMAC-SHA256 over the message and the counter, truncated   |
+//!
+//! The communication module numbers its requests and commands with a counter that only ever
+//! increases, so a receiver rejects any counter it has already seen. A node answers a request with
+//! the request's counter, which ties the response to that request. Frames a node pushes unasked
+//! carry a counter of the node's own, kept increasing the same way. Pairing messages use counter 0,
+//! the nonce the node picks for every `Join` ties the handshake together instead.
+//!
+//! Keys are derived from a network key known only to the communication module: each node gets its
+//! own key, derived from its node id, and the ESP-NOW primary master key (PMK) is shared by the
+//! network. The ESP-NOW local master key (LMK) of a node is derived from the node's key, so a node
+//! only needs its own key and the PMK.
+
+use sha2::{ Digest, Sha256 };
+
+use crate::FrameError;
+
+pub const KEY_LEN: usize = 16;
+pub const TAG_LEN: usize = 8;
+pub const AUTH_LEN: usize = 4 + TAG_LEN;
+
+/// Counter of pairing messages, which carry no request to be tied to
+pub const PAIRING_COUNTER: u32 = 0;
+
+pub type Key = [u8; KEY_LEN];
+
+const BLOCK_LEN: usize = 64;
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut block = [0u8; BLOCK_LEN];
+
+    if key.len() > BLOCK_LEN {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+
+    outer.finalize().into()
+}
+
+fn derive(key: &Key, label: &[u8], context: &[u8]) -> Key {
+    let mut derived = [0u8; KEY_LEN];
+    derived.copy_from_slice(&hmac_sha256(key, &[label, context])[..KEY_LEN]);
+    derived
+}
+
+/// Key a node signs its messages with and the communication module verifies them with
+pub fn node_key(network_key: &Key, node_id: u16) -> Key {
+    derive(network_key, b"AQ node key", &node_id.to_le_bytes())
+}
+
+/// ESP-NOW primary master key, the same for every device on the network
+pub fn primary_master_key(network_key: &Key) -> Key {
+    derive(network_key, b"AQ ESP-NOW PMK", &[])
+}
+
+/// ESP-NOW local master key encrypting the link to one node
+pub fn local_master_key(node_key: &Key) -> Key {
+    derive(node_key, b"AQ ESP-NOW LMK", &[])
+}
+
+/// Parses a key written as 32 hex digits
+pub fn parse_key(hex: &str) -> Option<Key> {
+    let hex = hex.as_bytes();
+
+    if hex.len() != KEY_LEN * 2 {
+        return None;
+    }
+
+    let mut key = [0u8; KEY_LEN];
+
+    for (byte, digits) in key.iter_mut().zip(hex.as_chunks::<2>().0) {
+        let digits = core::str::from_utf8(digits).ok()?;
+        *byte = u8::from_str_radix(digits, 16).ok()?;
+    }
+
+    Some(key)
+}
+
+fn tag(key: &Key, counter: u32, message: &[u8]) -> [u8; TAG_LEN] {
+    let mut tag = [0u8; TAG_LEN];
+    tag.copy_from_slice(&hmac_sha256(key, &[message, &counter.to_le_bytes()])[..TAG_LEN]);
+    tag
+}
+
+/// Trailer to append to a message
+pub fn sign(key: &Key, counter: u32, message: &[u8]) -> [u8; AUTH_LEN] {
+    let mut trailer = [0u8; AUTH_LEN];
+    trailer[0..4].copy_from_slice(&counter.to_le_bytes());
+    trailer[4..].copy_from_slice(&tag(key, counter, message));
+    trailer
+}
+
+/// Message received with an authentication trailer, not yet verified
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signed<'a> {
+    pub message: &'a [u8],
+    pub counter: u32,
+    tag: [u8; TAG_LEN],
+}
+
+impl<'a> Signed<'a> {
+    /// Splits the trailer off a received message
+    ///
+    /// The message can be decoded before it is verified, since its contents tell which key to use.
+    pub fn split(bytes: &'a [u8]) -> Result<Self, FrameError> {
+        if bytes.len() < AUTH_LEN {
+            return Err(FrameError::InvalidLength(bytes.len()));
+        }
+
+        let (message, trailer) = bytes.split_at(bytes.len() - AUTH_LEN);
+        let counter = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+
+        let mut tag = [0u8; TAG_LEN];
+        tag.copy_from_slice(&trailer[4..]);
+
+        Ok(Signed { message, counter, tag })
+    }
+
+    pub fn verify(&self, key: &Key) -> Result<(), FrameError> {
+        let expected = tag(key, self.counter, self.message);
+
+        // Compared without an early exit so the time taken doesn't reveal how much of the tag matched
+        let difference = expected.iter().zip(self.tag.iter()).fold(0, |difference, (a, b)| difference | (a ^ b));
+
+        if difference == 0 {
+            Ok(())
+        } else {
+            Err(FrameError::AuthenticationFailed)
+        }
+    }
+}
+
+/// Rejects counters that aren't above the highest one accepted so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGuard {
+    highest: u32,
+}
+
+impl ReplayGuard {
+    /// Starts above a counter persisted before a restart, so older messages stay rejected
+    pub fn new(floor: u32) -> Self {
+        ReplayGuard { highest: floor }
+    }
+
+    pub fn highest(&self) -> u32 {
+        self.highest
+    }
+
+    pub fn accept(&mut self, counter: u32) -> Result<(), FrameError> {
+        if counter <= self.highest {
+            return Err(FrameError::Replayed(counter));
+        }
+
+        self.highest = counter;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        let mac = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(mac[..8], [0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e]);
+        assert_eq!(mac[24..], [0x9d, 0xec, 0x58, 0xb9, 0x64, 0xec, 0x38, 0x43]);
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let network_key = parse_key("000102030405060708090a0b0c0d0e0f").unwrap();
+        let key = node_key(&network_key, 0x0dc4);
+        assert_ne!(key, node_key(&network_key, 0xfe88));
+
+        let mut bytes = b"REQUEST DATA".to_vec();
+        bytes.extend_from_slice(&sign(&key, 7, b"REQUEST DATA"));
+
+        let signed = Signed::split(&bytes).unwrap();
+        assert_eq!(signed.message, b"REQUEST DATA");
+        assert_eq!(signed.counter, 7);
+        assert_eq!(signed.verify(&key), Ok(()));
+        assert_eq!(signed.verify(&node_key(&network_key, 0xfe88)), Err(FrameError::AuthenticationFailed));
+
+        // Changing the counter invalidates the tag
+        bytes[12] = 8;
+        assert_eq!(Signed::split(&bytes).unwrap().verify(&key), Err(FrameError::AuthenticationFailed));
+    }
+
+    #[test]
+    fn test_replay_guard() {
+        let mut guard = ReplayGuard::new(10);
+
+        assert_eq!(guard.accept(10), Err(FrameError::Replayed(10)));
+        assert_eq!(guard.accept(11), Ok(()));
+        assert_eq!(guard.accept(11), Err(FrameError::Replayed(11)));
+        assert_eq!(guard.accept(40), Ok(()));
+        assert_eq!(guard.highest(), 40);
+    }
+}
@@ -0,0 +1,211 @@
+//! Commands sent by the communication module to a sensor node over ESP-NOW
+//!
+//! A command frame is 8 bytes, multi-byte fields little-endian:
+//!
+//! | Offset | Size | Field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 2    | Magic, `AC`                                |
+//! | 2      | 1    | Schema version                             |
+//! | 3      | 1    | Command code                               |
+//! | 4      | 2    | Argument, zero for commands without one    |
+//! | 6      | 2    | CRC-16/CCITT-FALSE over the previous bytes |
+//!
+//! A node acknowledges every command it ran, signed with the command's counter, so the
+//! communication module can resend commands that were lost, e.g. while the node was asleep. An
+//! acknowledgement has the same length, with the magic `AK`, whether the command succeeded in
+//! place of the command code and a zero argument.
+
+use crate::{ crc16, read_u16, FrameError, VERSION };
+
+pub const COMMAND_MAGIC: [u8; 2] = *b"AC";
+pub const ACK_MAGIC: [u8; 2] = *b"AK";
+pub const COMMAND_LEN: usize = 8;
+
+const CRC_OFFSET: usize = COMMAND_LEN - 2;
+
+const CO2_ZERO_POINT: u8 = 0x01;
+const CO2_SPAN_POINT: u8 = 0x02;
+const CO2_AUTO_BASELINE: u8 = 0x03;
+const CO2_DETECTION_RANGE: u8 = 0x04;
+const CO_CALIBRATE: u8 = 0x05;
+const CO_LOAD_RESISTANCE: u8 = 0x06;
+const O3_CALIBRATE: u8 = 0x07;
+const O3_LOAD_RESISTANCE: u8 = 0x08;
+
+/// Maintenance commands a node runs on its sensors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorCommand {
+    /// Calibrate the CO2 sensor's 400 ppm zero point in fresh air
+    Co2ZeroPoint,
+    /// Calibrate the CO2 sensor's span against a reference gas of the given ppm
+    Co2SpanPoint(u16),
+    /// Turn the CO2 sensor's automatic baseline correction on or off
+    Co2AutoBaseline(bool),
+    /// Set the upper limit of the CO2 sensor's detection range in ppm
+    Co2DetectionRange(u16),
+    /// Derive the CO sensor's R0 from heater cycles run in clean air
+    CoCalibrate,
+    /// Set the load resistor fitted next to the CO sensor in ohms
+    CoLoadResistance(u16),
+    /// Derive the ozone sensor's R0 from readings taken in clean air
+    O3Calibrate,
+    /// Set the load resistor fitted next to the ozone sensor in ohms
+    O3LoadResistance(u16),
+}
+
+impl SensorCommand {
+    pub fn encode(&self) -> [u8; COMMAND_LEN] {
+        let (code, argument) = match *self {
+            SensorCommand::Co2ZeroPoint => (CO2_ZERO_POINT, 0),
+            SensorCommand::Co2SpanPoint(span) => (CO2_SPAN_POINT, span),
+            SensorCommand::Co2AutoBaseline(enabled) => (CO2_AUTO_BASELINE, enabled as u16),
+            SensorCommand::Co2DetectionRange(range) => (CO2_DETECTION_RANGE, range),
+            SensorCommand::CoCalibrate => (CO_CALIBRATE, 0),
+            SensorCommand::CoLoadResistance(ohms) => (CO_LOAD_RESISTANCE, ohms),
+            SensorCommand::O3Calibrate => (O3_CALIBRATE, 0),
+            SensorCommand::O3LoadResistance(ohms) => (O3_LOAD_RESISTANCE, ohms),
+        };
+
+        let mut frame = [0u8; COMMAND_LEN];
+        frame[0..2].copy_from_slice(&COMMAND_MAGIC);
+        frame[2] = VERSION;
+        frame[3] = code;
+        frame[4..6].copy_from_slice(&argument.to_le_bytes());
+
+        let crc = crc16(&frame[..CRC_OFFSET]);
+        frame[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        frame
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() != COMMAND_LEN {
+            return Err(FrameError::InvalidLength(bytes.len()));
+        }
+
+        if bytes[0..2] != COMMAND_MAGIC {
+            return Err(FrameError::InvalidMagic);
+        }
+
+        if bytes[2] != VERSION {
+            return Err(FrameError::UnsupportedVersion(bytes[2]));
+        }
+
+        let expected = read_u16(bytes, CRC_OFFSET);
+        let actual = crc16(&bytes[..CRC_OFFSET]);
+
+        if expected != actual {
+            return Err(FrameError::CrcMismatch { expected, actual });
+        }
+
+        let argument = read_u16(bytes, 4);
+
+        match bytes[3] {
+            CO2_ZERO_POINT => Ok(SensorCommand::Co2ZeroPoint),
+            CO2_SPAN_POINT => Ok(SensorCommand::Co2SpanPoint(argument)),
+            CO2_AUTO_BASELINE => Ok(SensorCommand::Co2AutoBaseline(argument != 0)),
+            CO2_DETECTION_RANGE => Ok(SensorCommand::Co2DetectionRange(argument)),
+            CO_CALIBRATE => Ok(SensorCommand::CoCalibrate),
+            CO_LOAD_RESISTANCE => Ok(SensorCommand::CoLoadResistance(argument)),
+            O3_CALIBRATE => Ok(SensorCommand::O3Calibrate),
+            O3_LOAD_RESISTANCE => Ok(SensorCommand::O3LoadResistance(argument)),
+            code => Err(FrameError::UnknownCommand(code)),
+        }
+    }
+}
+
+/// Sent by a node once it has run a command, signed with the command's counter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandAck {
+    pub succeeded: bool,
+}
+
+impl CommandAck {
+    pub fn encode(&self) -> [u8; COMMAND_LEN] {
+        let mut frame = [0u8; COMMAND_LEN];
+        frame[0..2].copy_from_slice(&ACK_MAGIC);
+        frame[2] = VERSION;
+        frame[3] = self.succeeded as u8;
+
+        let crc = crc16(&frame[..CRC_OFFSET]);
+        frame[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        frame
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() != COMMAND_LEN {
+            return Err(FrameError::InvalidLength(bytes.len()));
+        }
+
+        if bytes[0..2] != ACK_MAGIC {
+            return Err(FrameError::InvalidMagic);
+        }
+
+        if bytes[2] != VERSION {
+            return Err(FrameError::UnsupportedVersion(bytes[2]));
+        }
+
+        let expected = read_u16(bytes, CRC_OFFSET);
+        let actual = crc16(&bytes[..CRC_OFFSET]);
+
+        if expected != actual {
+            return Err(FrameError::CrcMismatch { expected, actual });
+        }
+
+        Ok(CommandAck { succeeded: bytes[3] != 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let commands = [
+            SensorCommand::Co2ZeroPoint,
+            SensorCommand::Co2SpanPoint(2000),
+            SensorCommand::Co2AutoBaseline(true),
+            SensorCommand::Co2AutoBaseline(false),
+            SensorCommand::Co2DetectionRange(5000),
+            SensorCommand::CoCalibrate,
+            SensorCommand::CoLoadResistance(10_000),
+            SensorCommand::O3Calibrate,
+            SensorCommand::O3LoadResistance(10_000),
+        ];
+
+        for command in commands {
+            assert_eq!(SensorCommand::decode(&command.encode()), Ok(command));
+        }
+    }
+
+    #[test]
+    fn test_ack_round_trip() {
+        for ack in [CommandAck { succeeded: true }, CommandAck { succeeded: false }] {
+            assert_eq!(CommandAck::decode(&ack.encode()), Ok(ack));
+        }
+
+        // Only the magic tells acknowledgements and commands apart
+        let command = SensorCommand::CoCalibrate.encode();
+        assert_eq!(CommandAck::decode(&command), Err(FrameError::InvalidMagic));
+        assert_eq!(SensorCommand::decode(&CommandAck { succeeded: true }.encode()), Err(FrameError::InvalidMagic));
+    }
+
+    #[test]
+    fn test_invalid_commands_are_rejected() {
+        let encoded = SensorCommand::Co2SpanPoint(2000).encode();
+
+        assert_eq!(SensorCommand::decode(b"REQUEST DATA"), Err(FrameError::InvalidLength(12)));
+
+        let mut corrupted = encoded;
+        corrupted[4] ^= 1;
+        assert!(matches!(SensorCommand::decode(&corrupted), Err(FrameError::CrcMismatch { .. })));
+
+        let mut unknown = encoded;
+        unknown[3] = 0x7F;
+        let crc = crc16(&unknown[..CRC_OFFSET]);
+        unknown[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(SensorCommand::decode(&unknown), Err(FrameError::UnknownCommand(0x7F)));
+    }
+}
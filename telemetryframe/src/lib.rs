@@ -0,0 +1,306 @@
+//! Binary telemetry frame exchanged over ESP-NOW between the sensor nodes and the communication module
+//!
+//! All multi-byte fields are little-endian. Layout of a version 2 frame:
+//!
+//! | Offset | Size | Field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 2    | Magic, `AQ`                                        |
+//! | 2      | 1    | Schema version                                     |
+//! | 3      | 2    | Sequence number                                    |
+//! | 5      | 2    | Node id                                            |
+//! | 7      | 2    | Validity bitmap, one bit per reading               |
+//! | 9      | 12   | Temperature, pressure, humidity as `f32`           |
+//! | 21     | 16   | PM1.0, PM2.5, PM10, CO2, CO, O3, NO2, SO2 as `u16` |
+//! | 37     | 2    | CRC-16/CCITT-FALSE over the previous bytes         |
+//!
+//! Readings whose validity bit is clear are encoded as zero and decoded as `None`. Version 2 appended NO2 and SO2
+//! to the version 1 layout.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod auth;
+pub mod command;
+pub mod pairing;
+pub mod request;
+pub mod store;
+
+pub const MAGIC: [u8; 2] = *b"AQ";
+pub const VERSION: u8 = 2;
+pub const FRAME_LEN: usize = 39;
+
+const HEADER_LEN: usize = 9;
+const CRC_OFFSET: usize = FRAME_LEN - 2;
+
+const TEMPERATURE: u16 = 1 << 0;
+const PRESSURE: u16 = 1 << 1;
+const HUMIDITY: u16 = 1 << 2;
+const PM1_0: u16 = 1 << 3;
+const PM2_5: u16 = 1 << 4;
+const PM10: u16 = 1 << 5;
+const CO2: u16 = 1 << 6;
+const CO: u16 = 1 << 7;
+const O3: u16 = 1 << 8;
+const NO2: u16 = 1 << 9;
+const SO2: u16 = 1 << 10;
+
+/// Readings carried by a frame, `None` for channels the node failed to read
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Readings {
+    pub temperature: Option<f32>,
+    pub pressure: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pm1_0: Option<u16>,
+    pub pm2_5: Option<u16>,
+    pub pm10: Option<u16>,
+    pub co2: Option<u16>,
+    pub co: Option<u16>,
+    pub o3: Option<u16>,
+    /// NO2 in ppb
+    pub no2: Option<u16>,
+    /// SO2 in ppb
+    pub so2: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TelemetryFrame {
+    /// Incremented by the node for every frame it sends, wrapping on overflow
+    pub sequence: u16,
+    pub node_id: u16,
+    pub readings: Readings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameError {
+    InvalidLength(usize),
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    CrcMismatch { expected: u16, actual: u16 },
+    UnknownCommand(u8),
+    AuthenticationFailed,
+    Replayed(u32),
+}
+
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FrameError::InvalidLength(length) => write!(f, "invalid frame length {}", length),
+            FrameError::InvalidMagic => write!(f, "invalid frame magic"),
+            FrameError::UnsupportedVersion(version) => write!(f, "unsupported frame version {}", version),
+            FrameError::CrcMismatch { expected, actual } => {
+                write!(f, "CRC mismatch, expected {:#06x} but computed {:#06x}", expected, actual)
+            }
+            FrameError::UnknownCommand(code) => write!(f, "unknown command {:#04x}", code),
+            FrameError::AuthenticationFailed => write!(f, "authentication failed"),
+            FrameError::Replayed(counter) => write!(f, "replayed counter {}", counter),
+        }
+    }
+}
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF)
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+impl TelemetryFrame {
+    pub fn new(sequence: u16, node_id: u16, readings: Readings) -> Self {
+        TelemetryFrame { sequence, node_id, readings }
+    }
+
+    pub fn encode(&self) -> [u8; FRAME_LEN] {
+        let readings = &self.readings;
+        let mut frame = [0u8; FRAME_LEN];
+        let mut validity = 0;
+
+        let floats = [
+            (TEMPERATURE, readings.temperature),
+            (PRESSURE, readings.pressure),
+            (HUMIDITY, readings.humidity),
+        ];
+        let integers = [
+            (PM1_0, readings.pm1_0),
+            (PM2_5, readings.pm2_5),
+            (PM10, readings.pm10),
+            (CO2, readings.co2),
+            (CO, readings.co),
+            (O3, readings.o3),
+            (NO2, readings.no2),
+            (SO2, readings.so2),
+        ];
+
+        let mut offset = HEADER_LEN;
+
+        for (flag, value) in floats {
+            if let Some(value) = value {
+                frame[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                validity |= flag;
+            }
+            offset += 4;
+        }
+
+        for (flag, value) in integers {
+            if let Some(value) = value {
+                frame[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+                validity |= flag;
+            }
+            offset += 2;
+        }
+
+        frame[0..2].copy_from_slice(&MAGIC);
+        frame[2] = VERSION;
+        frame[3..5].copy_from_slice(&self.sequence.to_le_bytes());
+        frame[5..7].copy_from_slice(&self.node_id.to_le_bytes());
+        frame[7..9].copy_from_slice(&validity.to_le_bytes());
+
+        let crc = crc16(&frame[..CRC_OFFSET]);
+        frame[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        frame
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() != FRAME_LEN {
+            return Err(FrameError::InvalidLength(bytes.len()));
+        }
+
+        if bytes[0..2] != MAGIC {
+            return Err(FrameError::InvalidMagic);
+        }
+
+        if bytes[2] != VERSION {
+            return Err(FrameError::UnsupportedVersion(bytes[2]));
+        }
+
+        let expected = read_u16(bytes, CRC_OFFSET);
+        let actual = crc16(&bytes[..CRC_OFFSET]);
+
+        if expected != actual {
+            return Err(FrameError::CrcMismatch { expected, actual });
+        }
+
+        let validity = read_u16(bytes, 7);
+        let float = |flag: u16, offset: usize| {
+            (validity & flag != 0).then(|| f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]))
+        };
+        let integer = |flag: u16, offset: usize| (validity & flag != 0).then(|| read_u16(bytes, offset));
+
+        Ok(TelemetryFrame {
+            sequence: read_u16(bytes, 3),
+            node_id: read_u16(bytes, 5),
+            readings: Readings {
+                temperature: float(TEMPERATURE, 9),
+                pressure: float(PRESSURE, 13),
+                humidity: float(HUMIDITY, 17),
+                pm1_0: integer(PM1_0, 21),
+                pm2_5: integer(PM2_5, 23),
+                pm10: integer(PM10, 25),
+                co2: integer(CO2, 27),
+                co: integer(CO, 29),
+                o3: integer(O3, 31),
+                no2: integer(NO2, 33),
+                so2: integer(SO2, 35),
+            },
+        })
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> TelemetryFrame {
+        TelemetryFrame::new(
+            41,
+            0xfe88,
+            Readings {
+                temperature: Some(23.25),
+                pressure: Some(83_512.5),
+                humidity: Some(48.5),
+                pm1_0: Some(4),
+                pm2_5: Some(11),
+                pm10: Some(17),
+                co2: Some(612),
+                co: None,
+                o3: None,
+                no2: Some(18),
+                so2: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original = frame();
+        let encoded = original.encode();
+
+        assert_eq!(encoded.len(), FRAME_LEN);
+        assert_eq!(&encoded[0..2], b"AQ");
+        assert_eq!(encoded[2], VERSION);
+        assert_eq!(TelemetryFrame::decode(&encoded), Ok(original));
+
+        let empty = TelemetryFrame::new(u16::MAX, 1, Readings::default());
+        assert_eq!(TelemetryFrame::decode(&empty.encode()), Ok(empty));
+    }
+
+    #[test]
+    fn test_missing_readings_are_encoded_as_zero() {
+        let encoded = frame().encode();
+
+        assert_eq!(read_u16(&encoded, 7), TEMPERATURE | PRESSURE | HUMIDITY | PM1_0 | PM2_5 | PM10 | CO2 | NO2);
+        assert_eq!(&encoded[29..33], &[0, 0, 0, 0]);
+        assert_eq!(read_u16(&encoded, 33), 18);
+        assert_eq!(&encoded[35..37], &[0, 0]);
+    }
+
+    #[test]
+    fn test_corruption_is_detected() {
+        let encoded = frame().encode();
+
+        // Every single bit flip outside the magic and version must be caught by the CRC
+        for byte in 3..FRAME_LEN {
+            for bit in 0..8 {
+                let mut corrupted = encoded;
+                corrupted[byte] ^= 1 << bit;
+
+                assert!(
+                    matches!(TelemetryFrame::decode(&corrupted), Err(FrameError::CrcMismatch { .. })),
+                    "Bit {} of byte {} was not detected",
+                    bit,
+                    byte
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_frames_are_rejected() {
+        let encoded = frame().encode();
+
+        assert_eq!(TelemetryFrame::decode(&encoded[..FRAME_LEN - 1]), Err(FrameError::InvalidLength(FRAME_LEN - 1)));
+        assert_eq!(TelemetryFrame::decode(b"REQUEST DATA"), Err(FrameError::InvalidLength(12)));
+
+        let mut wrong_magic = encoded;
+        wrong_magic[0] = b'{';
+        assert_eq!(TelemetryFrame::decode(&wrong_magic), Err(FrameError::InvalidMagic));
+
+        let mut wrong_version = encoded;
+        wrong_version[2] = VERSION + 1;
+        assert_eq!(TelemetryFrame::decode(&wrong_version), Err(FrameError::UnsupportedVersion(VERSION + 1)));
+    }
+}
@@ -0,0 +1,129 @@
+//! Pairing handshake between the communication module and the sensor nodes over ESP-NOW
+//!
+//! While pairing is open the communication module broadcasts `Discover`. A node that isn't
+//! paired yet, or is paired with that module, answers with `Join` carrying its node id and a
+//! random nonce. The module adds the node to its peer table and confirms with `Accepted`, echoing
+//! the nonce and naming its own MAC address. The node only takes an `Accepted` that echoes the
+//! nonce of its latest `Join` and comes from the address it names, so a recorded `Accepted`
+//! can't pair it with anyone else later. It then stores the module's MAC address and only
+//! answers requests from it.
+//!
+//! A pairing message is 18 bytes, multi-byte fields little-endian:
+//!
+//! | Offset | Size | Field                                                    |
+//! |--------|------|----------------------------------------------------------|
+//! | 0      | 2    | Magic, `AP`                                              |
+//! | 2      | 1    | Schema version                                           |
+//! | 3      | 1    | Message type                                             |
+//! | 4      | 2    | Node id, zero for `Discover`                             |
+//! | 6      | 4    | Nonce of the `Join`, zero for `Discover`                 |
+//! | 10     | 6    | MAC address of the communication module, `Accepted` only |
+//! | 16     | 2    | CRC-16/CCITT-FALSE over the previous bytes               |
+
+use crate::{ crc16, read_u16, FrameError, VERSION };
+
+pub const PAIRING_MAGIC: [u8; 2] = *b"AP";
+pub const PAIRING_LEN: usize = 18;
+
+const CRC_OFFSET: usize = PAIRING_LEN - 2;
+
+const DISCOVER: u8 = 0x01;
+const JOIN: u8 = 0x02;
+const ACCEPTED: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PairingMessage {
+    /// Broadcast by the communication module while it accepts new nodes
+    Discover,
+    /// A node asking to be added to the communication module's peer table
+    Join { node_id: u16, nonce: u32 },
+    /// The communication module at `gateway` confirming that the node has been added, in answer
+    /// to the `Join` with `nonce`
+    Accepted { node_id: u16, nonce: u32, gateway: [u8; 6] },
+}
+
+impl PairingMessage {
+    pub fn encode(&self) -> [u8; PAIRING_LEN] {
+        let (code, node_id, nonce, gateway) = match *self {
+            PairingMessage::Discover => (DISCOVER, 0, 0, [0; 6]),
+            PairingMessage::Join { node_id, nonce } => (JOIN, node_id, nonce, [0; 6]),
+            PairingMessage::Accepted { node_id, nonce, gateway } => (ACCEPTED, node_id, nonce, gateway),
+        };
+
+        let mut frame = [0u8; PAIRING_LEN];
+        frame[0..2].copy_from_slice(&PAIRING_MAGIC);
+        frame[2] = VERSION;
+        frame[3] = code;
+        frame[4..6].copy_from_slice(&node_id.to_le_bytes());
+        frame[6..10].copy_from_slice(&nonce.to_le_bytes());
+        frame[10..16].copy_from_slice(&gateway);
+
+        let crc = crc16(&frame[..CRC_OFFSET]);
+        frame[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        frame
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() != PAIRING_LEN {
+            return Err(FrameError::InvalidLength(bytes.len()));
+        }
+
+        if bytes[0..2] != PAIRING_MAGIC {
+            return Err(FrameError::InvalidMagic);
+        }
+
+        if bytes[2] != VERSION {
+            return Err(FrameError::UnsupportedVersion(bytes[2]));
+        }
+
+        let expected = read_u16(bytes, CRC_OFFSET);
+        let actual = crc16(&bytes[..CRC_OFFSET]);
+
+        if expected != actual {
+            return Err(FrameError::CrcMismatch { expected, actual });
+        }
+
+        let node_id = read_u16(bytes, 4);
+        let nonce = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+
+        let mut gateway = [0u8; 6];
+        gateway.copy_from_slice(&bytes[10..16]);
+
+        match bytes[3] {
+            DISCOVER => Ok(PairingMessage::Discover),
+            JOIN => Ok(PairingMessage::Join { node_id, nonce }),
+            ACCEPTED => Ok(PairingMessage::Accepted { node_id, nonce, gateway }),
+            code => Err(FrameError::UnknownCommand(code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::SensorCommand;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = [
+            PairingMessage::Discover,
+            PairingMessage::Join { node_id: 0xfe88, nonce: 0x1234_5678 },
+            PairingMessage::Accepted { node_id: 0x0dc4, nonce: 0x1234_5678, gateway: [0x40, 0x4c, 0xca, 0x5e, 0x0d, 0xc4] },
+        ];
+
+        for message in messages {
+            assert_eq!(PairingMessage::decode(&message.encode()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_other_messages_are_rejected() {
+        let command = SensorCommand::Co2ZeroPoint.encode();
+        assert_eq!(PairingMessage::decode(&command), Err(FrameError::InvalidLength(command.len())));
+
+        let mut corrupted = PairingMessage::Join { node_id: 0xfe88, nonce: 7 }.encode();
+        corrupted[5] ^= 1;
+        assert!(matches!(PairingMessage::decode(&corrupted), Err(FrameError::CrcMismatch { .. })));
+    }
+}
@@ -0,0 +1,136 @@
+//! Data requests sent by the communication module to a sensor node, and the node's response
+//!
+//! Every exchange is numbered with a request id. The communication module resends an unanswered
+//! request with the same id and a higher attempt number, and the node echoes the id in its
+//! response, so a retry answered late is still matched to its exchange. A node answering the
+//! same id twice resends the frame it already read rather than reading its sensors again.
+//!
+//! A request is 8 bytes, multi-byte fields little-endian:
+//!
+//! | Offset | Size | Field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 2    | Magic, `AR`                                |
+//! | 2      | 1    | Schema version                             |
+//! | 3      | 1    | Attempt, zero for the first transmission   |
+//! | 4      | 2    | Request id                                 |
+//! | 6      | 2    | CRC-16/CCITT-FALSE over the previous bytes |
+//!
+//! A response is a telemetry frame followed by the request id it answers. The id is covered by the
+//! authentication trailer rather than the frame's CRC.
+//!
+//! A node in push mode also sends frames nobody asked for. Those are sent as a bare telemetry frame,
+//! without a request id.
+
+use crate::{ crc16, read_u16, FrameError, TelemetryFrame, FRAME_LEN, VERSION };
+
+pub const REQUEST_MAGIC: [u8; 2] = *b"AR";
+pub const REQUEST_LEN: usize = 8;
+pub const RESPONSE_LEN: usize = FRAME_LEN + 2;
+
+const CRC_OFFSET: usize = REQUEST_LEN - 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DataRequest {
+    pub request_id: u16,
+    /// Number of times the request has been resent
+    pub attempt: u8,
+}
+
+impl DataRequest {
+    pub fn encode(&self) -> [u8; REQUEST_LEN] {
+        let mut frame = [0u8; REQUEST_LEN];
+        frame[0..2].copy_from_slice(&REQUEST_MAGIC);
+        frame[2] = VERSION;
+        frame[3] = self.attempt;
+        frame[4..6].copy_from_slice(&self.request_id.to_le_bytes());
+
+        let crc = crc16(&frame[..CRC_OFFSET]);
+        frame[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        frame
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() != REQUEST_LEN {
+            return Err(FrameError::InvalidLength(bytes.len()));
+        }
+
+        if bytes[0..2] != REQUEST_MAGIC {
+            return Err(FrameError::InvalidMagic);
+        }
+
+        if bytes[2] != VERSION {
+            return Err(FrameError::UnsupportedVersion(bytes[2]));
+        }
+
+        let expected = read_u16(bytes, CRC_OFFSET);
+        let actual = crc16(&bytes[..CRC_OFFSET]);
+
+        if expected != actual {
+            return Err(FrameError::CrcMismatch { expected, actual });
+        }
+
+        Ok(DataRequest {
+            request_id: read_u16(bytes, 4),
+            attempt: bytes[3],
+        })
+    }
+}
+
+/// Frame sent by a node in answer to a data request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DataResponse {
+    pub request_id: u16,
+    pub frame: TelemetryFrame,
+}
+
+impl DataResponse {
+    pub fn encode(&self) -> [u8; RESPONSE_LEN] {
+        let mut response = [0u8; RESPONSE_LEN];
+        response[..FRAME_LEN].copy_from_slice(&self.frame.encode());
+        response[FRAME_LEN..].copy_from_slice(&self.request_id.to_le_bytes());
+
+        response
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() != RESPONSE_LEN {
+            return Err(FrameError::InvalidLength(bytes.len()));
+        }
+
+        Ok(DataResponse {
+            request_id: read_u16(bytes, FRAME_LEN),
+            frame: TelemetryFrame::decode(&bytes[..FRAME_LEN])?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ command::SensorCommand, Readings };
+
+    #[test]
+    fn test_request_round_trip() {
+        let request = DataRequest { request_id: 0xbeef, attempt: 2 };
+        assert_eq!(DataRequest::decode(&request.encode()), Ok(request));
+
+        // Commands have the same length, so only the magic tells them apart
+        let command = SensorCommand::Co2ZeroPoint.encode();
+        assert_eq!(DataRequest::decode(&command), Err(FrameError::InvalidMagic));
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let frame = TelemetryFrame::new(7, 0x0dc4, Readings { co2: Some(455), ..Readings::default() });
+        let response = DataResponse { request_id: 300, frame };
+        let encoded = response.encode();
+
+        assert_eq!(DataResponse::decode(&encoded), Ok(response));
+        assert_eq!(DataResponse::decode(&encoded[..FRAME_LEN]), Err(FrameError::InvalidLength(FRAME_LEN)));
+
+        let mut corrupted = encoded;
+        corrupted[10] ^= 1;
+        assert!(matches!(DataResponse::decode(&corrupted), Err(FrameError::CrcMismatch { .. })));
+    }
+}
@@ -0,0 +1,876 @@
+//! Records the communication module and the sensor nodes keep in their own flash partitions
+//!
+//! Each partition is a single sector holding one record, rewritten in full on every save:
+//!
+//! | Offset | Size | Field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 4    | Magic, different for every kind of record  |
+//! | 4      | 2    | Payload length, little-endian              |
+//! | 6      | 2    | CRC-16/CCITT-FALSE over the payload        |
+//! | 8      | n    | Payload, padded with `0xFF` to whole words |
+//!
+//! A sector that was never written, or whose write was interrupted, fails the magic or CRC check
+//! and loads as empty.
+//!
+//! Values that change too often to rewrite a sector every time are appended to a [`CounterLog`]
+//! instead, and records kept until they have been sent on to a [`SlotRing`].
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc16;
+
+pub const HEADER_LEN: usize = 8;
+
+/// The ESP32's flash is read and written in whole 32-bit words
+const WORD_LEN: usize = 4;
+
+/// Counters reserved with every flash write, so a counter's sector is only rewritten once every this many values
+pub const RESERVATION: u32 = 1024;
+
+/// A record in a flash partition of its own
+pub struct RecordStore<F: NorFlash> {
+    flash: F,
+    offset: u32,
+    size: u32,
+    magic: [u8; 4],
+}
+
+impl<F: NorFlash> RecordStore<F> {
+    /// Store for the partition at `offset`, which must be a whole number of erase sectors
+    pub fn new(flash: F, offset: u32, size: u32, magic: [u8; 4]) -> Self {
+        RecordStore { flash, offset, size, magic }
+    }
+
+    /// Longest payload the partition can hold
+    pub fn capacity(&self) -> usize {
+        self.size as usize - HEADER_LEN
+    }
+
+    /// Reads the saved payload into `buffer`, `None` when nothing valid has been saved or it doesn't fit
+    pub fn load<'b>(&mut self, buffer: &'b mut [u8]) -> Option<&'b [u8]> {
+        let mut header = [0u8; HEADER_LEN];
+        self.flash.read(self.offset, &mut header).ok()?;
+
+        if header[0..4] != self.magic {
+            return None;
+        }
+
+        let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let expected = u16::from_le_bytes([header[6], header[7]]);
+
+        if len > buffer.len() || len > self.capacity() {
+            return None;
+        }
+
+        let payload = &mut buffer[..len];
+        let (words, rest) = payload.split_at_mut(len - len % WORD_LEN);
+        let start = self.offset + HEADER_LEN as u32;
+
+        if !words.is_empty() {
+            self.flash.read(start, words).ok()?;
+        }
+
+        if !rest.is_empty() {
+            let mut word = [0u8; WORD_LEN];
+            self.flash.read(start + words.len() as u32, &mut word).ok()?;
+            rest.copy_from_slice(&word[..rest.len()]);
+        }
+
+        (crc16(payload) == expected).then_some(payload)
+    }
+
+    pub fn save(&mut self, payload: &[u8]) -> Result<(), &'static str> {
+        if payload.len() > self.capacity() {
+            return Result::Err("Record is too large for its partition");
+        }
+
+        let mut header = [0u8; HEADER_LEN];
+        header[0..4].copy_from_slice(&self.magic);
+        header[4..6].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        header[6..8].copy_from_slice(&crc16(payload).to_le_bytes());
+
+        let (words, rest) = payload.split_at(payload.len() - payload.len() % WORD_LEN);
+        let start = self.offset + HEADER_LEN as u32;
+
+        self.flash
+            .erase(self.offset, self.offset + self.size)
+            .map_err(|_| "Failed to erase flash record")?;
+
+        // The header goes last, so a record whose write was interrupted is never taken as valid
+        if !words.is_empty() {
+            self.flash.write(start, words).map_err(|_| "Failed to write flash record")?;
+        }
+
+        if !rest.is_empty() {
+            let mut word = [0xFFu8; WORD_LEN];
+            word[..rest.len()].copy_from_slice(rest);
+            self.flash.write(start + words.len() as u32, &word).map_err(|_| "Failed to write flash record")?;
+        }
+
+        self.flash.write(self.offset, &header).map_err(|_| "Failed to write flash record")
+    }
+}
+
+/// Counter that keeps increasing across restarts, such as the counters authenticating messages
+///
+/// Rather than writing every value to flash, blocks of values are reserved and a restart skips to
+/// the end of the current block.
+pub struct ReservedCounter<F: NorFlash> {
+    store: RecordStore<F>,
+    next: u32,
+    reserved: u32,
+}
+
+impl<F: NorFlash> ReservedCounter<F> {
+    /// Continues after the last block reserved in `store`, never returning a value below `first`
+    pub fn new(mut store: RecordStore<F>, first: u32) -> Self {
+        let reserved = Self::load(&mut store);
+
+        ReservedCounter { store, next: reserved.max(first), reserved }
+    }
+
+    /// Continues from `next`, kept from before the node went into deep sleep, rather than
+    /// skipping to the end of the block
+    ///
+    /// Values from `next` on haven't been used yet, and another block is reserved once `next`
+    /// reaches the saved one.
+    pub fn resume(mut store: RecordStore<F>, next: u32) -> Self {
+        let reserved = Self::load(&mut store);
+
+        ReservedCounter { store, next, reserved }
+    }
+
+    fn load(store: &mut RecordStore<F>) -> u32 {
+        let mut buffer = [0u8; 4];
+
+        match store.load(&mut buffer) {
+            Some(&[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]),
+            _ => 0,
+        }
+    }
+
+    /// Value the next call to [`next_value`](Self::next_value) returns
+    pub fn peek(&self) -> u32 {
+        self.next
+    }
+
+    /// Returns the next value, reserving another block in flash when the current one is used up
+    pub fn next_value(&mut self) -> Result<u32, &'static str> {
+        if self.next >= self.reserved {
+            let reserved = self.next.checked_add(RESERVATION).ok_or("Counter exhausted")?;
+            self.store.save(&reserved.to_le_bytes())?;
+            self.reserved = reserved;
+        }
+
+        let counter = self.next;
+        self.next += 1;
+
+        Ok(counter)
+    }
+}
+
+/// Length of a [`CounterLog`] entry: key, counter and a CRC-16/CCITT-FALSE over both
+const LOG_ENTRY_LEN: usize = 8;
+
+/// Latest counters of up to `N` senders, such as the counters of the frames the nodes push
+///
+/// Each new counter is appended to a log spanning two erase sectors, so a sector is only erased
+/// once it has filled up. The latest counters are then copied to the other sector and the full
+/// one erased. Loading takes the highest counter logged for each key, so an interrupted write or
+/// copy never lowers a counter.
+pub struct CounterLog<F: NorFlash, const N: usize> {
+    flash: F,
+    offset: u32,
+    counters: [(u16, u32); N],
+    len: usize,
+    /// Sector new entries are appended to, and the next free entry in it
+    sector: u32,
+    next_entry: u32,
+}
+
+impl<F: NorFlash, const N: usize> CounterLog<F, N> {
+    /// Log in the partition at `offset`, which must be two erase sectors long
+    ///
+    /// Keys beyond the first `N` found are dropped.
+    pub fn new(flash: F, offset: u32) -> Self {
+        let mut log = CounterLog { flash, offset, counters: [(0, 0); N], len: 0, sector: 0, next_entry: 0 };
+
+        let used = [log.scan(0), log.scan(1)];
+
+        // Entries go after the last one written, in the sector that isn't full, or the one still
+        // being filled when a copy was interrupted
+        log.sector = match used {
+            [_, 0] => 0,
+            [0, _] => 1,
+            [first, second] => (second < first) as u32,
+        };
+        log.next_entry = used[log.sector as usize];
+
+        log
+    }
+
+    fn entries_per_sector() -> u32 {
+        (F::ERASE_SIZE / LOG_ENTRY_LEN) as u32
+    }
+
+    fn entry_address(&self, sector: u32, entry: u32) -> u32 {
+        self.offset + sector * F::ERASE_SIZE as u32 + entry * LOG_ENTRY_LEN as u32
+    }
+
+    /// Takes the valid entries of a sector into the counters, returning how many entries are used
+    fn scan(&mut self, sector: u32) -> u32 {
+        let mut used = 0;
+
+        for index in 0..Self::entries_per_sector() {
+            let mut entry = [0u8; LOG_ENTRY_LEN];
+
+            if self.flash.read(self.entry_address(sector, index), &mut entry).is_err() {
+                continue;
+            }
+
+            if entry == [0xFF; LOG_ENTRY_LEN] {
+                continue;
+            }
+
+            used = index + 1;
+
+            // An entry whose write was interrupted is skipped
+            if crc16(&entry[..6]) != u16::from_le_bytes([entry[6], entry[7]]) {
+                continue;
+            }
+
+            let key = u16::from_le_bytes([entry[0], entry[1]]);
+            let counter = u32::from_le_bytes([entry[2], entry[3], entry[4], entry[5]]);
+
+            match self.counters[..self.len].iter_mut().find(|(existing, _)| *existing == key) {
+                Some((_, highest)) => *highest = (*highest).max(counter),
+                None if self.len < N => {
+                    self.counters[self.len] = (key, counter);
+                    self.len += 1;
+                }
+                None => {}
+            }
+        }
+
+        used
+    }
+
+    /// Latest counter logged for `key`, zero when none has been
+    pub fn get(&self, key: u16) -> u32 {
+        self.counters[..self.len].iter().find(|(existing, _)| *existing == key).map_or(0, |(_, counter)| *counter)
+    }
+
+    /// Logs a new counter for `key`, which must be above the one logged before
+    pub fn record(&mut self, key: u16, counter: u32) -> Result<(), &'static str> {
+        match self.counters[..self.len].iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, latest)) => *latest = counter,
+            None if self.len < N => {
+                self.counters[self.len] = (key, counter);
+                self.len += 1;
+            }
+            None => return Result::Err("Counter log is full"),
+        }
+
+        if self.next_entry >= Self::entries_per_sector() {
+            // The copy logs this counter as well
+            return self.compact();
+        }
+
+        self.append(key, counter)
+    }
+
+    /// Drops the counter of `key`, which then starts again from zero
+    pub fn forget(&mut self, key: u16) -> Result<(), &'static str> {
+        let Some(index) = self.counters[..self.len].iter().position(|(existing, _)| *existing == key) else {
+            return Ok(());
+        };
+
+        self.counters.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+
+        // Its entries are only gone once the sector holding them has been erased
+        self.compact()
+    }
+
+    fn append(&mut self, key: u16, counter: u32) -> Result<(), &'static str> {
+        let mut entry = [0u8; LOG_ENTRY_LEN];
+        entry[0..2].copy_from_slice(&key.to_le_bytes());
+        entry[2..6].copy_from_slice(&counter.to_le_bytes());
+        let crc = crc16(&entry[..6]);
+        entry[6..8].copy_from_slice(&crc.to_le_bytes());
+
+        let address = self.entry_address(self.sector, self.next_entry);
+
+        self.flash.write(address, &entry).map_err(|_| "Failed to write flash record")?;
+        self.next_entry += 1;
+
+        // A counter only counts as logged once it reads back, a write cut short would load as a lower one
+        let mut written = [0u8; LOG_ENTRY_LEN];
+        self.flash.read(address, &mut written).map_err(|_| "Failed to read flash record")?;
+
+        if written != entry {
+            return Result::Err("Failed to verify flash record");
+        }
+
+        Ok(())
+    }
+
+    /// Copies the latest counters to the other sector and erases the current one
+    fn compact(&mut self) -> Result<(), &'static str> {
+        let full = self.sector;
+
+        self.sector = 1 - full;
+        self.next_entry = 0;
+
+        let start = self.entry_address(self.sector, 0);
+        self.flash
+            .erase(start, start + F::ERASE_SIZE as u32)
+            .map_err(|_| "Failed to erase flash record")?;
+
+        for index in 0..self.len {
+            let (key, counter) = self.counters[index];
+            self.append(key, counter)?;
+        }
+
+        let start = self.entry_address(full, 0);
+        self.flash
+            .erase(start, start + F::ERASE_SIZE as u32)
+            .map_err(|_| "Failed to erase flash record")
+    }
+}
+
+// Slot states only ever clear bits, so a slot can move from empty to pending to sent without an erase
+const SLOT_EMPTY: u32 = 0xFFFF_FFFF;
+const SLOT_PENDING: u32 = 0xFFFF_0000;
+const SLOT_SENT: u32 = 0x0000_0000;
+
+/// Length of a [`SlotRing`] slot's header: state and sequence number
+const SLOT_HEADER_LEN: usize = 8;
+
+/// Bounded store-and-forward queue of fixed length records in a flash ring buffer
+///
+/// Slots of `SLOT_SIZE` bytes hold a state, a sequence number, the payload and a CRC-16/CCITT-FALSE
+/// over the sequence number and payload. Records are appended in ring order, so reading on from
+/// the oldest pending record gives them in the order they were written. When the ring wraps, the next
+/// sector is erased and the records still pending in it are dropped, so the ring never blocks new
+/// records.
+pub struct SlotRing<F: NorFlash, const SLOT_SIZE: usize> {
+    flash: F,
+    offset: u32,
+    payload_len: usize,
+    slot_count: usize,
+    /// No record before this slot, in ring order, is still pending
+    oldest: usize,
+    write_index: usize,
+    next_sequence: u32,
+    pending: usize,
+    dropped: u32,
+}
+
+impl<F: NorFlash, const SLOT_SIZE: usize> SlotRing<F, SLOT_SIZE> {
+    /// Scans the ring in the partition at `offset`, which must be a whole number of erase sectors,
+    /// and restores the records left pending before a reset
+    ///
+    /// Records whose write was interrupted are marked sent, so they are only counted as dropped once.
+    pub fn new(flash: F, offset: u32, size: u32, payload_len: usize) -> Result<Self, &'static str> {
+        assert!(Self::record_len(payload_len) <= SLOT_SIZE, "payload does not fit in a slot");
+
+        let mut ring = SlotRing {
+            flash,
+            offset,
+            payload_len,
+            slot_count: size as usize / SLOT_SIZE,
+            oldest: 0,
+            write_index: 0,
+            next_sequence: 0,
+            pending: 0,
+            dropped: 0,
+        };
+
+        let mut latest: Option<(u32, usize)> = None;
+        let mut oldest_pending: Option<(u32, usize)> = None;
+
+        for index in 0..ring.slot_count {
+            let record = ring.read_slot(index)?;
+            let state = read_u32(&record, 0);
+
+            if state == SLOT_EMPTY {
+                continue;
+            }
+
+            if !ring.is_valid(&record) {
+                if state == SLOT_PENDING {
+                    ring.mark_sent(index)?;
+                    ring.dropped += 1;
+                }
+                continue;
+            }
+
+            let sequence = read_u32(&record, 4);
+
+            if latest.is_none_or(|(latest_sequence, _)| sequence > latest_sequence) {
+                latest = Some((sequence, index));
+            }
+
+            if state == SLOT_PENDING {
+                ring.pending += 1;
+
+                if oldest_pending.is_none_or(|(oldest_sequence, _)| sequence < oldest_sequence) {
+                    oldest_pending = Some((sequence, index));
+                }
+            }
+        }
+
+        if let Some((sequence, index)) = latest {
+            ring.next_sequence = sequence.wrapping_add(1);
+            ring.write_index = (index + 1) % ring.slot_count;
+        }
+
+        ring.oldest = oldest_pending.map_or(ring.write_index, |(_, index)| index);
+
+        Ok(ring)
+    }
+
+    fn record_len(payload_len: usize) -> usize {
+        (SLOT_HEADER_LEN + payload_len + 2).next_multiple_of(WORD_LEN)
+    }
+
+    fn slots_per_sector() -> usize {
+        F::ERASE_SIZE / SLOT_SIZE
+    }
+
+    /// Records waiting to be sent
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Records overwritten or corrupted before they could be sent
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Appends a record of `payload_len` bytes
+    pub fn push(&mut self, payload: &[u8]) -> Result<(), &'static str> {
+        if payload.len() != self.payload_len {
+            return Result::Err("Record has the wrong length for its ring");
+        }
+
+        // Find the next empty slot, erasing the oldest sector whenever the ring wraps into it
+        for _ in 0..self.slot_count {
+            if self.write_index.is_multiple_of(Self::slots_per_sector()) {
+                self.erase_sector(self.write_index / Self::slots_per_sector())?;
+            }
+
+            if read_u32(&self.read_slot(self.write_index)?, 0) == SLOT_EMPTY {
+                break;
+            }
+
+            self.write_index = (self.write_index + 1) % self.slot_count;
+        }
+
+        let mut record = [0xFFu8; SLOT_SIZE];
+        let crc_offset = SLOT_HEADER_LEN + self.payload_len;
+
+        record[0..4].copy_from_slice(&SLOT_PENDING.to_le_bytes());
+        record[4..SLOT_HEADER_LEN].copy_from_slice(&self.next_sequence.to_le_bytes());
+        record[SLOT_HEADER_LEN..crc_offset].copy_from_slice(payload);
+
+        let crc = crc16(&record[4..crc_offset]);
+        record[crc_offset..crc_offset + 2].copy_from_slice(&crc.to_le_bytes());
+
+        self.flash
+            .write(self.slot_offset(self.write_index), &record[..Self::record_len(self.payload_len)])
+            .map_err(|_| "Failed to write record to flash")?;
+
+        if self.pending == 0 {
+            self.oldest = self.write_index;
+        }
+
+        self.pending += 1;
+        self.write_index = (self.write_index + 1) % self.slot_count;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        Ok(())
+    }
+
+    /// Passes up to `max` of the oldest pending payloads to `take` without removing them
+    ///
+    /// Records that fail their CRC, or that `take` rejects by returning `false`, are dropped and
+    /// marked sent in flash.
+    pub fn peek(&mut self, max: usize, mut take: impl FnMut(&[u8]) -> bool) -> Result<(), &'static str> {
+        let mut taken = 0;
+
+        for step in 0..self.slot_count {
+            if taken >= max || taken >= self.pending {
+                break;
+            }
+
+            let index = (self.oldest + step) % self.slot_count;
+            let record = self.read_slot(index)?;
+
+            if read_u32(&record, 0) != SLOT_PENDING {
+                continue;
+            }
+
+            if self.is_valid(&record) && take(&record[SLOT_HEADER_LEN..SLOT_HEADER_LEN + self.payload_len]) {
+                taken += 1;
+            } else {
+                self.mark_sent(index)?;
+                self.pending -= 1;
+                self.dropped += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Marks the `count` oldest pending records as sent, returning how many there were
+    pub fn consume(&mut self, count: usize) -> Result<u32, &'static str> {
+        let mut consumed = 0;
+
+        for _ in 0..self.slot_count {
+            if consumed as usize >= count || self.pending == 0 {
+                break;
+            }
+
+            let index = self.oldest;
+
+            if read_u32(&self.read_slot(index)?, 0) == SLOT_PENDING {
+                self.mark_sent(index)?;
+                self.pending -= 1;
+                consumed += 1;
+            }
+
+            self.oldest = (index + 1) % self.slot_count;
+        }
+
+        Ok(consumed)
+    }
+
+    fn slot_offset(&self, index: usize) -> u32 {
+        self.offset + (index * SLOT_SIZE) as u32
+    }
+
+    fn is_valid(&self, record: &[u8; SLOT_SIZE]) -> bool {
+        let crc_offset = SLOT_HEADER_LEN + self.payload_len;
+        let expected = u16::from_le_bytes([record[crc_offset], record[crc_offset + 1]]);
+
+        crc16(&record[4..crc_offset]) == expected
+    }
+
+    fn mark_sent(&mut self, index: usize) -> Result<(), &'static str> {
+        self.flash
+            .write(self.slot_offset(index), &SLOT_SENT.to_le_bytes())
+            .map_err(|_| "Failed to mark record as sent")
+    }
+
+    fn erase_sector(&mut self, sector: usize) -> Result<(), &'static str> {
+        let first = sector * Self::slots_per_sector();
+        let slots = first..first + Self::slots_per_sector();
+
+        for index in slots.clone() {
+            if read_u32(&self.read_slot(index)?, 0) == SLOT_PENDING {
+                self.pending -= 1;
+                self.dropped += 1;
+            }
+        }
+
+        // Whatever was pending in the sector is gone, the oldest record left is in the next one
+        if slots.contains(&self.oldest) {
+            self.oldest = slots.end % self.slot_count;
+        }
+
+        let start = self.slot_offset(first);
+
+        self.flash
+            .erase(start, start + F::ERASE_SIZE as u32)
+            .map_err(|_| "Failed to erase flash sector")
+    }
+
+    fn read_slot(&mut self, index: usize) -> Result<[u8; SLOT_SIZE], &'static str> {
+        let mut record = [0u8; SLOT_SIZE];
+
+        self.flash
+            .read(self.slot_offset(index), &mut record[..Self::record_len(self.payload_len)])
+            .map_err(|_| "Failed to read record from flash")?;
+
+        Ok(record)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash };
+
+    const SECTOR: u32 = 4096;
+
+    #[derive(Debug)]
+    struct MockFlashError;
+
+    impl NorFlashError for MockFlashError {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    /// Flash that, like the real thing, only clears bits on writes
+    struct MockFlash {
+        bytes: Vec<u8>,
+        writes: usize,
+        /// Writes only land halfway, as when the power fails during one
+        tear_writes: bool,
+    }
+
+    impl MockFlash {
+        fn new() -> Self {
+            MockFlash { bytes: vec![0xFF; 3 * SECTOR as usize], writes: 0, tear_writes: false }
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = MockFlashError;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), MockFlashError> {
+            assert!(offset.is_multiple_of(4) && bytes.len().is_multiple_of(4), "unaligned read");
+            bytes.copy_from_slice(&self.bytes[offset as usize..offset as usize + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR as usize;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), MockFlashError> {
+            assert!(from.is_multiple_of(SECTOR) && to.is_multiple_of(SECTOR), "unaligned erase");
+            self.bytes[from as usize..to as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MockFlashError> {
+            assert!(offset.is_multiple_of(4) && bytes.len().is_multiple_of(4), "unaligned write");
+            let landed = if self.tear_writes { bytes.len() / 2 } else { bytes.len() };
+            for (cell, byte) in self.bytes[offset as usize..].iter_mut().zip(&bytes[..landed]) {
+                *cell &= byte;
+            }
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut flash = MockFlash::new();
+        let mut buffer = [0u8; 16];
+
+        let mut store = RecordStore::new(&mut flash, SECTOR, SECTOR, *b"TEST");
+        assert_eq!(store.load(&mut buffer), None);
+
+        // Payloads that don't end on a word boundary are padded
+        store.save(b"hello world").unwrap();
+        assert_eq!(store.load(&mut buffer), Some(&b"hello world"[..]));
+
+        store.save(b"").unwrap();
+        assert_eq!(store.load(&mut buffer), Some(&b""[..]));
+
+        store.save(b"0123456789abcdef").unwrap();
+        assert_eq!(store.load(&mut buffer), Some(&b"0123456789abcdef"[..]));
+        assert_eq!(store.load(&mut buffer[..8]), None, "a payload longer than the buffer can't be loaded");
+
+        // Records of another kind in the same place are ignored
+        assert_eq!(RecordStore::new(&mut flash, SECTOR, SECTOR, *b"ELSE").load(&mut buffer), None);
+
+        // The first sector was never touched
+        assert!(flash.bytes[..SECTOR as usize].iter().all(|&byte| byte == 0xFF));
+
+        flash.bytes[SECTOR as usize + HEADER_LEN] ^= 1;
+        assert_eq!(RecordStore::new(&mut flash, SECTOR, SECTOR, *b"TEST").load(&mut buffer), None);
+
+        let mut store = RecordStore::new(&mut flash, SECTOR, SECTOR, *b"TEST");
+        assert_eq!(store.save(&[0u8; SECTOR as usize]), Err("Record is too large for its partition"));
+    }
+
+    #[test]
+    fn test_counter_survives_restarts() {
+        let mut flash = MockFlash::new();
+        fn store(flash: &mut MockFlash) -> RecordStore<&mut MockFlash> {
+            RecordStore::new(flash, 0, SECTOR, *b"CNTR")
+        }
+
+        let mut counter = ReservedCounter::new(store(&mut flash), 1);
+        assert_eq!(counter.next_value(), Ok(1));
+        assert_eq!(counter.next_value(), Ok(2));
+
+        // Only the first value of a block touches the flash
+        assert_eq!(flash.writes, 2);
+
+        // A restart skips the rest of the reserved block, so no value is ever used twice
+        let mut counter = ReservedCounter::new(store(&mut flash), 1);
+        assert_eq!(counter.next_value(), Ok(1 + RESERVATION));
+        let retained = counter.peek();
+
+        // Waking from deep sleep continues where the counter stopped, within the same block
+        let mut counter = ReservedCounter::resume(store(&mut flash), retained);
+        assert_eq!(counter.next_value(), Ok(2 + RESERVATION));
+        assert_eq!(flash.writes, 4);
+    }
+
+    /// Ring of two sectors after an untouched first one, with 32 slots per sector
+    type TestRing<'a> = SlotRing<&'a mut MockFlash, 128>;
+
+    fn open_ring(flash: &mut MockFlash) -> TestRing<'_> {
+        SlotRing::new(flash, SECTOR, 2 * SECTOR, 4).unwrap()
+    }
+
+    fn peek_all(ring: &mut TestRing) -> Vec<u32> {
+        let mut records = Vec::new();
+        ring.peek(usize::MAX, |payload| {
+            records.push(u32::from_le_bytes(payload.try_into().unwrap()));
+            true
+        }).unwrap();
+        records
+    }
+
+    #[test]
+    fn test_ring_drops_the_oldest_sector_when_it_wraps() {
+        let mut flash = MockFlash::new();
+        let mut ring = open_ring(&mut flash);
+
+        for record in 0..64u32 {
+            ring.push(&record.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!((ring.pending(), ring.dropped()), (64, 0));
+
+        // The ring is full, the next record takes the place of the oldest sector
+        ring.push(&64u32.to_le_bytes()).unwrap();
+        assert_eq!((ring.pending(), ring.dropped()), (33, 32));
+        assert_eq!(peek_all(&mut ring), (32..65).collect::<Vec<_>>());
+
+        assert_eq!(ring.push(&[0u8; 8]), Err("Record has the wrong length for its ring"));
+
+        // The first sector, outside the ring, was never touched
+        assert!(flash.bytes[..SECTOR as usize].iter().all(|&byte| byte == 0xFF));
+
+        // Records are read back after the wrap in the order they were written
+        let mut ring = open_ring(&mut flash);
+        assert_eq!((ring.pending(), ring.dropped()), (33, 0));
+        assert_eq!(peek_all(&mut ring), (32..65).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ring_recovers_after_restart() {
+        let mut flash = MockFlash::new();
+        let mut ring = open_ring(&mut flash);
+
+        for record in 0..5u32 {
+            ring.push(&record.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(ring.consume(2), Ok(2));
+
+        let mut ring = open_ring(&mut flash);
+        assert_eq!(ring.pending(), 3);
+        ring.push(&5u32.to_le_bytes()).unwrap();
+        assert_eq!(peek_all(&mut ring), [2, 3, 4, 5]);
+
+        // A record whose write was interrupted is dropped, and only counted once
+        flash.bytes[SECTOR as usize + 3 * 128 + SLOT_HEADER_LEN] ^= 1;
+
+        let mut ring = open_ring(&mut flash);
+        assert_eq!((ring.pending(), ring.dropped()), (3, 1));
+        assert_eq!(peek_all(&mut ring), [2, 4, 5]);
+
+        let mut ring = open_ring(&mut flash);
+        assert_eq!((ring.pending(), ring.dropped()), (3, 0));
+
+        // Records the caller can't decode are dropped as well
+        ring.peek(1, |payload| payload != 2u32.to_le_bytes()).unwrap();
+        assert_eq!((ring.pending(), ring.dropped()), (2, 1));
+        assert_eq!(peek_all(&mut ring), [4, 5]);
+    }
+
+    #[test]
+    fn test_ring_consumes_across_a_sector_boundary() {
+        let mut flash = MockFlash::new();
+        let mut ring = open_ring(&mut flash);
+
+        for record in 0..40u32 {
+            ring.push(&record.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(ring.consume(35), Ok(35));
+        assert_eq!(peek_all(&mut ring), (35..40).collect::<Vec<_>>());
+
+        let mut ring = open_ring(&mut flash);
+        assert_eq!(ring.pending(), 5);
+        assert_eq!(peek_all(&mut ring), (35..40).collect::<Vec<_>>());
+
+        assert_eq!(ring.consume(10), Ok(5));
+        assert_eq!(ring.pending(), 0);
+
+        let mut ring = open_ring(&mut flash);
+        assert_eq!(ring.pending(), 0);
+
+        // Wrapping into the first sector drops nothing, all of it was sent
+        for record in 40..70u32 {
+            ring.push(&record.to_le_bytes()).unwrap();
+        }
+
+        assert_eq!((ring.pending(), ring.dropped()), (30, 0));
+        assert_eq!(peek_all(&mut ring), (40..70).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_counter_log_keeps_the_latest_counters() {
+        let mut flash = MockFlash::new();
+        let entries = SECTOR / LOG_ENTRY_LEN as u32;
+
+        let mut log = CounterLog::<_, 2>::new(&mut flash, SECTOR);
+        assert_eq!(log.get(0x0dc4), 0);
+
+        log.record(0x0dc4, 5).unwrap();
+        log.record(0xfe88, 9).unwrap();
+        assert_eq!(log.record(0x1234, 1), Err("Counter log is full"));
+
+        // Enough counters to fill the first sector and move on to the second
+        for counter in 6..6 + entries {
+            log.record(0x0dc4, counter).unwrap();
+        }
+
+        let log = CounterLog::<_, 2>::new(&mut flash, SECTOR);
+        assert_eq!(log.get(0x0dc4), 5 + entries);
+        assert_eq!(log.get(0xfe88), 9);
+
+        // The first sector, outside the log, was never touched
+        assert!(flash.bytes[..SECTOR as usize].iter().all(|&byte| byte == 0xFF));
+
+        // A torn entry fails the record, and is skipped on load rather than taken as a lower counter
+        flash.tear_writes = true;
+        let mut log = CounterLog::<_, 2>::new(&mut flash, SECTOR);
+        assert_eq!(log.record(0xfe88, 10), Err("Failed to verify flash record"));
+        flash.tear_writes = false;
+
+        let mut log = CounterLog::<_, 2>::new(&mut flash, SECTOR);
+        assert_eq!(log.get(0xfe88), 9);
+
+        log.forget(0xfe88).unwrap();
+        log.record(0x1234, 1).unwrap();
+
+        let log = CounterLog::<_, 2>::new(&mut flash, SECTOR);
+        assert_eq!((log.get(0x0dc4), log.get(0xfe88), log.get(0x1234)), (5 + entries, 0, 1));
+    }
+}
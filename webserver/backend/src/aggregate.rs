@@ -0,0 +1,247 @@
+use serde::{ Serialize, Deserialize };
+use chrono::{ DateTime, NaiveDateTime };
+use database::models::AirQualityData;
+use crate::timestamps;
+
+/// Seconds between the Unix epoch (a Thursday) and the following Monday,
+/// used so that weekly buckets start on Mondays
+const WEEK_ALIGNMENT_SECONDS: i64 = 4 * 86_400;
+
+/// Fixed bucket widths supported by GET /airquality/aggregate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BucketSize {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+    #[serde(rename = "1w")]
+    OneWeek,
+}
+
+impl BucketSize {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            BucketSize::OneMinute => 60,
+            BucketSize::FiveMinutes => 5 * 60,
+            BucketSize::OneHour => 3_600,
+            BucketSize::OneDay => 86_400,
+            BucketSize::OneWeek => 7 * 86_400,
+        }
+    }
+
+    /// Returns the start of the bucket the timestamp falls into
+    pub fn bucket_start(&self, timestamp: NaiveDateTime) -> NaiveDateTime {
+        let offset = if *self == BucketSize::OneWeek { WEEK_ALIGNMENT_SECONDS } else { 0 };
+        let seconds = timestamp.and_utc().timestamp() - offset;
+        let start = seconds - seconds.rem_euclid(self.seconds()) + offset;
+
+        DateTime::from_timestamp(start, 0)
+            .map(|start| start.naive_utc())
+            .unwrap_or(timestamp)
+    }
+}
+
+/// Summary statistics for a single metric within a bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SummaryStatistics {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub count: usize,
+}
+
+/// Aggregated readings for one time bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregateBucket {
+    pub bucket_start: String,
+    /// Number of records in the bucket, regardless of which metrics they contain
+    pub count: usize,
+    pub temperature: Option<SummaryStatistics>,
+    pub pressure: Option<SummaryStatistics>,
+    pub humidity: Option<SummaryStatistics>,
+    pub pm1_0: Option<SummaryStatistics>,
+    pub pm2_5: Option<SummaryStatistics>,
+    pub pm10: Option<SummaryStatistics>,
+    pub co2: Option<SummaryStatistics>,
+    pub co: Option<SummaryStatistics>,
+    pub o3: Option<SummaryStatistics>,
+    pub no2: Option<SummaryStatistics>,
+    pub so2: Option<SummaryStatistics>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AggregateResponse {
+    /// The bucket width, or `None` when the whole range is summarised as one bucket
+    pub bucket: Option<BucketSize>,
+    pub buckets: Vec<AggregateBucket>,
+}
+
+/// Computes summary statistics for a set of values, ignoring NaNs
+pub fn summarize(values: impl IntoIterator<Item = f64>) -> Option<SummaryStatistics> {
+    let mut values: Vec<f64> = values.into_iter().filter(|value| !value.is_nan()).collect();
+
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(|a, b| a.total_cmp(b));
+
+    let count = values.len();
+    let mean = values.iter().sum::<f64>() / count as f64;
+
+    Some(SummaryStatistics {
+        min: values[0],
+        max: values[count - 1],
+        mean,
+        median: percentile(&values, 50.0),
+        p95: percentile(&values, 95.0),
+        count,
+    })
+}
+
+/// Percentile of sorted values using linear interpolation between the closest ranks
+fn percentile(sorted_values: &[f64], percentile: f64) -> f64 {
+    let rank = (percentile / 100.0) * (sorted_values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let weight = rank - lower as f64;
+
+    sorted_values[lower] + (sorted_values[upper] - sorted_values[lower]) * weight
+}
+
+fn aggregate_bucket(bucket_start: NaiveDateTime, records: &[AirQualityData]) -> AggregateBucket {
+    let summarize_field = |field: fn(&AirQualityData) -> Option<f64>| {
+        summarize(records.iter().filter_map(field))
+    };
+
+    AggregateBucket {
+        bucket_start: timestamps::format_utc(bucket_start),
+        count: records.len(),
+        temperature: summarize_field(|record| record.temperature),
+        pressure: summarize_field(|record| record.pressure),
+        humidity: summarize_field(|record| record.humidity),
+        pm1_0: summarize_field(|record| record.pm1_0),
+        pm2_5: summarize_field(|record| record.pm2_5),
+        pm10: summarize_field(|record| record.pm10),
+        co2: summarize_field(|record| record.co2),
+        co: summarize_field(|record| record.co),
+        o3: summarize_field(|record| record.o3),
+        no2: summarize_field(|record| record.no2),
+        so2: summarize_field(|record| record.so2),
+    }
+}
+
+/// Groups records into fixed buckets and summarises each one
+///
+/// Records must be sorted by timestamp. Without a bucket size all records are
+/// summarised into a single bucket starting at the earliest record.
+pub fn aggregate(records: &[AirQualityData], bucket: Option<BucketSize>) -> Vec<AggregateBucket> {
+    let Some(bucket) = bucket else {
+        return match records.first() {
+            Some(first) => vec![aggregate_bucket(first.timestamp, records)],
+            None => Vec::new(),
+        };
+    };
+
+    records
+        .chunk_by(|a, b| bucket.bucket_start(a.timestamp) == bucket.bucket_start(b.timestamp))
+        .map(|chunk| aggregate_bucket(bucket.bucket_start(chunk[0].timestamp), chunk))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(timestamp: &str, pm2_5: Option<f64>) -> AirQualityData {
+        AirQualityData {
+            id: 0,
+            timestamp: NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap(),
+            longitude: None,
+            latitude: None,
+            location: None,
+            temperature: None,
+            pressure: None,
+            humidity: None,
+            pm1_0: None,
+            pm2_5,
+            pm10: None,
+            co2: None,
+            co: None,
+            o3: None,
+            device_id: None,
+            quality_flags: 0,
+            no2: None,
+            so2: None,
+            node_id: None,
+        }
+    }
+
+    #[test]
+    fn test_summarize_values() {
+        let stats = summarize((1..=20).map(|value| value as f64)).unwrap();
+
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 20.0);
+        assert_eq!(stats.mean, 10.5);
+        assert_eq!(stats.median, 10.5);
+        assert!((stats.p95 - 19.05).abs() < 1e-9);
+        assert_eq!(stats.count, 20);
+
+        assert!(summarize(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_bucket_start_alignment() {
+        let timestamp = NaiveDateTime::parse_from_str("2025-03-30 12:34:56", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        let expected = [
+            (BucketSize::OneMinute, "2025-03-30 12:34:00"),
+            (BucketSize::FiveMinutes, "2025-03-30 12:30:00"),
+            (BucketSize::OneHour, "2025-03-30 12:00:00"),
+            (BucketSize::OneDay, "2025-03-30 00:00:00"),
+            // 2025-03-30 is a Sunday, so its week starts on Monday the 24th
+            (BucketSize::OneWeek, "2025-03-24 00:00:00"),
+        ];
+
+        for (bucket, start) in expected {
+            assert_eq!(bucket.bucket_start(timestamp).to_string(), start, "{:?}", bucket);
+        }
+    }
+
+    #[test]
+    fn test_aggregate_into_buckets() {
+        let records = vec![
+            record("2025-03-30 10:05:00", Some(10.0)),
+            record("2025-03-30 10:35:00", Some(20.0)),
+            record("2025-03-30 10:50:00", None),
+            record("2025-03-30 12:15:00", Some(40.0)),
+        ];
+
+        let buckets = aggregate(&records, Some(BucketSize::OneHour));
+
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].bucket_start, "2025-03-30T10:00:00Z");
+        assert_eq!(buckets[0].count, 3);
+        assert_eq!(buckets[0].pm2_5.as_ref().unwrap().mean, 15.0);
+        assert_eq!(buckets[0].pm2_5.as_ref().unwrap().count, 2);
+        assert!(buckets[0].co.is_none());
+        assert_eq!(buckets[1].bucket_start, "2025-03-30T12:00:00Z");
+        assert_eq!(buckets[1].pm2_5.as_ref().unwrap().max, 40.0);
+
+        let summary = aggregate(&records, None);
+
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].bucket_start, "2025-03-30T10:05:00Z");
+        assert_eq!(summary[0].count, 4);
+        assert_eq!(summary[0].pm2_5.as_ref().unwrap().median, 20.0);
+
+        assert!(aggregate(&[], Some(BucketSize::OneDay)).is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+use axum::http::{ HeaderMap, StatusCode };
+use diesel::sqlite::SqliteConnection;
+use database::devices::find_device_by_api_key;
+use database::models::Device;
+
+/// Header carrying the per-device API key on ingestion requests
+pub const DEVICE_KEY_HEADER: &str = "X-Device-Key";
+
+/// Resolves the device sending a request from its `X-Device-Key` header
+///
+/// Missing or unknown keys are rejected with 401, deactivated devices with 403.
+pub fn authenticate_device(conn: &mut SqliteConnection, headers: &HeaderMap) -> Result<Device, (StatusCode, String)> {
+    let api_key = headers
+        .get(DEVICE_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.trim().is_empty())
+        .ok_or((StatusCode::UNAUTHORIZED, format!("Missing {} header", DEVICE_KEY_HEADER)))?;
+
+    let device = find_device_by_api_key(conn, api_key)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::UNAUTHORIZED, "Unknown device key".to_string()))?;
+
+    if !device.active {
+        return Err((StatusCode::FORBIDDEN, format!("Device {} is deactivated", device.id)));
+    }
+
+    Ok(device)
+}
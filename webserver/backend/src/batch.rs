@@ -0,0 +1,158 @@
+use serde::{ Serialize, Deserialize };
+use crate::handlers::AirQualityInputOutput;
+
+/// Maximum number of records a single POST /airquality/batch request may contain
+pub const MAX_BATCH_RECORDS: usize = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordStatus {
+    Accepted,
+    /// A record for the same device and timestamp already exists
+    Duplicate,
+    Rejected,
+}
+
+/// State of a communication module's upload queue, sent with every record it uploads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct QueueCounters {
+    /// Readings queued, uploaded and dropped since the module booted
+    pub queued: u32,
+    pub sent: u32,
+    pub dropped: u32,
+    /// Readings still waiting in the module's flash
+    pub pending: u32,
+}
+
+/// Outcome of a single record within a batch
+#[derive(Debug, Serialize)]
+pub struct RecordResult {
+    /// Position of the record in the request, starting at 0
+    pub index: usize,
+    pub status: RecordStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quality_flags: Vec<String>,
+}
+
+impl RecordResult {
+    pub fn accepted(index: usize, quality_flags: Vec<String>) -> Self {
+        RecordResult { index, status: RecordStatus::Accepted, error: None, quality_flags }
+    }
+
+    pub fn duplicate(index: usize) -> Self {
+        RecordResult { index, status: RecordStatus::Duplicate, error: None, quality_flags: Vec::new() }
+    }
+
+    pub fn rejected(index: usize, error: String) -> Self {
+        RecordResult { index, status: RecordStatus::Rejected, error: Some(error), quality_flags: Vec::new() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub accepted: usize,
+    pub duplicates: usize,
+    pub rejected: usize,
+    pub results: Vec<RecordResult>,
+}
+
+impl BatchResponse {
+    pub fn new(results: Vec<RecordResult>) -> Self {
+        let count = |status| results.iter().filter(|result| result.status == status).count();
+
+        BatchResponse {
+            accepted: count(RecordStatus::Accepted),
+            duplicates: count(RecordStatus::Duplicate),
+            rejected: count(RecordStatus::Rejected),
+            results,
+        }
+    }
+}
+
+/// Parses a batch body given either as a JSON array or as newline-delimited JSON
+///
+/// A body that cannot be split into records is rejected as a whole, while records that
+/// fail to parse are returned as errors so the rest of the batch can still be ingested.
+pub fn parse_batch(body: &str) -> Result<Vec<Result<AirQualityInputOutput, String>>, String> {
+    let body = body.trim();
+
+    if body.is_empty() {
+        return Err("Empty batch".to_string());
+    }
+
+    let records: Vec<Result<AirQualityInputOutput, String>> = if body.starts_with('[') {
+        serde_json::from_str::<Vec<serde_json::Value>>(body)
+            .map_err(|e| format!("Invalid JSON array: {}", e))?
+            .into_iter()
+            .map(|value| serde_json::from_value(value).map_err(|e| format!("Invalid record: {}", e)))
+            .collect()
+    } else {
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid record: {}", e)))
+            .collect()
+    };
+
+    if records.len() > MAX_BATCH_RECORDS {
+        return Err(format!("Batch too large: at most {} records are accepted", MAX_BATCH_RECORDS));
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_array() {
+        let records = parse_batch(r#"[
+            { "timestamp": "2025-03-30 12:00:00", "pm2_5": 10.0 },
+            { "timestamp": "2025-03-30 12:01:00", "pm2_5": 11.0, "queue": { "pending": 3 } },
+            { "pm2_5": 12.0 }
+        ]"#).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].as_ref().unwrap().timestamp, "2025-03-30 12:00:00");
+        assert_eq!(records[1].as_ref().unwrap().pm2_5, Some(11.0));
+        assert_eq!(records[0].as_ref().unwrap().queue, None);
+        assert_eq!(records[1].as_ref().unwrap().queue, Some(QueueCounters { pending: 3, ..Default::default() }));
+        assert!(records[2].is_err(), "Records without a timestamp should be rejected");
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let body = "{\"timestamp\": \"2025-03-30 12:00:00\", \"co2\": 410.0}\n\n{not json}\r\n{\"timestamp\": \"2025-03-30 12:01:00\"}\n";
+        let records = parse_batch(body).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].as_ref().unwrap().co2, Some(410.0));
+        assert!(records[1].is_err());
+        assert_eq!(records[2].as_ref().unwrap().timestamp, "2025-03-30 12:01:00");
+    }
+
+    #[test]
+    fn test_invalid_batches_are_rejected() {
+        assert!(parse_batch("").is_err());
+        assert!(parse_batch("  \n ").is_err());
+        assert!(parse_batch("[{\"timestamp\": ").is_err());
+
+        let too_large = format!("[{}]", vec!["{}"; MAX_BATCH_RECORDS + 1].join(","));
+        assert!(parse_batch(&too_large).is_err());
+    }
+
+    #[test]
+    fn test_response_counts() {
+        let response = BatchResponse::new(vec![
+            RecordResult::accepted(0, Vec::new()),
+            RecordResult::duplicate(1),
+            RecordResult::rejected(2, "Invalid timestamp".to_string()),
+            RecordResult::accepted(3, vec!["co".to_string()]),
+        ]);
+
+        assert_eq!((response.accepted, response.duplicates, response.rejected), (2, 1, 1));
+    }
+}
@@ -1,6 +1,13 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use tokio::{ sync::Mutex, time::Instant };
+
+/// Nominatim's usage policy allows at most one request per second
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the last request to Nominatim was made, shared by every handler
+static LAST_REQUEST: Mutex<Option<Instant>> = Mutex::const_new(None);
 
 #[derive(Debug, Serialize, Deserialize)]
 struct NominatimResponse {
@@ -35,6 +42,17 @@ pub async fn reverse_geocode(latitude: f64, longitude: f64) -> Result<String, St
         latitude, longitude
     );
     
+    // Requests are spaced out even when several uploads geocode at the same time
+    {
+        let mut last_request = LAST_REQUEST.lock().await;
+
+        if let Some(last) = *last_request {
+            tokio::time::sleep_until(last + MIN_REQUEST_INTERVAL).await;
+        }
+
+        *last_request = Some(Instant::now());
+    }
+
     let response = client.get(&url)
         .send()
         .await
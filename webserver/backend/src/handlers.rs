@@ -1,5 +1,6 @@
+use std::collections::HashMap;
 use serde::{ Serialize, Deserialize };
-use axum::{ Extension, Json };
+use axum::{ extract::Query, http::{ HeaderMap, StatusCode }, Extension, Json };
 use chrono::NaiveDateTime;
 use serde_json::json;
 use diesel::prelude::*;
@@ -7,6 +8,12 @@ use database::models::{AirQualityData, NewAirQualityData};
 use database::schema::air_quality_data::dsl::air_quality_data;
 use crate::database::DatabasePool;
 use crate::geocoding::reverse_geocode;
+use crate::auth::authenticate_device;
+use crate::query::{ AirQualityQuery, AggregateQuery };
+use crate::aggregate::{ aggregate, AggregateResponse };
+use crate::quality::{ sanitize, flag_names };
+use crate::batch::{ parse_batch, BatchResponse, QueueCounters, RecordResult };
+use crate::timestamps;
 
 /// Helper function to get location from coordinates
 /// This is extracted to make it easier to test
@@ -28,6 +35,7 @@ pub async fn get_location_from_coordinates(latitude: Option<f64>, longitude: Opt
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AirQualityInputOutput {
+    // RFC 3339 with an offset on input, always returned in UTC
     pub timestamp: String,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
@@ -42,26 +50,37 @@ pub struct AirQualityInputOutput {
     pub pm10: Option<f64>,
     pub co2: Option<f64>,
     pub co: Option<f64>,
-    pub o3: Option<f64>
+    pub o3: Option<f64>,
+    pub no2: Option<f64>,
+    pub so2: Option<f64>,
+    // The device is identified by the backend from the X-Device-Key header
+    // It should not be provided in the input, but will be included in the output
+    pub device_id: Option<i32>,
+    // Set by communication modules that relay readings from several sensor nodes
+    pub node_id: Option<i32>,
+    // Metrics discarded by the backend as sentinel or out-of-range values
+    // It should not be provided in the input, but will be included in the output
+    #[serde(default)]
+    pub quality_flags: Vec<String>,
+    // Upload queue of the communication module at the time of the upload, logged rather than stored
+    #[serde(default, skip_serializing)]
+    pub queue: Option<QueueCounters>
 }
 
-pub async fn create_air_quality_record(
-    Extension(pool): Extension<DatabasePool>,
-    Json(input): Json<AirQualityInputOutput>,
-) -> Result<Json<serde_json::Value>, String> {
-
-    let mut conn = pool.get().map_err(|e| e.to_string())?;
-
-    let timestamp = NaiveDateTime::parse_from_str(&input.timestamp, "%Y-%m-%d %H:%M:%S")
-    .map_err(|e| format!("Invalid timestamp: {}", e))?;
-
-    // Perform reverse geocoding if latitude and longitude are provided
-    // Note: We always use geocoding for location when coordinates are available,
-    // and we ignore any location that might have been provided in the input
-    let location = get_location_from_coordinates(input.latitude, input.longitude).await;
+fn parse_timestamp(value: &str) -> Result<NaiveDateTime, String> {
+    timestamps::parse_utc(value)
+        .ok_or_else(|| format!("Invalid timestamp '{}': expected RFC 3339, e.g. 2025-03-30T12:34:56+03:00", value))
+}
 
-    let new_record = NewAirQualityData {
-        timestamp,
+/// Builds the record to insert for a device, discarding sentinel and out-of-range readings
+fn new_record(
+    input: AirQualityInputOutput,
+    record_timestamp: NaiveDateTime,
+    location: Option<String>,
+    record_device_id: i32,
+) -> NewAirQualityData {
+    let mut new_record = NewAirQualityData {
+        timestamp: record_timestamp,
         longitude: input.longitude,
         latitude: input.latitude,
         location,
@@ -74,27 +93,175 @@ pub async fn create_air_quality_record(
         co2: input.co2,
         co: input.co,
         o3: input.o3,
+        device_id: Some(record_device_id),
+        quality_flags: 0,
+        no2: input.no2,
+        so2: input.so2,
+        node_id: input.node_id,
     };
 
-    diesel::insert_into(air_quality_data)
+    // Failed sensor reads are stored as NULL rather than as the values older firmware substitutes
+    sanitize(&mut new_record);
+
+    new_record
+}
+
+pub async fn create_air_quality_record(
+    Extension(pool): Extension<DatabasePool>,
+    headers: HeaderMap,
+    Json(input): Json<AirQualityInputOutput>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+
+    let mut conn = pool.get().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let device = authenticate_device(&mut conn, &headers)?;
+
+    let record_timestamp = parse_timestamp(&input.timestamp).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    // Perform reverse geocoding if latitude and longitude are provided
+    // Note: We always use geocoding for location when coordinates are available,
+    // and we ignore any location that might have been provided in the input
+    let location = get_location_from_coordinates(input.latitude, input.longitude).await;
+
+    let new_record = new_record(input, record_timestamp, location, device.id);
+
+    // A reading the module already uploaded, e.g. when the response to its POST was lost, is stored once
+    let inserted = diesel::insert_or_ignore_into(air_quality_data)
     .values(&new_record)
     .execute(&mut conn)
-    .map_err(|e| e.to_string())?;
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    Ok(Json(json!({ "status": "success" })))
+    if inserted == 0 {
+        return Ok(Json(json!({ "status": "duplicate" })));
+    }
+
+    Ok(Json(json!({ "status": "success", "quality_flags": flag_names(new_record.quality_flags) })))
+}
+
+/// Distinct places reverse geocoded for one batch, each lookup takes at least a second
+const MAX_GEOCODED_PER_BATCH: usize = 5;
+
+/// Coordinates rounded to 4 decimal places, about 11 m, so GPS jitter doesn't make every fix a new place
+fn location_key(latitude: f64, longitude: f64) -> (i64, i64) {
+    ((latitude * 1e4).round() as i64, (longitude * 1e4).round() as i64)
+}
+
+/// Ingests a JSON array or newline-delimited JSON of records from one device
+///
+/// All records are inserted in a single transaction. Records that duplicate an existing
+/// (device, node, timestamp) triple, or an earlier record in the batch, are skipped.
+/// Nodes polled in the same round share a timestamp, so the node id is part of the key.
+/// Only the first few distinct places in a batch are geocoded, later ones have no location.
+pub async fn create_air_quality_batch(
+    Extension(pool): Extension<DatabasePool>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<BatchResponse>, (StatusCode, String)> {
+
+    // The connection goes back to the pool while the batch is geocoded
+    let device = {
+        let mut conn = pool.get().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        authenticate_device(&mut conn, &headers)?
+    };
+
+    let inputs = parse_batch(&body).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    // Nodes flushing a backlog mostly report the same place, so each is geocoded once
+    let mut locations: HashMap<(i64, i64), Option<String>> = HashMap::new();
+    let mut prepared = Vec::with_capacity(inputs.len());
+    // The module's queue as of its latest record, to tell when it is dropping or falling behind on readings
+    let mut queue = None;
+
+    for input in inputs {
+        let input = match input {
+            Ok(input) => input,
+            Err(e) => {
+                prepared.push(Err(e));
+                continue;
+            }
+        };
+
+        queue = input.queue.or(queue);
+
+        let record_timestamp = match parse_timestamp(&input.timestamp) {
+            Ok(record_timestamp) => record_timestamp,
+            Err(e) => {
+                prepared.push(Err(e));
+                continue;
+            }
+        };
+
+        let location = match (input.latitude, input.longitude) {
+            (Some(latitude), Some(longitude)) => {
+                let key = location_key(latitude, longitude);
+
+                // Places past the limit are stored without a location rather than holding up the upload
+                if !locations.contains_key(&key) && locations.len() < MAX_GEOCODED_PER_BATCH {
+                    locations.insert(key, get_location_from_coordinates(input.latitude, input.longitude).await);
+                }
+
+                locations.get(&key).cloned().flatten()
+            },
+            _ => None
+        };
+
+        prepared.push(Ok(new_record(input, record_timestamp, location, device.id)));
+    }
+
+    let mut conn = pool.get().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let results = conn.immediate_transaction(|conn| {
+        let mut results = Vec::with_capacity(prepared.len());
+
+        for (index, record) in prepared.into_iter().enumerate() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    results.push(RecordResult::rejected(index, e));
+                    continue;
+                }
+            };
+
+            // The UNIQUE (device, node, timestamp) index also catches repeats within the batch
+            let inserted = diesel::insert_or_ignore_into(air_quality_data).values(&record).execute(conn)?;
+
+            if inserted == 0 {
+                results.push(RecordResult::duplicate(index));
+                continue;
+            }
+
+            results.push(RecordResult::accepted(index, flag_names(record.quality_flags)));
+        }
+
+        diesel::QueryResult::Ok(results)
+    }).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if let Some(queue) = queue {
+        println!(
+            "Device {} upload queue: {} queued, {} sent, {} dropped, {} pending",
+            device.id, queue.queued, queue.sent, queue.dropped, queue.pending
+        );
+    }
+
+    Ok(Json(BatchResponse::new(results)))
 }
 
 pub async fn get_air_quality_record(
-    Extension(pool): Extension<DatabasePool>
-) -> Result<Json<Vec<AirQualityInputOutput>>, String> {
+    Extension(pool): Extension<DatabasePool>,
+    Query(query): Query<AirQualityQuery>,
+) -> Result<Json<Vec<AirQualityInputOutput>>, (StatusCode, String)> {
+
+    let filter = query.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
-    let mut conn = pool.get().map_err(|e| e.to_string())?;
+    let mut conn = pool.get().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let records = air_quality_data.load::<AirQualityData>(&mut conn).map_err(|e| e.to_string())?;
+    let records = filter.apply(air_quality_data.into_boxed())
+    .load::<AirQualityData>(&mut conn)
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
     let output: Vec<AirQualityInputOutput> = records.into_iter().map(|record| {
         AirQualityInputOutput {
-            timestamp: record.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+            timestamp: timestamps::format_utc(record.timestamp),
             longitude: record.longitude,
             latitude: record.latitude,
             location: record.location,
@@ -107,12 +274,38 @@ pub async fn get_air_quality_record(
             co2: record.co2,
             co: record.co,
             o3: record.o3,
+            no2: record.no2,
+            so2: record.so2,
+            device_id: record.device_id,
+            node_id: record.node_id,
+            quality_flags: flag_names(record.quality_flags),
+            queue: None,
         }
     }).collect();
 
     Ok(Json(output))
 }
 
+pub async fn get_air_quality_aggregate(
+    Extension(pool): Extension<DatabasePool>,
+    Query(query): Query<AggregateQuery>,
+) -> Result<Json<AggregateResponse>, (StatusCode, String)> {
+
+    let (filter, bucket) = query.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let mut conn = pool.get().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Records are loaded in timestamp order so that buckets can be formed in a single pass
+    let records = filter.apply(air_quality_data.into_boxed())
+    .load::<AirQualityData>(&mut conn)
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(AggregateResponse {
+        bucket,
+        buckets: aggregate(&records, bucket),
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
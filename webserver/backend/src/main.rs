@@ -3,11 +3,17 @@ use dotenvy::dotenv;
 use tower_http::cors::{ CorsLayer, Any };
 
 use database::establish_connection_pool;
-use handlers::{create_air_quality_record, get_air_quality_record};
+use handlers::{create_air_quality_record, create_air_quality_batch, get_air_quality_record, get_air_quality_aggregate};
 
+mod aggregate;
+mod auth;
+mod batch;
 mod database;
 mod handlers;
 mod geocoding;
+mod query;
+mod quality;
+mod timestamps;
 
 #[tokio::main]
 async fn main() {
@@ -20,6 +26,8 @@ async fn main() {
     let app = Router::new()
     .route("/airquality", get(get_air_quality_record))
     .route("/airquality", post(create_air_quality_record))
+    .route("/airquality/batch", post(create_air_quality_batch))
+    .route("/airquality/aggregate", get(get_air_quality_aggregate))
     .layer(cors)
     .layer(Extension(pool));
 
@@ -0,0 +1,164 @@
+use std::ops::RangeInclusive;
+use database::models::NewAirQualityData;
+
+/// Placeholder value older sensor node firmware sends when a sensor read fails
+pub const SENTINEL_VALUE: f64 = 999.0;
+
+/// Pressures older clients send in hectopascals, stored in pascals like the sensor nodes send them
+const PRESSURE_HPA: RangeInclusive<f64> = 300.0..=1_100.0;
+
+/// Plausibility check for a single metric
+///
+/// Each metric owns one bit of the `quality_flags` column, set when its value
+/// was discarded on ingest.
+struct QualityCheck {
+    name: &'static str,
+    flag: i32,
+    range: RangeInclusive<f64>,
+    /// Whether an exact `SENTINEL_VALUE` is treated as a failed read. This is
+    /// disabled for metrics where 999 is a perfectly plausible reading, e.g. CO2 in ppm.
+    sentinel: bool,
+}
+
+impl QualityCheck {
+    fn accepts(&self, value: f64) -> bool {
+        self.range.contains(&value) && !(self.sentinel && value == SENTINEL_VALUE)
+    }
+}
+
+/// Checks in the same order as `readings`, which also determines each metric's flag bit
+const CHECKS: [QualityCheck; 11] = [
+    QualityCheck { name: "temperature", flag: 1 << 0, range: -40.0..=85.0, sentinel: false },
+    // Pascals, as the BME280 and BME680 drivers report it
+    QualityCheck { name: "pressure", flag: 1 << 1, range: 30_000.0..=110_000.0, sentinel: false },
+    QualityCheck { name: "humidity", flag: 1 << 2, range: 0.0..=100.0, sentinel: false },
+    QualityCheck { name: "pm1_0", flag: 1 << 3, range: 0.0..=1_000.0, sentinel: true },
+    QualityCheck { name: "pm2_5", flag: 1 << 4, range: 0.0..=1_000.0, sentinel: true },
+    QualityCheck { name: "pm10", flag: 1 << 5, range: 0.0..=1_000.0, sentinel: true },
+    QualityCheck { name: "co2", flag: 1 << 6, range: 0.0..=10_000.0, sentinel: false },
+    QualityCheck { name: "co", flag: 1 << 7, range: 0.0..=2_000.0, sentinel: true },
+    QualityCheck { name: "o3", flag: 1 << 8, range: 0.0..=1_000.0, sentinel: false },
+    QualityCheck { name: "no2", flag: 1 << 9, range: 0.0..=20_000.0, sentinel: false },
+    QualityCheck { name: "so2", flag: 1 << 10, range: 0.0..=20_000.0, sentinel: false },
+];
+
+fn readings(record: &mut NewAirQualityData) -> [&mut Option<f64>; 11] {
+    [
+        &mut record.temperature,
+        &mut record.pressure,
+        &mut record.humidity,
+        &mut record.pm1_0,
+        &mut record.pm2_5,
+        &mut record.pm10,
+        &mut record.co2,
+        &mut record.co,
+        &mut record.o3,
+        &mut record.no2,
+        &mut record.so2,
+    ]
+}
+
+/// Replaces sentinel and out-of-range readings with `None`, recording each
+/// discarded metric in the record's `quality_flags`
+///
+/// Pressures in hectopascals are converted to pascals first, so a single unit is stored.
+pub fn sanitize(record: &mut NewAirQualityData) {
+    record.pressure = record.pressure.map(|pressure| if PRESSURE_HPA.contains(&pressure) { pressure * 100.0 } else { pressure });
+
+    let mut flags = 0;
+
+    for (check, value) in CHECKS.iter().zip(readings(record)) {
+        if value.is_some_and(|value| !check.accepts(value)) {
+            *value = None;
+            flags |= check.flag;
+        }
+    }
+
+    record.quality_flags = flags;
+}
+
+/// Names of the metrics flagged in a `quality_flags` value
+pub fn flag_names(flags: i32) -> Vec<String> {
+    CHECKS
+        .iter()
+        .filter(|check| flags & check.flag != 0)
+        .map(|check| check.name.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn record() -> NewAirQualityData {
+        NewAirQualityData {
+            timestamp: NaiveDateTime::parse_from_str("2025-03-30 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            longitude: None,
+            latitude: None,
+            location: None,
+            temperature: Some(22.5),
+            pressure: Some(101_230.0),
+            humidity: Some(45.0),
+            pm1_0: Some(5.0),
+            pm2_5: Some(12.0),
+            pm10: Some(20.0),
+            co2: Some(999.0),
+            co: Some(3.0),
+            o3: None,
+            device_id: None,
+            quality_flags: 0,
+            no2: Some(21.0),
+            so2: None,
+            node_id: None,
+        }
+    }
+
+    #[test]
+    fn test_valid_readings_are_kept() {
+        let mut valid = record();
+        sanitize(&mut valid);
+
+        assert_eq!(valid.quality_flags, 0);
+        assert_eq!(valid.co2, Some(999.0), "999 ppm is a plausible CO2 reading");
+        assert_eq!(valid.pm2_5, Some(12.0));
+        assert!(valid.o3.is_none());
+        assert_eq!(valid.no2, Some(21.0));
+    }
+
+    #[test]
+    fn test_pressure_is_stored_in_pascals() {
+        let mut hectopascals = NewAirQualityData { pressure: Some(1012.5), ..record() };
+        sanitize(&mut hectopascals);
+
+        assert_eq!(hectopascals.pressure, Some(101_250.0));
+        assert_eq!(hectopascals.quality_flags, 0);
+
+        // Neither unit, e.g. kilopascals, is discarded rather than mixed in
+        let mut kilopascals = NewAirQualityData { pressure: Some(101.3), ..record() };
+        sanitize(&mut kilopascals);
+
+        assert!(kilopascals.pressure.is_none());
+        assert_eq!(flag_names(kilopascals.quality_flags), vec!["pressure"]);
+    }
+
+    #[test]
+    fn test_sentinel_and_out_of_range_readings_are_flagged() {
+        let mut invalid = NewAirQualityData {
+            pm2_5: Some(SENTINEL_VALUE),
+            co: Some(SENTINEL_VALUE),
+            humidity: Some(140.0),
+            temperature: Some(-273.0),
+            so2: Some(-1.0),
+            ..record()
+        };
+        sanitize(&mut invalid);
+
+        assert!(invalid.pm2_5.is_none());
+        assert!(invalid.co.is_none());
+        assert!(invalid.humidity.is_none());
+        assert!(invalid.temperature.is_none());
+        assert_eq!(invalid.pm10, Some(20.0));
+        assert_eq!(flag_names(invalid.quality_flags), vec!["temperature", "humidity", "pm2_5", "co", "so2"]);
+    }
+}
@@ -0,0 +1,289 @@
+use serde::Deserialize;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sqlite::Sqlite;
+use database::schema::air_quality_data;
+use crate::aggregate::BucketSize;
+use crate::timestamps;
+
+/// Maximum number of records a single GET /airquality request may return
+pub const MAX_LIMIT: i64 = 10_000;
+
+/// Raw query parameters accepted by GET /airquality
+///
+/// All parameters are optional, e.g.
+/// `/airquality?start=2025-03-30 00:00:00&end=2025-03-31 00:00:00&location=Kilimani, Nairobi, Kenya&limit=500&order=desc`
+#[derive(Debug, Default, Deserialize)]
+pub struct AirQualityQuery {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub location: Option<String>,
+    /// Bounding box as `min_lon,min_lat,max_lon,max_lat`
+    pub bbox: Option<String>,
+    pub device_id: Option<i32>,
+    pub node_id: Option<i32>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Either `asc` (default) or `desc`, ordered by timestamp
+    pub order: Option<String>,
+}
+
+/// Raw query parameters accepted by GET /airquality/aggregate
+///
+/// Without a `bucket` the whole time range is summarised as a single bucket, e.g.
+/// `/airquality/aggregate?start=2025-03-30 00:00:00&end=2025-03-31 00:00:00&bucket=1h`
+#[derive(Debug, Default, Deserialize)]
+pub struct AggregateQuery {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub location: Option<String>,
+    pub bbox: Option<String>,
+    pub device_id: Option<i32>,
+    pub node_id: Option<i32>,
+    pub bucket: Option<BucketSize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Validated filter that can be pushed down into a Diesel query
+#[derive(Debug, Default, PartialEq)]
+pub struct AirQualityFilter {
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+    pub location: Option<String>,
+    pub bbox: Option<BoundingBox>,
+    pub device_id: Option<i32>,
+    pub node_id: Option<i32>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub order: SortOrder,
+}
+
+pub type BoxedAirQualityQuery<'a> = air_quality_data::BoxedQuery<'a, Sqlite>;
+
+impl AirQualityQuery {
+    /// Validates the raw parameters, returning a message suitable for a 400 response on failure
+    pub fn validate(self) -> Result<AirQualityFilter, String> {
+        let start = self.start.as_deref().map(|s| parse_query_timestamp("start", s)).transpose()?;
+        let end = self.end.as_deref().map(|s| parse_query_timestamp("end", s)).transpose()?;
+
+        if let (Some(start), Some(end)) = (start, end) && start > end {
+            return Err("Invalid time range: start must not be after end".to_string());
+        }
+
+        let location = match self.location {
+            Some(location) if location.trim().is_empty() => {
+                return Err("Invalid location: must not be empty".to_string());
+            },
+            location => location,
+        };
+
+        let bbox = self.bbox.as_deref().map(parse_bbox).transpose()?;
+
+        if self.limit.is_some_and(|limit| !(1..=MAX_LIMIT).contains(&limit)) {
+            return Err(format!("Invalid limit: must be between 1 and {}", MAX_LIMIT));
+        }
+
+        if self.offset.is_some_and(|offset| offset < 0) {
+            return Err("Invalid offset: must not be negative".to_string());
+        }
+
+        let order = match self.order.as_deref() {
+            None | Some("asc") => SortOrder::Ascending,
+            Some("desc") => SortOrder::Descending,
+            Some(other) => return Err(format!("Invalid order '{}': expected 'asc' or 'desc'", other)),
+        };
+
+        Ok(AirQualityFilter {
+            start,
+            end,
+            location,
+            bbox,
+            device_id: self.device_id,
+            node_id: self.node_id,
+            limit: self.limit,
+            offset: self.offset,
+            order,
+        })
+    }
+}
+
+impl AggregateQuery {
+    /// Validates the time range, location and bounding box the same way as GET /airquality
+    pub fn validate(self) -> Result<(AirQualityFilter, Option<BucketSize>), String> {
+        let filter = AirQualityQuery {
+            start: self.start,
+            end: self.end,
+            location: self.location,
+            bbox: self.bbox,
+            device_id: self.device_id,
+            node_id: self.node_id,
+            ..Default::default()
+        }.validate()?;
+
+        Ok((filter, self.bucket))
+    }
+}
+
+impl AirQualityFilter {
+    /// Applies the time range, location, bounding box, device and node filters to a boxed query
+    pub fn apply_filters<'a>(&self, mut query: BoxedAirQualityQuery<'a>) -> BoxedAirQualityQuery<'a> {
+        if let Some(start) = self.start {
+            query = query.filter(air_quality_data::timestamp.ge(start));
+        }
+
+        if let Some(end) = self.end {
+            query = query.filter(air_quality_data::timestamp.le(end));
+        }
+
+        if let Some(location) = &self.location {
+            query = query.filter(air_quality_data::location.eq(location.clone()));
+        }
+
+        if let Some(bbox) = self.bbox {
+            query = query
+                .filter(air_quality_data::longitude.between(bbox.min_lon, bbox.max_lon))
+                .filter(air_quality_data::latitude.between(bbox.min_lat, bbox.max_lat));
+        }
+
+        if let Some(device_id) = self.device_id {
+            query = query.filter(air_quality_data::device_id.eq(device_id));
+        }
+
+        if let Some(node_id) = self.node_id {
+            query = query.filter(air_quality_data::node_id.eq(node_id));
+        }
+
+        query
+    }
+
+    /// Applies the filters along with ordering and pagination
+    pub fn apply<'a>(&self, query: BoxedAirQualityQuery<'a>) -> BoxedAirQualityQuery<'a> {
+        let mut query = self.apply_filters(query);
+
+        query = match self.order {
+            SortOrder::Ascending => query.order(air_quality_data::timestamp.asc()),
+            SortOrder::Descending => query.order(air_quality_data::timestamp.desc()),
+        };
+
+        if let Some(limit) = self.limit {
+            query = query.limit(limit);
+        }
+
+        if let Some(offset) = self.offset {
+            // SQLite only accepts OFFSET together with LIMIT
+            if self.limit.is_none() {
+                query = query.limit(-1);
+            }
+            query = query.offset(offset);
+        }
+
+        query
+    }
+}
+
+fn parse_query_timestamp(name: &str, value: &str) -> Result<NaiveDateTime, String> {
+    timestamps::parse_utc(value)
+        .ok_or_else(|| format!("Invalid {} timestamp '{}': expected RFC 3339 or YYYY-MM-DD HH:MM:SS in UTC", name, value))
+}
+
+fn parse_bbox(value: &str) -> Result<BoundingBox, String> {
+    let parts: Vec<f64> = value
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Invalid bbox '{}': expected min_lon,min_lat,max_lon,max_lat", value))?;
+
+    let [min_lon, min_lat, max_lon, max_lat] = parts[..] else {
+        return Err(format!("Invalid bbox '{}': expected min_lon,min_lat,max_lon,max_lat", value));
+    };
+
+    let longitudes_valid = (-180.0..=180.0).contains(&min_lon) && (-180.0..=180.0).contains(&max_lon);
+    let latitudes_valid = (-90.0..=90.0).contains(&min_lat) && (-90.0..=90.0).contains(&max_lat);
+
+    if !longitudes_valid || !latitudes_valid {
+        return Err(format!("Invalid bbox '{}': coordinates out of range", value));
+    }
+
+    if min_lon > max_lon || min_lat > max_lat {
+        return Err(format!("Invalid bbox '{}': minimum must not exceed maximum", value));
+    }
+
+    Ok(BoundingBox { min_lon, min_lat, max_lon, max_lat })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_query_is_valid() {
+        let filter = AirQualityQuery::default().validate().unwrap();
+        assert_eq!(filter, AirQualityFilter::default());
+    }
+
+    #[test]
+    fn test_full_query_is_parsed() {
+        let query = AirQualityQuery {
+            start: Some("2025-03-30 00:00:00".to_string()),
+            end: Some("2025-03-31T15:00:00+03:00".to_string()),
+            location: Some("Kilimani, Nairobi, Kenya".to_string()),
+            bbox: Some("36.7,-1.35,36.9,-1.2".to_string()),
+            device_id: Some(3),
+            node_id: Some(0x0dc4),
+            limit: Some(100),
+            offset: Some(200),
+            order: Some("desc".to_string()),
+        };
+
+        let filter = query.validate().unwrap();
+
+        assert_eq!(filter.start.unwrap().to_string(), "2025-03-30 00:00:00");
+        assert_eq!(filter.end.unwrap().to_string(), "2025-03-31 12:00:00");
+        assert_eq!(filter.location.as_deref(), Some("Kilimani, Nairobi, Kenya"));
+        assert_eq!(filter.bbox, Some(BoundingBox { min_lon: 36.7, min_lat: -1.35, max_lon: 36.9, max_lat: -1.2 }));
+        assert_eq!(filter.device_id, Some(3));
+        assert_eq!(filter.node_id, Some(0x0dc4));
+        assert_eq!(filter.limit, Some(100));
+        assert_eq!(filter.offset, Some(200));
+        assert_eq!(filter.order, SortOrder::Descending);
+    }
+
+    #[test]
+    fn test_invalid_parameters_are_rejected() {
+        let invalid_queries = [
+            AirQualityQuery { start: Some("yesterday".to_string()), ..Default::default() },
+            AirQualityQuery {
+                start: Some("2025-03-31 00:00:00".to_string()),
+                end: Some("2025-03-30 00:00:00".to_string()),
+                ..Default::default()
+            },
+            AirQualityQuery { location: Some("  ".to_string()), ..Default::default() },
+            AirQualityQuery { bbox: Some("36.7,-1.35,36.9".to_string()), ..Default::default() },
+            AirQualityQuery { bbox: Some("36.9,-1.35,36.7,-1.2".to_string()), ..Default::default() },
+            AirQualityQuery { bbox: Some("200,-1.35,210,-1.2".to_string()), ..Default::default() },
+            AirQualityQuery { limit: Some(0), ..Default::default() },
+            AirQualityQuery { limit: Some(MAX_LIMIT + 1), ..Default::default() },
+            AirQualityQuery { offset: Some(-1), ..Default::default() },
+            AirQualityQuery { order: Some("sideways".to_string()), ..Default::default() },
+        ];
+
+        for query in invalid_queries {
+            let description = format!("{:?}", query);
+            assert!(query.validate().is_err(), "Query should be rejected: {}", description);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use chrono::{ DateTime, NaiveDateTime, SecondsFormat };
+
+/// Formats without an offset, accepted from older clients and taken to be UTC
+const NAIVE_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"];
+
+/// Parses an RFC 3339 timestamp into the UTC time stored in the database
+pub fn parse_utc(value: &str) -> Option<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|datetime| datetime.naive_utc())
+        .ok()
+        .or_else(|| NAIVE_FORMATS.iter().find_map(|format| NaiveDateTime::parse_from_str(value, format).ok()))
+}
+
+/// Formats a stored UTC time as RFC 3339, e.g. `2025-03-30T12:34:56Z`
+pub fn format_utc(timestamp: NaiveDateTime) -> String {
+    timestamp.and_utc().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offsets_are_converted_to_utc() {
+        let expected = parse_utc("2025-03-30T12:34:56Z").unwrap();
+
+        assert_eq!(expected.to_string(), "2025-03-30 12:34:56");
+        assert_eq!(parse_utc("2025-03-30T15:34:56+03:00"), Some(expected));
+        assert_eq!(parse_utc("2025-03-30T07:34:56.000-05:00"), Some(expected));
+        assert_eq!(parse_utc("2025-03-30 12:34:56"), Some(expected));
+        assert_eq!(parse_utc("2025-03-30T12:34:56"), Some(expected));
+
+        assert_eq!(parse_utc("30/03/2025 12:34"), None);
+        assert_eq!(parse_utc("2025-03-30T12:34:56+25:00"), None);
+    }
+
+    #[test]
+    fn test_format_utc() {
+        let timestamp = parse_utc("2025-03-30T15:34:56+03:00").unwrap();
+
+        assert_eq!(format_utc(timestamp), "2025-03-30T12:34:56Z");
+    }
+}
@@ -19,6 +19,16 @@ struct AirQualityData {
     co2: Option<f64>,
     co: Option<f64>,
     o3: Option<f64>,
+    no2: Option<f64>,
+    so2: Option<f64>,
+    device_id: Option<i32>,
+    node_id: Option<i32>,
+    quality_flags: Vec<String>,
+}
+
+/// API key of a device registered with the `register_device` binary of the database crate
+fn device_key() -> String {
+    std::env::var("TEST_DEVICE_KEY").expect("TEST_DEVICE_KEY must be set to a registered device's API key")
 }
 
 #[tokio::test]
@@ -27,8 +37,11 @@ async fn test_create_air_quality_record() {
 
     let url = "http://127.0.0.1:3000/airquality";
 
+    // Readings are unique per device and timestamp, so every run posts a new one
+    let test_timestamp = format!("2025-03-31T{}+03:00", chrono::Utc::now().format("%H:%M:%S"));
+
     let payload = json!({
-        "timestamp": "2025-03-30 12:34:56",
+        "timestamp": test_timestamp,
         "longitude": -122.4194,
         "latitude": 37.7749,
         "temperature": 18.5,
@@ -42,7 +55,7 @@ async fn test_create_air_quality_record() {
         "o3": 0.03
     });
 
-    let response = client.post(url).json(&payload).send().await.unwrap();
+    let response = client.post(url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
 
     assert_eq!(response.status().as_u16(), 200);
 
@@ -58,6 +71,46 @@ async fn test_create_air_quality_record() {
     println!("Response Body: {:?}", response_body);
 
     assert_eq!(response_body["status"], "success");
+
+    // A module resending a reading whose response it missed must not store it twice
+    let response = client.post(url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(response_body["status"], "duplicate");
+}
+
+#[tokio::test]
+async fn test_create_requires_device_key() {
+    // This test verifies that:
+    // 1. Posting without an X-Device-Key header is rejected with a 401
+    // 2. Posting with an unknown key is rejected with a 401
+    // 3. Records posted with a valid key are attributed to the device
+
+    let client = Client::new();
+    let base_url = "http://127.0.0.1:3000/airquality";
+
+    let test_timestamp = format!("2025-03-30T{}Z", chrono::Utc::now().format("%H:%M:%S"));
+    let payload = json!({
+        "timestamp": test_timestamp,
+        "temperature": 18.5,
+        "pm2_5": 10.2
+    });
+
+    let response = client.post(base_url).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = client.post(base_url).header("X-Device-Key", "not-a-real-key").json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = client.post(base_url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = client.get(base_url).send().await.unwrap();
+    let records: Vec<AirQualityData> = response.json().await.unwrap();
+
+    let record = records.iter().find(|r| r.timestamp == test_timestamp).expect("Could not find our test record");
+    assert!(record.device_id.is_some(), "Record should be attributed to the posting device");
 }
 
 #[tokio::test]
@@ -70,7 +123,7 @@ async fn test_location_is_set_by_geocoding() {
     let base_url = "http://127.0.0.1:3000/airquality";
 
     // First, create a record with lat/long but no location
-    let test_timestamp = format!("2025-03-30 {}", chrono::Utc::now().format("%H:%M:%S"));
+    let test_timestamp = format!("2025-03-24T{}Z", chrono::Utc::now().format("%H:%M:%S"));
     let payload = json!({
         "timestamp": test_timestamp,
         "longitude": -122.4194,  // San Francisco coordinates
@@ -87,7 +140,7 @@ async fn test_location_is_set_by_geocoding() {
     });
 
     // Post the data
-    let response = client.post(base_url).json(&payload).send().await.unwrap();
+    let response = client.post(base_url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
     assert_eq!(response.status().as_u16(), 200);
 
     // Now retrieve all records
@@ -123,7 +176,7 @@ async fn test_provided_location_is_ignored() {
     let base_url = "http://127.0.0.1:3000/airquality";
 
     // Create a record with lat/long AND a location (which should be ignored)
-    let test_timestamp = format!("2025-03-30 {}", chrono::Utc::now().format("%H:%M:%S"));
+    let test_timestamp = format!("2025-03-23T{}Z", chrono::Utc::now().format("%H:%M:%S"));
     let payload = json!({
         "timestamp": test_timestamp,
         "longitude": -122.4194,  // San Francisco coordinates
@@ -141,7 +194,7 @@ async fn test_provided_location_is_ignored() {
     });
 
     // Post the data
-    let response = client.post(base_url).json(&payload).send().await.unwrap();
+    let response = client.post(base_url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
     assert_eq!(response.status().as_u16(), 200);
 
     // Now retrieve all records
@@ -177,7 +230,7 @@ async fn test_no_location_when_coordinates_missing() {
     let base_url = "http://127.0.0.1:3000/airquality";
 
     // Create a record without lat/long but with a location (which should be ignored)
-    let test_timestamp = format!("2025-03-30 {}", chrono::Utc::now().format("%H:%M:%S"));
+    let test_timestamp = format!("2025-03-22T{}Z", chrono::Utc::now().format("%H:%M:%S"));
     let payload = json!({
         "timestamp": test_timestamp,
         // No longitude or latitude
@@ -194,7 +247,7 @@ async fn test_no_location_when_coordinates_missing() {
     });
 
     // Post the data
-    let response = client.post(base_url).json(&payload).send().await.unwrap();
+    let response = client.post(base_url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
     assert_eq!(response.status().as_u16(), 200);
 
     // Now retrieve all records
@@ -215,4 +268,246 @@ async fn test_no_location_when_coordinates_missing() {
     // Also verify that the provided location was ignored
     assert_ne!(record.location, Some("THIS LOCATION SHOULD BE IGNORED".to_string()),
                "Provided location should be ignored");
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_timestamps_are_stored_in_utc() {
+    // This test verifies that:
+    // 1. Timestamps posted with an offset are converted to UTC
+    // 2. Timestamps are returned in RFC 3339 format
+
+    let client = Client::new();
+    let base_url = "http://127.0.0.1:3000/airquality";
+
+    let now = chrono::Utc::now();
+    let local_timestamp = format!("2025-03-25T{}+03:00", now.format("%H:%M:%S"));
+
+    let payload = json!({
+        "timestamp": local_timestamp,
+        "pm2_5": 10.2
+    });
+
+    let response = client.post(base_url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let expected = chrono::DateTime::parse_from_rfc3339(&local_timestamp).unwrap()
+        .with_timezone(&chrono::Utc)
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+
+    let response = client.get(base_url).send().await.unwrap();
+    let records: Vec<AirQualityData> = response.json().await.unwrap();
+
+    assert!(records.iter().any(|r| r.timestamp == expected), "Could not find our test record at {}", expected);
+}
+
+#[tokio::test]
+async fn test_sentinel_values_are_stored_as_missing() {
+    // This test verifies that:
+    // 1. 999 placeholders and out-of-range readings are stored as null
+    // 2. The discarded metrics are reported as quality flags
+    // 3. Valid readings in the same record are kept
+
+    let client = Client::new();
+    let base_url = "http://127.0.0.1:3000/airquality";
+
+    let test_timestamp = format!("2025-03-28T{}Z", chrono::Utc::now().format("%H:%M:%S"));
+    let payload = json!({
+        "timestamp": test_timestamp,
+        "temperature": 18.5,
+        "humidity": 140.0,
+        "pm1_0": 999,
+        "pm2_5": 999,
+        "pm10": 999,
+        "co2": 999,
+        "co": null,
+        "no2": 21.0,
+        "so2": -1.0
+    });
+
+    let response = client.post(base_url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response_body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(response_body["quality_flags"], json!(["humidity", "pm1_0", "pm2_5", "pm10", "so2"]));
+
+    let response = client.get(base_url).send().await.unwrap();
+    let records: Vec<AirQualityData> = response.json().await.unwrap();
+
+    let record = records.iter().find(|r| r.timestamp == test_timestamp).expect("Could not find our test record");
+    assert!(record.pm1_0.is_none() && record.pm2_5.is_none() && record.pm10.is_none(), "Sentinel readings should be null");
+    assert!(record.humidity.is_none(), "Out-of-range readings should be null");
+    assert!(record.co.is_none());
+    assert_eq!(record.temperature, Some(18.5));
+    assert_eq!(record.co2, Some(999.0), "999 ppm is a valid CO2 reading");
+    assert_eq!(record.no2, Some(21.0));
+    assert!(record.so2.is_none(), "Negative readings should be null");
+    assert_eq!(record.quality_flags, vec!["humidity", "pm1_0", "pm2_5", "pm10", "so2"]);
+}
+
+#[tokio::test]
+async fn test_batch_ingestion() {
+    // This test verifies that:
+    // 1. JSON arrays and NDJSON bodies are both accepted
+    // 2. Each record gets its own result, with invalid records rejected individually
+    // 3. Records repeating a (device, timestamp) pair are reported as duplicates
+
+    let client = Client::new();
+    let url = "http://127.0.0.1:3000/airquality/batch";
+
+    let time = chrono::Utc::now().format("%H:%M:%S");
+    let first_timestamp = format!("2025-03-27T{}Z", time);
+    let second_timestamp = format!("2025-03-26T{}Z", time);
+
+    let payload = json!([
+        { "timestamp": first_timestamp, "pm2_5": 10.2 },
+        { "timestamp": "not a timestamp", "pm2_5": 11.0 },
+        { "timestamp": first_timestamp, "pm2_5": 12.0 },
+        { "timestamp": second_timestamp, "co": 999 }
+    ]);
+
+    let response = client.post(url).header("X-Device-Key", device_key()).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["accepted"], 2);
+    assert_eq!(body["duplicates"], 1);
+    assert_eq!(body["rejected"], 1);
+
+    let statuses: Vec<&str> = body["results"].as_array().unwrap().iter()
+        .map(|result| result["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, vec!["accepted", "rejected", "duplicate", "accepted"]);
+    assert_eq!(body["results"][3]["quality_flags"], json!(["co"]));
+
+    // Resending the same readings as NDJSON should only produce duplicates
+    let ndjson = format!(
+        "{{\"timestamp\": \"{}\", \"pm2_5\": 10.2}}\n{{\"timestamp\": \"{}\"}}\n",
+        first_timestamp, second_timestamp
+    );
+
+    let response = client.post(url)
+        .header("X-Device-Key", device_key())
+        .header("Content-Type", "application/x-ndjson")
+        .body(ndjson)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["accepted"], 0);
+    assert_eq!(body["duplicates"], 2);
+
+    let response = client.post(url).header("X-Device-Key", device_key()).body("[{").send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = client.post(url).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn test_nodes_sharing_a_timestamp_are_stored_separately() {
+    // This test verifies that:
+    // 1. Readings relayed for different sensor nodes in the same round are not duplicates
+    // 2. Records can be filtered by node id
+
+    let client = Client::new();
+
+    let timestamp = format!("2025-03-25T{}Z", chrono::Utc::now().format("%H:%M:%S"));
+
+    let payload = json!([
+        { "node_id": 0x0dc4, "timestamp": timestamp, "pm2_5": 10.2 },
+        { "node_id": 0xfe88, "timestamp": timestamp, "pm2_5": 14.8 },
+        { "node_id": 0x0dc4, "timestamp": timestamp, "pm2_5": 10.2 }
+    ]);
+
+    let response = client.post("http://127.0.0.1:3000/airquality/batch")
+        .header("X-Device-Key", device_key())
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["accepted"], 2);
+    assert_eq!(body["duplicates"], 1);
+
+    let response = client.get("http://127.0.0.1:3000/airquality")
+        .query(&[("start", timestamp.as_str()), ("end", timestamp.as_str()), ("node_id", "65160")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let records: Vec<AirQualityData> = response.json().await.unwrap();
+    assert_eq!(records.len(), 1, "Only the requested node's reading should be returned");
+    assert_eq!(records[0].node_id, Some(0xfe88));
+    assert_eq!(records[0].pm2_5, Some(14.8));
+}
+
+#[tokio::test]
+async fn test_query_parameters_filter_records() {
+    // This test verifies that:
+    // 1. Records can be filtered by time range and limited on the backend
+    // 2. Invalid query parameters are rejected with a 400
+
+    let client = Client::new();
+    let base_url = "http://127.0.0.1:3000/airquality";
+
+    let response = client.get(base_url)
+        .query(&[
+            ("start", "2025-03-30 00:00:00"),
+            ("end", "2025-03-30 23:59:59"),
+            ("order", "desc"),
+            ("limit", "2"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let records: Vec<AirQualityData> = response.json().await.unwrap();
+    assert!(records.len() <= 2, "Limit should cap the number of records");
+    assert!(records.iter().all(|r| r.timestamp.starts_with("2025-03-30")),
+            "All records should be within the requested time range");
+    assert!(records.windows(2).all(|pair| pair[0].timestamp >= pair[1].timestamp),
+            "Records should be in descending timestamp order");
+
+    for query in [("start", "not a timestamp"), ("limit", "0"), ("order", "sideways"), ("bbox", "1,2,3")] {
+        let response = client.get(base_url).query(&[query]).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 400, "Query {:?} should be rejected", query);
+    }
+}
+
+#[tokio::test]
+async fn test_aggregate_endpoint_returns_buckets() {
+    // This test verifies that:
+    // 1. Readings are grouped into buckets with summary statistics per pollutant
+    // 2. Unknown bucket sizes are rejected with a 400
+
+    let client = Client::new();
+    let url = "http://127.0.0.1:3000/airquality/aggregate";
+
+    let response = client.get(url)
+        .query(&[("start", "2025-03-30 00:00:00"), ("end", "2025-03-30 23:59:59"), ("bucket", "1h")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["bucket"], "1h");
+
+    for bucket in body["buckets"].as_array().unwrap() {
+        assert!(bucket["bucket_start"].as_str().unwrap().ends_with(":00:00Z"), "Buckets should start on the hour");
+
+        if let Some(pm2_5) = bucket["pm2_5"].as_object() {
+            assert!(pm2_5["min"].as_f64() <= pm2_5["median"].as_f64());
+            assert!(pm2_5["median"].as_f64() <= pm2_5["max"].as_f64());
+        }
+    }
+
+    let response = client.get(url).query(&[("bucket", "2h")]).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,29 @@
+-- This file should undo anything in `up.sql`
+-- SQLite cannot drop a column that takes part in a foreign key, so the table is rebuilt
+DROP INDEX air_quality_data_device_id_timestamp;
+
+CREATE TABLE air_quality_data_without_device (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    timestamp DATETIME NOT NULL,
+    longitude DOUBLE,
+    latitude DOUBLE,
+    location TEXT,
+    temperature DOUBLE,
+    pressure DOUBLE,
+    humidity DOUBLE,
+    pm1_0 DOUBLE,
+    pm2_5 DOUBLE,
+    pm10 DOUBLE,
+    co2 DOUBLE,
+    co DOUBLE,
+    o3 DOUBLE
+);
+
+INSERT INTO air_quality_data_without_device
+SELECT id, timestamp, longitude, latitude, location, temperature, pressure, humidity, pm1_0, pm2_5, pm10, co2, co, o3
+FROM air_quality_data;
+
+DROP TABLE air_quality_data;
+ALTER TABLE air_quality_data_without_device RENAME TO air_quality_data;
+
+DROP TABLE devices;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE devices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL,
+    mac_address TEXT NOT NULL UNIQUE,
+    firmware_version TEXT,
+    install_location TEXT,
+    api_key_hash TEXT NOT NULL UNIQUE,
+    active BOOLEAN NOT NULL DEFAULT 1
+);
+
+ALTER TABLE air_quality_data ADD COLUMN device_id INTEGER REFERENCES devices(id);
+
+CREATE INDEX air_quality_data_device_id_timestamp ON air_quality_data (device_id, timestamp);
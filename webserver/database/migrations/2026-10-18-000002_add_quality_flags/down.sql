@@ -0,0 +1,2 @@
+-- Readings cleared by the up migration cannot be restored
+ALTER TABLE air_quality_data DROP COLUMN quality_flags;
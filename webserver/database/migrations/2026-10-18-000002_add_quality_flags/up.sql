@@ -0,0 +1,14 @@
+-- Bitmask of the metrics discarded on ingest, one bit per metric in the order
+-- temperature, pressure, humidity, pm1_0, pm2_5, pm10, co2, co, o3
+ALTER TABLE air_quality_data ADD COLUMN quality_flags INTEGER NOT NULL DEFAULT 0;
+
+-- Clear the 999 placeholders and out-of-range values already stored by older firmware
+UPDATE air_quality_data SET temperature = NULL, quality_flags = quality_flags | 1 WHERE temperature < -40 OR temperature > 85;
+UPDATE air_quality_data SET pressure = NULL, quality_flags = quality_flags | 2 WHERE pressure < 300 OR pressure > 110000;
+UPDATE air_quality_data SET humidity = NULL, quality_flags = quality_flags | 4 WHERE humidity < 0 OR humidity > 100;
+UPDATE air_quality_data SET pm1_0 = NULL, quality_flags = quality_flags | 8 WHERE pm1_0 = 999 OR pm1_0 < 0 OR pm1_0 > 1000;
+UPDATE air_quality_data SET pm2_5 = NULL, quality_flags = quality_flags | 16 WHERE pm2_5 = 999 OR pm2_5 < 0 OR pm2_5 > 1000;
+UPDATE air_quality_data SET pm10 = NULL, quality_flags = quality_flags | 32 WHERE pm10 = 999 OR pm10 < 0 OR pm10 > 1000;
+UPDATE air_quality_data SET co2 = NULL, quality_flags = quality_flags | 64 WHERE co2 < 0 OR co2 > 10000;
+UPDATE air_quality_data SET co = NULL, quality_flags = quality_flags | 128 WHERE co = 999 OR co < 0 OR co > 2000;
+UPDATE air_quality_data SET o3 = NULL, quality_flags = quality_flags | 256 WHERE o3 < 0 OR o3 > 1000;
@@ -0,0 +1,7 @@
+-- Restore local time using the offset the up migration applied
+UPDATE air_quality_data SET timestamp = datetime(
+    timestamp,
+    printf('%+d minutes', (SELECT utc_offset_minutes FROM timestamp_migration_settings LIMIT 1))
+);
+
+DROP TABLE timestamp_migration_settings;
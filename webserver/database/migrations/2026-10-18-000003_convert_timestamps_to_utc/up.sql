@@ -0,0 +1,14 @@
+-- Older firmware stamped readings with local time, timestamps are now stored in UTC.
+-- The offset of the stored local time defaults to East Africa Time (UTC+3). For stations
+-- elsewhere, insert the offset before running this migration, e.g.
+--   CREATE TABLE timestamp_migration_settings (utc_offset_minutes INTEGER PRIMARY KEY NOT NULL);
+--   INSERT INTO timestamp_migration_settings VALUES (60);
+-- The table is kept so the down migration can undo the same offset
+CREATE TABLE IF NOT EXISTS timestamp_migration_settings (utc_offset_minutes INTEGER PRIMARY KEY NOT NULL);
+INSERT INTO timestamp_migration_settings (utc_offset_minutes)
+    SELECT 180 WHERE NOT EXISTS (SELECT 1 FROM timestamp_migration_settings);
+
+UPDATE air_quality_data SET timestamp = datetime(
+    timestamp,
+    printf('%+d minutes', -(SELECT utc_offset_minutes FROM timestamp_migration_settings LIMIT 1))
+);
@@ -0,0 +1,3 @@
+-- Readings stored in the dropped columns cannot be restored
+ALTER TABLE air_quality_data DROP COLUMN so2;
+ALTER TABLE air_quality_data DROP COLUMN no2;
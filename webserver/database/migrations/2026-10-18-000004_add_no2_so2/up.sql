@@ -0,0 +1,3 @@
+-- Nitrogen and sulphur dioxide in ppb, flagged in quality_flags with bits 512 and 1024
+ALTER TABLE air_quality_data ADD COLUMN no2 DOUBLE;
+ALTER TABLE air_quality_data ADD COLUMN so2 DOUBLE;
@@ -0,0 +1,5 @@
+DROP INDEX air_quality_data_device_id_node_id_timestamp;
+CREATE INDEX air_quality_data_device_id_timestamp ON air_quality_data (device_id, timestamp);
+
+-- Node ids stored in the dropped column cannot be restored
+ALTER TABLE air_quality_data DROP COLUMN node_id;
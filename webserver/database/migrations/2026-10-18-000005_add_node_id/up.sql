@@ -0,0 +1,6 @@
+-- Sensor node a reading came from, for communication modules serving several nodes
+ALTER TABLE air_quality_data ADD COLUMN node_id INTEGER;
+
+-- Nodes polled in the same round share a timestamp, so duplicates are detected per node
+DROP INDEX air_quality_data_device_id_timestamp;
+CREATE INDEX air_quality_data_device_id_node_id_timestamp ON air_quality_data (device_id, node_id, timestamp);
@@ -0,0 +1,3 @@
+-- Duplicate readings removed by the migration cannot be restored
+DROP INDEX air_quality_data_device_id_node_id_timestamp;
+CREATE INDEX air_quality_data_device_id_node_id_timestamp ON air_quality_data (device_id, node_id, timestamp);
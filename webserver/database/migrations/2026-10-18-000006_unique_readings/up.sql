@@ -0,0 +1,12 @@
+-- Keep the first copy of every reading a device uploaded more than once
+DELETE FROM air_quality_data
+WHERE device_id IS NOT NULL
+AND id NOT IN (
+    SELECT MIN(id) FROM air_quality_data
+    WHERE device_id IS NOT NULL
+    GROUP BY device_id, IFNULL(node_id, -1), timestamp
+);
+
+-- Modules serving a single node send no node id, and NULLs never collide in a UNIQUE index
+DROP INDEX air_quality_data_device_id_node_id_timestamp;
+CREATE UNIQUE INDEX air_quality_data_device_id_node_id_timestamp ON air_quality_data (device_id, IFNULL(node_id, -1), timestamp);
@@ -0,0 +1,2 @@
+-- Converted and discarded pressures cannot be told apart from the rest, so nothing is restored
+SELECT 1;
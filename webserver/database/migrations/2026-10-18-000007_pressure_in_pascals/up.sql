@@ -0,0 +1,5 @@
+-- Pressure is stored in pascals, readings older clients sent in hectopascals are converted
+UPDATE air_quality_data SET pressure = pressure * 100 WHERE pressure BETWEEN 300 AND 1100;
+
+-- Anything left in neither unit is discarded like on ingest
+UPDATE air_quality_data SET pressure = NULL, quality_flags = quality_flags | 2 WHERE pressure < 30000 OR pressure > 110000;
@@ -0,0 +1,29 @@
+use database::devices::register_device;
+use database::establish_connection;
+use std::env;
+use std::process;
+
+/// Registers a sensor node and prints its API key
+///
+/// Usage: register_device <name> <mac_address> [firmware_version] [install_location]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.len() < 2 {
+        eprintln!("Usage: register_device <name> <mac_address> [firmware_version] [install_location]");
+        process::exit(1);
+    }
+
+    let mut conn = establish_connection();
+
+    match register_device(&mut conn, &args[0], &args[1], args.get(2).cloned(), args.get(3).cloned()) {
+        Ok((device, api_key)) => {
+            println!("Registered device {} ({}) with id {}", device.name, device.mac_address, device.id);
+            println!("API key (store it now, it cannot be shown again): {}", api_key);
+        },
+        Err(e) => {
+            eprintln!("Failed to register device: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use diesel::prelude::*;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::models::{Device, NewDevice};
+use crate::schema::devices;
+
+/// Number of random bytes in a generated device API key
+const API_KEY_BYTES: usize = 32;
+
+/// Generates a new random API key, hex encoded
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; API_KEY_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    to_hex(&bytes)
+}
+
+/// Hashes an API key for storage, only the hash is ever persisted
+///
+/// Keys are long random values rather than user chosen passwords,
+/// so a single SHA-256 round is sufficient.
+pub fn hash_api_key(api_key: &str) -> String {
+    to_hex(&Sha256::digest(api_key.trim().as_bytes()))
+}
+
+/// Registers a new active device, returning it along with its plaintext API key
+///
+/// The API key cannot be recovered afterwards and has to be flashed onto the device.
+pub fn register_device(
+    conn: &mut SqliteConnection,
+    name: &str,
+    mac_address: &str,
+    firmware_version: Option<String>,
+    install_location: Option<String>,
+) -> QueryResult<(Device, String)> {
+    let api_key = generate_api_key();
+
+    let new_device = NewDevice {
+        name: name.to_string(),
+        mac_address: mac_address.to_lowercase(),
+        firmware_version,
+        install_location,
+        api_key_hash: hash_api_key(&api_key),
+        active: true,
+    };
+
+    diesel::insert_into(devices::table)
+        .values(&new_device)
+        .execute(conn)?;
+
+    let device = devices::table
+        .filter(devices::mac_address.eq(&new_device.mac_address))
+        .select(Device::as_select())
+        .first(conn)?;
+
+    Ok((device, api_key))
+}
+
+/// Looks up the device owning an API key
+pub fn find_device_by_api_key(conn: &mut SqliteConnection, api_key: &str) -> QueryResult<Option<Device>> {
+    devices::table
+        .filter(devices::api_key_hash.eq(hash_api_key(api_key)))
+        .select(Device::as_select())
+        .first(conn)
+        .optional()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_keys_are_unique_hex() {
+        let first = generate_api_key();
+        let second = generate_api_key();
+
+        assert_eq!(first.len(), API_KEY_BYTES * 2);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_hash_api_key() {
+        assert_eq!(
+            hash_api_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hash_api_key(" abc\n"), hash_api_key("abc"));
+        assert_ne!(hash_api_key("abc"), hash_api_key("abd"));
+    }
+}
@@ -2,6 +2,7 @@ use diesel::prelude::*;
 use dotenvy::dotenv;
 use std::env;
 
+pub mod devices;
 pub mod models;
 pub mod schema;
 
@@ -1,13 +1,14 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::sqlite::Sqlite;
-use crate::schema::air_quality_data;
+use crate::schema::{air_quality_data, devices};
 
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = air_quality_data)]
 #[diesel(check_for_backend(Sqlite))]
 pub struct AirQualityData {
     pub id: i32,
+    /// Time of the reading in UTC
     pub timestamp: NaiveDateTime,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
@@ -20,12 +21,22 @@ pub struct AirQualityData {
     pub pm10: Option<f64>,
     pub co2: Option<f64>,
     pub co: Option<f64>,
-    pub o3: Option<f64>
+    pub o3: Option<f64>,
+    pub device_id: Option<i32>,
+    /// Bitmask of the metrics discarded on ingest as sentinel or out-of-range values
+    pub quality_flags: i32,
+    /// Nitrogen dioxide in ppb
+    pub no2: Option<f64>,
+    /// Sulphur dioxide in ppb
+    pub so2: Option<f64>,
+    /// Sensor node that took the reading, when the device relays readings from several nodes
+    pub node_id: Option<i32>
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = air_quality_data)]
 pub struct NewAirQualityData {
+    /// Time of the reading in UTC
     pub timestamp: NaiveDateTime,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
@@ -38,5 +49,38 @@ pub struct NewAirQualityData {
     pub pm10: Option<f64>,
     pub co2: Option<f64>,
     pub co: Option<f64>,
-    pub o3: Option<f64>
+    pub o3: Option<f64>,
+    pub device_id: Option<i32>,
+    /// Bitmask of the metrics discarded on ingest as sentinel or out-of-range values
+    pub quality_flags: i32,
+    /// Nitrogen dioxide in ppb
+    pub no2: Option<f64>,
+    /// Sulphur dioxide in ppb
+    pub so2: Option<f64>,
+    /// Sensor node that took the reading, when the device relays readings from several nodes
+    pub node_id: Option<i32>
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = devices)]
+#[diesel(check_for_backend(Sqlite))]
+pub struct Device {
+    pub id: i32,
+    pub name: String,
+    pub mac_address: String,
+    pub firmware_version: Option<String>,
+    pub install_location: Option<String>,
+    pub api_key_hash: String,
+    pub active: bool
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = devices)]
+pub struct NewDevice {
+    pub name: String,
+    pub mac_address: String,
+    pub firmware_version: Option<String>,
+    pub install_location: Option<String>,
+    pub api_key_hash: String,
+    pub active: bool
 }
\ No newline at end of file
@@ -16,5 +16,36 @@ diesel::table! {
         co2 -> Nullable<Double>,
         co -> Nullable<Double>,
         o3 -> Nullable<Double>,
+        device_id -> Nullable<Integer>,
+        quality_flags -> Integer,
+        no2 -> Nullable<Double>,
+        so2 -> Nullable<Double>,
+        node_id -> Nullable<Integer>,
     }
 }
+
+diesel::table! {
+    devices (id) {
+        id -> Integer,
+        name -> Text,
+        mac_address -> Text,
+        firmware_version -> Nullable<Text>,
+        install_location -> Nullable<Text>,
+        api_key_hash -> Text,
+        active -> Bool,
+    }
+}
+
+diesel::table! {
+    timestamp_migration_settings (utc_offset_minutes) {
+        utc_offset_minutes -> Integer,
+    }
+}
+
+diesel::joinable!(air_quality_data -> devices (device_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    air_quality_data,
+    devices,
+    timestamp_migration_settings,
+);
@@ -1,7 +1,7 @@
 use yew::prelude::*;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use crate::app::utils::time_filter::TimeRange;
-use crate::app::utils::parse_timestamp::parse_timestamp;
+use crate::app::utils::local_time::{ local_midnight, to_local };
 use web_sys::{HtmlSelectElement, HtmlInputElement};
 
 #[derive(Properties, Clone, PartialEq)]
@@ -30,8 +30,8 @@ pub fn time_filter(props: &TimeFilterProps) -> Html {
         use_effect_with(props.selected_range.clone(), move |selected_range| {
             if let TimeRange::Custom(start, end) = selected_range {
                 // Format dates for input fields (YYYY-MM-DD)
-                start_date.set(start.format("%Y-%m-%d").to_string());
-                end_date.set(end.format("%Y-%m-%d").to_string());
+                start_date.set(to_local(start).format("%Y-%m-%d").to_string());
+                end_date.set(to_local(end).format("%Y-%m-%d").to_string());
                 show_custom_dates.set(true);
             } else {
                 // For non-custom ranges, initialize with reasonable defaults
@@ -39,8 +39,8 @@ pub fn time_filter(props: &TimeFilterProps) -> Html {
                 let one_week_ago = now - chrono::Duration::days(7);
 
                 // Set default date range (last 7 days)
-                start_date.set(one_week_ago.format("%Y-%m-%d").to_string());
-                end_date.set(now.format("%Y-%m-%d").to_string());
+                start_date.set(to_local(&one_week_ago).format("%Y-%m-%d").to_string());
+                end_date.set(to_local(&now).format("%Y-%m-%d").to_string());
             }
             || ()
         });
@@ -217,14 +217,10 @@ pub fn time_filter(props: &TimeFilterProps) -> Html {
     }
 }
 
-// Helper function to parse a date string (YYYY-MM-DD) to DateTime<Utc>
+// Helper function to parse a date string (YYYY-MM-DD) to the start of that day in the browser's timezone
 fn parse_date(date_str: &str) -> Option<DateTime<Utc>> {
-    // Format the date string to include time
-    let datetime_str = format!("{} 00:00:00 +0000", date_str);
-
-    // Use the parse_timestamp function
-    match parse_timestamp(&datetime_str) {
-        Ok(dt) => Some(dt),
+    match NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+        Ok(date) => Some(local_midnight(date)),
         Err(e) => {
             log::warn!("Failed to parse date: {} - Error: {}", date_str, e);
             None
@@ -1,6 +1,6 @@
 use yew::prelude::*;
 use wasm_bindgen_futures::spawn_local;
-use crate::app::utils::air_quality_client::get_air_quality_data;
+use crate::app::utils::air_quality_client::get_air_quality_summary;
 use crate::app::utils::time_filter::TimeRange;
 use crate::app::utils::location_filter::LocationFilter;
 use crate::app::utils::aqi_calculator::calculate_overall_aqi;
@@ -33,10 +33,10 @@ pub fn aqi_metrics(props: &AqiMetricsProps) -> Html {
             aqi_result.set(None);
 
             spawn_local(async move {
-                match get_air_quality_data().await {
-                    Ok(data) => {
-                        // Calculate AQI using the data, time range, and location filter
-                        let result = calculate_overall_aqi(&data, &time_range, &location_filter);
+                match get_air_quality_summary(&time_range, &location_filter).await {
+                    Ok(summary) => {
+                        // Calculate AQI from the average concentrations over the time range
+                        let result = summary.as_ref().and_then(calculate_overall_aqi);
                         aqi_result.set(result);
                         is_loading.set(false);
                     },
@@ -1,6 +1,6 @@
 use yew::prelude::*;
 use wasm_bindgen_futures::spawn_local;
-use crate::app::utils::air_quality_client::get_air_quality_data;
+use crate::app::utils::air_quality_client::get_air_quality_summary;
 use crate::app::utils::time_filter::TimeRange;
 use crate::app::utils::location_filter::LocationFilter;
 use crate::app::utils::average_calculator::calculate_average;
@@ -33,12 +33,12 @@ pub fn average_co2_metrics(props: &CO2MetricsProps) -> Html {
             metrics.set(Vec::new());
 
             spawn_local(async move {
-                match get_air_quality_data().await {
-                    Ok(fetched_data) => {
+                match get_air_quality_summary(&time_range, &location_filter).await {
+                    Ok(summary) => {
                         let mut metrics_vec = Vec::new();
 
                         // Calculate CO2 average
-                        if let Some(avg_co2) = calculate_average(&fetched_data, &time_range, &location_filter, |record| record.co2) {
+                        if let Some(avg_co2) = calculate_average(summary.as_ref(), |record| record.co2.as_ref()) {
                             metrics_vec.push(MetricData {
                                 label: "Carbon Dioxide".to_string(),
                                 value: avg_co2,
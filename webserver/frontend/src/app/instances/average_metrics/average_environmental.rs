@@ -1,6 +1,6 @@
 use yew::prelude::*;
 use wasm_bindgen_futures::spawn_local;
-use crate::app::utils::air_quality_client::get_air_quality_data;
+use crate::app::utils::air_quality_client::get_air_quality_summary;
 use crate::app::utils::time_filter::TimeRange;
 use crate::app::utils::location_filter::LocationFilter;
 use crate::app::utils::average_calculator::calculate_average;
@@ -33,12 +33,12 @@ pub fn average_environmental_metrics(props: &EnvironmentalMetricsProps) -> Html
             metrics.set(Vec::new());
 
             spawn_local(async move {
-                match get_air_quality_data().await {
-                    Ok(fetched_data) => {
+                match get_air_quality_summary(&time_range, &location_filter).await {
+                    Ok(summary) => {
                         let mut metrics_vec = Vec::new();
 
                         // Calculate temperature average
-                        if let Some(avg_temp) = calculate_average(&fetched_data, &time_range, &location_filter, |record| record.temperature) {
+                        if let Some(avg_temp) = calculate_average(summary.as_ref(), |record| record.temperature.as_ref()) {
                             metrics_vec.push(MetricData {
                                 label: "Temperature".to_string(),
                                 value: avg_temp,
@@ -47,7 +47,7 @@ pub fn average_environmental_metrics(props: &EnvironmentalMetricsProps) -> Html
                         }
 
                         // Calculate humidity average
-                        if let Some(avg_humidity) = calculate_average(&fetched_data, &time_range, &location_filter, |record| record.humidity) {
+                        if let Some(avg_humidity) = calculate_average(summary.as_ref(), |record| record.humidity.as_ref()) {
                             metrics_vec.push(MetricData {
                                 label: "Humidity".to_string(),
                                 value: avg_humidity,
@@ -55,11 +55,11 @@ pub fn average_environmental_metrics(props: &EnvironmentalMetricsProps) -> Html
                             });
                         }
 
-                        // Calculate pressure average
-                        if let Some(avg_pressure) = calculate_average(&fetched_data, &time_range, &location_filter, |record| record.pressure) {
+                        // Calculate pressure average, stored in pascals and shown in hectopascals
+                        if let Some(avg_pressure) = calculate_average(summary.as_ref(), |record| record.pressure.as_ref()) {
                             metrics_vec.push(MetricData {
                                 label: "Pressure".to_string(),
-                                value: avg_pressure,
+                                value: avg_pressure / 100.0,
                                 unit: "hPa".to_string(),
                             });
                         }
@@ -1,6 +1,6 @@
 use yew::prelude::*;
 use wasm_bindgen_futures::spawn_local;
-use crate::app::utils::air_quality_client::get_air_quality_data;
+use crate::app::utils::air_quality_client::get_air_quality_summary;
 use crate::app::utils::time_filter::TimeRange;
 use crate::app::utils::location_filter::LocationFilter;
 use crate::app::utils::average_calculator::calculate_average;
@@ -33,12 +33,12 @@ pub fn average_particulate_metrics(props: &ParticulateMetricsProps) -> Html {
             metrics.set(Vec::new());
 
             spawn_local(async move {
-                match get_air_quality_data().await {
-                    Ok(fetched_data) => {
+                match get_air_quality_summary(&time_range, &location_filter).await {
+                    Ok(summary) => {
                         let mut metrics_vec = Vec::new();
 
                         // Calculate PM1.0 average
-                        if let Some(avg_pm1) = calculate_average(&fetched_data, &time_range, &location_filter, |record| record.pm1_0) {
+                        if let Some(avg_pm1) = calculate_average(summary.as_ref(), |record| record.pm1_0.as_ref()) {
                             metrics_vec.push(MetricData {
                                 label: "PM1.0".to_string(),
                                 value: avg_pm1,
@@ -47,7 +47,7 @@ pub fn average_particulate_metrics(props: &ParticulateMetricsProps) -> Html {
                         }
 
                         // Calculate PM2.5 average
-                        if let Some(avg_pm25) = calculate_average(&fetched_data, &time_range, &location_filter, |record| record.pm2_5) {
+                        if let Some(avg_pm25) = calculate_average(summary.as_ref(), |record| record.pm2_5.as_ref()) {
                             metrics_vec.push(MetricData {
                                 label: "PM2.5".to_string(),
                                 value: avg_pm25,
@@ -56,7 +56,7 @@ pub fn average_particulate_metrics(props: &ParticulateMetricsProps) -> Html {
                         }
 
                         // Calculate PM10 average
-                        if let Some(avg_pm10) = calculate_average(&fetched_data, &time_range, &location_filter, |record| record.pm10) {
+                        if let Some(avg_pm10) = calculate_average(summary.as_ref(), |record| record.pm10.as_ref()) {
                             metrics_vec.push(MetricData {
                                 label: "PM10".to_string(),
                                 value: avg_pm10,
@@ -3,3 +3,5 @@ pub mod average_particulate;
 pub mod average_co;
 pub mod average_co2;
 pub mod average_o3;
+pub mod average_no2;
+pub mod average_so2;
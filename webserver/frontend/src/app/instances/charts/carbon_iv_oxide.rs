@@ -1,7 +1,7 @@
 use yew::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use crate::app::utils::parse_timestamp::parse_timestamp;
-use crate::app::utils::air_quality_client::get_air_quality_data;
+use crate::app::utils::air_quality_client::get_aggregated_air_quality_data;
 use crate::app::utils::{series_builder::build_series, range_calculations::{compute_time_range, compute_value_range}};
 use crate::app::components::time_series_chart::{
     TimeSeriesChart,
@@ -9,8 +9,8 @@ use crate::app::components::time_series_chart::{
     TimeSeriesChartConfig,
     ChartSeries,
 };
-use crate::app::utils::time_filter::{TimeRange, filter_data_by_time_range};
-use crate::app::utils::location_filter::{LocationFilter, filter_data_by_location};
+use crate::app::utils::time_filter::TimeRange;
+use crate::app::utils::location_filter::LocationFilter;
 use std::rc::Rc;
 use plotters::prelude::*;
 
@@ -33,24 +33,10 @@ pub fn carbon_iv_oxide_chart(props: &CarbonIVOxideChartProps) -> Html {
         let location_filter = location_filter.clone();
 
         spawn_local(async move {
-            match get_air_quality_data().await {
-                Ok(fetched_data) => {
-                    // First filter by time range
-                    let time_filtered_data = filter_data_by_time_range(
-                        &fetched_data,
-                        &time_range,
-                        |record| parse_timestamp(&record.timestamp).ok()
-                    );
-
-                    // Then filter by location
-                    let filtered_data = filter_data_by_location(
-                        &time_filtered_data,
-                        &location_filter,
-                        |record| record.location.clone(),
-                        |record| parse_timestamp(&record.timestamp).ok()
-                    );
-
-                    log::info!("Filtered data for CO2 chart: {} records", filtered_data.len());
+            // Filtering and bucketing happens on the backend, each point is a bucket mean
+            match get_aggregated_air_quality_data(&time_range, &location_filter, time_range.bucket_size()).await {
+                Ok(filtered_data) => {
+                    log::info!("Aggregated data for CO2 chart: {} buckets", filtered_data.len());
 
                     if filtered_data.is_empty() {
                         log::warn!("No data available for the selected time range");
@@ -59,8 +45,8 @@ pub fn carbon_iv_oxide_chart(props: &CarbonIVOxideChartProps) -> Html {
                     }
 
                     let series_co2 = build_series(&filtered_data,
-                        |record| record.co2,
-                        |record| parse_timestamp(&record.timestamp).unwrap()
+                        |record| record.co2.as_ref().map(|stats| stats.mean),
+                        |record| parse_timestamp(&record.bucket_start).unwrap()
                     );
 
                     // If series is empty after filtering, show no data
@@ -5,3 +5,5 @@ pub mod temperature;
 pub mod pressure;
 pub mod humidity;
 pub mod carbon_iv_oxide;
+pub mod nitrogen_iv_oxide;
+pub mod sulphur_iv_oxide;
@@ -15,11 +15,15 @@ pub use charts::temperature;
 pub use charts::pressure;
 pub use charts::humidity;
 pub use charts::carbon_iv_oxide;
+pub use charts::nitrogen_iv_oxide;
+pub use charts::sulphur_iv_oxide;
 
 pub use average_metrics::average_environmental;
 pub use average_metrics::average_particulate;
 pub use average_metrics::average_co;
 pub use average_metrics::average_co2;
 pub use average_metrics::average_o3;
+pub use average_metrics::average_no2;
+pub use average_metrics::average_so2;
 
 pub use aqi::aqi_metrics;
\ No newline at end of file
@@ -12,6 +12,8 @@ use crate::app::instances::charts::humidity::HumidityChart;
 use crate::app::instances::charts::carbon_iv_oxide::CarbonIVOxideChart;
 use crate::app::instances::charts::carbon_ii_oxide::CarbonIIOxideChart;
 use crate::app::instances::charts::ozone::OzoneChart;
+use crate::app::instances::charts::nitrogen_iv_oxide::NitrogenIVOxideChart;
+use crate::app::instances::charts::sulphur_iv_oxide::SulphurIVOxideChart;
 
 // Import AQI component
 use crate::app::instances::aqi::aqi_metrics::AqiMetrics;
@@ -22,6 +24,8 @@ use crate::app::instances::average_metrics::average_particulate::AverageParticul
 use crate::app::instances::average_metrics::average_co::AverageCOMetrics;
 use crate::app::instances::average_metrics::average_co2::AverageCO2Metrics;
 use crate::app::instances::average_metrics::average_o3::AverageO3Metrics;
+use crate::app::instances::average_metrics::average_no2::AverageNO2Metrics;
+use crate::app::instances::average_metrics::average_so2::AverageSO2Metrics;
 
 #[function_component(Dashboard)]
 pub fn dashboard() -> Html {
@@ -115,6 +119,18 @@ pub fn dashboard() -> Html {
                         time_range={(*selected_time_range).clone()}
                         location_filter={(*selected_location).clone()}
                     />
+
+                    // Nitrogen dioxide metrics
+                    <AverageNO2Metrics
+                        time_range={(*selected_time_range).clone()}
+                        location_filter={(*selected_location).clone()}
+                    />
+
+                    // Sulphur dioxide metrics
+                    <AverageSO2Metrics
+                        time_range={(*selected_time_range).clone()}
+                        location_filter={(*selected_location).clone()}
+                    />
                 </div>
             </div>
 
@@ -218,6 +234,34 @@ pub fn dashboard() -> Html {
                         />
                     </div>
                 </div>
+
+                // NO2 chart
+                <div class="chart-container chart-medium">
+                    <div class="chart-header">
+                        <h3>{ "Nitrogen (IV) Oxide" }</h3>
+                        <span class="chart-subtitle">{ "NO₂ (ppb)" }</span>
+                    </div>
+                    <div class="chart-content">
+                        <NitrogenIVOxideChart
+                            time_range={(*selected_time_range).clone()}
+                            location_filter={(*selected_location).clone()}
+                        />
+                    </div>
+                </div>
+
+                // SO2 chart
+                <div class="chart-container chart-medium">
+                    <div class="chart-header">
+                        <h3>{ "Sulphur (IV) Oxide" }</h3>
+                        <span class="chart-subtitle">{ "SO₂ (ppb)" }</span>
+                    </div>
+                    <div class="chart-content">
+                        <SulphurIVOxideChart
+                            time_range={(*selected_time_range).clone()}
+                            location_filter={(*selected_location).clone()}
+                        />
+                    </div>
+                </div>
             </div>
         </div>
     }
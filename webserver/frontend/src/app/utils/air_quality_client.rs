@@ -1,5 +1,14 @@
 use reqwest::Client;
 use serde::Deserialize;
+use crate::app::utils::time_filter::TimeRange;
+use crate::app::utils::location_filter::LocationFilter;
+
+//const AIR_QUALITY_URL: &str = "https://airqualitymonitoring.cc/airquality";
+const AIR_QUALITY_URL: &str = "http://127.0.0.1:3000/airquality";
+const AGGREGATE_URL: &str = "http://127.0.0.1:3000/airquality/aggregate";
+
+/// Timestamp format expected by the backend's `start` and `end` query parameters
+const QUERY_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 #[derive(Deserialize, Clone, PartialEq)]
 pub struct AirQualityData {
@@ -16,13 +25,53 @@ pub struct AirQualityData {
     pub co2: Option<f64>,
     pub co: Option<f64>,
     pub o3: Option<f64>,
+    pub no2: Option<f64>,
+    pub so2: Option<f64>,
 }
 
-pub async fn get_air_quality_data() -> Result<Vec<AirQualityData>, String> {
+/// Summary statistics for a single metric within a bucket, computed by the backend
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct SummaryStatistics {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub count: usize,
+}
+
+/// Aggregated readings for one time bucket
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct AggregateBucket {
+    pub bucket_start: String,
+    pub count: usize,
+    pub temperature: Option<SummaryStatistics>,
+    pub pressure: Option<SummaryStatistics>,
+    pub humidity: Option<SummaryStatistics>,
+    pub pm1_0: Option<SummaryStatistics>,
+    pub pm2_5: Option<SummaryStatistics>,
+    pub pm10: Option<SummaryStatistics>,
+    pub co2: Option<SummaryStatistics>,
+    pub co: Option<SummaryStatistics>,
+    pub o3: Option<SummaryStatistics>,
+    pub no2: Option<SummaryStatistics>,
+    pub so2: Option<SummaryStatistics>,
+}
+
+#[derive(Deserialize)]
+struct AggregateResponse {
+    buckets: Vec<AggregateBucket>,
+}
+
+async fn fetch_air_quality_data(params: &[(&str, String)]) -> Result<Vec<AirQualityData>, String> {
     let client = Client::new();
 
-    //match client.get("https://airqualitymonitoring.cc/airquality").send().await {
-    match client.get("http://127.0.0.1:3000/airquality").send().await {
+    match client.get(AIR_QUALITY_URL).query(params).send().await {
+        Ok(response) if !response.status().is_success() => {
+            let status = response.status();
+            let message = response.text().await.unwrap_or_default();
+            Err(format!("Server returned {}: {}", status, message))
+        }
         Ok(response) => match response.json::<Vec<AirQualityData>>().await {
             Ok(data) => Ok(data),
             Err(e) => Err(format!("Failed to parse response: {:?}", e))
@@ -30,5 +79,101 @@ pub async fn get_air_quality_data() -> Result<Vec<AirQualityData>, String> {
         }
         Err(e) => Err(format!("Error fetching data: {:?}", e)),
     }
+}
 
-}
\ No newline at end of file
+async fn fetch_aggregated_data(params: &[(&str, String)]) -> Result<Vec<AggregateBucket>, String> {
+    let client = Client::new();
+
+    match client.get(AGGREGATE_URL).query(params).send().await {
+        Ok(response) if !response.status().is_success() => {
+            let status = response.status();
+            let message = response.text().await.unwrap_or_default();
+            Err(format!("Server returned {}: {}", status, message))
+        }
+        Ok(response) => match response.json::<AggregateResponse>().await {
+            Ok(data) => Ok(data.buckets),
+            Err(e) => Err(format!("Failed to parse response: {:?}", e))
+        }
+        Err(e) => Err(format!("Error fetching data: {:?}", e)),
+    }
+}
+
+pub async fn get_air_quality_data() -> Result<Vec<AirQualityData>, String> {
+    fetch_air_quality_data(&[]).await
+}
+
+/// Builds the time range and location query parameters for the backend
+///
+/// For `LocationFilter::MostRecent` the location of the latest reading within the time range is
+/// looked up first, matching the behaviour of `filter_data_by_location`. Returns `None` when
+/// no location could be determined, in which case there is nothing to show.
+async fn build_filter_params(
+    time_range: &TimeRange,
+    location_filter: &LocationFilter,
+) -> Result<Option<Vec<(&'static str, String)>>, String> {
+    let (start_date, end_date) = time_range.to_date_range();
+
+    let mut params = vec![
+        ("start", start_date.format(QUERY_TIMESTAMP_FORMAT).to_string()),
+        ("end", end_date.format(QUERY_TIMESTAMP_FORMAT).to_string()),
+    ];
+
+    let location = match location_filter {
+        LocationFilter::Specific(location) => Some(location.clone()),
+        LocationFilter::MostRecent => {
+            let mut latest_params = params.clone();
+            latest_params.push(("order", "desc".to_string()));
+            latest_params.push(("limit", "1".to_string()));
+
+            fetch_air_quality_data(&latest_params)
+                .await?
+                .into_iter()
+                .next()
+                .and_then(|record| record.location)
+        }
+    };
+
+    Ok(location.map(|location| {
+        params.push(("location", location));
+        params
+    }))
+}
+
+/// Fetches only the readings within the time range and location, letting the backend do the filtering
+pub async fn get_filtered_air_quality_data(
+    time_range: &TimeRange,
+    location_filter: &LocationFilter,
+) -> Result<Vec<AirQualityData>, String> {
+    match build_filter_params(time_range, location_filter).await? {
+        Some(params) => fetch_air_quality_data(&params).await,
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Fetches per-bucket statistics for the time range and location
+///
+/// `bucket` is one of the backend's bucket sizes (`1m`, `5m`, `1h`, `1d`, `1w`).
+pub async fn get_aggregated_air_quality_data(
+    time_range: &TimeRange,
+    location_filter: &LocationFilter,
+    bucket: &str,
+) -> Result<Vec<AggregateBucket>, String> {
+    match build_filter_params(time_range, location_filter).await? {
+        Some(mut params) => {
+            params.push(("bucket", bucket.to_string()));
+            fetch_aggregated_data(&params).await
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Fetches statistics summarising the whole time range for the location
+pub async fn get_air_quality_summary(
+    time_range: &TimeRange,
+    location_filter: &LocationFilter,
+) -> Result<Option<AggregateBucket>, String> {
+    match build_filter_params(time_range, location_filter).await? {
+        Some(params) => Ok(fetch_aggregated_data(&params).await?.into_iter().next()),
+        None => Ok(None),
+    }
+}
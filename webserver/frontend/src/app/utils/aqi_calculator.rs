@@ -1,7 +1,5 @@
-use crate::app::utils::air_quality_client::AirQualityData;
-use crate::app::utils::time_filter::{TimeRange, filter_data_by_time_range};
-use crate::app::utils::location_filter::{LocationFilter, filter_data_by_location};
-use crate::app::utils::parse_timestamp::parse_timestamp;
+use crate::app::utils::air_quality_client::AggregateBucket;
+use crate::app::utils::average_calculator::calculate_average;
 use std::cmp::Ordering;
 
 /// AQI category with color and description
@@ -127,6 +125,34 @@ fn calculate_o3_aqi(concentration: f64) -> i32 {
     calculate_aqi_for_pollutant(concentration_ppm, &breakpoints)
 }
 
+/// Calculate AQI for NO2 (1-hour average, ppb)
+fn calculate_no2_aqi(concentration: f64) -> i32 {
+    let breakpoints = [
+        Breakpoint { aqi_low: 0, aqi_high: 50, conc_low: 0.0, conc_high: 53.0 },
+        Breakpoint { aqi_low: 51, aqi_high: 100, conc_low: 54.0, conc_high: 100.0 },
+        Breakpoint { aqi_low: 101, aqi_high: 150, conc_low: 101.0, conc_high: 360.0 },
+        Breakpoint { aqi_low: 151, aqi_high: 200, conc_low: 361.0, conc_high: 649.0 },
+        Breakpoint { aqi_low: 201, aqi_high: 300, conc_low: 650.0, conc_high: 1249.0 },
+        Breakpoint { aqi_low: 301, aqi_high: 500, conc_low: 1250.0, conc_high: 2049.0 },
+    ];
+
+    calculate_aqi_for_pollutant(concentration, &breakpoints)
+}
+
+/// Calculate AQI for SO2 (1-hour average, ppb)
+fn calculate_so2_aqi(concentration: f64) -> i32 {
+    let breakpoints = [
+        Breakpoint { aqi_low: 0, aqi_high: 50, conc_low: 0.0, conc_high: 35.0 },
+        Breakpoint { aqi_low: 51, aqi_high: 100, conc_low: 36.0, conc_high: 75.0 },
+        Breakpoint { aqi_low: 101, aqi_high: 150, conc_low: 76.0, conc_high: 185.0 },
+        Breakpoint { aqi_low: 151, aqi_high: 200, conc_low: 186.0, conc_high: 304.0 },
+        Breakpoint { aqi_low: 201, aqi_high: 300, conc_low: 305.0, conc_high: 604.0 },
+        Breakpoint { aqi_low: 301, aqi_high: 500, conc_low: 605.0, conc_high: 1004.0 },
+    ];
+
+    calculate_aqi_for_pollutant(concentration, &breakpoints)
+}
+
 /// Generic AQI calculation using the EPA formula
 fn calculate_aqi_for_pollutant(concentration: f64, breakpoints: &[Breakpoint]) -> i32 {
     // If concentration is negative or NaN, return 0
@@ -154,52 +180,15 @@ fn calculate_aqi_for_pollutant(concentration: f64, breakpoints: &[Breakpoint]) -
     0
 }
 
-/// Calculate average concentration for a specific pollutant
-fn calculate_average_concentration<F>(
-    data: &[AirQualityData],
-    time_range: &TimeRange,
-    location_filter: &LocationFilter,
-    value_extractor: F,
-) -> Option<f64>
-where
-    F: Fn(&AirQualityData) -> Option<f64>,
-{
-    // First filter data by time range
-    let time_filtered_data = filter_data_by_time_range(
-        data,
-        time_range,
-        |record| parse_timestamp(&record.timestamp).ok(),
-    );
-
-    // Then filter by location
-    let filtered_data = filter_data_by_location(
-        &time_filtered_data,
-        location_filter,
-        |record| record.location.clone(),
-        |record| parse_timestamp(&record.timestamp).ok(),
-    );
-
-    // Extract values and calculate average
-    let values: Vec<f64> = filtered_data
-        .iter()
-        .filter_map(|record| value_extractor(record))
-        .collect();
-
-    if values.is_empty() {
-        None
-    } else {
-        let sum: f64 = values.iter().sum();
-        Some(sum / values.len() as f64)
-    }
-}
-
 /// Calculate AQI for all pollutants and return the overall AQI
-pub fn calculate_overall_aqi(data: &[AirQualityData], time_range: &TimeRange, location_filter: &LocationFilter) -> Option<AqiResult> {
-    // Calculate average concentrations
-    let avg_pm25 = calculate_average_concentration(data, time_range, location_filter, |record| record.pm2_5);
-    let avg_pm10 = calculate_average_concentration(data, time_range, location_filter, |record| record.pm10);
-    let avg_co = calculate_average_concentration(data, time_range, location_filter, |record| record.co);
-    let avg_o3 = calculate_average_concentration(data, time_range, location_filter, |record| record.o3);
+pub fn calculate_overall_aqi(summary: &AggregateBucket) -> Option<AqiResult> {
+    // Average concentrations over the time range, as aggregated by the backend
+    let avg_pm25 = calculate_average(Some(summary), |record| record.pm2_5.as_ref());
+    let avg_pm10 = calculate_average(Some(summary), |record| record.pm10.as_ref());
+    let avg_co = calculate_average(Some(summary), |record| record.co.as_ref());
+    let avg_o3 = calculate_average(Some(summary), |record| record.o3.as_ref());
+    let avg_no2 = calculate_average(Some(summary), |record| record.no2.as_ref());
+    let avg_so2 = calculate_average(Some(summary), |record| record.so2.as_ref());
 
     // Calculate AQI for each pollutant
     let mut aqi_values = Vec::new();
@@ -224,6 +213,16 @@ pub fn calculate_overall_aqi(data: &[AirQualityData], time_range: &TimeRange, lo
         aqi_values.push((aqi, "O₃".to_string()));
     }
 
+    if let Some(no2) = avg_no2 {
+        let aqi = calculate_no2_aqi(no2);
+        aqi_values.push((aqi, "NO₂".to_string()));
+    }
+
+    if let Some(so2) = avg_so2 {
+        let aqi = calculate_so2_aqi(so2);
+        aqi_values.push((aqi, "SO₂".to_string()));
+    }
+
     // Find the maximum AQI value and its corresponding pollutant
     if aqi_values.is_empty() {
         return None;
@@ -1,52 +1,21 @@
-use crate::app::utils::air_quality_client::AirQualityData;
-use crate::app::utils::time_filter::{TimeRange, filter_data_by_time_range};
-use crate::app::utils::location_filter::{LocationFilter, filter_data_by_location};
-use crate::app::utils::parse_timestamp::parse_timestamp;
+use crate::app::utils::air_quality_client::{AggregateBucket, SummaryStatistics};
 
-/// Calculate the average value for a specific metric from air quality data
+/// Get the average value for a specific metric from a summary aggregated by the backend
 pub fn calculate_average<F>(
-    data: &[AirQualityData],
-    time_range: &TimeRange,
-    location_filter: &LocationFilter,
+    summary: Option<&AggregateBucket>,
     value_extractor: F,
 ) -> Option<f64>
 where
-    F: Fn(&AirQualityData) -> Option<f64>,
+    F: Fn(&AggregateBucket) -> Option<&SummaryStatistics>,
 {
-    // First filter data by time range
-    let time_filtered_data = filter_data_by_time_range(
-        data,
-        time_range,
-        |record| parse_timestamp(&record.timestamp).ok(),
-    );
-
-    // Then filter by location
-    let filtered_data = filter_data_by_location(
-        &time_filtered_data,
-        location_filter,
-        |record| record.location.clone(),
-        |record| parse_timestamp(&record.timestamp).ok(),
-    );
-
-    // Extract values and calculate average
-    let values: Vec<f64> = filtered_data
-        .iter()
-        .filter_map(|record| value_extractor(record))
-        .collect();
-
-    if values.is_empty() {
-        None
-    } else {
-        let sum: f64 = values.iter().sum();
-        Some(sum / values.len() as f64)
-    }
+    summary
+        .and_then(value_extractor)
+        .map(|stats| stats.mean)
 }
 
-/// Calculate multiple averages at once
+/// Get multiple averages at once
 pub fn calculate_multiple_averages(
-    data: &[AirQualityData],
-    time_range: &TimeRange,
-    location_filter: &LocationFilter,
+    summary: Option<&AggregateBucket>,
 ) -> (
     Option<f64>, // temperature
     Option<f64>, // humidity
@@ -57,16 +26,20 @@ pub fn calculate_multiple_averages(
     Option<f64>, // co2
     Option<f64>, // co
     Option<f64>, // o3
+    Option<f64>, // no2
+    Option<f64>, // so2
 ) {
     (
-        calculate_average(data, time_range, location_filter, |record| record.temperature),
-        calculate_average(data, time_range, location_filter, |record| record.humidity),
-        calculate_average(data, time_range, location_filter, |record| record.pressure),
-        calculate_average(data, time_range, location_filter, |record| record.pm1_0),
-        calculate_average(data, time_range, location_filter, |record| record.pm2_5),
-        calculate_average(data, time_range, location_filter, |record| record.pm10),
-        calculate_average(data, time_range, location_filter, |record| record.co2),
-        calculate_average(data, time_range, location_filter, |record| record.co),
-        calculate_average(data, time_range, location_filter, |record| record.o3),
+        calculate_average(summary, |record| record.temperature.as_ref()),
+        calculate_average(summary, |record| record.humidity.as_ref()),
+        calculate_average(summary, |record| record.pressure.as_ref()),
+        calculate_average(summary, |record| record.pm1_0.as_ref()),
+        calculate_average(summary, |record| record.pm2_5.as_ref()),
+        calculate_average(summary, |record| record.pm10.as_ref()),
+        calculate_average(summary, |record| record.co2.as_ref()),
+        calculate_average(summary, |record| record.co.as_ref()),
+        calculate_average(summary, |record| record.o3.as_ref()),
+        calculate_average(summary, |record| record.no2.as_ref()),
+        calculate_average(summary, |record| record.so2.as_ref()),
     )
 }
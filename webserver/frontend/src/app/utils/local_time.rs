@@ -0,0 +1,30 @@
+use chrono::{ DateTime, FixedOffset, NaiveDate, Utc };
+use wasm_bindgen::JsValue;
+
+/// Offset of the browser's timezone from UTC at the given instant, accounting for daylight saving time
+pub fn local_offset(at: &DateTime<Utc>) -> FixedOffset {
+    let date = js_sys::Date::new(&JsValue::from_f64(at.timestamp_millis() as f64));
+
+    // getTimezoneOffset is in minutes and positive west of UTC
+    let minutes = date.get_timezone_offset() as i32;
+
+    FixedOffset::west_opt(minutes * 60).unwrap_or_else(|| FixedOffset::east_opt(0).unwrap())
+}
+
+/// Converts a UTC timestamp from the backend to the browser's local time for display
+pub fn to_local(at: &DateTime<Utc>) -> DateTime<FixedOffset> {
+    at.with_timezone(&local_offset(at))
+}
+
+/// Start of the given calendar day in the browser's timezone
+pub fn local_midnight(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    let offset = local_offset(&midnight.and_utc());
+
+    (midnight - offset).and_utc()
+}
+
+/// Start of the local day the given instant falls on
+pub fn start_of_local_day(at: &DateTime<Utc>) -> DateTime<Utc> {
+    local_midnight(to_local(at).date_naive())
+}
@@ -6,4 +6,5 @@ pub mod range_calculations;
 pub mod series_builder;
 pub mod time_formatter;
 pub mod time_filter;
-pub mod location_filter;
\ No newline at end of file
+pub mod location_filter;
+pub mod local_time;
\ No newline at end of file
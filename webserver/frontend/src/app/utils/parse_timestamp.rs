@@ -1,6 +1,12 @@
 use chrono::{DateTime, Utc};
 
 pub fn parse_timestamp(ts: &str) -> Result<DateTime<Utc>, chrono::format::ParseError> {
+    // The backend returns RFC 3339 timestamps in UTC
+    let result = DateTime::parse_from_rfc3339(ts);
+    if result.is_ok() {
+        return result.map(|dt| dt.with_timezone(&Utc));
+    }
+
     // Try parsing with different formats
     let result = DateTime::parse_from_str(ts, "%Y-%m-%d %H:%M:%S %z");
     if result.is_ok() {
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc, Duration};
+use crate::app::utils::local_time::{ start_of_local_day, to_local };
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum TimeRange {
@@ -18,14 +19,14 @@ impl TimeRange {
 
         let start = match self {
             TimeRange::Today => {
-                // Start of today
-                let start_of_day = now.date_naive().and_hms_opt(0, 0, 0).unwrap().and_local_timezone(Utc).unwrap();
+                // Start of today in the browser's timezone
+                let start_of_day = start_of_local_day(&now);
                 log::info!("Today range: {} to {}", start_of_day, now);
                 start_of_day
             },
             TimeRange::Yesterday => {
-                // Start of yesterday
-                let yesterday_start = (now - Duration::days(1)).date_naive().and_hms_opt(0, 0, 0).unwrap().and_local_timezone(Utc).unwrap();
+                // Start of yesterday in the browser's timezone
+                let yesterday_start = start_of_local_day(&(start_of_local_day(&now) - Duration::seconds(1)));
                 log::info!("Yesterday range start: {}", yesterday_start);
                 yesterday_start
             },
@@ -51,7 +52,7 @@ impl TimeRange {
         // For Yesterday, we need to set the end date to the end of yesterday
         let end = match self {
             TimeRange::Yesterday => {
-                let yesterday_end = now.date_naive().and_hms_opt(0, 0, 0).unwrap().and_local_timezone(Utc).unwrap() - Duration::seconds(1);
+                let yesterday_end = start_of_local_day(&now) - Duration::seconds(1);
                 log::info!("Yesterday range end: {}", yesterday_end);
                 yesterday_end
             },
@@ -65,6 +66,26 @@ impl TimeRange {
         (start, end)
     }
 
+    // Bucket size used when requesting aggregated chart series, keeping the number of points manageable
+    pub fn bucket_size(&self) -> &'static str {
+        match self {
+            TimeRange::Today | TimeRange::Yesterday => "5m",
+            TimeRange::LastWeek | TimeRange::LastMonth => "1h",
+            TimeRange::Custom(start_date, end_date) => {
+                let span = *end_date - *start_date;
+                if span <= Duration::days(2) {
+                    "5m"
+                } else if span <= Duration::days(31) {
+                    "1h"
+                } else if span <= Duration::days(366) {
+                    "1d"
+                } else {
+                    "1w"
+                }
+            },
+        }
+    }
+
     // Format for display
     pub fn display_name(&self) -> String {
         match self {
@@ -74,8 +95,8 @@ impl TimeRange {
             TimeRange::LastMonth => "Last 30 Days".to_string(),
             TimeRange::Custom(start, end) => {
                 format!("{} to {}",
-                    start.format("%Y-%m-%d"),
-                    end.format("%Y-%m-%d"))
+                    to_local(start).format("%Y-%m-%d"),
+                    to_local(end).format("%Y-%m-%d"))
             }
         }
     }
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc, Timelike, Datelike};
+use crate::app::utils::local_time::to_local;
 use std::cell::RefCell;
 
 thread_local! {
@@ -27,7 +28,7 @@ pub fn reset_time_label_state() {
     });
 }
 
-/// Given a DateTime<Utc>, only show the largest unit that has changed:
+/// Given a DateTime<Utc>, only show the largest unit of the browser's local time that has changed:
 ///   * On year change, show `YYYY/MM/DD HH:MM`
 ///   * On month change, show `MM/DD HH:MM`
 ///   * On day change, show `DD HH:MM`
@@ -36,6 +37,7 @@ pub fn reset_time_label_state() {
 pub fn smart_time_label(dt: &DateTime<Utc>) -> String {
     PREV.with(|cell| {
         let mut state = cell.borrow_mut();
+        let dt = to_local(dt);
         let y = dt.year();
         let m = dt.month();
         let d = dt.day();